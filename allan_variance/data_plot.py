@@ -0,0 +1,34 @@
+# グラフ作成
+
+import csv
+import matplotlib.pyplot as plt
+
+# ---------- CSVファイルからデータ読み込み ----------- #
+# クラスタ時間
+tau = []
+# Allan偏差
+adev = [[], [], []]
+
+# CSVからデータを読み出して配列に追加
+with open('./result.csv') as f:
+    reader = csv.reader(f)
+    for row in reader:
+        nums = [float(v) for v in row]  # 文字列から浮動小数点数に変換
+
+        tau.append(nums[0])
+        for i in range(3):
+            adev[i].append(nums[i+1])
+
+
+# ----------------- グラフ描画の準備 ------------------ #
+fig = plt.figure(figsize = (9, 6))
+plt.suptitle('Allan Deviation of Angular velocity',fontsize=20)
+plt.loglog(tau, adev[0], label="X axis")
+plt.loglog(tau, adev[1], label="Y axis")
+plt.loglog(tau, adev[2], label="Z axis")
+plt.grid(which='both')
+plt.legend()
+plt.ylabel('Allan deviation [rad/s]')
+plt.xlabel('tau [s]')
+
+plt.show()
@@ -0,0 +1,241 @@
+//! 静止状態で記録した角速度ログからAllan偏差を計算する
+//!
+//! 引数にログファイルを指定した場合はそれを解析し，
+//! 指定しない場合はシミュレーションと同じノイズモデルで静止ログを生成して解析する．
+//!
+//! ログの形式（CSV，数値として読めない行は読み飛ばす）：
+//! 時刻[s], 角速度x[rad/s], 角速度y[rad/s], 角速度z[rad/s]
+
+use std::env;
+use std::fs;
+use std::io::{self, Write, BufWriter, BufRead, BufReader};
+use std::process;
+
+use rand::distributions::{Distribution, Normal};
+
+/// 静止ログを生成する際のサンプリング周期（シミュレーションと同じ値）
+const DT: f64 = 0.02;
+
+/// 静止ログを生成する際の記録時間[s]
+const LOG_TIME: f64 = 3600.0;
+
+/// 角速度センサのノイズ分散（シミュレーションと同じ値）
+const GYR_VAR: f64 = 0.0001;
+
+/// 角速度バイアス（シミュレーションと同じ値）
+const GYR_BIAS: [f64; 3] = [-0.02, 0.01, 0.05];
+
+/// 1桁（10倍）あたりに計算するクラスタ時間の数
+const POINTS_PER_DECADE: f64 = 10.0;
+
+/// 解析結果の出力先
+const RESULT_PATH: &str = "result.csv";
+
+/// 生成した静止ログの出力先
+const STATIC_LOG_PATH: &str = "gyro_static.csv";
+
+fn main() {
+    // ファイルの読み書きに失敗した場合は，原因を表示して終了コード1で終了する
+    if let Err(e) = run() {
+        eprintln!("エラー: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> io::Result<()> {
+    let (time, gyr) = match env::args().nth(1) {
+        Some(path) => read_log(&path).map_err(|e| context(e, &path))?,
+        None => {
+            let log = generate_static_log();
+            write_log(STATIC_LOG_PATH, &log.0, &log.1).map_err(|e| context(e, STATIC_LOG_PATH))?;
+            log
+        },
+    };
+    if time.len() <= 2 {
+        return Err( io::Error::new(io::ErrorKind::InvalidData, "ログのサンプル数が少なすぎます") );
+    }
+
+    // サンプリング周期は平均値を使う
+    let tau0 = (time[time.len() - 1] - time[0]) / (time.len() - 1) as f64;
+
+    let m_list = cluster_sizes(time.len());
+    let mut adev = [vec![], vec![], vec![]];
+    for (i, adev_i) in adev.iter_mut().enumerate() {
+        let omega: Vec<f64> = gyr.iter().map(|g| g[i]).collect();
+        *adev_i = allan_deviation(&omega, tau0, &m_list);
+    }
+    let tau: Vec<f64> = m_list.iter().map(|&m| m as f64 * tau0).collect();
+
+    // CSVファイルにデータ保存（同一ファイルが存在したら上書き）
+    write_result(RESULT_PATH, &tau, &adev).map_err(|e| context(e, RESULT_PATH))?;
+
+    // ノイズパラメータの読み取り
+    println!("サンプリング周期: {:.5} s, サンプル数: {}", tau0, time.len());
+    for (i, axis) in ["x", "y", "z"].iter().enumerate() {
+        let arw = angle_random_walk(&tau, &adev[i]);
+        let bi = bias_instability(&adev[i]);
+        println!("[{}軸]", axis);
+        println!("  ARW             : {:.4e} rad/√s", arw);
+        println!("  バイアス不安定性: {:.4e} rad/s", bi);
+        // ARWをサンプリング周期DTの白色ノイズに換算（σ^2 = N^2 / Δt）
+        println!("  GYR_VAR相当値   : {:.4e} (DT = {} s)", arw * arw / DT, DT);
+    }
+    Ok(())
+}
+
+/// 入出力のエラーに対象のファイル名を付ける．
+fn context(e: io::Error, path: &str) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path, e))
+}
+
+/// クラスタ時間ごとのAllan偏差をCSVファイルに書き出す．
+fn write_result(path: &str, tau: &[f64], adev: &[Vec<f64>; 3]) -> io::Result<()> {
+    let mut file = BufWriter::new( fs::File::create(path)? );
+    for k in 0..tau.len() {
+        file.write_all( format!("{:.7},", tau[k]).as_bytes() )?;
+        file.write_all( format!("{:.7e},{:.7e},{:.7e}\n", adev[0][k], adev[1][k], adev[2][k]).as_bytes() )?;
+    }
+    file.flush()
+}
+
+/// シミュレーションと同じノイズモデル（白色ノイズ＋一定バイアス）で静止ログを生成する．
+fn generate_static_log() -> (Vec<f64>, Vec<[f64; 3]>) {
+    let randn = Normal::new(0.0, 1.0);  // 平均値:0，標準偏差:1
+    let std_dev = GYR_VAR.sqrt();
+
+    let n = (LOG_TIME / DT) as usize;
+    let mut time = Vec::with_capacity(n);
+    let mut gyr = Vec::with_capacity(n);
+    for t in 0..n {
+        let mut g = [0.0; 3];
+        for (i, g_i) in g.iter_mut().enumerate() {
+            *g_i = GYR_BIAS[i] + randn.sample(&mut rand::thread_rng()) * std_dev;
+        }
+        time.push(t as f64 * DT);
+        gyr.push(g);
+    }
+    (time, gyr)
+}
+
+/// 角速度ログをCSVファイルに書き出す．
+fn write_log(path: &str, time: &[f64], gyr: &[[f64; 3]]) -> io::Result<()> {
+    let mut file = BufWriter::new( fs::File::create(path)? );
+    for (t, g) in time.iter().zip(gyr) {
+        file.write_all( format!("{:.3},{:.7},{:.7},{:.7}\n", t, g[0], g[1], g[2]).as_bytes() )?;
+    }
+    file.flush()
+}
+
+/// 角速度ログをCSVファイルから読み込む．
+fn read_log(path: &str) -> io::Result<(Vec<f64>, Vec<[f64; 3]>)> {
+    let file = BufReader::new( fs::File::open(path)? );
+
+    let mut time = vec![];
+    let mut gyr = vec![];
+    for line in file.lines() {
+        let line = line?;
+        let nums: Result<Vec<f64>, _> = line.split(',').map(|v| v.trim().parse::<f64>()).collect();
+        // ヘッダ行などは読み飛ばす
+        if let Ok(nums) = nums {
+            if nums.len() >= 4 {
+                time.push(nums[0]);
+                gyr.push([nums[1], nums[2], nums[3]]);
+            }
+        }
+    }
+    Ok( (time, gyr) )
+}
+
+/// Allan偏差を計算するクラスタサイズ（対数軸上でほぼ等間隔）を返す．
+///
+/// * n: サンプル数
+fn cluster_sizes(n: usize) -> Vec<usize> {
+    let m_max = (n - 1) / 2;
+    let ratio = 10f64.powf(1.0 / POINTS_PER_DECADE);
+
+    let mut m_list: Vec<usize> = vec![];
+    let mut m = 1.0;
+    while (m as usize) <= m_max {
+        if m_list.last() != Some(&(m as usize)) {
+            m_list.push(m as usize);
+        }
+        m *= ratio;
+    }
+    m_list
+}
+
+/// オーバーラップ型のAllan偏差を計算する．
+///
+/// * omega : 角速度[rad/s]
+/// * tau0  : サンプリング周期[s]
+/// * m_list: クラスタサイズ（< omega.len() / 2）
+fn allan_deviation(omega: &[f64], tau0: f64, m_list: &[usize]) -> Vec<f64> {
+    // 角度に積分しておく
+    let mut theta = Vec::with_capacity(omega.len() + 1);
+    theta.push(0.0);
+    for w in omega {
+        theta.push(theta[theta.len() - 1] + w * tau0);
+    }
+
+    let n = theta.len();
+    let mut adev = Vec::with_capacity(m_list.len());
+    for &m in m_list {
+        let tau = m as f64 * tau0;
+        let mut sum = 0.0;
+        for k in 0..(n - 2 * m) {
+            let tmp = theta[k + 2 * m] - 2.0 * theta[k + m] + theta[k];
+            sum += tmp * tmp;
+        }
+        let avar = sum / (2.0 * tau * tau * (n - 2 * m) as f64);
+        adev.push(avar.sqrt());
+    }
+    adev
+}
+
+/// Allan偏差から角度ランダムウォーク（ARW）係数N[rad/√s]を読み取る．
+///
+/// 傾きが-1/2に最も近い区間を延長して，τ=1sでの値を求める．
+fn angle_random_walk(tau: &[f64], adev: &[f64]) -> f64 {
+    let mut best = 0;
+    let mut best_diff = f64::MAX;
+    for k in 0..(tau.len() - 1) {
+        let slope = (adev[k + 1].ln() - adev[k].ln()) / (tau[k + 1].ln() - tau[k].ln());
+        if (slope + 0.5).abs() < best_diff {
+            best_diff = (slope + 0.5).abs();
+            best = k;
+        }
+    }
+    adev[best] * tau[best].sqrt()
+}
+
+/// Allan偏差の最小値からバイアス不安定性B[rad/s]を読み取る．
+fn bias_instability(adev: &[f64]) -> f64 {
+    let min = adev.iter().cloned().fold(f64::MAX, f64::min);
+    // 傾き0の区間ではσ(τ) = B * sqrt(2ln2/π)
+    min / (2.0 * 2f64.ln() / std::f64::consts::PI).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    /// 白色ノイズのARWはN = σ√τ0になる．
+    #[test]
+    fn white_noise_arw() {
+        let (sigma, tau0) = (0.01, 0.02);
+        let randn = Normal::new(0.0, sigma);
+        let mut rng = StdRng::seed_from_u64(1);
+        let omega: Vec<f64> = (0..100_000).map(|_| randn.sample(&mut rng)).collect();
+
+        let m_list = cluster_sizes(omega.len());
+        let adev = allan_deviation(&omega, tau0, &m_list);
+        let tau: Vec<f64> = m_list.iter().map(|&m| m as f64 * tau0).collect();
+
+        // クラスタサイズ1では標本の標準偏差そのもの
+        assert!((adev[0] - sigma).abs() < 0.02 * sigma, "{}", adev[0]);
+        let arw = angle_random_walk(&tau, &adev);
+        let expected = sigma * tau0.sqrt();
+        assert!((arw - expected).abs() < 0.1 * expected, "{} != {}", arw, expected);
+    }
+}
@@ -0,0 +1,85 @@
+//! predict()とcorrect()の処理時間を計測する（bank::FilterBankで多数のフィルタをまとめて更新する場合も）
+//! 
+//! フィルタはf64でのみ実装しているので，計測もf64のみ．
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use omega_ff_dynamic_acc::{ahrs, bank, quat};
+
+/// 外乱判定の閾値（シミュレーションと同じ値）
+const THR_WEAK: f64 = 0.04;
+const THR_STRONG: f64 = 0.08;
+
+fn new_filter() -> ahrs::AttitudeFilter {
+    ahrs::AttitudeFilter::new(1.0, 0.2, THR_WEAK, THR_STRONG)
+}
+
+fn bench_predict(c: &mut Criterion) {
+    let mut filter = new_filter();
+    let gyr = [0.1, -0.05, 0.02];
+    c.bench_function("predict", |b| b.iter(|| filter.predict(black_box(gyr))));
+}
+
+fn bench_correct(c: &mut Criterion) {
+    let mag = ahrs::MAG_R;
+    // 重力方向に外乱を加えて各判定分岐を通るようにする（姿勢は単位四元数のままなので，E1 = E2 = |Δ|/g）
+    let cases = [
+        ("correct/no_disturbance", 0.0),
+        ("correct/weak_disturbance", 0.06 * ahrs::STANDARD_GRAVITY),
+        ("correct/strong_disturbance", 0.15 * ahrs::STANDARD_GRAVITY),
+    ];
+    for (name, a_dr) in cases {
+        let mut filter = new_filter();
+        let acc = quat::add_vec(ahrs::ACC_R, [0.0, 0.0, a_dr]);
+        c.bench_function(name, |b| b.iter(|| filter.correct(black_box(acc), black_box(mag))));
+    }
+
+    // ヒステリシス処理：強い外乱の後，閾値付近の外乱が続く場合
+    let mut filter = new_filter();
+    filter.correct(quat::add_vec(ahrs::ACC_R, [0.0, 0.0, 0.15 * ahrs::STANDARD_GRAVITY]), mag);
+    let acc = quat::add_vec(ahrs::ACC_R, [0.0, 0.0, 0.075 * ahrs::STANDARD_GRAVITY]);
+    c.bench_function("correct/hysteresis", |b| b.iter(|| filter.correct(black_box(acc), black_box(mag))));
+}
+
+fn bench_step(c: &mut Criterion) {
+    let mut filter = new_filter();
+    let gyr = [0.1, -0.05, 0.02];
+    let acc = ahrs::ACC_R;
+    let mag = ahrs::MAG_R;
+    c.bench_function("predict+correct", |b| b.iter(|| {
+        filter.predict(black_box(gyr));
+        filter.correct(black_box(acc), black_box(mag));
+    }));
+}
+
+fn bench_bank(c: &mut Criterion) {
+    // モンテカルロ試行：外乱無し・弱い外乱・強い外乱が混ざった，試行ごとに違う計測値
+    const N: usize = 1000;
+    let samples: Vec<ahrs::Sample> = (0..N).map(|i| {
+        let a_dr = match i % 3 {
+            0 => 0.0,
+            1 => 0.06 * ahrs::STANDARD_GRAVITY,
+            _ => 0.15 * ahrs::STANDARD_GRAVITY,
+        };
+        ahrs::Sample {
+            gyr: [0.1, -0.05, 0.02],
+            acc: quat::add_vec(ahrs::ACC_R, [0.0, 0.0, a_dr]),
+            mag: ahrs::MAG_R,
+        }
+    }).collect();
+
+    let mut filters = vec![new_filter(); N];
+    c.bench_function("monte_carlo/filters/1000", |b| b.iter(|| {
+        for (filter, s) in filters.iter_mut().zip(black_box(&samples)) {
+            filter.predict(s.gyr);
+            filter.correct(s.acc, s.mag);
+        }
+    }));
+
+    let mut bank = bank::FilterBank::new(N, 1.0, 0.2, THR_WEAK, THR_STRONG);
+    c.bench_function("monte_carlo/bank/1000", |b| b.iter(|| bank.step(black_box(&samples))));
+}
+
+criterion_group!(benches, bench_predict, bench_correct, bench_step, bench_bank);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+//! ffiフィーチャが有効な場合に，C言語用のヘッダファイルをOUT_DIRに生成する．
+//! ソースのツリーには書き込まないので，リポジトリのinclude/omega_ff_dynamic_acc.hはcbindgenのコマンドで更新する（README参照）．
+//! serveフィーチャが有効な場合に，gRPCのサービスのコードをproto/omega_ff.protoから生成する．
+
+fn main() {
+    #[cfg(feature = "ffi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let header = format!("{}/omega_ff_dynamic_acc.h", std::env::var("OUT_DIR").unwrap());
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+        // フィルタ本体は不透明型として扱うので，ffi.rsだけを解析する
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/ffi.rs", crate_dir))
+            .generate()
+            .expect("ヘッダファイルの生成に失敗しました")
+            .write_to_file(&header);
+        // 同梱のヘッダファイルが古くなっていれば知らせる
+        if std::fs::read(&header).ok() != std::fs::read(format!("{}/include/omega_ff_dynamic_acc.h", crate_dir)).ok() {
+            println!("cargo:warning=include/omega_ff_dynamic_acc.hがsrc/ffi.rsと合っていません（生成したもの：{}）", header);
+        }
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-changed=include/omega_ff_dynamic_acc.h");
+    }
+
+    #[cfg(feature = "serve")]
+    {
+        // protocはPATHに無くてもよいように，PROTOCで指定されていなければクレートに同梱のものを使う
+        if std::env::var_os("PROTOC").is_none() {
+            let protoc = protoc_bin_vendored::protoc_bin_path().expect("同梱のprotocがこのプラットフォームにありません");
+            std::env::set_var("PROTOC", protoc);
+        }
+        tonic_prost_build::compile_protos("proto/omega_ff.proto").expect("gRPCのコードの生成に失敗しました");
+    }
+}
@@ -18,16 +18,22 @@ gyr_bias_hat = [[], [], []]
 q = [[], [], [], []]
 # 四元数の推定値
 q_hat = [[], [], [], []]
-# 加速度外乱
+# 角速度の積分だけで求めたオイラー角（補正なし）
+ypr_gyro = [[], [], []]
+# 加速度外乱の真値
 a_dr = [[], [], []]
+# 加速度外乱の推定値
+a_dr_hat = [[], [], []]
 # 外乱検知
 err = []
+# 姿勢誤差の回転角
+angle_err = []
 
 # CSVからデータを読み出して配列に追加
 with open('./result.csv') as f:
     reader = csv.reader(f)
     for row in reader:
-        nums = [float(v) for v in row]  # 文字列から浮動小数点数に変換
+        nums = [float(v) if v else float('nan') for v in row]  # 文字列から浮動小数点数に変換（空の列はNaN）
 
         # 時刻
         t.append(nums[0])
@@ -35,6 +41,7 @@ with open('./result.csv') as f:
         for i in range(3):
             ypr[i].append(nums[i+1])
             ypr_hat[i].append(nums[i+4])
+            ypr_gyro[i].append(nums[i+42])
         # ジャイロバイアス
         for i in range(3):
             gyr_bias[i].append(nums[i+7])
@@ -46,14 +53,16 @@ with open('./result.csv') as f:
         # 加速度外乱
         for i in range(3):
             a_dr[i].append(nums[i+21])
+            a_dr_hat[i].append(nums[i+24])
         
-        err.append(nums[24])
+        err.append(nums[27])
+        angle_err.append(nums[28])
 
 
 # ----------------- グラフ描画の準備 ------------------ #
 # Figureを追加
 fig1 = plt.figure(figsize = (13, 7))
-plt.suptitle('Omega Feedback Filter (E2)',fontsize=20)
+plt.suptitle('Omega Feedback Filter',fontsize=20)
 
 ax1 = fig1.add_subplot(331, ylabel='X axis (Roll)', title='Euler angles [rad]')
 ax2 = fig1.add_subplot(334, ylabel='Y axis (Pitch)')
@@ -67,10 +76,13 @@ ax9 = fig1.add_subplot(339, xlabel='time [s]')
 
 ax1.plot(t, ypr[2],     label="True", color="black")
 ax1.plot(t, ypr_hat[2], label="Estimated", color="red", linestyle = "--")
+ax1.plot(t, ypr_gyro[2], label="Gyro only", color="gray", linestyle = ":")
 ax2.plot(t, ypr[1],     label="True", color="black")
 ax2.plot(t, ypr_hat[1], label="Estimated", color="red", linestyle = "--")
+ax2.plot(t, ypr_gyro[1], label="Gyro only", color="gray", linestyle = ":")
 ax3.plot(t, ypr[0],     label="True", color="black")
 ax3.plot(t, ypr_hat[0], label="Estimated", color="red", linestyle = "--")
+ax3.plot(t, ypr_gyro[0], label="Gyro only", color="gray", linestyle = ":")
 ax4.plot(t, gyr_bias[0],     label="True", color="black")
 ax4.plot(t, gyr_bias_hat[0], label="Estimated", color="red", linestyle = "--")
 ax5.plot(t, gyr_bias[1],     label="True", color="black")
@@ -78,8 +90,11 @@ ax5.plot(t, gyr_bias_hat[1], label="Estimated", color="red", linestyle = "--")
 ax6.plot(t, gyr_bias[2],     label="True", color="black")
 ax6.plot(t, gyr_bias_hat[2], label="Estimated", color="red", linestyle = "--")
 ax7.plot(t, a_dr[0], label="True", color="black")
+ax7.plot(t, a_dr_hat[0], label="Estimated", color="red", linestyle = "--")
 ax8.plot(t, a_dr[1], label="True", color="black")
+ax8.plot(t, a_dr_hat[1], label="Estimated", color="red", linestyle = "--")
 ax9.plot(t, a_dr[2], label="True", color="black")
+ax9.plot(t, a_dr_hat[2], label="Estimated", color="red", linestyle = "--")
 ax1.legend()
 ax4.legend()
 ax7.legend()
@@ -126,11 +141,19 @@ ax4.set_ylim(-1.2, 1.2)
 
 # 外乱検出の誤差関数
 fig3 = plt.figure(figsize = (9, 6))
-plt.suptitle('Disturbance Detecting Function: E2',fontsize=20)
+plt.suptitle('Disturbance Detecting Function',fontsize=20)
 plt.plot(t, err)
 plt.xlim(0, t[-1])
 #plt.ylim(0, 0.32)
 plt.ylabel('Error')
 plt.xlabel('time [s]')
 
+# 姿勢誤差（q_err = q_true^-1 * q_hat の回転角）
+fig4 = plt.figure(figsize = (9, 6))
+plt.suptitle('Attitude Error',fontsize=20)
+plt.plot(t, angle_err)
+plt.xlim(0, t[-1])
+plt.ylabel('Rotation angle of q_err [rad]')
+plt.xlabel('time [s]')
+
 plt.show()
\ No newline at end of file
@@ -0,0 +1,55 @@
+//! tokioで受信したテレメトリ（UDP，TCP，WebSocket）から姿勢を推定してオイラー角を表示する
+//!
+//! ```
+//! cargo run --release --example async_live --features async -- udp:0.0.0.0:5555
+//! cargo run --release --example async_live --features async -- tcp:192.168.0.10:5555
+//! cargo run --release --example async_live --features async -- ws://192.168.0.10:8080/imu
+//! ```
+//!
+//! パケットは--streamと同じCSV（時刻と9軸の計測値）．tcp:は相手に接続して読む．
+
+use std::env;
+use std::error::Error;
+
+use omega_ff_dynamic_acc::ahrs;
+use omega_ff_dynamic_acc::async_source::{AsyncSensorSource, AsyncSource};
+use omega_ff_dynamic_acc::source::PacketFormat;
+
+/// 外乱判定の閾値（シミュレーションと同じ値）
+const THR_WEAK: f64 = 0.04;
+const THR_STRONG: f64 = 0.08;
+
+/// 表示する間隔（サンプル数）
+const PRINT_INTERVAL: u64 = 10;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let addr = env::args().nth(1).unwrap_or_else(|| "udp:0.0.0.0:5555".to_string());
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build()?;
+    runtime.block_on(async {
+        if let Some(addr) = addr.strip_prefix("udp:") {
+            estimate(AsyncSource::udp(addr, PacketFormat::Csv).await?).await;
+        } else if let Some(addr) = addr.strip_prefix("tcp:") {
+            estimate(AsyncSource::connect(addr, PacketFormat::Csv).await?).await;
+        } else if addr.starts_with("ws://") {
+            estimate(AsyncSource::websocket(&addr, PacketFormat::Csv).await?).await;
+        } else {
+            return Err(format!("udp:，tcp:，ws://のいずれかで始まるアドレスを指定してください: {}", addr).into());
+        }
+        Ok(())
+    })
+}
+
+/// 取得元が終わるまで推定する．
+async fn estimate<S: AsyncSensorSource>(mut source: S) {
+    let mut filter = ahrs::AttitudeFilter::new(1.0, 0.2, THR_WEAK, THR_STRONG);
+    while let Some(sample) = source.next_sample().await {
+        filter.predict(sample.gyr);
+        filter.correct(sample.acc, sample.mag);
+
+        if filter.n_steps.is_multiple_of(PRINT_INTERVAL) {
+            let [yaw, pitch, roll] = ahrs::to_euler_angles(filter.q, ahrs::EulerSequence::ZYX).map(f64::to_degrees);
+            println!("{:8.3} s  yaw {:7.1}  pitch {:6.1}  roll {:7.1} [deg]  外乱 {:.3}",
+                sample.time, yaw, pitch, roll, filter.disturbance_error(sample.acc));
+        }
+    }
+}
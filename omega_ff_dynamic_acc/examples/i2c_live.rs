@@ -0,0 +1,53 @@
+//! Raspberry PiなどのLinuxのI2CにつないだMPU-9250を読み，姿勢を推定してオイラー角を表示する
+//!
+//! ```
+//! cargo run --release --example i2c_live --features linux-i2c -- /dev/i2c-1
+//! ```
+//!
+//! /dev/i2c-Nをlinux-embedded-halのI2cdevで開き（i2c-devカーネルモジュールが必要），hal::Mpu9250で読む．
+//! 地磁気センサ（AK8963）はMPU-9250のバイパスモードで同じバスから読む．
+//! ICM-20948などの他のチップは，hal::Mpu9250と同じようにhalのトレイトを実装した型を用意する．
+
+use std::env;
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use linux_embedded_hal::{Delay, I2cdev};
+
+use omega_ff_dynamic_acc::{ahrs, hal, DT};
+use omega_ff_dynamic_acc::source::SensorSource;
+
+/// 外乱判定の閾値（シミュレーションと同じ値）
+const THR_WEAK: f64 = 0.04;
+const THR_STRONG: f64 = 0.08;
+
+/// 表示する間隔（サンプル数）
+const PRINT_INTERVAL: u64 = 10;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args().nth(1).unwrap_or_else(|| "/dev/i2c-1".to_string());
+    let i2c = I2cdev::new(&path).map_err(|e| format!("{}: {}", path, e))?;
+    let imu = hal::Mpu9250::new(i2c, &mut Delay)?;
+    let mut source = hal::HalSource::new(imu);
+    let mut filter = ahrs::AttitudeFilter::new(1.0, 0.2, THR_WEAK, THR_STRONG);
+
+    // DT秒ごとに読む
+    let start = Instant::now();
+    while let Some(sample) = source.next_sample() {
+        filter.predict(sample.gyr);
+        filter.correct(sample.acc, sample.mag);
+
+        if filter.n_steps.is_multiple_of(PRINT_INTERVAL) {
+            let [yaw, pitch, roll] = ahrs::to_euler_angles(filter.q, ahrs::EulerSequence::ZYX).map(f64::to_degrees);
+            println!("{:8.3} s  yaw {:7.1}  pitch {:6.1}  roll {:7.1} [deg]  外乱 {:.3}  読み取り失敗 {}",
+                sample.time, yaw, pitch, roll, filter.disturbance_error(sample.acc), source.n_errors());
+        }
+
+        let next = Duration::from_secs_f64((sample.time + DT).max(0.0));
+        if let Some(wait) = next.checked_sub(start.elapsed()) {
+            thread::sleep(wait);
+        }
+    }
+    Ok(())
+}
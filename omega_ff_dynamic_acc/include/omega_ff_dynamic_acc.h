@@ -0,0 +1,60 @@
+#ifndef OMEGA_FF_DYNAMIC_ACC_H
+#define OMEGA_FF_DYNAMIC_ACC_H
+
+/* このファイルはcbindgenで自動生成しています．直接編集しないでください． */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+typedef struct AttitudeFilter AttitudeFilter;
+
+/*
+ フィルタを生成する．不要になったら`omega_ff_destroy`で破棄すること．
+ 
+ 引数は`AttitudeFilter::new`と同じ．
+ */
+AttitudeFilter *omega_ff_create(double alpha, double beta, double thr_weak, double thr_strong);
+
+/*
+ 予測ステップ
+ 
+ * gyr: 機体上で計測した角速度[rad/s]（要素数3）
+ 
+ # Safety
+ `filter`は`omega_ff_create`で生成したもの，`gyr`は要素数3の配列を指していること．
+ */
+void omega_ff_predict(AttitudeFilter *filter,
+                      const double *gyr);
+
+/*
+ 補正ステップ
+ 
+ * acc: 機体上のセンサで計測した加速度[m/s^2]（要素数3）
+ * mag: 機体上のセンサで計測した地磁気（要素数3）
+ 
+ # Safety
+ `filter`は`omega_ff_create`で生成したもの，`acc`と`mag`は要素数3の配列を指していること．
+ */
+void omega_ff_correct(AttitudeFilter *filter,
+                      const double *acc,
+                      const double *mag);
+
+/*
+ 姿勢推定値を`q`（要素数4，[q0, q1, q2, q3]の順）に書き込む．
+ 
+ # Safety
+ `filter`は`omega_ff_create`で生成したもの，`q`は要素数4の配列を指していること．
+ */
+void omega_ff_get_quaternion(const AttitudeFilter *filter,
+                             double *q);
+
+/*
+ フィルタを破棄する．NULLを渡した場合は何もしない．
+ 
+ # Safety
+ `filter`は`omega_ff_create`で生成したもので，まだ破棄していないこと．
+ */
+void omega_ff_destroy(AttitudeFilter *filter);
+
+#endif  /* OMEGA_FF_DYNAMIC_ACC_H */
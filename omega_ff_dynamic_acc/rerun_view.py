@@ -0,0 +1,105 @@
+# rerunで姿勢の真値と推定値を3次元で表示する
+#
+# python3 rerun_view.py [result.csv] [--record sensors.bin]
+#
+# シミュレーションの結果（result.csv）を読み，真値と推定値の姿勢を直方体で，外乱判定の誤差関数とフラグを時系列で表示する．
+# --recordで記録した計測値のファイルを与えると，加速度と地磁気の計測値を機体に固定した矢印で表示する．
+# rerun-sdk（pip install rerun-sdk，0.15〜0.22）が必要．
+
+import csv
+import struct
+import sys
+
+import rerun as rr
+
+# 外乱判定の閾値とヒステリシスの幅（シミュレーションと同じ値）
+THR_WEAK = 0.04
+THR_STRONG = 0.08
+HYSTERESIS = 0.2
+
+# --recordのファイルの識別子と1サンプルの値の数
+MAGIC = b'OFFREC1\0'
+N_VALUES = 10
+
+# 機体を表す直方体の大きさの半分
+HALF_SIZE = [1.0, 0.5, 0.2]
+
+
+def load_record(path):
+    """--recordで記録したファイルから時刻ごとの加速度と地磁気を読む．"""
+    samples = {}
+    with open(path, 'rb') as f:
+        assert f.read(len(MAGIC)) == MAGIC, '記録したファイルではありません: ' + path
+        while True:
+            buf = f.read(8 * N_VALUES)
+            if len(buf) < 8 * N_VALUES:
+                break
+            v = struct.unpack('<' + 'd' * N_VALUES, buf)
+            samples[round(v[0], 3)] = (v[4:7], v[7:10])
+    return samples
+
+
+def update_flags(e, weak, strong):
+    """外乱判定のフラグを更新する（AttitudeFilterの判定と同じ）．"""
+    if e > THR_STRONG:
+        return weak, True
+    if e > THR_WEAK:
+        if strong and e > THR_STRONG * (1.0 - HYSTERESIS):
+            return weak, strong
+        return True, False
+    if weak and e > THR_WEAK * (1.0 - HYSTERESIS):
+        return weak, strong
+    return False, False
+
+
+def log_body(path, q, color):
+    """四元数q（w, x, y, z）の姿勢の機体を表示する．"""
+    rr.log(path, rr.Transform3D(rotation=rr.Quaternion(xyzw=[q[1], q[2], q[3], q[0]])))
+    rr.log(path + '/body', rr.Boxes3D(half_sizes=[HALF_SIZE], colors=[color]))
+
+
+def main():
+    args = sys.argv[1:]
+    record = None
+    if '--record' in args:
+        i = args.index('--record')
+        record = load_record(args[i + 1])
+        del args[i:i + 2]
+    result_path = args[0] if args else './result.csv'
+
+    rr.init('omega_ff_dynamic_acc', spawn=True)
+    rr.log('world', rr.ViewCoordinates.RIGHT_HAND_Z_UP, static=True)
+
+    weak = strong = False
+    with open(result_path) as f:
+        for row in csv.reader(f):
+            nums = [float(v) if v else float('nan') for v in row]
+            t = nums[0]
+            q_true = nums[13:17]
+            q_hat = nums[17:21]
+            e = nums[27]
+            gyro_free = nums[-1]
+
+            weak, strong = update_flags(e, weak, strong)
+
+            rr.set_time_seconds('time', t)
+            log_body('world/truth', q_true, [128, 128, 128])
+            # 外乱中は推定値の色を変える（強い外乱：赤，弱い外乱：黄）
+            color = [255, 0, 0] if strong else [255, 200, 0] if weak else [0, 160, 255]
+            log_body('world/estimate', q_hat, color)
+
+            if record is not None and round(t, 3) in record:
+                acc, mag = record[round(t, 3)]
+                g = sum(a * a for a in acc) ** 0.5
+                rr.log('world/estimate/acc', rr.Arrows3D(vectors=[[a / g for a in acc]], colors=[[255, 0, 255]]))
+                rr.log('world/estimate/mag', rr.Arrows3D(vectors=[mag], colors=[[0, 255, 0]]))
+
+            rr.log('disturbance/e', rr.Scalar(e))
+            rr.log('disturbance/weak', rr.Scalar(float(weak)))
+            rr.log('disturbance/strong', rr.Scalar(float(strong)))
+            rr.log('attitude_error', rr.Scalar(nums[28]))
+            rr.log('gyro_free', rr.Scalar(gyro_free))
+
+
+if __name__ == '__main__':
+    main()
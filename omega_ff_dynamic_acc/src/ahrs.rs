@@ -0,0 +1,2322 @@
+//! 姿勢推定フィルタ
+
+use super::DT;
+use super::quat;
+use super::quat::{Vector3, Quaternion, DCM};
+use super::wahba::{triad, davenport, svd};
+
+#[cfg(feature = "serde")]
+use std::{fs, io, path::Path};
+
+/// 標準重力
+pub const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// 基準座標系上における加速度計測値
+pub const ACC_R: [f64; 3] = [0.0, 0.0, STANDARD_GRAVITY];
+
+/// 基準座標系上における地磁気計測値（デフォルト，AttitudeFilter::set_reference_field()で変えられる）
+pub const MAG_R: [f64; 3] = [0.0, 1.0, 0.0];
+
+/// 自由度3のカイ二乗分布の上側1%点（イノベーションゲートの閾値に使う）
+pub const CHI2_3DOF_99: f64 = 11.345;
+
+/// 外乱検知判定のヒステリシス
+pub(crate) const HYSTERESIS: f64 = 0.2;
+
+/// 静止中に角速度バイアスの推定値を計測値に近づける割合（1サンプルあたり）
+pub const ZUPT_BIAS_GAIN: f64 = 0.05;
+
+/// 角速度を使わないモードで，加速度と地磁気から求めた姿勢を平滑化する時定数[s]（デフォルト）
+pub const GYRO_FREE_TAU: f64 = 0.2;
+
+/// 姿勢推定四元数のノルムがこれより小さくなったら発散したとみなす
+pub(crate) const MIN_NORM: f64 = 1e-6;
+
+/// self_test()の収束確認で動かす時間（収束時間alphaの何倍か）とステップ数の上限，初期の姿勢誤差に対して許容する割合
+const SELF_TEST_ALPHAS: f64 = 20.0;
+const SELF_TEST_MAX_STEPS: usize = 50_000;
+const SELF_TEST_RATIO: f64 = 0.01;
+
+/// self_test_with_sample()で静止しているとみなす角速度の大きさの上限[rad/s]，加速度の大きさの相対誤差の上限
+const SELF_TEST_MAX_RATE: f64 = 0.1;
+const SELF_TEST_ACC_TOL: f64 = 0.1;
+
+/// フィルタの状態（直近のpredict()からの補正サイクルの結果）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Health {
+    /// 正常
+    Ok,
+    /// 入力にNaNや無限大が含まれていたので，その入力を使わなかった
+    InvalidInput,
+    /// 状態が発散したので初期状態に戻した
+    Diverged,
+}
+
+/// self_test()の結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestReport {
+    pub problems: Vec<&'static str>,  // 見つかった問題（空なら合格）
+    pub quaternion_norm: f64,         // 姿勢推定値のノルム
+    pub convergence_error: f64,       // 合成した計測値で収束させた後の姿勢誤差[rad]
+    pub health: Health,               // 直近の補正サイクルの状態
+}
+
+impl SelfTestReport {
+    /// 問題が無かったかどうか
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// 加速度外乱の判定に使う誤差関数
+/// 
+/// ジェネリクスを使わずに実行時に判定式を切り替えるための列挙型で，new_with_detector()かwith_detector()で設定する．
+/// 判定式ごとの分岐はDisturbanceDetector::error()の1か所だけで，correct()はそれを呼ぶ．
+/// 使える判定式はdetector-e1，detector-e2フィーチャで決まる（デフォルトは両方，Bothは両方が有効な場合だけ）．
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Detector {
+    /// E1 = | |acc| - g | / g（計測値の大きさと重力加速度の差）
+    #[cfg(feature = "detector-e1")]
+    E1,
+    /// E2 = |acc - acc_q| / g（計測値と，姿勢推定値から予測した重力加速度の差）
+    #[cfg(feature = "detector-e2")]
+    E2,
+    /// E1とE2の大きい方（どちらか一方でも外乱とみなせば外乱とする）
+    #[cfg(all(feature = "detector-e1", feature = "detector-e2"))]
+    Both,
+}
+
+#[cfg(all(feature = "detector-e1", feature = "detector-e2"))]
+impl Detector {
+    /// Bothの別名（E1とE2を組み合わせた判定式）
+    #[allow(non_upper_case_globals)]
+    pub const Combined: Detector = Detector::Both;
+}
+
+/// Detectorの別名（DetectorKind::{E1, E2, Combined}とも書ける）
+pub type DetectorKind = Detector;
+
+#[cfg(not(any(feature = "detector-e1", feature = "detector-e2")))]
+compile_error!("detector-e1かdetector-e2の少なくとも一方のフィーチャを有効にしてください");
+
+impl Default for Detector {
+    /// E1（detector-e1フィーチャが無効ならE2）
+    fn default() -> Self {
+        #[cfg(feature = "detector-e1")]
+        return Detector::E1;
+        #[cfg(not(feature = "detector-e1"))]
+        return Detector::E2;
+    }
+}
+
+/// 加速度外乱の判定に使う誤差関数
+/// 
+/// 閾値による判定とヒステリシス処理はフィルタ側で行うので，誤差関数だけを実装すれば新しい判定式を追加できる．
+/// 実装した判定式はAttitudeFilter::correct_with_detector()に渡して使う．
+pub trait DisturbanceDetector {
+    /// 誤差関数の値（0で外乱無し，大きいほど強い外乱）を返す．
+    /// 
+    /// * acc  : 機体上のセンサで計測した加速度[m/s^2]
+    /// * acc_q: 姿勢推定値から予測した重力加速度（機体座標系）[m/s^2]
+    fn error(&self, acc: Vector3<f64>, acc_q: Vector3<f64>) -> f64;
+}
+
+/// 外乱判定式E1 = | |acc| - g | / g
+#[cfg(feature = "detector-e1")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct E1;
+
+#[cfg(feature = "detector-e1")]
+impl DisturbanceDetector for E1 {
+    fn error(&self, acc: Vector3<f64>, _acc_q: Vector3<f64>) -> f64 {
+        ( quat::norm_vec(acc) - STANDARD_GRAVITY ).abs() / STANDARD_GRAVITY
+    }
+}
+
+/// 外乱判定式E2 = |acc - acc_q| / g
+#[cfg(feature = "detector-e2")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct E2;
+
+#[cfg(feature = "detector-e2")]
+impl DisturbanceDetector for E2 {
+    fn error(&self, acc: Vector3<f64>, acc_q: Vector3<f64>) -> f64 {
+        quat::norm_vec( quat::sub_vec(acc, acc_q) ) / STANDARD_GRAVITY
+    }
+}
+
+impl DisturbanceDetector for Detector {
+    fn error(&self, acc: Vector3<f64>, acc_q: Vector3<f64>) -> f64 {
+        match self {
+            #[cfg(feature = "detector-e1")]
+            Detector::E1 => E1.error(acc, acc_q),
+            #[cfg(feature = "detector-e2")]
+            Detector::E2 => E2.error(acc, acc_q),
+            #[cfg(all(feature = "detector-e1", feature = "detector-e2"))]
+            Detector::Both => E1.error(acc, acc_q).max( E2.error(acc, acc_q) ),
+        }
+    }
+}
+
+/// 加速度外乱に応じた補正ゲインの変え方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GainSchedule {
+    /// 閾値で切り替える（外乱無し：1倍，弱い外乱：0.5倍，強い外乱：補正しない）
+    Switching,
+    /// 誤差関数のシグモイド関数で連続的に変える（弱い外乱と強い外乱の閾値の中間で0.5倍）
+    Sigmoid,
+    /// 誤差関数のHuber重みで変える（弱い外乱の閾値までは1倍，それを超えると誤差関数に反比例して小さくする）
+    Huber,
+    /// 誤差関数のCauchy重みで変える（弱い外乱の閾値で0.5倍，誤差関数の2乗に反比例して0に近づく）
+    Cauchy,
+}
+
+/// 補正の目標にする姿勢を加速度と地磁気から計算する方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AttitudeSolver {
+    /// get_q_gm()（伏角を与えている場合はget_q_gm_full_field()）
+    GetQGm,
+    /// 加速度を主ベクトルとするTRIAD法（伏角が無ければget_q_gm()と同じ解だが，水平成分を取り出さず外積で基底を作る）
+    Triad,
+    /// 地磁気を主ベクトルとするTRIAD法（with_inclination()と併用する．伏角が無いとその分だけチルトがずれる）
+    TriadMag,
+    /// Davenportのq-method（加速度と地磁気を信頼度で重み付けする．加速度の重みには外乱判定の結果も掛ける）
+    Davenport,
+    /// 特異値分解によるWahba問題の解（伏角が無ければget_q_gm()と同じ解．反復計算なので閉じた形のget_q_gm()より遅い）
+    Svd,
+}
+
+/// イノベーション（計測値と推定姿勢から予測した値の差）の統計的検定による外乱判定
+///
+/// 計測ノイズだけなら差の二乗和を分散で割った値（マハラノビス距離の二乗）は自由度3のカイ二乗分布に従うので，
+/// 閾値chi2を超えたら外乱とみなしてその計測値を補正に使わない．閾値は誤って棄却する確率から決められる
+/// （CHI2_3DOF_99なら1%）．地磁気のイノベーションは自由度1なので，同じ閾値なら棄却されにくい側になる．
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InnovationGate {
+    pub acc_var: f64,  // 加速度センサのノイズ分散（各軸）[(m/s^2)^2]
+    pub mag_var: f64,  // 地磁気センサのノイズ分散（各軸，計測値と同じ単位の二乗）
+    pub chi2: f64,     // マハラノビス距離の二乗の閾値
+}
+
+impl InnovationGate {
+    /// 加速度のイノベーション（AttitudeFilter::acc_innovation()）が閾値を超えているかどうか
+    pub fn rejects_acc(&self, r: Vector3<f64>) -> bool {
+        quat::dot_vec(r, r) / self.acc_var > self.chi2
+    }
+
+    /// 地磁気のイノベーション（AttitudeFilter::mag_innovation()）が閾値を超えているかどうか
+    pub fn rejects_mag(&self, r: Vector3<f64>) -> bool {
+        quat::dot_vec(r, r) / self.mag_var > self.chi2
+    }
+}
+
+/// 角速度バイアスの軸ごとの可観測性（積分項を更新する重みに使う）
+/// 
+/// 補正に使う基準ベクトル（重力・地磁気）の機体座標系での向きをvとすると，v周りの回転はそのベクトルでは分からない．
+/// 機体の各軸について，基準ベクトルと直交する度合い 1 - v_i^2 を補正の重みを掛けて足し，指数移動平均をとる．
+/// 重力の向きが変わらないまま重力軸周りにだけ回転していると，加速度だけではその軸の値が0に近づく．
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Observability {
+    tau: f64,           // 指数移動平均の時定数[s]
+    thr: f64,           // この値以上なら積分項をそのまま更新する（下回ると比例して重みを下げる）
+    info: Vector3<f64>, // 軸ごとの可観測性（0〜基準ベクトルの数）
+}
+
+/// 地磁気の大きさの学習と，大きさの変化による地磁気の重み付け
+/// 
+/// 機体の近くの鉄や電流による一時的な磁場（ハードアイアン）が加わると，地磁気の向きと一緒に大きさも変わる．
+/// 外乱の無い間に大きさの指数移動平均をとり，そこから外れた程度に応じて地磁気の補正を弱める．
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct MagNorm {
+    tol: f64,     // 重みを下げ始める大きさの相対誤差
+    tau: f64,     // 大きさの指数移動平均の時定数[s]
+    norm: f64,    // 学習した大きさ（0なら未学習）
+    weight: f64,  // 直近の地磁気の重み（0〜1）
+}
+
+/// 加速度バイアスの推定
+/// 
+/// 外乱の無い間の加速度の残差（計測値 − バイアス推定値 − 推定姿勢から予測した重力加速度）をゆっくり積分する．
+/// ある姿勢で分かるのは重力方向の成分だけで，直交する成分は姿勢の誤差と区別できない．
+/// 様々な姿勢をとるうちに全ての軸の成分が重力方向に現れるので，推定値が収束する．
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct AccelBias {
+    tau: f64,            // 推定の時定数[s]
+    bias: Vector3<f64>,  // バイアスの推定値[m/s^2]
+}
+
+/// 角速度から角加速度を推定するオブザーバ（角加速度一定のモデル，臨界減衰の2次系）
+///
+/// 角速度を単純に差分するとノイズをDTで割ることになり使えないので，角速度と角加速度を状態として予測し，
+/// 計測値との差で補正する．帯域wnより速い角加速度の変化には遅れて追従する（wn * DTは1より十分小さくする）．
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AngularAccelerationObserver {
+    wn: f64,                 // 帯域（固有角周波数）[rad/s]
+    rate: Vector3<f64>,      // 角速度の推定値[rad/s]
+    accel: Vector3<f64>,     // 角加速度の推定値[rad/s^2]
+    initialized: bool,       // 最初の計測値を受け取ったかどうか
+}
+
+impl AngularAccelerationObserver {
+    /// * wn: 帯域（固有角周波数）[rad/s]
+    pub fn new(wn: f64) -> Self {
+        Self {
+            wn,
+            rate: [0.0; 3],
+            accel: [0.0; 3],
+            initialized: false,
+        }
+    }
+
+    /// 角速度の計測値で状態を更新する（DTごとに呼ぶ）．
+    pub fn update(&mut self, rate: Vector3<f64>) {
+        self.update_with_dt(rate, DT);
+    }
+
+    /// 前回の更新からの経過時間dt[s]を与えて，角速度の計測値で状態を更新する．
+    pub fn update_with_dt(&mut self, rate: Vector3<f64>, dt: f64) {
+        if !self.initialized {
+            self.rate = rate;
+            self.initialized = true;
+            return;
+        }
+        let predicted = quat::scale_add_vec(dt, self.accel, self.rate);
+        let e = quat::sub_vec(rate, predicted);
+        self.rate = quat::scale_add_vec(2.0 * self.wn * dt, e, predicted);
+        self.accel = quat::scale_add_vec(self.wn * self.wn * dt, e, self.accel);
+    }
+
+    /// 角速度の推定値[rad/s]
+    pub fn rate(&self) -> Vector3<f64> {
+        self.rate
+    }
+
+    /// 角加速度の推定値[rad/s^2]
+    pub fn acceleration(&self) -> Vector3<f64> {
+        self.accel
+    }
+}
+
+/// オイラー角の回転順序（いずれも機体に固定した軸周りの回転）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EulerSequence {
+    /// Z-Y-X（ヨー・ピッチ・ロール，航空宇宙分野で一般的な順序）
+    ZYX,
+    /// X-Y-Z
+    XYZ,
+}
+
+/// 1サンプル分の計測値
+/// 
+/// 配列で渡したときにf64が隙間なく並ぶようにしている．
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sample {
+    pub gyr: Vector3<f64>,  // 角速度[rad/s]
+    pub acc: Vector3<f64>,  // 加速度[m/s^2]
+    pub mag: Vector3<f64>,  // 地磁気
+}
+
+/// 状態（姿勢，積分項，ヒステリシスのフラグ）とパラメータをすべて保持しているので，
+/// serdeフィーチャを有効にすればそのまま保存・復元できる．
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttitudeFilter {
+    pub q: Quaternion<f64>,      // 姿勢推定値
+    gyr_correct: Vector3<f64>,   // 補正角速度（角速度バイアスの推定値を含む）
+    coef_gyr_c: Vector3<f64>,    // 補正角速度を計算するときのパラメータ（機体座標系の軸ごと）
+    coef_integ: Vector3<f64>,    // 補正角速度の積分係数（機体座標系の軸ごと）
+    pub gyr_integ: Vector3<f64>, // 補正角速度の積分項
+    thr_weak: f64,               // 弱い外乱判定の閾値
+    thr_strong: f64,             // 強い外乱判定の閾値
+    flag_acc_weak: bool,    // ヒステリシス処理に使う変数
+    flag_acc_strong: bool,  // ヒステリシス処理に使う変数
+    #[cfg_attr(feature = "serde", serde(default))]
+    acc_residual: Vector3<f64>,  // 直近の外乱判定での加速度の残差（機体座標系）
+    #[cfg_attr(feature = "serde", serde(default))]
+    directional_rejection: bool, // 強い外乱の間，残差が最大の軸の成分だけを棄却するかどうか
+    coef_yaw: Option<f64>,  // ヨー角補正の係数（Someならチルトとヨーを分離して補正する）
+    #[cfg_attr(feature = "serde", serde(default = "default_mag_r"))]
+    mag_r: Vector3<f64>,          // 基準座標系上における地磁気の向き（水平な単位ベクトル）
+    #[cfg_attr(feature = "serde", serde(default))]
+    inclination: Option<f64>,     // 地磁気の伏角[rad]（Someなら鉛直成分も含めた地磁気で姿勢を計算する）
+    detector: Detector,           // 加速度外乱の判定に使う誤差関数
+    gain_schedule: GainSchedule,  // 加速度外乱に応じた補正ゲインの変え方
+    #[cfg_attr(feature = "serde", serde(default = "default_solver"))]
+    solver: AttitudeSolver,       // 補正の目標にする姿勢の計算方法
+    gate: Option<InnovationGate>, // イノベーションの検定による外乱判定（Someなら閾値による判定の代わりに使う）
+    integ_limit: Option<f64>,     // 積分項から計算したバイアス推定値の上限[rad/s]（アンチワインドアップ）
+    integ_err_limit: Option<f64>, // 積分を行う姿勢の差の上限（sin(θ/2)で保持，アンチワインドアップ）
+    integ_leak: f64,              // 積分項の減衰率[1/s]
+    health: Health,               // 直近の補正サイクルの状態
+    #[cfg_attr(feature = "serde", serde(default))]
+    gyro_fault_limit: Option<u32>,  // 角速度の不正な入力がこの回数続いたら故障とみなす
+    #[cfg_attr(feature = "serde", serde(default))]
+    n_gyro_invalid: u32,            // 角速度の不正な入力が続いた回数
+    #[cfg_attr(feature = "serde", serde(default))]
+    gyro_failed: bool,              // 外部から角速度センサの故障を通知されたかどうか
+    #[cfg_attr(feature = "serde", serde(default = "default_gyro_free_tau"))]
+    gyro_free_tau: f64,             // 角速度を使わないモードの平滑化の時定数[s]
+    #[cfg_attr(feature = "serde", serde(default))]
+    observability: Option<Observability>,  // 軸ごとの可観測性（Someなら可観測性の低い軸の積分を止める）
+    #[cfg_attr(feature = "serde", serde(default))]
+    mag_norm: Option<MagNorm>,      // 地磁気の大きさによる重み付け（Someなら学習した大きさから外れた地磁気の補正を弱める）
+    #[cfg_attr(feature = "serde", serde(default))]
+    accel_bias: Option<AccelBias>,  // 加速度バイアスの推定（Someなら計測値から推定値を引いて補正に使う）
+    #[cfg_attr(feature = "serde", serde(default = "default_substeps"))]
+    substeps: u32,                  // predict()の積分を分割する数
+    #[cfg_attr(feature = "serde", serde(default))]
+    ang_accel: Option<AngularAccelerationObserver>,  // 角加速度の推定（Someなら予測ステップで更新する）
+    #[cfg_attr(feature = "serde", serde(default = "default_dt"))]
+    dt: f64,                        // 直近の予測ステップの経過時間[s]（補正ステップの積分や時定数の計算に使う）
+    #[cfg_attr(feature = "serde", serde(skip))]
+    on_reset: Option<fn(Health)>, // 発散して初期状態に戻したときに呼ぶ関数（保存・復元はしない）
+    pub n_steps: u64,       // 補正ステップの実行回数（保存した状態から再開する際の位置合わせに使う）
+}
+
+impl AttitudeFilter {
+    /// * alpha : 基準姿勢に収束するまでの時間[s]
+    /// * beta  : 補正角速度の積分係数
+    /// * thr_weak  : 弱い外乱判定の閾値（< thr_strong）
+    /// * thr_strong: 強い外乱判定の閾値（> thr_weak）
+    pub fn new(alpha: f64, beta: f64, thr_weak: f64, thr_strong: f64) -> Self {
+        Self {
+            q: (1.0, [0.0; 3]),
+            gyr_correct: [0.0; 3],
+            coef_gyr_c: [2.0 / alpha; 3],
+            coef_integ: [beta; 3],
+            gyr_integ: [0.0; 3],
+            thr_weak,
+            thr_strong,
+            flag_acc_weak: false,
+            flag_acc_strong: false,
+            acc_residual: [0.0; 3],
+            directional_rejection: false,
+            coef_yaw: None,
+            mag_r: MAG_R,
+            inclination: None,
+            detector: Detector::default(),
+            gain_schedule: GainSchedule::Switching,
+            solver: AttitudeSolver::GetQGm,
+            gate: None,
+            integ_limit: None,
+            integ_err_limit: None,
+            integ_leak: 0.0,
+            health: Health::Ok,
+            gyro_fault_limit: None,
+            n_gyro_invalid: 0,
+            gyro_failed: false,
+            gyro_free_tau: GYRO_FREE_TAU,
+            observability: None,
+            mag_norm: None,
+            accel_bias: None,
+            substeps: 1,
+            ang_accel: None,
+            dt: DT,
+            on_reset: None,
+            n_steps: 0,
+        }
+    }
+
+    /// 加速度外乱の判定に使う誤差関数を指定して作る（new()の後にwith_detector()を呼ぶのと同じ）．
+    /// 
+    /// * detector: 外乱判定式（DetectorKind::{E1, E2, Combined}）
+    pub fn new_with_detector(alpha: f64, beta: f64, thr_weak: f64, thr_strong: f64, detector: DetectorKind) -> Self {
+        Self::new(alpha, beta, thr_weak, thr_strong).with_detector(detector)
+    }
+
+    /// チルト（ロール・ピッチ）とヨー角を分離して補正するように設定する．
+    /// 
+    /// 加速度はチルトの補正だけに，地磁気はヨー角の補正だけに使うので，
+    /// 地磁気の乱れがロール・ピッチに影響せず，加速度外乱がヨー角に影響しない．
+    /// 
+    /// * alpha_yaw: ヨー角が収束するまでの時間[s]（チルトはnew()のalpha）
+    pub fn with_decoupled_yaw(mut self, alpha_yaw: f64) -> Self {
+        self.coef_yaw = Some(2.0 / alpha_yaw);
+        self
+    }
+
+    /// 強い加速度外乱を検知したとき，加速度全体ではなく外乱の方向の成分だけを棄却するように設定する．
+    /// 
+    /// デフォルトでは計測値をすべて推定姿勢から予測した重力加速度に置き換えて，加速度による補正を止める．
+    /// この設定では残差が最大の機体座標系の軸（disturbance_axis()）の成分だけを予測値に置き換え，
+    /// 残りの2軸の成分はそのまま補正に使う．推進方向など外乱が1軸に偏る機体で，その軸に直交する面のチルトの補正を続けられる．
+    pub fn with_directional_rejection(mut self) -> Self {
+        self.directional_rejection = true;
+        self
+    }
+
+    /// 基準座標系上における地磁気の向きを設定する（デフォルトはMAG_R）．
+    pub fn with_reference_field(mut self, mag_r: Vector3<f64>) -> Self {
+        self.set_reference_field(mag_r);
+        self
+    }
+
+    /// 地磁気の伏角を与え，鉛直成分も含めた3次元の地磁気で姿勢を計算するように設定する（get_q_gm_full_field()）．
+    /// 
+    /// デフォルトでは地磁気の鉛直成分を捨てて偏角だけを使うが，伏角が大きい地域では水平成分が小さく，ノイズに弱くなる．
+    /// 伏角が分かっていれば地磁気の情報をすべて使えるうえ，加速度外乱の間は地磁気を優先した姿勢で補正できる．
+    /// チルトとヨー角を分離して補正する場合（with_decoupled_yaw()）は使わない．
+    /// 
+    /// * inclination: 伏角[rad]（水平面から下向きを正とする）
+    pub fn with_inclination(mut self, inclination: f64) -> Self {
+        self.inclination = Some(inclination);
+        self
+    }
+
+    /// 基準座標系上における地磁気の向きを変える（位置によって変わる磁場のモデルなどから，推定の途中で与える）．
+    /// 
+    /// 偏角（水平成分の向き）は鉛直成分を除いて正規化して保持する．with_inclination()を使っている場合は伏角も更新する．
+    /// 水平成分が無い場合（磁極付近）は何もしない．
+    /// 
+    /// * mag_r: 基準座標系上における地磁気（大きさ・単位は不問）
+    pub fn set_reference_field(&mut self, mag_r: Vector3<f64>) {
+        let mag_h = [mag_r[0], mag_r[1], 0.0];
+        let norm = quat::norm_vec(mag_h);
+        if norm > 0.0 && norm.is_finite() {
+            self.mag_r = quat::scale_vec(norm.recip(), mag_h);
+            if self.inclination.is_some() {
+                self.inclination = Some( (-mag_r[2]).atan2(norm) );
+            }
+        }
+    }
+
+    /// 基準座標系上における地磁気の向き（単位ベクトル，with_inclination()を使っていなければ水平）
+    pub fn reference_field(&self) -> Vector3<f64> {
+        match self.inclination {
+            Some(inc) => {
+                let h = quat::scale_vec(inc.cos(), self.mag_r);
+                [h[0], h[1], -inc.sin()]
+            },
+            None => self.mag_r,
+        }
+    }
+
+    /// 加速度外乱の判定に使う誤差関数を設定する（デフォルトはDetector::default()）．
+    /// 
+    /// E2は姿勢推定値を使うので，初期姿勢の誤差が大きいと外乱とみなして補正しない点に注意．
+    pub fn with_detector(mut self, detector: Detector) -> Self {
+        self.detector = detector;
+        self
+    }
+
+    /// 加速度外乱に応じた補正ゲインの変え方を設定する（デフォルトはGainSchedule::Switching）．
+    pub fn with_gain_schedule(mut self, gain_schedule: GainSchedule) -> Self {
+        self.gain_schedule = gain_schedule;
+        self
+    }
+
+    /// 補正の目標にする姿勢の計算方法を設定する（デフォルトはAttitudeSolver::GetQGm）．
+    /// 
+    /// チルトとヨー角を分離して補正する場合（with_decoupled_yaw()）は使わない．
+    pub fn with_attitude_solver(mut self, solver: AttitudeSolver) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    /// 誤差関数の閾値による外乱判定の代わりに，加速度と地磁気のイノベーションの検定で外乱を判定する．
+    /// 
+    /// 棄却した計測値は推定姿勢からの予測値に置き換えて補正する（その計測値による補正は行わない）．
+    /// 推定姿勢を使うので，E2と同様に初期姿勢の誤差が大きいと棄却し続ける点に注意．
+    pub fn with_innovation_gate(mut self, gate: InnovationGate) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    /// 補正のパラメータを機体座標系の軸ごとに設定する（new()のalpha，betaを置き換える）．
+    /// 
+    /// 水平姿勢付近ではz軸がヨー角に対応するので，ノイズの大きい地磁気で決まるヨー角だけ補正を弱くできる．
+    /// 
+    /// * alpha: 各軸の基準姿勢に収束するまでの時間[s]
+    /// * beta : 各軸の補正角速度の積分係数
+    pub fn with_axis_gains(mut self, alpha: Vector3<f64>, beta: Vector3<f64>) -> Self {
+        self.coef_gyr_c = alpha.map(|a| 2.0 / a);
+        self.coef_integ = beta;
+        self
+    }
+
+    /// 角速度バイアスの推定値を各軸±max_bias[rad/s]に制限する（アンチワインドアップ）．
+    pub fn with_integral_limit(mut self, max_bias: f64) -> Self {
+        self.integ_limit = Some(max_bias);
+        self
+    }
+
+    /// 推定姿勢と計測値から求めた姿勢の差がmax_err[rad]を超えている間は積分を止める（アンチワインドアップ）．
+    /// 
+    /// 姿勢が大きくずれている間の補正角速度はバイアスとは無関係なので，積分すると外乱が収まった後にオーバーシュートする．
+    pub fn with_conditional_integration(mut self, max_err: f64) -> Self {
+        self.integ_err_limit = Some((0.5 * max_err).sin());
+        self
+    }
+
+    /// 積分項を毎ステップ(1 - k*dt)倍して減衰させる（dtは予測ステップの経過時間，デフォルトはk=0で減衰しない）．
+    /// 
+    /// 外乱で補正が長時間止まっている間に，古くなったバイアスの推定値が残り続けないようにする．
+    /// 減衰させる分だけバイアスの推定値は真値より小さくなるので，kは積分係数betaより十分小さくする．
+    /// 
+    /// * k: 減衰率[1/s]
+    pub fn with_integral_leak(mut self, k: f64) -> Self {
+        self.integ_leak = k;
+        self
+    }
+
+    /// 角速度の不正な入力（NaNや無限大）がmax_invalid回続いたら，角速度センサが故障したとみなして
+    /// 角速度を使わないモード（is_gyro_free()）に自動で切り替える．正常な入力が来たら元に戻す．
+    pub fn with_gyro_fault_detection(mut self, max_invalid: u32) -> Self {
+        self.gyro_fault_limit = Some(max_invalid);
+        self
+    }
+
+    /// 角速度を使わないモードで，加速度と地磁気から求めた姿勢を平滑化する時定数を設定する（デフォルトはGYRO_FREE_TAU）．
+    /// 
+    /// 長くするほどノイズは減るが，機体の回転に遅れて追従する．
+    pub fn with_gyro_free_smoothing(mut self, tau: f64) -> Self {
+        self.gyro_free_tau = tau;
+        self
+    }
+
+    /// 角速度バイアスの軸ごとの可観測性を監視し，可観測性の低い軸の積分項の更新を止める（または弱める）．
+    /// 
+    /// 重力軸周りにだけ回転している場合など，ある軸のバイアスが補正に使うベクトルから分からない間は，
+    /// その軸の積分項が姿勢誤差や外乱を溜め込まないようにする．可観測性は軸ごとに0〜2（加速度と地磁気）の値をとる．
+    /// 
+    /// * tau: 可観測性の指数移動平均の時定数[s]
+    /// * thr: 積分項をそのまま更新する可観測性の下限（これより小さいと比例して重みを下げ，0で止める）
+    pub fn with_observability_freeze(mut self, tau: f64, thr: f64) -> Self {
+        self.observability = Some(Observability { tau, thr, info: [1.0; 3] });
+        self
+    }
+
+    /// 地磁気の大きさを外乱の無い間に学習し，学習した大きさから外れた地磁気の補正を弱める．
+    /// 
+    /// 相対誤差がtolを超えると，重みtol / 相対誤差で地磁気の計測値を推定姿勢からの予測値に近づける（ヨー角の補正が弱まる）．
+    /// 大きさは加速度外乱を検知していない間だけ，重みを掛けて学習するので，場所が変わって大きさが変わった場合もゆっくり追従する．
+    /// 最初の計測値の大きさを初期値にする．
+    /// 
+    /// * tol: 重みを下げ始める大きさの相対誤差
+    /// * tau: 大きさの指数移動平均の時定数[s]
+    pub fn with_mag_norm_gate(mut self, tol: f64, tau: f64) -> Self {
+        self.mag_norm = Some(MagNorm { tol, tau, norm: 0.0, weight: 1.0 });
+        self
+    }
+
+    /// 学習した地磁気の大きさ（with_mag_norm_gate()を使っていないか，まだ学習していなければNone）
+    pub fn expected_mag_norm(&self) -> Option<f64> {
+        self.mag_norm.map(|m| m.norm).filter(|&norm| norm > 0.0)
+    }
+
+    /// 直近の補正での地磁気の重み（0〜1，with_mag_norm_gate()を使っていなければNone）
+    pub fn mag_norm_weight(&self) -> Option<f64> {
+        self.mag_norm.map(|m| m.weight)
+    }
+
+    /// 加速度バイアスを推定し，計測値から除いてから補正に使う．
+    /// 
+    /// 一定の加速度バイアスは推定姿勢のチルトの誤差として残り続け，角速度バイアスの積分項では吸収できない．
+    /// 静止したままでは重力方向の成分しか分からないので，様々な姿勢をとる間にゆっくり収束する．
+    /// 加速度外乱を検知している間は推定値を更新しない．
+    /// 
+    /// * tau: 推定の時定数[s]（姿勢の収束時間より十分長くする）
+    pub fn with_accel_bias_estimation(mut self, tau: f64) -> Self {
+        self.accel_bias = Some(AccelBias { tau, bias: [0.0; 3] });
+        self
+    }
+
+    /// 加速度バイアスの推定値[m/s^2]（with_accel_bias_estimation()を使っていなければNone）
+    pub fn accel_bias(&self) -> Option<Vector3<f64>> {
+        self.accel_bias.map(|b| b.bias)
+    }
+
+    /// 角速度バイアスの軸ごとの可観測性（with_observability_freeze()を使っていなければNone）
+    pub fn bias_observability(&self) -> Option<Vector3<f64>> {
+        self.observability.map(|obs| obs.info)
+    }
+
+    /// predict()の積分をsubsteps回に分割する（デフォルトは1）．
+    /// 
+    /// DT * |ω|が大きいと1次の積分の誤差が大きくなるが，フィルタ全体の更新周期はそのままで積分だけを細かくできる．
+    /// 角速度はDTの間一定とみなす．
+    pub fn with_substeps(mut self, substeps: u32) -> Self {
+        self.substeps = substeps.max(1);
+        self
+    }
+
+    /// 予測ステップで，バイアスの推定値を除いた角速度から角加速度も推定する（angular_acceleration()）．
+    /// 
+    /// * wn: オブザーバの帯域（固有角周波数）[rad/s]
+    pub fn with_angular_acceleration(mut self, wn: f64) -> Self {
+        self.ang_accel = Some(AngularAccelerationObserver::new(wn));
+        self
+    }
+
+    /// 状態が発散して初期状態に戻したときに呼ぶ関数を設定する．
+    pub fn with_reset_callback(mut self, on_reset: fn(Health)) -> Self {
+        self.on_reset = Some(on_reset);
+        self
+    }
+
+    /// 姿勢と積分項，外乱判定のフラグを初期状態に戻す（パラメータとn_stepsはそのまま）．
+    pub fn reset(&mut self) {
+        self.q = (1.0, [0.0; 3]);
+        self.gyr_correct = [0.0; 3];
+        self.gyr_integ = [0.0; 3];
+        self.flag_acc_weak = false;
+        self.flag_acc_strong = false;
+        if let Some(ref mut b) = self.accel_bias {
+            b.bias = [0.0; 3];
+        }
+    }
+
+    /// 角速度バイアスの推定値がbiasになるように積分項を設定する（積分項を使わない軸はそのまま）．
+    /// 
+    /// 起動時に静止させて求めたバイアスを与えておくと，推定値が収束するまでの時間を短縮できる．
+    pub fn set_gyro_bias(&mut self, bias: Vector3<f64>) {
+        for ((v, &coef), b) in self.gyr_integ.iter_mut().zip(self.coef_integ.iter()).zip(bias) {
+            if coef > 0.0 {
+                *v = -b / coef;
+            }
+        }
+        self.gyr_correct = quat::hadamard_vec(self.coef_integ, self.gyr_integ);
+    }
+
+    /// 加速度のイノベーション（計測値と推定姿勢から予測した重力加速度の差）[m/s^2]
+    ///
+    /// 外乱が無く推定姿勢が正しければ，加速度センサのノイズだけになる（3自由度）．
+    pub fn acc_innovation(&self, acc: Vector3<f64>) -> Vector3<f64> {
+        quat::sub_vec(acc, quat::frame_rotation(self.q, ACC_R))
+    }
+
+    /// 地磁気のイノベーション（計測値の水平成分と，推定姿勢から予測した向きに同じ大きさを掛けたものの差）
+    ///
+    /// 地磁気は大きさが不明なので向きの違いだけを比べる．伏角の影響を受けないように水平成分どうしで比べる．
+    /// 外乱が無く推定姿勢が正しければ，水平面内で予測した向きと直交する成分のノイズだけになる（1自由度）．
+    pub fn mag_innovation(&self, mag: Vector3<f64>) -> Vector3<f64> {
+        let up = quat::frame_rotation(self.q, [0.0, 0.0, 1.0]);
+        let mag_h = quat::scale_add_vec(-quat::dot_vec(mag, up), up, mag);
+        let mag_q = quat::frame_rotation(self.q, self.mag_r);
+        quat::sub_vec(mag_h, quat::scale_vec(quat::norm_vec(mag_h), mag_q))
+    }
+
+    /// with_innovation_gate()で設定したノイズ分散を更新する（設定していなければ何もしない）．
+    ///
+    /// noise_estimation::NoiseEstimatorで推定した値を使う．
+    pub fn set_noise_variance(&mut self, acc_var: f64, mag_var: f64) {
+        if let Some(ref mut gate) = self.gate {
+            gate.acc_var = acc_var;
+            gate.mag_var = mag_var;
+        }
+    }
+
+    /// 角速度センサの故障を通知する（redundant::ImuArrayで故障を検知した場合など）．
+    /// 
+    /// trueにしている間は角速度を使わないモードで推定する．
+    pub fn set_gyro_failed(&mut self, failed: bool) {
+        self.gyro_failed = failed;
+    }
+
+    /// 角速度を使わないモード（加速度と地磁気から求めた姿勢を平滑化するだけ）で推定しているかどうか
+    /// 
+    /// set_gyro_failed()で故障を通知したか，with_gyro_fault_detection()の回数だけ角速度の不正な入力が続いた場合．
+    pub fn is_gyro_free(&self) -> bool {
+        self.gyro_failed || self.gyro_fault_limit.is_some_and(|limit| self.n_gyro_invalid >= limit)
+    }
+
+    /// 強い加速度外乱を検知して，加速度による補正を止めているかどうかを返す．
+    /// 
+    /// 判定は補正ゲインの変え方によらず閾値とヒステリシス処理で行う．GainSchedule::Switching以外では補正を完全には止めず，
+    /// 重みで弱めるだけ（with_innovation_gate()を使う場合は加速度を棄却しているかどうか）．
+    pub fn is_disturbed(&self) -> bool {
+        self.flag_acc_strong
+    }
+
+    /// 弱い加速度外乱を検知して，補正ゲインを下げているかどうかを返す（強い外乱を検知している間はfalse）．
+    pub fn is_weakly_disturbed(&self) -> bool {
+        self.flag_acc_weak && !self.flag_acc_strong
+    }
+
+    /// 直近の外乱判定での加速度の残差（計測値 − 推定姿勢から予測した重力加速度，機体座標系）[m/s^2]を返す．
+    /// 
+    /// 軸ごとの大きさから，外乱がどの軸に加わっているかが分かる．
+    pub fn disturbance_residual(&self) -> Vector3<f64> {
+        self.acc_residual
+    }
+
+    /// 加速度外乱を検知している間，残差の絶対値が最大の機体座標系の軸（0: x，1: y，2: z）を返す（検知していなければNone）．
+    pub fn disturbance_axis(&self) -> Option<usize> {
+        (self.flag_acc_weak || self.flag_acc_strong).then(|| dominant_axis(self.acc_residual))
+    }
+
+    /// 直近のpredict()からの補正サイクルの状態を返す．
+    pub fn health(&self) -> Health {
+        self.health
+    }
+
+    /// 予測ステップ
+    /// 
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    pub fn predict(&mut self, gyr: Vector3<f64>) {
+        self.predict_with_dt(gyr, DT);
+    }
+
+    /// 前回の予測からの経過時間を与えて予測する（実機のログなどでサンプリング周期が一定でない場合）．
+    /// 
+    /// 経過時間は姿勢の積分に加えて，続く補正ステップの積分項の更新と減衰，可観測性などの時定数の計算にも使う
+    /// （補正角速度の比例項は角速度なので，次の予測ステップでこの経過時間だけ積分される）．
+    /// 
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    /// * dt: 前回の予測からの経過時間[s]
+    pub fn predict_with_dt(&mut self, gyr: Vector3<f64>, dt: f64) {
+        self.dt = dt;
+        if !self.accept_gyro(gyr) {
+            return;
+        }
+
+        let omega = quat::add_vec(gyr, self.gyr_correct);
+
+        // 積分（q[n+1] = q[n] + Δt/2 *q[n]*ω[n]，Δt = dt / substeps）
+        let dt = dt / self.substeps as f64;
+        for _ in 0..self.substeps {
+            let tmp0 = quat::scale_vec(self.q.0, omega);
+            let dot = quat::dot_vec(self.q.1, omega);
+            let cross = quat::cross_vec(self.q.1, omega);
+            let tmp1 = (-dot, quat::add_vec(tmp0, cross));
+            self.q = quat::scale_add(0.5 * dt, tmp1, self.q);
+            self.normalize_or_recover();
+        }
+    }
+
+    /// 角速度の代わりに，DTの間の回転ベクトル（角度増分）で予測する．
+    /// 
+    /// 角速度をフィルタの更新より速い周期で計測している場合に，coning::ConingIntegratorでコーニング補正を
+    /// しながら積算した角度増分を渡す．回転ベクトルから四元数を厳密に計算するので，DTの間に大きく回転しても誤差が出ない．
+    /// 
+    /// * dtheta: 機体座標系での角度増分[rad]（DTの間，バイアスを含んだまま）
+    pub fn predict_delta_angle(&mut self, dtheta: Vector3<f64>) {
+        self.dt = DT;
+        if !self.accept_gyro(quat::scale_vec(DT.recip(), dtheta)) {
+            return;
+        }
+        let rotation = quat::scale_add_vec(DT, self.gyr_correct, dtheta);
+        self.q = quat::mul(self.q, quat::from_rotation_vector(rotation));
+        self.normalize_or_recover();
+    }
+
+    /// 予測ステップの角速度を検査し，角加速度の推定を更新する（積分してよければtrueを返す）．
+    fn accept_gyro(&mut self, gyr: Vector3<f64>) -> bool {
+        // 入力が不正な場合は姿勢を更新しない
+        if !is_finite_all(&[gyr]) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(?gyr, n_steps = self.n_steps, "角速度にNaNや無限大が含まれているので読み飛ばしました");
+            self.health = Health::InvalidInput;
+            self.n_gyro_invalid = self.n_gyro_invalid.saturating_add(1);
+            return false;
+        }
+        self.health = Health::Ok;
+        self.n_gyro_invalid = 0;
+        // 故障を通知されている場合は積分しない（補正ステップで計測値から姿勢を求める）
+        if self.gyro_failed {
+            return false;
+        }
+        let rate = self.angular_rate(gyr);
+        if let Some(ref mut ang_accel) = self.ang_accel {
+            ang_accel.update_with_dt(rate, self.dt);
+        }
+        true
+    }
+
+    /// 姿勢推定値を正規化する（ノルムが潰れていたら発散とみなして初期状態に戻す）．
+    fn normalize_or_recover(&mut self) {
+        let norm = quat::norm(self.q);
+        if norm.is_finite() && norm > MIN_NORM {
+            self.q = quat::scale(norm.recip(), self.q);
+        } else {
+            self.recover();
+        }
+    }
+
+    /// 補正ステップ（外乱検知も行う）
+    /// 
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    /// * mag: 機体上のセンサで計測した地磁気（方向だけわかれば良いので単位不問）
+    pub fn correct(&mut self, acc: Vector3<f64>, mag: Vector3<f64>) {
+        let detector = self.detector;
+        self.correct_with_detector(&detector, acc, mag);
+    }
+
+    /// with_detector()で設定したものの代わりに，任意の外乱判定式を使う補正ステップ
+    /// 
+    /// 閾値とヒステリシス処理，補正ゲインの変え方はcorrect()と同じ．
+    /// 
+    /// * detector: 外乱判定式
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    /// * mag: 機体上のセンサで計測した地磁気（方向だけわかれば良いので単位不問）
+    pub fn correct_with_detector<D>(&mut self, detector: &D, acc: Vector3<f64>, mag: Vector3<f64>)
+    where D: DisturbanceDetector + ?Sized {
+        if !self.check_input(&[acc, mag]) {
+            return;
+        }
+        let (acc, coef) = self.detect_disturbance_with(detector, self.compensate_accel_bias(acc));
+        self.update_accel_bias(coef);
+        let mag = self.weight_mag_norm(mag);
+        let mag = self.gate_mag(mag);
+        if self.is_gyro_free() {
+            self.smooth_toward(self.measurement_attitude(acc, mag, coef), coef);
+            // 角速度が戻ったときに古い比例項で回さないよう，バイアスの推定値だけにしておく
+            self.gyr_correct = quat::hadamard_vec(self.coef_integ, self.gyr_integ);
+            self.n_steps += 1;
+            return;
+        }
+        self.gyr_correct = self.attitude_correction(acc, mag, coef);
+        self.update_observability(acc, Some(mag), coef);
+        self.update_integral();
+    }
+
+    /// 機体の速度がわかっている場合の補正ステップ（旋回中の遠心力を補償する）
+    /// 
+    /// 旋回中の加速度計測値には重力に加えて向心加速度ω×vが含まれ，外乱として棄却されてしまう．
+    /// 対気速度や対地速度から向心加速度を差し引いてからcorrect()を行うので，旋回中も補正を続けられる．
+    /// 
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    /// * mag: 機体上のセンサで計測した地磁気（方向だけわかれば良いので単位不問）
+    /// * gyr: 機体上で計測した角速度[rad/s]（バイアスの推定値を除いて使う）
+    /// * vel: 機体座標系での速度[m/s]（固定翼機なら[対気速度, 0, 0]）
+    pub fn correct_with_velocity(&mut self, acc: Vector3<f64>, mag: Vector3<f64>, gyr: Vector3<f64>, vel: Vector3<f64>) {
+        let acc_centripetal = quat::cross_vec(self.angular_rate(gyr), vel);
+        self.correct(quat::sub_vec(acc, acc_centripetal), mag);
+    }
+
+    /// 静止していることがわかっているときの補正ステップ（ZUPT）
+    /// 
+    /// 静止中の角速度計測値はバイアスそのものなので，バイアスの推定値を計測値に強く引き寄せる．
+    /// 加速度は重力だけとみなせるので外乱検知は行わず，外乱判定のフラグは静止前の状態のまま保持する．
+    /// 静止の判定にはzupt::StationaryDetectorを使う．
+    /// 
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    /// * mag: 機体上のセンサで計測した地磁気（方向だけわかれば良いので単位不問）
+    pub fn correct_stationary(&mut self, gyr: Vector3<f64>, acc: Vector3<f64>, mag: Vector3<f64>) {
+        if !self.check_input(&[gyr, acc, mag]) {
+            return;
+        }
+        // バイアスの推定値（= -coef_integ * gyr_integ）を計測値に近づける（積分項を使わない軸は何もしない）
+        for (i, &g) in gyr.iter().enumerate() {
+            if self.coef_integ[i] > 0.0 {
+                let integ_target = -g / self.coef_integ[i];
+                self.gyr_integ[i] += ZUPT_BIAS_GAIN * (integ_target - self.gyr_integ[i]);
+            }
+        }
+
+        self.gyr_correct = self.attitude_correction(acc, mag, 1.0);
+        self.update_observability(acc, Some(mag), 1.0);
+        self.update_integral();
+    }
+
+    /// 加速度だけを使う補正ステップ（地磁気センサを使えない環境向け）
+    /// 
+    /// ロール・ピッチだけを補正し，ヨー角は補正しない（角速度の積分でドリフトする）．
+    /// 補正角速度は常に重力方向と直交するので，積分項（バイアスの推定値）も可観測な軸の成分だけが更新される．
+    /// 
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    pub fn correct_acc_only(&mut self, acc: Vector3<f64>) {
+        if !self.check_input(&[acc]) {
+            return;
+        }
+        let (acc, coef) = self.detect_disturbance(self.compensate_accel_bias(acc));
+        self.update_accel_bias(coef);
+        self.gyr_correct = self.tilt_correction(acc, coef);
+        self.update_observability(acc, None, coef);
+        self.update_integral();
+    }
+
+    /// 加速度と地磁気から補正角速度（比例項）を計算する．
+    /// 
+    /// * coef: 補正ゲインの倍率（外乱検知の結果）
+    fn attitude_correction(&self, acc: Vector3<f64>, mag: Vector3<f64>, coef: f64) -> Vector3<f64> {
+        if let Some(coef_yaw) = self.coef_yaw {
+            // チルトとヨー角を別々に補正
+            let gyr_tilt = self.tilt_correction(acc, coef);
+            let gyr_yaw = self.yaw_correction(mag, coef_yaw);
+            quat::add_vec(gyr_tilt, gyr_yaw)
+        } else {
+            let q_gm = self.measurement_attitude(acc, mag, coef);
+
+            // qからq_gmに到達するための角速度を計算
+            let term1 = quat::scale_vec(self.q.0, q_gm.1);
+            let term2 = quat::scale_vec(q_gm.0, self.q.1);
+            let term3 = quat::cross_vec(q_gm.1, self.q.1);
+            let dq = quat::add_vec(quat::sub_vec(term1, term2), term3);
+            let gyr_correct = quat::hadamard_vec(self.coef_gyr_c, quat::scale_vec(coef, dq));
+            // 符号をqに合わせる
+            if quat::dot(self.q, q_gm).is_sign_negative() {
+                quat::negate_vec(gyr_correct)
+            } else {
+                gyr_correct
+            }
+        }
+    }
+
+    /// 加速度と地磁気から補正の目標にする姿勢q_gmを計算する（伏角を与えている場合は，外乱の程度に応じて地磁気を優先する）．
+    fn measurement_attitude(&self, acc: Vector3<f64>, mag: Vector3<f64>, coef: f64) -> Quaternion<f64> {
+        match (self.solver, self.inclination) {
+            (AttitudeSolver::GetQGm, Some(_)) => get_q_gm_full_field(acc, mag, self.reference_field(), coef),
+            (AttitudeSolver::GetQGm, None) => get_q_gm_with_reference(acc, mag, self.mag_r),
+            (AttitudeSolver::Triad, _) => triad(acc, mag, ACC_R, self.reference_field()),
+            (AttitudeSolver::TriadMag, _) => triad(mag, acc, self.reference_field(), ACC_R),
+            (AttitudeSolver::Davenport, _) => self.weighted_attitude(acc, mag, coef),
+            (AttitudeSolver::Svd, Some(_)) => svd(&[(acc, ACC_R, 1.0), (mag, self.reference_field(), 1.0)]),
+            (AttitudeSolver::Svd, None) => {
+                // 地磁気から加速度に平行な成分を除くと，基準座標系の鉛直なACC_Rと水平なmag_rの組と同じく直交する
+                let acc_n = quat::normalize_vec(acc);
+                let mag_h = quat::scale_add_vec(-quat::dot_vec(mag, acc_n), acc_n, mag);
+                svd(&[(acc, ACC_R, 1.0), (mag_h, self.mag_r, 1.0)])
+            },
+        }
+    }
+
+    /// 角速度を使わないモードで，姿勢推定値をq_gmに一次遅れで近づける（外乱の間はcoefに応じて遅くする）．
+    fn smooth_toward(&mut self, q_gm: Quaternion<f64>, coef: f64) {
+        // 符号をqに合わせる
+        let q_gm = if quat::dot(self.q, q_gm).is_sign_negative() { quat::negate(q_gm) } else { q_gm };
+        let k = (coef * self.dt / self.gyro_free_tau.max(self.dt)).clamp(0.0, 1.0);
+        let q = quat::normalize( quat::scale_add(k, quat::sub(q_gm, self.q), self.q) );
+        if is_finite_all(&[q.1]) && q.0.is_finite() {
+            self.q = q;
+        }
+    }
+
+    /// 加速度と地磁気の方向の誤差分散の逆数で重み付けして，Davenportのq-methodで姿勢を計算する．
+    /// 
+    /// 分散はwith_innovation_gate()で与えた値（set_noise_variance()で更新できる）を使い，無ければ同じ重みにする．
+    /// 加速度の重みには外乱判定の結果coefを掛けるので，外乱が強いほどチルトも地磁気に頼る．
+    /// 伏角を与えていない場合は，推定姿勢の水平面に射影した地磁気を水平なmag_rに合わせる．
+    fn weighted_attitude(&self, acc: Vector3<f64>, mag: Vector3<f64>, coef: f64) -> Quaternion<f64> {
+        let (mag_b, mag_r) = match self.inclination {
+            Some(_) => (mag, self.reference_field()),
+            None => {
+                let up = quat::frame_rotation(self.q, [0.0, 0.0, 1.0]);
+                (quat::scale_add_vec(-quat::dot_vec(mag, up), up, mag), self.mag_r)
+            },
+        };
+        let (w_acc, w_mag) = match self.gate {
+            // 計測値の大きさで割って方向の分散にする
+            Some(gate) => (
+                STANDARD_GRAVITY.powi(2) / gate.acc_var,
+                quat::dot_vec(mag_b, mag_b) / gate.mag_var,
+            ),
+            None => (1.0, 1.0),
+        };
+        davenport(&[(acc, ACC_R, coef * w_acc), (mag_b, mag_r, w_mag)])
+    }
+
+    /// 加速度の計測値を予測値に重ねる回転（機体座標系）から，チルトの補正角速度を計算する．
+    fn tilt_correction(&self, acc: Vector3<f64>, coef: f64) -> Vector3<f64> {
+        let acc_q = quat::frame_rotation(self.q, ACC_R);
+        let dq = quat::rotate_a_to_b(acc, acc_q);
+        quat::hadamard_vec(self.coef_gyr_c, quat::scale_vec(coef, dq.1))
+    }
+
+    /// 地磁気の水平成分を予測値に重ねる回転から，ヨー角の補正角速度を計算する．
+    /// 
+    /// 水平面は推定姿勢から求めるので，補正角速度は常に鉛直軸周りの成分だけになる．
+    fn yaw_correction(&self, mag: Vector3<f64>, coef: f64) -> Vector3<f64> {
+        // 機体座標系で見た鉛直軸
+        let up = quat::frame_rotation(self.q, [0.0, 0.0, 1.0]);
+        // 計測値から鉛直成分を除く（mag_rは水平成分の向きなので予測値はそのまま使える）
+        let mag_h = quat::scale_add_vec(-quat::dot_vec(mag, up), up, mag);
+        let mag_q = quat::frame_rotation(self.q, self.mag_r);
+        let dq = quat::rotate_a_to_b(mag_h, mag_q);
+        quat::scale_vec(coef, dq.1)
+    }
+
+    /// 加速度外乱を検知して，補正に使う加速度と補正ゲインの倍率（0〜1）を返す．
+    fn detect_disturbance(&mut self, acc: Vector3<f64>) -> (Vector3<f64>, f64) {
+        let detector = self.detector;
+        self.detect_disturbance_with(&detector, acc)
+    }
+
+    /// 外乱判定式detectorで加速度外乱を検知して，補正に使う加速度と補正ゲインの倍率（0〜1）を返す．
+    fn detect_disturbance_with<D>(&mut self, detector: &D, acc: Vector3<f64>) -> (Vector3<f64>, f64)
+    where D: DisturbanceDetector + ?Sized {
+        let coef = 1.0;
+
+        // 加速度外乱検知
+        let acc_q = quat::frame_rotation(self.q, ACC_R);
+        self.acc_residual = quat::sub_vec(acc, acc_q);
+        if let Some(gate) = self.gate {
+            let prev = (self.flag_acc_weak, self.flag_acc_strong);
+            self.flag_acc_strong = gate.rejects_acc(quat::sub_vec(acc, acc_q));
+            self.report_disturbance(prev, detector.error(acc, acc_q));
+            return (if self.flag_acc_strong { self.reject_acc(acc, acc_q) } else { acc }, coef);
+        }
+        let e = detector.error(acc, acc_q);
+        let prev = (self.flag_acc_weak, self.flag_acc_strong);
+
+        // 判定のフラグは補正ゲインの変え方によらず閾値とヒステリシス処理で決める
+        let mut acc_sw = acc;
+        let mut coef_sw = coef;
+        if e > self.thr_strong {
+            // 強い外乱なので，加速度による補正をストップする．
+            self.flag_acc_strong = true;
+            acc_sw = self.reject_acc(acc, acc_q);
+        } else if e > self.thr_weak {
+            // ヒステリシス処理：強い外乱 -> 弱い外乱
+            if self.flag_acc_strong && e > (self.thr_strong - self.thr_strong * HYSTERESIS) {
+                acc_sw = self.reject_acc(acc, acc_q);
+            } else {
+                // 弱い外乱なので，補正角速度の重みを変更．
+                self.flag_acc_strong = false;
+                self.flag_acc_weak = true;
+                coef_sw *= 0.5;
+            }
+        } else {
+            // ヒステリシス処理：弱い外乱 -> 外乱無し
+            if self.flag_acc_weak && e > (self.thr_weak - self.thr_weak * HYSTERESIS) {
+                coef_sw *= 0.5;
+            } else {
+                self.flag_acc_weak = false;
+                self.flag_acc_strong = false;
+            }
+        }
+        self.report_disturbance(prev, e);
+
+        // 閾値で切り替える場合以外は，計測値を棄却せずに重みだけを変える
+        match self.gain_schedule {
+            GainSchedule::Switching => (acc_sw, coef_sw),
+            GainSchedule::Sigmoid => (acc, coef * self.sigmoid_weight(e)),
+            GainSchedule::Huber => (acc, coef * huber_weight(e, self.thr_weak)),
+            GainSchedule::Cauchy => (acc, coef * cauchy_weight(e, self.thr_weak)),
+        }
+    }
+
+    /// 強い外乱を検知した加速度を棄却する（with_directional_rejection()なら残差が最大の軸の成分だけを予測値に置き換える）．
+    fn reject_acc(&self, mut acc: Vector3<f64>, acc_q: Vector3<f64>) -> Vector3<f64> {
+        if !self.directional_rejection {
+            return acc_q;
+        }
+        let k = dominant_axis(self.acc_residual);
+        acc[k] = acc_q[k];
+        acc
+    }
+
+    /// 加速度の計測値からバイアスの推定値を除く（with_accel_bias_estimation()を使っていなければそのまま返す）．
+    fn compensate_accel_bias(&self, acc: Vector3<f64>) -> Vector3<f64> {
+        match self.accel_bias {
+            Some(b) => quat::sub_vec(acc, b.bias),
+            None => acc,
+        }
+    }
+
+    /// 外乱判定で求めた加速度の残差で，加速度バイアスの推定値を更新する（外乱を検知している間は更新しない）．
+    /// 
+    /// * coef: 補正ゲインの倍率（外乱判定の結果）
+    fn update_accel_bias(&mut self, coef: f64) {
+        if self.flag_acc_weak || self.flag_acc_strong || self.is_gyro_free() {
+            return;
+        }
+        if let Some(ref mut b) = self.accel_bias {
+            let k = (coef * self.dt / b.tau).min(1.0);
+            b.bias = quat::scale_add_vec(k, self.acc_residual, b.bias);
+        }
+    }
+
+    /// 地磁気の大きさを学習し，学習した大きさから外れていれば重みに応じて推定姿勢からの予測値に近づける
+    /// （with_mag_norm_gate()を使っていなければそのまま返す）．
+    fn weight_mag_norm(&mut self, mag: Vector3<f64>) -> Vector3<f64> {
+        let undisturbed = !self.flag_acc_weak && !self.flag_acc_strong;
+        let dt = self.dt;
+        let Some(ref mut m) = self.mag_norm else { return mag };
+        let norm = quat::norm_vec(mag);
+        if m.norm == 0.0 {
+            m.norm = norm;
+        }
+        if !(norm > 0.0 && m.norm > 0.0) {
+            return mag;
+        }
+        m.weight = huber_weight((norm / m.norm - 1.0).abs(), m.tol);
+        if undisturbed {
+            m.norm += (dt / m.tau).min(1.0) * m.weight * (norm - m.norm);
+        }
+        let w = m.weight;
+        if w >= 1.0 {
+            return mag;
+        }
+        // 予測値を計測値の大きさにそろえて内分する
+        let mag_q = quat::frame_rotation(self.q, self.reference_field());
+        quat::add_vec(quat::scale_vec(w, mag), quat::scale_vec((1.0 - w) * norm, mag_q))
+    }
+
+    /// イノベーションの検定で棄却した地磁気を推定姿勢からの予測値に置き換える（検定しない場合はそのまま返す）．
+    fn gate_mag(&self, mag: Vector3<f64>) -> Vector3<f64> {
+        match self.gate {
+            Some(gate) if gate.rejects_mag(self.mag_innovation(mag)) => quat::frame_rotation(self.q, self.mag_r),
+            _ => mag,
+        }
+    }
+
+    /// 外乱判定が変わったらイベントを記録する（tracingフィーチャが無効な場合は何もしない）．
+    /// 
+    /// * prev: 判定前のフラグ（弱い外乱，強い外乱）
+    /// * e   : 誤差関数の値
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn report_disturbance(&self, prev: (bool, bool), e: f64) {
+        #[cfg(feature = "tracing")]
+        if (self.flag_acc_weak, self.flag_acc_strong) != prev {
+            let state = match (self.flag_acc_weak, self.flag_acc_strong) {
+                (_, true) => "強い加速度外乱を検知しました",
+                (true, false) => "弱い加速度外乱を検知しました",
+                (false, false) => "加速度外乱が収まりました",
+            };
+            tracing::debug!(e, axis = ?self.disturbance_axis(), n_steps = self.n_steps, "{}", state);
+        }
+    }
+
+    /// 補正ステップの入力を検査する．
+    /// 
+    /// NaNや無限大が含まれていたら補正を行わずにfalseを返す（再開時の位置合わせのためn_stepsは進める）．
+    fn check_input(&mut self, inputs: &[Vector3<f64>]) -> bool {
+        if is_finite_all(inputs) {
+            true
+        } else {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(?inputs, n_steps = self.n_steps, "計測値にNaNや無限大が含まれているので読み飛ばしました");
+            self.health = Health::InvalidInput;
+            self.n_steps += 1;
+            false
+        }
+    }
+
+    /// 発散した状態を初期状態に戻し，コールバックで通知する．
+    fn recover(&mut self) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(q = ?self.q, gyr_correct = ?self.gyr_correct, n_steps = self.n_steps, "発散したので初期状態に戻しました");
+        self.reset();
+        self.health = Health::Diverged;
+        if let Some(on_reset) = self.on_reset {
+            on_reset(Health::Diverged);
+        }
+    }
+
+    /// 誤差関数eに対する補正ゲインの重み（0〜1）を返す．
+    /// 
+    /// 弱い外乱と強い外乱の閾値の中間で0.5になり，thr_weakで約0.88，thr_strongで約0.12になる．
+    fn sigmoid_weight(&self, e: f64) -> f64 {
+        let center = 0.5 * (self.thr_weak + self.thr_strong);
+        let scale = 0.25 * (self.thr_strong - self.thr_weak);
+        1.0 / (1.0 + ((e - center) / scale).exp())
+    }
+
+    /// 補正に使ったベクトルから軸ごとの可観測性を更新する．
+    /// 
+    /// * coef: 加速度の重み（外乱検知の結果）
+    fn update_observability(&mut self, acc: Vector3<f64>, mag: Option<Vector3<f64>>, coef: f64) {
+        let decoupled = self.coef_yaw.is_some();
+        let dt = self.dt;
+        let Some(ref mut obs) = self.observability else {
+            return;
+        };
+        let g = quat::normalize_vec(acc);
+        let mut info = g.map(|v| coef * (1.0 - v * v));
+        if let Some(mag) = mag {
+            // チルトとヨー角を分離する場合，地磁気は鉛直軸周りの回転だけを補正する
+            let m = quat::normalize_vec(mag);
+            for (i, v) in info.iter_mut().enumerate() {
+                *v += if decoupled { g[i] * g[i] } else { 1.0 - m[i] * m[i] };
+            }
+        }
+        if is_finite_all(&[info]) {
+            let k = (dt / obs.tau).min(1.0);
+            obs.info = quat::scale_add_vec(k, quat::sub_vec(info, obs.info), obs.info);
+        }
+    }
+
+    /// 補正角速度（比例項）から積分項を更新し，補正角速度に反映する．
+    fn update_integral(&mut self) {
+        // 積分項を減衰（減衰し続けて非正規化数になった値は0にする）
+        self.gyr_integ = quat::scale_vec((1.0 - self.integ_leak * self.dt).max(0.0), self.gyr_integ);
+        for v in self.gyr_integ.iter_mut() {
+            if v.is_subnormal() {
+                *v = 0.0;
+            }
+        }
+
+        // 積分項を更新
+        self.accumulate_integral(self.gyr_correct);
+
+        // 積分項の値を補正角速度に反映
+        self.gyr_correct = quat::hadamard_add_vec(self.coef_integ, self.gyr_integ, self.gyr_correct);
+
+        // 補正角速度が発散していたら初期状態に戻す
+        if !is_finite_all(&[self.gyr_correct, self.gyr_integ]) {
+            self.recover();
+        }
+
+        self.n_steps += 1;
+    }
+
+    /// 比例項gyr_pを積分項に足し込む（update_integral()とcorrect_heading()で共通）．
+    /// 
+    /// 比例項が大きい間は積分せず，可観測性の低い軸は重みを下げ，バイアスの推定値が上限を超えないように制限する．
+    fn accumulate_integral(&mut self, gyr_p: Vector3<f64>) {
+        // 比例項が大きい間は積分しない
+        let integrate = match self.integ_err_limit {
+            Some(limit) => {
+                let coef_max = self.coef_gyr_c.iter().fold(0.0_f64, |a, &b| a.max(b));
+                quat::norm_vec(gyr_p) <= coef_max * limit
+            },
+            None => true,
+        };
+        if integrate {
+            // 可観測性の低い軸は積分の重みを下げる
+            let weight = match self.observability {
+                Some(obs) => obs.info.map(|v| (v / obs.thr).clamp(0.0, 1.0)),
+                None => [1.0; 3],
+            };
+            let increment = quat::hadamard_vec(weight, gyr_p);
+            self.gyr_integ = quat::scale_add_vec(self.dt, increment, self.gyr_integ);
+        }
+
+        // バイアスの推定値が上限を超えないように積分項を制限
+        if let Some(max_bias) = self.integ_limit {
+            for (v, &coef) in self.gyr_integ.iter_mut().zip(self.coef_integ.iter()) {
+                if coef > 0.0 {
+                    let max_integ = max_bias / coef;
+                    *v = v.clamp(-max_integ, max_integ);
+                }
+            }
+        }
+    }
+
+    /// 外部から得た方位（GNSSの対地針路や2アンテナ方式の方位など）で補正する．
+    /// 
+    /// correct()の後に呼ぶ．地磁気が乱れている場合や地磁気センサを使わない場合に，ヨー角のドリフトを抑えるために使う．
+    /// 
+    /// * yaw   : ヨー角[rad]（to_euler_angles(q, ZYX)の第1要素と同じ定義：基準座標系z軸周りの回転で，x軸から反時計回り）
+    /// * weight: 補正の重み（0で補正無し，1でcorrect()の姿勢補正と同じ強さ．with_decoupled_yaw()ならヨー角の補正と同じ強さ）
+    pub fn correct_heading(&mut self, yaw: f64, weight: f64) {
+        use std::f64::consts::PI;
+
+        if !(yaw.is_finite() && weight.is_finite()) {
+            self.health = Health::InvalidInput;
+            return;
+        }
+
+        // ヨー角の差を[-π, π)に収める
+        let yaw_hat = quat::to_euler_angles(self.q)[0];
+        let diff = (yaw - yaw_hat + PI).rem_euclid(2.0 * PI) - PI;
+
+        // 基準座標系のz軸周りにdiffだけ回転させる角速度（機体座標系，チルトとヨー角を分離する場合はヨー角の係数で）
+        let axis = quat::frame_rotation(self.q, [0.0, 0.0, 1.0]);
+        let dq = quat::scale_vec(weight * (0.5 * diff).sin(), axis);
+        let gyr_heading = match self.coef_yaw {
+            Some(coef_yaw) => quat::scale_vec(coef_yaw, dq),
+            None => quat::hadamard_vec(self.coef_gyr_c, dq),
+        };
+
+        // 積分項を更新（減衰はcorrect()のupdate_integral()でこのステップの分を済ませている）
+        let integ_prev = self.gyr_integ;
+        self.accumulate_integral(gyr_heading);
+
+        // 補正角速度に加える（積分項の増分も反映する）
+        let d_integ = quat::sub_vec(self.gyr_integ, integ_prev);
+        self.gyr_correct = quat::add_vec(self.gyr_correct, quat::hadamard_add_vec(self.coef_integ, d_integ, gyr_heading));
+    }
+
+    /// 加速度外乱の判定に使う誤差関数（with_detector()で設定したもの）の値を返す．
+    /// 
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    pub fn disturbance_error(&self, acc: Vector3<f64>) -> f64 {
+        self.detector.error(acc, quat::frame_rotation(self.q, ACC_R))
+    }
+
+    /// 角速度バイアスの推定値[rad/s]を返す．
+    /// 
+    /// 積分項は補正角速度として加えているので，バイアスとは符号が反転している．
+    pub fn gyro_bias(&self) -> Vector3<f64> {
+        quat::negate_vec( quat::hadamard_vec(self.coef_integ, self.gyr_integ) )
+    }
+
+    /// 角速度計測値からバイアスの推定値を除いた角速度[rad/s]を返す．
+    /// 
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    pub fn angular_rate(&self, gyr: Vector3<f64>) -> Vector3<f64> {
+        quat::sub_vec(gyr, self.gyro_bias())
+    }
+
+    /// 角加速度の推定値[rad/s^2]を返す（with_angular_acceleration()を使っていなければNone）．
+    pub fn angular_acceleration(&self) -> Option<Vector3<f64>> {
+        self.ang_accel.map(|obs| obs.acceleration())
+    }
+
+    /// 角速度計測値に補正角速度（バイアスの推定値と姿勢誤差を補正する成分）を加えた角速度[rad/s]を返す．
+    /// 
+    /// 予測ステップで積分している角速度と同じ．
+    /// 
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    pub fn angular_rate_with_correction(&self, gyr: Vector3<f64>) -> Vector3<f64> {
+        quat::add_vec(gyr, self.gyr_correct)
+    }
+
+    /// 姿勢推定値を方向余弦行列に変換して返す．
+    /// 
+    /// 機体座標系上のベクトルvを基準座標系上に変換する行列（`R v`）．
+    pub fn rotation_matrix(&self) -> DCM<f64> {
+        quat::to_dcm(self.q)
+    }
+
+    /// 機体座標系上における重力の向き（鉛直下向きの単位ベクトル）を返す．
+    /// 
+    /// 静止時の加速度計測値はこれと逆向きになる．
+    pub fn gravity_body(&self) -> Vector3<f64> {
+        quat::frame_rotation(self.q, [0.0, 0.0, -1.0])
+    }
+
+    /// 計測した加速度から重力を除いた加速度（機体座標系）[m/s^2]を返す．
+    /// 
+    /// 運動による加速度や加速度外乱の推定値として使える．
+    /// 
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    pub fn linear_acceleration(&self, acc: Vector3<f64>) -> Vector3<f64> {
+        quat::sub_vec(acc, quat::frame_rotation(self.q, ACC_R))
+    }
+
+    /// 計測した加速度から重力を除いた加速度（基準座標系）[m/s^2]を返す．
+    /// 
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    pub fn linear_acceleration_ref(&self, acc: Vector3<f64>) -> Vector3<f64> {
+        quat::vector_rotation(self.q, self.linear_acceleration(acc))
+    }
+
+    /// 1サンプル分の予測・補正ステップを行い，姿勢推定値を返す（ahrsクレートのAhrs::update()と同じ使い方）．
+    /// 
+    /// 計測値が不正で使わなかった場合や，発散して初期状態に戻した場合はErrを返す．
+    /// ahrsクレートのMadgwickやMahonyの代わりにAhrsトレイトとして使う場合は，ahrsフィーチャのahrs_compat::AhrsFilterで包む．
+    pub fn update(&mut self, gyr: Vector3<f64>, acc: Vector3<f64>, mag: Vector3<f64>) -> Result<Quaternion<f64>, &'static str> {
+        self.predict(gyr);
+        self.correct(acc, mag);
+        self.update_result()
+    }
+
+    /// 地磁気を使わずに1サンプル分の予測・補正ステップを行う（Ahrs::update_imu()に当たる，correct_acc_only()を使う）．
+    pub fn update_imu(&mut self, gyr: Vector3<f64>, acc: Vector3<f64>) -> Result<Quaternion<f64>, &'static str> {
+        self.predict(gyr);
+        self.correct_acc_only(acc);
+        self.update_result()
+    }
+
+    /// 予測ステップだけを行う（Ahrs::update_gyro()に当たる）．
+    pub fn update_gyro(&mut self, gyr: Vector3<f64>) -> Quaternion<f64> {
+        self.predict(gyr);
+        self.q
+    }
+
+    fn update_result(&self) -> Result<Quaternion<f64>, &'static str> {
+        match self.health {
+            Health::Ok => Ok(self.q),
+            Health::InvalidInput => Err("計測値にNaNか無限大が含まれています"),
+            Health::Diverged => Err("発散したので初期状態に戻しました"),
+        }
+    }
+
+    /// 機体を動かす前の確認（パラメータの妥当性，姿勢推定値のノルム，合成した計測値での収束）を行う．
+    /// 
+    /// 収束の確認では，パラメータを同じにしたフィルタを初期状態から動かし，静止した姿勢の計測値を
+    /// 収束時間alphaのSELF_TEST_ALPHAS倍の間与えて，姿勢誤差が初期のSELF_TEST_RATIO倍より小さくなるかを調べる（このフィルタの状態は変えない）．
+    /// 初期姿勢の誤差は弱い外乱の閾値の半分程度にする（E2でも外乱とみなさない大きさ）．
+    pub fn self_test(&self) -> SelfTestReport {
+        let mut problems = self.check_parameters();
+        let quaternion_norm = quat::norm(self.q);
+        let normalized = (quaternion_norm - 1.0).abs() < 1e-6;
+        if !normalized {
+            problems.push("姿勢推定値が正規化されていません");
+        }
+        if self.gyro_failed {
+            problems.push("角速度センサの故障を通知されています");
+        }
+        let (initial_error, convergence_error) = self.convergence_error();
+        let converged = convergence_error < SELF_TEST_RATIO * initial_error;
+        if !converged {
+            problems.push("合成した計測値で姿勢が収束しません");
+        }
+        SelfTestReport { problems, quaternion_norm, convergence_error, health: self.health }
+    }
+
+    /// self_test()に加えて，静止させた機体の計測値を確かめる（値が有限か，加速度の大きさが重力加速度に近いか，
+    /// バイアスの推定値を除いた角速度が小さいか，地磁気が0でないか）．
+    /// 
+    /// * sample: 静止させた機体の計測値
+    pub fn self_test_with_sample(&self, sample: &Sample) -> SelfTestReport {
+        let mut report = self.self_test();
+        if !is_finite_all(&[sample.gyr, sample.acc, sample.mag]) {
+            report.problems.push("計測値にNaNか無限大が含まれています");
+            return report;
+        }
+        if (quat::norm_vec(sample.acc) / STANDARD_GRAVITY - 1.0).abs() > SELF_TEST_ACC_TOL {
+            report.problems.push("加速度の大きさが重力加速度と合いません");
+        }
+        if quat::norm_vec(self.angular_rate(sample.gyr)) > SELF_TEST_MAX_RATE {
+            report.problems.push("静止しているはずなのに角速度が大きすぎます");
+        }
+        if quat::norm_vec(sample.mag) == 0.0 {
+            report.problems.push("地磁気の計測値が0です");
+        }
+        report
+    }
+
+    /// パラメータの妥当性を調べ，問題を返す．
+    fn check_parameters(&self) -> Vec<&'static str> {
+        let positive = |v: f64| v > 0.0 && v.is_finite();
+        let mut problems = Vec::new();
+        if !(positive(self.thr_weak) && self.thr_weak < self.thr_strong && self.thr_strong.is_finite()) {
+            problems.push("外乱判定の閾値は0 < thr_weak < thr_strongにしてください");
+        }
+        if !self.coef_gyr_c.iter().all(|&c| positive(c)) {
+            problems.push("収束時間alphaは正の値にしてください");
+        }
+        if !self.coef_integ.iter().all(|&c| c >= 0.0 && c.is_finite()) {
+            problems.push("積分係数betaは0以上にしてください");
+        }
+        if !(self.integ_leak >= 0.0 && self.integ_leak.is_finite()) {
+            problems.push("積分項の減衰率は0以上にしてください");
+        }
+        if !self.integ_limit.into_iter().chain(self.integ_err_limit).all(positive) {
+            problems.push("積分項の上限は正の値にしてください");
+        }
+        if self.substeps == 0 {
+            problems.push("積分の分割数は1以上にしてください");
+        }
+        let taus = [Some(self.gyro_free_tau), self.observability.map(|o| o.tau), self.mag_norm.map(|m| m.tau), self.accel_bias.map(|b| b.tau)];
+        if !taus.into_iter().flatten().all(positive) {
+            problems.push("時定数は正の値にしてください");
+        }
+        problems
+    }
+
+    /// パラメータを同じにしたフィルタを，静止した姿勢の合成した計測値で動かしたときの初期と最終的な姿勢誤差[rad]
+    fn convergence_error(&self) -> (f64, f64) {
+        let mut filter = self.clone();
+        filter.on_reset = None;
+        filter.gyro_failed = false;
+        filter.n_gyro_invalid = 0;
+        filter.reset();
+        if let Some(ref mut m) = filter.mag_norm {
+            m.norm = 0.0;
+        }
+        let q_true = quat::from_axis_angle(quat::normalize_vec([1.0, 2.0, 3.0]), 0.5);
+        let acc = quat::frame_rotation(q_true, ACC_R);
+        let mag = quat::frame_rotation(q_true, self.reference_field());
+        let initial_error = 0.5 * self.thr_weak;
+        filter.q = quat::mul(q_true, quat::from_axis_angle([1.0, 0.0, 0.0], initial_error));
+        let alpha = 2.0 / self.coef_gyr_c.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+        let n_steps = ((SELF_TEST_ALPHAS * alpha / DT).ceil() as usize).min(SELF_TEST_MAX_STEPS);
+        for _ in 0..n_steps {
+            filter.predict([0.0; 3]);
+            filter.correct(acc, mag);
+        }
+        let dot = quat::dot(q_true, filter.q).abs().min(1.0);
+        (initial_error, 2.0 * dot.acos())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl AttitudeFilter {
+    /// フィルタの状態をJSON形式でファイルに保存する．
+    /// 
+    /// 書き込み途中で中断しても前回の保存内容が壊れないように，一時ファイルに書いてから置き換える．
+    pub fn save_state<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// save_stateで保存したフィルタの状態を読み込む．
+    pub fn load_state<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Ok( serde_json::from_str(&json)? )
+    }
+}
+
+/// 姿勢を表す四元数をオイラー角[rad]に変換する．
+/// 
+/// 返り値は回転させる順に並べる（ZYXなら[yaw, pitch, roll]，XYZなら[x, y, z]軸周りの角度）．
+/// 2番目の回転が±90[deg]付近（ジンバルロック）の場合は，3番目の角度を0とする．
+pub fn to_euler_angles(q: Quaternion<f64>, seq: EulerSequence) -> Vector3<f64> {
+    match seq {
+        EulerSequence::ZYX => quat::to_euler_angles(q),
+        EulerSequence::XYZ => {
+            // q = Rx(a) * Ry(b) * Rz(c)
+            let [
+                [m11, m12, m13],
+                [  _, m22, m23],
+                [  _, m32, m33],
+            ] = quat::to_dcm(q);
+
+            if m13.abs() < 0.9999984 {  // < 89.9[deg]
+                [
+                    (-m23).atan2(m33),
+                    m13.asin(),
+                    (-m12).atan2(m11),
+                ]
+            } else {  // ジンバルロック
+                [
+                    m32.atan2(m22),
+                    std::f64::consts::FRAC_PI_2.copysign(m13),
+                    0.0,
+                ]
+            }
+        },
+    }
+}
+
+// 加速度に外乱が入っていなければ良いが、外乱がある場合地磁気の伏角除去に影響が出る。
+/// 機体座標系上で計測した加速度と地磁気ベクトルから，基準座標系に対する姿勢を計算する．
+pub fn get_q_gm(acc: Vector3<f64>, mag: Vector3<f64>) -> Quaternion<f64> {
+    get_q_gm_with_reference(acc, mag, MAG_R)
+}
+
+/// 基準座標系上における地磁気の向きをmag_r（水平なベクトル）として，get_q_gm()と同じく姿勢を計算する．
+pub fn get_q_gm_with_reference(acc: Vector3<f64>, mag: Vector3<f64>, mag_r: Vector3<f64>) -> Quaternion<f64> {
+    let q_g = quat::rotate_a_to_b(acc, ACC_R);
+    let mag_b2r = quat::hadamard_vec(quat::vector_rotation(q_g, mag), [1.0, 1.0, 0.0]);
+    let q_e = quat::rotate_a_to_b(mag_b2r, mag_r);
+    quat::mul(q_e, q_g)
+}
+
+/// 鉛直成分も含めた3次元の地磁気mag_rを使って，加速度と地磁気から姿勢を計算する（TRIAD法）．
+/// 
+/// get_q_gm()のように地磁気の鉛直成分を捨てないので，伏角が大きい地域でも地磁気の情報をすべて使える．
+/// 加速度の向きを正確に合わせる解と地磁気の向きを正確に合わせる解をacc_weight（0〜1）で球面線形補間するので，
+/// 加速度外乱に応じて重みを下げれば地磁気を優先した姿勢になる（地磁気の向き周りの回転は加速度で決まる）．
+/// 
+/// * mag_r     : 基準座標系上における地磁気の向き（伏角を含む）
+/// * acc_weight: 加速度を優先する度合い（1なら加速度，0なら地磁気の向きを正確に合わせる）
+pub fn get_q_gm_full_field(acc: Vector3<f64>, mag: Vector3<f64>, mag_r: Vector3<f64>, acc_weight: f64) -> Quaternion<f64> {
+    let q_acc = triad(acc, mag, ACC_R, mag_r);
+    let q_mag = triad(mag, acc, mag_r, ACC_R);
+    quat::slerp(q_mag, q_acc, acc_weight.clamp(0.0, 1.0))
+}
+
+/// 保存した状態にmag_rが無い場合の値
+#[cfg(feature = "serde")]
+fn default_mag_r() -> Vector3<f64> {
+    MAG_R
+}
+
+/// 保存した状態にsubstepsが無い場合の値
+#[cfg(feature = "serde")]
+fn default_substeps() -> u32 {
+    1
+}
+
+/// 保存した状態にdtが無い場合の値
+#[cfg(feature = "serde")]
+fn default_dt() -> f64 {
+    DT
+}
+
+/// 保存した状態にgyro_free_tauが無い場合の値
+#[cfg(feature = "serde")]
+fn default_gyro_free_tau() -> f64 {
+    GYRO_FREE_TAU
+}
+
+/// 保存した状態にsolverが無い場合の値
+#[cfg(feature = "serde")]
+fn default_solver() -> AttitudeSolver {
+    AttitudeSolver::GetQGm
+}
+
+/// ベクトルの要素がすべて有限の値かどうか
+fn is_finite_all(vs: &[Vector3<f64>]) -> bool {
+    vs.iter().flatten().all(|v| v.is_finite())
+}
+
+/// 絶対値が最大の要素の番号
+fn dominant_axis(v: Vector3<f64>) -> usize {
+    (0..3).fold(0, |i, j| if v[j].abs() > v[i].abs() { j } else { i })
+}
+
+/// 誤差関数eに対するHuber重み（0〜1）．e <= cでは1，それより大きいとc/e．
+fn huber_weight(e: f64, c: f64) -> f64 {
+    if e <= c { 1.0 } else { c / e }
+}
+
+/// 誤差関数eに対するCauchy重み（0〜1）．e = cで0.5．
+fn cauchy_weight(e: f64, c: f64) -> f64 {
+    1.0 / (1.0 + (e / c).powi(2))
+}
+
+// 判定式を指定するテストは，その判定式のフィーチャが有効な場合だけ
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2つの姿勢の差（回転角）[rad]
+    fn angle_between(a: Quaternion<f64>, b: Quaternion<f64>) -> f64 {
+        let q_err = quat::mul(quat::conj(a), b);
+        2.0 * quat::norm_vec(q_err.1).atan2(q_err.0.abs())
+    }
+
+    /// 姿勢qで静止しているときの加速度と地磁気の計測値
+    fn measurements(q: Quaternion<f64>) -> (Vector3<f64>, Vector3<f64>) {
+        (quat::frame_rotation(q, ACC_R), quat::frame_rotation(q, MAG_R))
+    }
+
+    /// 誤差関数がeになる加速度（姿勢が恒等四元数のとき）
+    fn acc_with_error(e: f64) -> Vector3<f64> {
+        [0.0, 0.0, STANDARD_GRAVITY * (1.0 + e)]
+    }
+
+    #[test]
+    fn static_converges_to_identity() {
+        // E2は推定値と計測値の差で外乱を判定するので，初期姿勢の誤差が大きいと外乱とみなして補正しない．
+        // E2では誤差関数が弱い外乱の閾値を下回る程度（2*sin(θ/2) < 0.04）にずらす．
+        let cases = [
+            #[cfg(feature = "detector-e1")]
+            (Detector::E1, quat::normalize((0.9, [0.2, -0.3, 0.1]))),
+            #[cfg(feature = "detector-e2")]
+            (Detector::E2, quat::from_axis_angle([0.6, -0.8, 0.0], 0.03)),
+            #[cfg(all(feature = "detector-e1", feature = "detector-e2"))]
+            (Detector::Both, quat::from_axis_angle([0.6, -0.8, 0.0], 0.03)),
+        ];
+        for (detector, q0) in cases {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_detector(detector);
+            filter.q = q0;
+
+            let (acc, mag) = measurements((1.0, [0.0; 3]));
+            for _ in 0..3000 {
+                filter.predict([0.0; 3]);
+                filter.correct(acc, mag);
+            }
+            assert!(angle_between(filter.q, (1.0, [0.0; 3])) < 1e-3, "{:?}", detector);
+        }
+    }
+
+    // 初期姿勢の誤差が大きいので，デフォルトの判定式がE1の場合だけ（E2では外乱とみなして補正しない）
+    #[test]
+    #[cfg(feature = "detector-e1")]
+    fn reference_field_can_change_at_runtime() {
+        // 偏角0.3 rad，伏角のある地磁気（基準座標系）
+        let field = |declination: f64| {
+            let h = quat::frame_rotation(quat::from_axis_angle([0.0, 0.0, 1.0], declination), [0.0, 0.3, 0.0]);
+            [h[0], h[1], -0.4]
+        };
+        let q_true = quat::from_axis_angle([0.2, -0.1, 1.0], 0.8);
+        let acc = quat::frame_rotation(q_true, ACC_R);
+        for decoupled in [false, true] {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_reference_field(field(0.3));
+            if decoupled {
+                filter = filter.with_decoupled_yaw(1.0);
+            }
+            for declination in [0.3, -0.5] {
+                // 途中で基準の磁場を変えても，新しい磁場に合わせた姿勢に収束する
+                filter.set_reference_field(field(declination));
+                let mag = quat::frame_rotation(q_true, field(declination));
+                for _ in 0..3000 {
+                    filter.predict([0.0; 3]);
+                    filter.correct(acc, mag);
+                }
+                assert!(angle_between(filter.q, q_true) < 1e-3, "decoupled = {}, declination = {}", decoupled, declination);
+            }
+        }
+    }
+
+    // 初期姿勢の誤差が大きいので，デフォルトの判定式がE1の場合だけ（E2では外乱とみなして補正しない）
+    #[test]
+    #[cfg(feature = "detector-e1")]
+    fn full_field_uses_inclination() {
+        let inclination = 1.2;  // 伏角約69度
+        let q_true = quat::from_axis_angle([0.2, -0.1, 1.0], 0.8);
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_inclination(inclination);
+        let mag_r = filter.reference_field();
+        assert!((mag_r[2] + inclination.sin()).abs() < 1e-12);
+
+        // 加速度と地磁気から正しい姿勢が求まり，重みによらない
+        let acc = quat::frame_rotation(q_true, ACC_R);
+        let mag = quat::frame_rotation(q_true, mag_r);
+        for w in [0.0, 0.5, 1.0] {
+            assert!(angle_between(get_q_gm_full_field(acc, mag, mag_r, w), q_true) < 1e-9, "w = {}", w);
+        }
+
+        // 加速度外乱がある場合は，地磁気を優先した方が誤差が小さい
+        let acc_dist = quat::add_vec(acc, [3.0, 0.0, 0.0]);
+        let err_acc = angle_between(get_q_gm_full_field(acc_dist, mag, mag_r, 1.0), q_true);
+        let err_mag = angle_between(get_q_gm_full_field(acc_dist, mag, mag_r, 0.0), q_true);
+        assert!(err_mag < err_acc, "{} >= {}", err_mag, err_acc);
+
+        // 補正ステップでも同じ姿勢に収束する
+        for _ in 0..3000 {
+            filter.predict([0.0; 3]);
+            filter.correct(acc, mag);
+        }
+        assert!(angle_between(filter.q, q_true) < 1e-3);
+
+        // set_reference_field()で伏角も更新する
+        filter.set_reference_field([0.0, 0.5, -0.5]);
+        assert!((filter.reference_field()[2] + std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn angular_acceleration_observer_tracks_ramp() {
+        use rand::distributions::{Distribution, Normal};
+        use rand::{SeedableRng, rngs::StdRng};
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let randn = Normal::new(0.0, 0.01);
+        let accel = [0.5, -1.0, 0.2];
+        let mut obs = AngularAccelerationObserver::new(5.0);
+        let (mut err_obs, mut err_diff) = (0.0, 0.0);
+        let mut prev = [0.0; 3];
+        for k in 0..1000 {
+            let rate = quat::scale_vec(k as f64 * DT, accel).map(|v| v + randn.sample(&mut rng));
+            obs.update(rate);
+            if k >= 500 {
+                let diff = quat::scale_vec(DT.recip(), quat::sub_vec(rate, prev));
+                err_obs += quat::norm_vec(quat::sub_vec(obs.acceleration(), accel)).powi(2);
+                err_diff += quat::norm_vec(quat::sub_vec(diff, accel)).powi(2);
+            }
+            prev = rate;
+        }
+        // 差分よりノイズが十分小さい
+        assert!(err_obs < 0.01 * err_diff, "{} {}", err_obs, err_diff);
+        assert!((err_obs / 500.0).sqrt() < 0.1);
+    }
+
+    #[test]
+    fn freezes_unobservable_bias_axis() {
+        // 重力がz軸に沿ったまま，加速度だけで補正する（z軸周りの回転は分からない）
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_observability_freeze(1.0, 0.5);
+        let acc = ACC_R;
+        for _ in 0..500 {
+            filter.predict([0.0, 0.0, 0.3]);
+            filter.correct_acc_only(acc);
+        }
+        let info = filter.bias_observability().unwrap();
+        assert!(info[0] > 0.99 && info[1] > 0.99 && info[2] < 1e-3, "{:?}", info);
+
+        // z軸の補正角速度があっても，z軸の積分項はほとんど更新しない
+        let integ_z = filter.gyr_integ[2];
+        filter.gyr_correct = [0.0, 0.0, 1.0];
+        filter.update_integral();
+        assert!((filter.gyr_integ[2] - integ_z).abs() < 1e-3 * DT);
+
+        // 地磁気も使えばz軸も可観測になる
+        let mag = quat::frame_rotation(quat::from_axis_angle([0.0, 0.0, 1.0], 0.3), MAG_R);
+        for _ in 0..500 {
+            filter.predict([0.0; 3]);
+            filter.correct(acc, mag);
+        }
+        assert!(filter.bias_observability().unwrap()[2] > 0.99);
+    }
+
+    #[test]
+    fn non_uniform_dt_matches_uniform_in_time() {
+        // 静止した機体で角速度バイアスだけがある場合，同じ時間が経てば積分項（と減衰）はサンプル間隔によらない
+        let bias = [0.02, -0.01, 0.03];
+        let (acc, mag) = measurements((1.0, [0.0; 3]));
+        let new_filter = || AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_integral_leak(0.05);
+        let duration = 2.0;
+
+        let mut uniform = new_filter();
+        for _ in 0..(duration / DT).round() as usize {
+            uniform.predict(bias);
+            uniform.correct(acc, mag);
+        }
+
+        // 平均がDTの半分になる不規則な間隔
+        let mut non_uniform = new_filter();
+        let pattern = [0.2 * DT, 0.9 * DT, 0.4 * DT];
+        let mut t = 0.0;
+        for dt in pattern.iter().cycle() {
+            if t + dt > duration + 1e-9 {
+                break;
+            }
+            t += dt;
+            non_uniform.predict_with_dt(bias, *dt);
+            non_uniform.correct(acc, mag);
+        }
+
+        let (b_u, b_n) = (uniform.gyro_bias(), non_uniform.gyro_bias());
+        let diff = quat::norm_vec(quat::sub_vec(b_u, b_n));
+        assert!(diff < 0.05 * quat::norm_vec(b_u), "{:?} vs {:?}", b_u, b_n);
+    }
+
+    #[test]
+    fn heading_aiding_respects_integral_limit() {
+        // 地磁気を使わずに方位だけで補正し続けても，バイアスの推定値は上限を超えない
+        let max_bias = 0.01;
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_integral_limit(max_bias);
+        for _ in 0..2000 {
+            filter.predict([0.0, 0.0, 0.5]);
+            filter.correct_acc_only(ACC_R);
+            filter.correct_heading(0.0, 1.0);
+            assert!(filter.gyro_bias().iter().all(|b| b.abs() <= max_bias + 1e-12), "{:?}", filter.gyro_bias());
+        }
+        assert!((filter.gyro_bias()[2].abs() - max_bias).abs() < 1e-9, "{:?}", filter.gyro_bias());
+
+        // 上限が無ければ同じ入力でバイアスの推定値は上限を超える
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        for _ in 0..2000 {
+            filter.predict([0.0, 0.0, 0.5]);
+            filter.correct_acc_only(ACC_R);
+            filter.correct_heading(0.0, 1.0);
+        }
+        assert!(filter.gyro_bias()[2].abs() > max_bias, "{:?}", filter.gyro_bias());
+    }
+
+    #[test]
+    fn heading_aiding_uses_yaw_gain_when_decoupled() {
+        // 積分項を使わなければ，方位の誤差はヨー角の収束時間alpha_yawで1/eになる
+        let alpha_yaw = 4.0;
+        let yaw0 = 0.2;
+        let mut filter = AttitudeFilter::new(1.0, 0.0, 0.04, 0.08).with_decoupled_yaw(alpha_yaw);
+        filter.q = quat::from_axis_angle([0.0, 0.0, 1.0], yaw0);
+        for _ in 0..(alpha_yaw / DT).round() as usize {
+            filter.predict([0.0; 3]);
+            filter.correct_acc_only(ACC_R);
+            filter.correct_heading(0.0, 1.0);
+        }
+        let ratio = to_euler_angles(filter.q, EulerSequence::ZYX)[0] / yaw0;
+        assert!((ratio - (-1.0_f64).exp()).abs() < 0.02, "{}", ratio);
+    }
+
+    #[test]
+    fn substeps_reduce_integration_error() {
+        let gyr = [3.0, -4.0, 6.0];
+        let n = 50;
+        let q_true = quat::from_rotation_vector(quat::scale_vec(n as f64 * DT, gyr));
+        let errors: Vec<f64> = [1, 10].iter().map(|&m| {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_substeps(m);
+            for _ in 0..n {
+                filter.predict(gyr);
+            }
+            angle_between(filter.q, q_true)
+        }).collect();
+        assert!(errors[1] < 0.1 * errors[0], "{:?}", errors);
+    }
+
+    // 初期姿勢の誤差が大きいので，デフォルトの判定式がE1の場合だけ（E2では外乱とみなして補正しない）
+    #[test]
+    #[cfg(feature = "detector-e1")]
+    fn gyro_free_mode_switches_over() {
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_gyro_fault_detection(5);
+        let q_true = quat::from_axis_angle([0.2, -0.1, 1.0], 0.8);
+        let acc = quat::frame_rotation(q_true, ACC_R);
+        let mag = quat::frame_rotation(q_true, MAG_R);
+        for k in 0..500 {
+            filter.predict([f64::NAN; 3]);
+            filter.correct(acc, mag);
+            assert_eq!(filter.is_gyro_free(), k >= 4, "k = {}", k);
+        }
+        assert!(angle_between(filter.q, q_true) < 1e-6);
+
+        // 正常な入力が来たら元に戻る（通知した故障は解除するまで続く）
+        filter.predict([0.0; 3]);
+        assert!(!filter.is_gyro_free());
+        let q = filter.q;
+        filter.set_gyro_failed(true);
+        filter.predict([1.0; 3]);
+        assert!(filter.is_gyro_free());
+        assert_eq!(filter.q, q);
+    }
+
+    // 初期姿勢の誤差が大きいので，デフォルトの判定式がE1の場合だけ（E2では外乱とみなして補正しない）
+    #[test]
+    #[cfg(feature = "detector-e1")]
+    fn attitude_solvers_converge() {
+        let q_true = quat::from_axis_angle([0.2, -0.1, 1.0], 0.8);
+        let acc = quat::frame_rotation(q_true, ACC_R);
+        for solver in [AttitudeSolver::GetQGm, AttitudeSolver::Triad, AttitudeSolver::TriadMag, AttitudeSolver::Davenport, AttitudeSolver::Svd] {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08)
+                .with_inclination(1.0)
+                .with_attitude_solver(solver);
+            let mag = quat::frame_rotation(q_true, filter.reference_field());
+            for _ in 0..3000 {
+                filter.predict([0.0; 3]);
+                filter.correct(acc, mag);
+            }
+            assert!(angle_between(filter.q, q_true) < 1e-3, "{:?}", solver);
+        }
+
+        // 伏角が無ければ加速度を主ベクトルとするTRIAD法はget_q_gm()と同じ解
+        let mag = quat::frame_rotation(q_true, [0.0, 0.4, -0.9]);
+        assert!(angle_between(triad(acc, mag, ACC_R, MAG_R), get_q_gm(acc, mag)) < 1e-9);
+    }
+
+    #[test]
+    fn get_q_gm_reproduces_known_rotations() {
+        let rotations = [
+            quat::from_axis_angle([0.0, 0.0, 1.0], 1.0),   // ヨー
+            quat::from_axis_angle([1.0, 0.0, 0.0], -0.7),  // ロール
+            quat::from_axis_angle([0.0, 1.0, 0.0], 0.5),   // ピッチ
+            quat::normalize((0.5, [0.3, -0.2, 0.8])),
+        ];
+        for q in rotations {
+            let (acc, mag) = measurements(q);
+            assert!(angle_between(get_q_gm(acc, mag), q) < 1e-9);
+        }
+    }
+
+    #[test]
+    fn bias_estimate_converges() {
+        let bias = [-0.02, 0.01, 0.05];
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+
+        let (acc, mag) = measurements((1.0, [0.0; 3]));
+        for _ in 0..3000 {
+            filter.predict(bias);
+            filter.correct(acc, mag);
+        }
+        let bias_hat = filter.gyro_bias();
+        for i in 0..3 {
+            assert!((bias_hat[i] - bias[i]).abs() < 1e-3, "{:?}", bias_hat);
+        }
+        assert!(angle_between(filter.q, (1.0, [0.0; 3])) < 1e-3);
+    }
+
+    #[test]
+    fn hysteresis_transitions() {
+        // 閾値：弱い外乱0.04（解除0.032），強い外乱0.08（解除0.064）
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        let acc_q = quat::frame_rotation(filter.q, ACC_R);
+
+        // 外乱無し
+        let (acc, coef) = filter.detect_disturbance(acc_with_error(0.01));
+        assert_eq!(coef, 1.0);
+        assert_eq!(acc, acc_with_error(0.01));
+
+        // 強い外乱：計測値の代わりに推定値を使う
+        let (acc, _) = filter.detect_disturbance(acc_with_error(0.1));
+        assert!(filter.flag_acc_strong);
+        assert_eq!(acc, acc_q);
+
+        // 強い外乱の解除閾値より大きい間は強い外乱のまま
+        let (acc, _) = filter.detect_disturbance(acc_with_error(0.07));
+        assert!(filter.flag_acc_strong);
+        assert_eq!(acc, acc_q);
+
+        // 弱い外乱に移る
+        let (_, coef) = filter.detect_disturbance(acc_with_error(0.06));
+        assert!(!filter.flag_acc_strong && filter.flag_acc_weak);
+        assert_eq!(coef, 0.5);
+
+        // 弱い外乱の解除閾値より大きい間は弱い外乱のまま
+        let (_, coef) = filter.detect_disturbance(acc_with_error(0.035));
+        assert!(filter.flag_acc_weak);
+        assert_eq!(coef, 0.5);
+
+        // 外乱無しに戻る
+        let (_, coef) = filter.detect_disturbance(acc_with_error(0.03));
+        assert!(!filter.flag_acc_weak && !filter.flag_acc_strong);
+        assert_eq!(coef, 1.0);
+    }
+
+    #[test]
+    fn robust_weights_degrade_gracefully() {
+        for (schedule, w_thr) in [(GainSchedule::Huber, 1.0), (GainSchedule::Cauchy, 0.5)] {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_gain_schedule(schedule);
+            // 弱い外乱の閾値での重み
+            let (_, coef) = filter.detect_disturbance(acc_with_error(0.04));
+            assert!((coef - w_thr).abs() < 1e-9, "{:?}: {}", schedule, coef);
+            // 誤差関数が大きくなるほど重みは小さくなるが，計測値を使った補正は止めない
+            let mut prev = coef;
+            for e in [0.06, 0.1, 0.2, 0.5] {
+                let (acc, coef) = filter.detect_disturbance(acc_with_error(e));
+                assert!(coef > 0.0 && coef < prev, "{:?}: e = {}", schedule, e);
+                assert_eq!(acc, acc_with_error(e));
+                // 判定のフラグは閾値による切り替えと同じ
+                assert_eq!(filter.is_disturbed(), e > 0.08, "{:?}: e = {}", schedule, e);
+                prev = coef;
+            }
+        }
+    }
+
+    #[test]
+    fn innovation_gate_rejects_outliers() {
+        let gate = InnovationGate { acc_var: 0.01, mag_var: 0.01, chi2: CHI2_3DOF_99 };
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_innovation_gate(gate);
+        let acc_q = quat::frame_rotation(filter.q, ACC_R);
+
+        // ノイズ程度の差なら計測値を使う
+        let (acc, _) = filter.detect_disturbance(quat::add_vec(acc_q, [0.1, -0.1, 0.1]));
+        assert_eq!(acc, quat::add_vec(acc_q, [0.1, -0.1, 0.1]));
+        assert!(!filter.is_disturbed());
+        // 大きな差なら予測値に置き換える
+        let (acc, _) = filter.detect_disturbance(quat::add_vec(acc_q, [1.0, 0.0, 0.0]));
+        assert_eq!(acc, acc_q);
+        assert!(filter.is_disturbed());
+
+        // 地磁気は向きだけを比べる（大きさや鉛直成分が違っても棄却しない）
+        assert_eq!(filter.gate_mag([0.0, 2.0, -1.5]), [0.0, 2.0, -1.5]);
+        let rotated = quat::frame_rotation(quat::from_axis_angle([0.0, 0.0, 1.0], 0.5), MAG_R);
+        assert_eq!(filter.gate_mag(rotated), MAG_R);
+    }
+
+    #[test]
+    #[cfg(all(feature = "detector-e1", feature = "detector-e2"))]
+    fn detector_kind_selects_at_construction() {
+        // Combinedは両方の判定式の大きい方を使う
+        let mut filter = AttitudeFilter::new_with_detector(1.0, 0.2, 0.04, 0.08, DetectorKind::Combined);
+        assert!(matches!(filter.detector, DetectorKind::Combined));
+        assert_eq!(filter.detector, Detector::Both);
+        let q = quat::from_axis_angle([1.0, 0.0, 0.0], 0.1);
+        filter.q = q;
+        let acc = quat::add_vec(ACC_R, [0.0, 0.0, 0.5]);
+        let acc_q = quat::frame_rotation(q, ACC_R);
+        let expected = E1.error(acc, acc_q).max(E2.error(acc, acc_q));
+        assert_eq!(filter.disturbance_error(acc), expected);
+
+        // 判定式を指定しなければnew()と同じ
+        let filter = AttitudeFilter::new_with_detector(1.0, 0.2, 0.04, 0.08, DetectorKind::default());
+        assert_eq!(filter.detector, AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).detector);
+    }
+
+    #[test]
+    fn custom_detector_plugs_in() {
+        /// 常に強い外乱と判定する
+        struct AlwaysStrong;
+        impl DisturbanceDetector for AlwaysStrong {
+            fn error(&self, _acc: Vector3<f64>, _acc_q: Vector3<f64>) -> f64 {
+                1.0
+            }
+        }
+
+        // 補正を止めるので姿勢の誤差が残る
+        let q0 = quat::from_axis_angle([1.0, 0.0, 0.0], 0.3);
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        filter.q = q0;
+        let (acc, mag) = measurements((1.0, [0.0; 3]));
+        for _ in 0..500 {
+            filter.predict([0.0; 3]);
+            filter.correct_with_detector(&AlwaysStrong, acc, mag);
+        }
+        assert!(filter.is_disturbed());
+        assert!(angle_between(filter.q, q0) < 1e-9);
+
+        // 組み込みの判定式はDetectorと同じ値を返す
+        let acc = [0.5, -0.3, 9.0];
+        let acc_q = quat::frame_rotation(q0, ACC_R);
+        #[cfg(feature = "detector-e1")]
+        assert_eq!(E1.error(acc, acc_q), Detector::E1.error(acc, acc_q));
+        #[cfg(feature = "detector-e2")]
+        assert_eq!(E2.error(acc, acc_q), Detector::E2.error(acc, acc_q));
+    }
+
+    #[test]
+    fn localizes_disturbance_axis() {
+        let (acc, mag) = measurements((1.0, [0.0; 3]));
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        filter.predict([0.0; 3]);
+        filter.correct(acc, mag);
+        assert_eq!(filter.disturbance_axis(), None);
+
+        // 機体のy軸方向に加わった外乱
+        filter.predict([0.0; 3]);
+        filter.correct(quat::add_vec(acc, [0.2, -3.0, 0.5]), mag);
+        assert!(filter.is_disturbed());
+        assert_eq!(filter.disturbance_axis(), Some(1));
+        assert!((filter.disturbance_residual()[1] + 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    #[cfg(feature = "detector-e1")]
+    fn directional_rejection_keeps_orthogonal_tilt() {
+        // 推定姿勢がピッチ方向にずれている状態で，機体のy軸方向に強い外乱が加わり続ける
+        let q_true = (1.0, [0.0; 3]);
+        let (acc, mag) = measurements(q_true);
+        let acc = quat::add_vec(acc, [0.0, 6.0, 0.0]);
+        let q0 = quat::from_axis_angle([0.0, 1.0, 0.0], 0.05);
+        let mut replace = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_detector(Detector::E1);
+        replace.q = q0;
+        let mut directional = replace.clone().with_directional_rejection();
+        for _ in 0..100 {
+            for filter in [&mut replace, &mut directional] {
+                filter.predict([0.0; 3]);
+                filter.correct(acc, mag);
+                assert!(filter.is_disturbed());
+            }
+        }
+        assert_eq!(directional.disturbance_axis(), Some(1));
+        // 加速度全体を棄却するとピッチは補正されないが，y軸の成分だけを棄却すればx軸の成分でピッチを補正できる
+        assert!(quat::dot(replace.q, q0).abs() > 1.0 - 1e-9);
+        assert!(quat::dot(directional.q, q_true).abs() > 1.0 - 1e-6);
+    }
+
+    #[test]
+    fn mag_norm_gate_ignores_hard_iron() {
+        let (acc, mag) = measurements((1.0, [0.0; 3]));
+        let mut plain = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        let mut gated = plain.clone().with_mag_norm_gate(0.1, 5.0);
+        for _ in 0..100 {
+            for filter in [&mut plain, &mut gated] {
+                filter.predict([0.0; 3]);
+                filter.correct(acc, mag);
+            }
+        }
+        assert!((gated.expected_mag_norm().unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(gated.mag_norm_weight(), Some(1.0));
+
+        // 一時的な磁場が加わり，向きと大きさが変わる
+        let mag_disturbed = quat::add_vec(mag, [2.0, 0.0, 0.0]);
+        for _ in 0..50 {
+            for filter in [&mut plain, &mut gated] {
+                filter.predict([0.0; 3]);
+                filter.correct(acc, mag_disturbed);
+            }
+        }
+        let yaw = |f: &AttitudeFilter| 2.0 * f.q.1[2].atan2(f.q.0);
+        assert!(yaw(&plain).abs() > 0.3);
+        assert!(yaw(&gated).abs() < 0.2 * yaw(&plain).abs());
+        assert!(gated.mag_norm_weight().unwrap() < 0.2);
+    }
+
+    #[test]
+    fn estimates_accel_bias() {
+        // 重力方向が機体のすべての軸に現れるように，傾いた軸周りに回転し続ける
+        let bias = [0.2, -0.1, 0.15];
+        let axis = quat::normalize_vec([1.0, 0.5, 0.3]);
+        let omega = quat::scale_vec(0.3, axis);
+        let mut q_true = (1.0, [0.0; 3]);
+        let mut plain = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        let mut estimating = plain.clone().with_accel_bias_estimation(5.0);
+        let tilt_error = |f: &AttitudeFilter, q: Quaternion<f64>| {
+            let up = quat::frame_rotation(q, [0.0, 0.0, 1.0]);
+            quat::norm_vec(quat::cross_vec(up, quat::frame_rotation(f.q, [0.0, 0.0, 1.0])))
+        };
+        for _ in 0..20000 {
+            q_true = quat::mul(q_true, quat::from_axis_angle(axis, 0.3 * DT));
+            let (acc, mag) = measurements(q_true);
+            let acc = quat::add_vec(acc, bias);
+            for filter in [&mut plain, &mut estimating] {
+                filter.predict(omega);
+                filter.correct(acc, mag);
+            }
+        }
+        let b = estimating.accel_bias().unwrap();
+        assert!(quat::norm_vec(quat::sub_vec(b, bias)) < 0.02, "{:?}", b);
+        assert!(tilt_error(&estimating, q_true) < 0.2 * tilt_error(&plain, q_true));
+        assert_eq!(plain.accel_bias(), None);
+    }
+
+    #[test]
+    fn self_test_reports_problems() {
+        let filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        let report = filter.self_test();
+        assert!(report.passed(), "{:?}", report);
+        assert!(report.convergence_error < SELF_TEST_RATIO * 0.02);
+        let (acc, mag) = measurements((1.0, [0.0; 3]));
+        assert!(filter.self_test_with_sample(&Sample { gyr: [0.0; 3], acc, mag }).passed());
+
+        // 閾値の大小が逆で，姿勢推定値が正規化されていない
+        let mut bad = AttitudeFilter::new(1.0, 0.2, 0.08, 0.04);
+        bad.q = (2.0, [0.0; 3]);
+        let report = bad.self_test();
+        assert!(report.problems.contains(&"外乱判定の閾値は0 < thr_weak < thr_strongにしてください"));
+        assert!(report.problems.contains(&"姿勢推定値が正規化されていません"));
+        assert_eq!(report.quaternion_norm, 2.0);
+
+        // 動いている機体の計測値
+        let report = filter.self_test_with_sample(&Sample { gyr: [0.5, 0.0, 0.0], acc: quat::scale_vec(1.5, acc), mag });
+        assert_eq!(report.problems.len(), 2);
+    }
+
+    #[test]
+    fn update_matches_predict_and_correct() {
+        let (acc, mag) = measurements((1.0, [0.0; 3]));
+        let mut a = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        let mut b = a.clone();
+        for _ in 0..100 {
+            b.predict([0.01, 0.0, 0.0]);
+            b.correct(acc, mag);
+            assert_eq!(a.update([0.01, 0.0, 0.0], acc, mag), Ok(b.q));
+        }
+        assert!(a.update([0.0; 3], [f64::NAN; 3], mag).is_err());
+        assert!(a.update_imu([0.0; 3], acc).is_ok());
+        assert_eq!(a.update_gyro([0.0; 3]), a.q);
+    }
+
+    // ---------- プロパティテスト ---------- //
+
+    use proptest::prelude::*;
+
+    fn vec3(range: std::ops::Range<f64>) -> impl Strategy<Value = Vector3<f64>> {
+        prop::array::uniform3(range)
+    }
+
+    /// 一様でなくても良いので，任意の単位四元数
+    fn versor() -> impl Strategy<Value = Quaternion<f64>> {
+        prop::array::uniform4(-1.0..1.0_f64)
+            .prop_filter("ノルムが小さすぎる", |v| v.iter().map(|x| x * x).sum::<f64>() > 0.01)
+            .prop_map(|v| quat::normalize((v[0], [v[1], v[2], v[3]])))
+    }
+
+    proptest! {
+        /// どのような入力に対しても姿勢推定値は単位四元数のまま
+        #[test]
+        fn output_is_unit_norm(
+            samples in prop::collection::vec((vec3(-10.0..10.0), vec3(-30.0..30.0), vec3(-2.0..2.0)), 1..100)
+        ) {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+            for (gyr, acc, mag) in samples {
+                filter.predict(gyr);
+                filter.correct(acc, mag);
+                prop_assert!((quat::norm(filter.q) - 1.0).abs() < 1e-9);
+            }
+        }
+
+        /// 機体を回転させて計測値の座標系をrだけ回しても，推定値はrだけ回るだけで変わらない
+        #[test]
+        fn invariant_to_common_rotation(
+            q_true in versor(),
+            r in versor(),
+            samples in prop::collection::vec((vec3(-1.0..1.0), vec3(-2.0..2.0)), 1..50)
+        ) {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+            let mut filter_r = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+            filter_r.q = r;
+
+            let (acc, mag) = measurements(q_true);
+            for (gyr, a_dr) in samples {
+                let acc = quat::add_vec(acc, a_dr);
+                filter.predict(gyr);
+                filter.correct(acc, mag);
+                filter_r.predict(quat::frame_rotation(r, gyr));
+                filter_r.correct(quat::frame_rotation(r, acc), quat::frame_rotation(r, mag));
+            }
+            prop_assert!(angle_between(filter_r.q, quat::mul(filter.q, r)) < 1e-6);
+        }
+
+        /// 強い外乱と判定した後，誤差関数が解除閾値との間にある間は判定が変わらない
+        #[test]
+        fn strong_flag_does_not_flicker(es in prop::collection::vec(0.0641..0.08, 1..50)) {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+            filter.detect_disturbance(acc_with_error(0.1));
+            for e in es {
+                filter.detect_disturbance(acc_with_error(e));
+                prop_assert!(filter.flag_acc_strong && !filter.flag_acc_weak);
+            }
+        }
+
+        /// 弱い外乱と判定した後，誤差関数が解除閾値と強い外乱の閾値の間にある間は判定が変わらない
+        #[test]
+        fn weak_flag_does_not_flicker(es in prop::collection::vec(0.0321..0.08, 1..50)) {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+            filter.detect_disturbance(acc_with_error(0.05));
+            for e in es {
+                filter.detect_disturbance(acc_with_error(e));
+                prop_assert!(filter.flag_acc_weak && !filter.flag_acc_strong);
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! ahrsクレートのAhrsトレイトの実装（ahrsフィーチャ）
+//!
+//! AttitudeFilterを包んでAhrs<f64>を実装するので，ahrsクレートのMadgwickやMahonyを使っているコードで
+//! そのまま置き換えられる．姿勢推定値はnalgebraのUnitQuaternionとして保持する（トレイトが参照を返すため）．
+//!
+//! ```ignore
+//! use ::ahrs::Ahrs;
+//! let mut ahrs = AhrsFilter::new(AttitudeFilter::new(1.0, 0.2, 0.04, 0.08));
+//! let q = ahrs.update(&gyr, &acc, &mag)?;
+//! ```
+
+use ::ahrs::{Ahrs, AhrsError};
+use nalgebra::{UnitQuaternion, Vector3};
+
+use super::ahrs::{AttitudeFilter, Health};
+use super::interop;
+
+/// Ahrs<f64>を実装したAttitudeFilterのラッパー
+/// 
+/// 加速度や地磁気の大きさが0の場合はMadgwickやMahonyと同じErrを返し，フィルタを更新しない．
+/// それ以外の不正な計測値（NaNなど）や発散はフィルタ側で読み飛ばし・初期化するのでOkを返す（health()で確認できる）．
+#[derive(Debug, Clone)]
+pub struct AhrsFilter {
+    filter: AttitudeFilter,
+    q: UnitQuaternion<f64>,
+}
+
+impl AhrsFilter {
+    pub fn new(filter: AttitudeFilter) -> Self {
+        let q = interop::to_unit_quaternion(filter.q);
+        Self { filter, q }
+    }
+
+    /// 包んでいるフィルタ
+    pub fn filter(&self) -> &AttitudeFilter {
+        &self.filter
+    }
+
+    /// 包んでいるフィルタ（パラメータや状態を変えた場合は次の更新から姿勢推定値に反映される）
+    pub fn filter_mut(&mut self) -> &mut AttitudeFilter {
+        &mut self.filter
+    }
+
+    /// 直近の更新の状態（AttitudeFilter::health()）
+    pub fn health(&self) -> Health {
+        self.filter.health()
+    }
+
+    fn sync(&mut self) -> &UnitQuaternion<f64> {
+        self.q = interop::to_unit_quaternion(self.filter.q);
+        &self.q
+    }
+}
+
+impl From<AttitudeFilter> for AhrsFilter {
+    fn from(filter: AttitudeFilter) -> Self {
+        Self::new(filter)
+    }
+}
+
+impl Ahrs<f64> for AhrsFilter {
+    fn update(
+        &mut self, gyroscope: &Vector3<f64>, accelerometer: &Vector3<f64>, magnetometer: &Vector3<f64>
+    ) -> Result<&UnitQuaternion<f64>, AhrsError> {
+        if accelerometer.norm() == 0.0 {
+            return Err(AhrsError::AccelerometerNormZero);
+        }
+        if magnetometer.norm() == 0.0 {
+            return Err(AhrsError::MagnetometerNormZero);
+        }
+        // 結果はhealth()で確認できるので，ここではエラーにしない
+        let _ = self.filter.update((*gyroscope).into(), (*accelerometer).into(), (*magnetometer).into());
+        Ok(self.sync())
+    }
+
+    fn update_imu(&mut self, gyroscope: &Vector3<f64>, accelerometer: &Vector3<f64>) -> Result<&UnitQuaternion<f64>, AhrsError> {
+        if accelerometer.norm() == 0.0 {
+            return Err(AhrsError::AccelerometerNormZero);
+        }
+        let _ = self.filter.update_imu((*gyroscope).into(), (*accelerometer).into());
+        Ok(self.sync())
+    }
+
+    fn update_gyro(&mut self, gyroscope: &Vector3<f64>) -> &UnitQuaternion<f64> {
+        self.filter.update_gyro((*gyroscope).into());
+        self.sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{ahrs, quat};
+
+    /// トレイト経由で使うコード（MadgwickやMahonyと同じ）
+    fn run<A: Ahrs<f64>>(ahrs: &mut A, gyr: Vector3<f64>, acc: Vector3<f64>, mag: Vector3<f64>, n: usize) -> UnitQuaternion<f64> {
+        for _ in 0..n {
+            ahrs.update(&gyr, &acc, &mag).unwrap();
+        }
+        *ahrs.update_gyro(&Vector3::zeros())
+    }
+
+    #[test]
+    fn implements_ahrs_trait() {
+        let q_true = quat::from_axis_angle(quat::normalize_vec([0.2, -0.1, 1.0]), 0.8);
+        let acc = Vector3::from( quat::frame_rotation(q_true, ahrs::ACC_R) );
+        let mag = Vector3::from( quat::frame_rotation(q_true, ahrs::MAG_R) );
+
+        // E2でも外乱とみなさない程度に初期姿勢をずらす
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        filter.q = quat::mul(q_true, quat::from_axis_angle([1.0, 0.0, 0.0], 0.03));
+        let mut wrapped = AhrsFilter::new(filter.clone());
+        let q = run(&mut wrapped, Vector3::zeros(), acc, mag, 3000);
+        assert!(q.angle_to(&interop::to_unit_quaternion(q_true)) < 1e-3);
+
+        // 直接呼んだ場合と同じ推定値
+        let mut direct = filter;
+        for _ in 0..3000 {
+            direct.update([0.0; 3], acc.into(), mag.into()).unwrap();
+        }
+        direct.update_gyro([0.0; 3]);
+        assert!(quat::norm(quat::sub(interop::from_unit_quaternion(&q), direct.q)) < 1e-12);
+
+        // 大きさが0の計測値はMadgwickやMahonyと同じErrにする
+        assert!(matches!(wrapped.update(&Vector3::zeros(), &Vector3::zeros(), &mag), Err(AhrsError::AccelerometerNormZero)));
+        assert!(matches!(wrapped.update(&Vector3::zeros(), &acc, &Vector3::zeros()), Err(AhrsError::MagnetometerNormZero)));
+        assert!(wrapped.update_imu(&Vector3::zeros(), &acc).is_ok());
+    }
+}
@@ -0,0 +1,347 @@
+//! 固定小数点版の姿勢推定フィルタ（FPU無しのマイコン向け）
+//!
+//! 計算内容はahrs.rsと同じ．値の範囲を抑えるために，加速度は内部で重力加速度を単位として扱う．
+//! Fixの範囲（±128）を超える入力は飽和させ，ノルムは最大の成分で割ってから計算するので，途中の2乗で桁あふれしない．
+//! 地磁気は最初に単位ベクトルにするが，±128を超える生の値（ADCのカウント値など）はto_fix_dirで正規化してから渡す．
+
+use fixed::types::I8F24;
+
+use super::DT;
+use super::ahrs::{Detector, STANDARD_GRAVITY, MAG_R};
+
+/// Q7.24形式（範囲：±128，分解能：約6e-8）
+pub type Fix = I8F24;
+
+pub type Vector3 = [Fix; 3];
+pub type Quaternion = (Fix, Vector3);
+
+const ZERO: Fix = Fix::ZERO;
+const ONE: Fix = Fix::ONE;
+const HALF: Fix = Fix::lit("0.5");
+
+/// 恒等四元数
+const IDENTITY: Quaternion = (ONE, [ZERO; 3]);
+
+/// 外乱検知判定のヒステリシス
+const HYSTERESIS: Fix = Fix::lit("0.2");
+
+/// 基準座標系上における加速度計測値（重力加速度単位）
+const ACC_R_UNIT: Vector3 = [ZERO, ZERO, ONE];
+
+pub struct AttitudeFilter {
+    pub q: Quaternion,        // 姿勢推定値
+    gyr_correct: Vector3,     // 補正角速度（角速度バイアスの推定値を含む）
+    coef_gyr_c: Fix,          // 補正角速度を計算するときのパラメータ
+    coef_integ: Fix,          // 補正角速度の積分係数
+    pub gyr_integ: Vector3,   // 補正角速度の積分項
+    thr_weak: Fix,            // 弱い外乱判定の閾値
+    thr_strong: Fix,          // 強い外乱判定の閾値
+    flag_acc_weak: bool,    // ヒステリシス処理に使う変数
+    flag_acc_strong: bool,  // ヒステリシス処理に使う変数
+    detector: Detector,       // 加速度外乱の判定に使う誤差関数
+    dt: Fix,                  // サンプリング周期
+    inv_gravity: Fix,         // 加速度を重力加速度単位に変換する係数
+    mag_r: Vector3,           // 基準座標系上における地磁気計測値
+}
+
+impl AttitudeFilter {
+    /// 引数はahrs::AttitudeFilter::newと同じ．
+    ///
+    /// 係数（2/alpha，beta）と閾値がFixの範囲（±128）に収まらない場合やNaNの場合はエラーを返す
+    /// （alphaは2/128 = 0.015625[s]より大きくする）．
+    pub fn new(alpha: f64, beta: f64, thr_weak: f64, thr_strong: f64) -> Result<Self, &'static str> {
+        let fix = |x: f64, message| Fix::checked_from_num(x).ok_or(message);
+        Ok( Self {
+            q: IDENTITY,
+            gyr_correct: [ZERO; 3],
+            coef_gyr_c: fix(2.0 / alpha, "2/alphaが固定小数点数の範囲を超えています")?,
+            coef_integ: fix(beta, "betaが固定小数点数の範囲を超えています")?,
+            gyr_integ: [ZERO; 3],
+            thr_weak: fix(thr_weak, "thr_weakが固定小数点数の範囲を超えています")?,
+            thr_strong: fix(thr_strong, "thr_strongが固定小数点数の範囲を超えています")?,
+            flag_acc_weak: false,
+            flag_acc_strong: false,
+            detector: Detector::default(),
+            dt: Fix::from_num(DT),
+            inv_gravity: Fix::from_num(1.0 / STANDARD_GRAVITY),
+            mag_r: to_fix_vec(MAG_R),
+        } )
+    }
+
+    /// 加速度外乱の判定に使う誤差関数を設定する（ahrs::AttitudeFilter::with_detectorと同じ）．
+    pub fn with_detector(mut self, detector: Detector) -> Self {
+        self.detector = detector;
+        self
+    }
+
+    /// 予測ステップ
+    ///
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    pub fn predict(&mut self, gyr: Vector3) {
+        let omega = add_vec(gyr, self.gyr_correct);
+
+        // 積分（q[n+1] = q[n] + Δt/2 *q[n]*ω[n]）
+        let tmp0 = scale_vec(self.q.0, omega);
+        let dot = dot_vec(self.q.1, omega);
+        let cross = cross_vec(self.q.1, omega);
+        let tmp1 = (-dot, add_vec(tmp0, cross));
+        let coef = HALF * self.dt;
+        self.q = (
+            self.q.0 + coef * tmp1.0,
+            add_vec(self.q.1, scale_vec(coef, tmp1.1))
+        );
+        // 正規化（ゼロになった場合は初期値に戻す）
+        self.q = normalize(self.q).unwrap_or(IDENTITY);
+    }
+
+    /// 補正ステップ（外乱検知も行う）
+    ///
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    /// * mag: 機体上のセンサで計測した地磁気（方向だけ使う．各成分が±128を超える場合はto_fix_dirで変換する）
+    pub fn correct(&mut self, acc: Vector3, mag: Vector3) {
+        let mut acc = scale_vec(self.inv_gravity, acc);
+        let mag = normalize_vec(mag).unwrap_or([ZERO; 3]);
+        let mut coef = self.coef_gyr_c;
+
+        // 加速度外乱検知
+        let acc_q = frame_rotation(self.q, ACC_R_UNIT);
+        let e = match self.detector {
+            #[cfg(feature = "detector-e1")]
+            Detector::E1 => (norm_vec(acc) - ONE).abs(),
+            #[cfg(feature = "detector-e2")]
+            Detector::E2 => norm_vec( sub_vec(acc, acc_q) ),
+            #[cfg(all(feature = "detector-e1", feature = "detector-e2"))]
+            Detector::Both => (norm_vec(acc) - ONE).abs().max( norm_vec( sub_vec(acc, acc_q) ) ),
+        };
+        if e > self.thr_strong {
+            // 強い外乱なので，加速度による補正をストップする．
+            self.flag_acc_strong = true;
+            acc = acc_q;
+        } else if e > self.thr_weak {
+            // ヒステリシス処理：強い外乱 -> 弱い外乱
+            if self.flag_acc_strong && e > (self.thr_strong - self.thr_strong * HYSTERESIS) {
+                acc = acc_q;
+            } else {
+                // 弱い外乱なので，補正角速度の重みを変更．
+                self.flag_acc_strong = false;
+                self.flag_acc_weak = true;
+                coef *= HALF;
+            }
+        } else {
+            // ヒステリシス処理：弱い外乱 -> 外乱無し
+            if self.flag_acc_weak && e > (self.thr_weak - self.thr_weak * HYSTERESIS) {
+                coef *= HALF;
+            } else {
+                self.flag_acc_weak = false;
+                self.flag_acc_strong = false;
+            }
+        }
+
+        // accとmagから姿勢q_gmを計算
+        let q_g = rotate_a_to_b(acc, ACC_R_UNIT);
+        let mut mag_b2r = vector_rotation(q_g, mag);
+        mag_b2r[2] = ZERO;
+        let q_e = rotate_a_to_b(mag_b2r, self.mag_r);
+        let q_gm = mul(q_e, q_g);
+
+        // qからq_gmに到達するための角速度を計算
+        let term1 = scale_vec(self.q.0, q_gm.1);
+        let term2 = scale_vec(q_gm.0, self.q.1);
+        let term3 = cross_vec(q_gm.1, self.q.1);
+        self.gyr_correct = scale_vec(coef, add_vec(sub_vec(term1, term2), term3));
+        // 符号をqに合わせる
+        if (self.q.0 * q_gm.0 + dot_vec(self.q.1, q_gm.1)).is_negative() {
+            self.gyr_correct = scale_vec(-ONE, self.gyr_correct);
+        }
+
+        // 積分項を更新
+        self.gyr_integ = add_vec(self.gyr_integ, scale_vec(self.dt, self.gyr_correct));
+
+        // 積分項の値を補正角速度に反映
+        self.gyr_correct = add_vec(self.gyr_correct, scale_vec(self.coef_integ, self.gyr_integ));
+    }
+
+    /// 姿勢推定値を浮動小数点数で返す．
+    pub fn q_f64(&self) -> (f64, [f64; 3]) {
+        (self.q.0.to_num(), to_f64_vec(self.q.1))
+    }
+
+    /// 角速度バイアスの推定値[rad/s]を浮動小数点数で返す（ahrs::AttitudeFilter::gyro_biasと同じ）．
+    pub fn gyro_bias(&self) -> [f64; 3] {
+        to_f64_vec( scale_vec(-self.coef_integ, self.gyr_integ) )
+    }
+}
+
+/// 浮動小数点数のベクトルを固定小数点数に変換する（範囲外の値は±128に飽和させ，NaNは0にする）．
+pub fn to_fix_vec(v: [f64; 3]) -> Vector3 {
+    v.map(|x| if x.is_nan() { ZERO } else { Fix::saturating_from_num(x) })
+}
+
+/// 浮動小数点数のベクトルを単位ベクトルにしてから固定小数点数に変換する（地磁気の生の値など）．
+///
+/// ゼロベクトルや有限でない値の場合はゼロベクトルを返す．
+pub fn to_fix_dir(v: [f64; 3]) -> Vector3 {
+    let norm = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if norm > 0.0 && norm.is_finite() {
+        to_fix_vec(v.map(|x| x / norm))
+    } else {
+        [ZERO; 3]
+    }
+}
+
+/// 固定小数点数のベクトルを浮動小数点数に変換する．
+pub fn to_f64_vec(v: Vector3) -> [f64; 3] {
+    [v[0].to_num(), v[1].to_num(), v[2].to_num()]
+}
+
+// ---------- 四元数演算（quaternion-coreの必要な部分だけ） ---------- //
+
+fn add_vec(a: Vector3, b: Vector3) -> Vector3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub_vec(a: Vector3, b: Vector3) -> Vector3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale_vec(s: Fix, v: Vector3) -> Vector3 {
+    [s * v[0], s * v[1], s * v[2]]
+}
+
+fn dot_vec(a: Vector3, b: Vector3) -> Fix {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross_vec(a: Vector3, b: Vector3) -> Vector3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// 成分の絶対値の最大値（ノルムの計算で2乗が桁あふれしないように，これで割ってから計算する）
+fn max_abs(v: Vector3) -> Fix {
+    v.iter().map(|x| x.saturating_abs()).fold(ZERO, Fix::max)
+}
+
+fn norm_vec(v: Vector3) -> Fix {
+    let m = max_abs(v);
+    if m == ZERO {
+        return ZERO;
+    }
+    let s = scale_div(v, m);
+    m.saturating_mul( dot_vec(s, s).sqrt() )
+}
+
+/// 各成分をsで割る（|v[i]| <= sなので桁あふれしない）．
+fn scale_div(v: Vector3, s: Fix) -> Vector3 {
+    [v[0] / s, v[1] / s, v[2] / s]
+}
+
+/// ゼロベクトルの場合はNoneを返す．
+fn normalize_vec(v: Vector3) -> Option<Vector3> {
+    let m = max_abs(v);
+    if m == ZERO {
+        return None;
+    }
+    let s = scale_div(v, m);
+    Some( scale_div(s, dot_vec(s, s).sqrt()) )
+}
+
+/// ゼロの場合はNoneを返す．
+fn normalize(q: Quaternion) -> Option<Quaternion> {
+    let norm = (q.0 * q.0 + dot_vec(q.1, q.1)).sqrt();
+    if norm == ZERO {
+        None
+    } else {
+        Some( (q.0 / norm, scale_div(q.1, norm)) )
+    }
+}
+
+fn mul(a: Quaternion, b: Quaternion) -> Quaternion {
+    let v = add_vec(add_vec(scale_vec(a.0, b.1), scale_vec(b.0, a.1)), cross_vec(a.1, b.1));
+    (a.0 * b.0 - dot_vec(a.1, b.1), v)
+}
+
+/// `q v q*`
+fn vector_rotation(q: Quaternion, v: Vector3) -> Vector3 {
+    let tmp = add_vec(scale_vec(q.0, v), cross_vec(q.1, v));
+    add_vec(scale_vec(Fix::from_num(2), cross_vec(q.1, tmp)), v)
+}
+
+/// `q* v q`
+fn frame_rotation(q: Quaternion, v: Vector3) -> Vector3 {
+    let tmp = add_vec(scale_vec(q.0, v), cross_vec(v, q.1));
+    add_vec(scale_vec(Fix::from_num(2), cross_vec(tmp, q.1)), v)
+}
+
+/// ベクトルaをbに回転させる四元数を計算する．
+///
+/// 固定小数点数では桁落ちしやすいので，単位ベクトル同士の中間の回転として計算する．
+/// ゼロベクトルや逆向きのベクトルを入力した場合は恒等四元数を返す．
+fn rotate_a_to_b(a: Vector3, b: Vector3) -> Quaternion {
+    let (a, b) = match (normalize_vec(a), normalize_vec(b)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return IDENTITY,
+    };
+    let q = (ONE + dot_vec(a, b), cross_vec(a, b));
+    if q.0 <= Fix::lit("0.000001") {
+        IDENTITY
+    } else {
+        normalize(q).unwrap_or(IDENTITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 大きな計測値（µT単位の地磁気，強い衝撃）や範囲外の値でも桁あふれせず，四元数の大きさが保たれる．
+    #[test]
+    fn large_inputs_do_not_overflow() {
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.1, 0.5).unwrap();
+        let inputs = [
+            ([0.0, 0.0, 9.8], [0.0, 20.0, -45.0]),
+            ([120.0, -120.0, 120.0], [100.0, -100.0, 100.0]),
+            ([1e6, 0.0, 0.0], [5000.0, 0.0, 0.0]),
+            ([0.0; 3], [0.0; 3]),
+        ];
+        for (acc, mag) in inputs {
+            filter.predict(to_fix_vec([0.1, -0.2, 0.3]));
+            filter.correct(to_fix_vec(acc), to_fix_vec(mag));
+            let q = filter.q_f64();
+            let norm = (q.0 * q.0 + q.1.iter().map(|x| x * x).sum::<f64>()).sqrt();
+            assert!((norm - 1.0).abs() < 1e-4, "{:?}", q);
+        }
+        assert_eq!(to_fix_vec([1e9, -1e9, f64::NAN]), [Fix::MAX, Fix::MIN, ZERO]);
+        assert_eq!(to_fix_dir([0.0, 3000.0, -4000.0]), to_fix_vec([0.0, 0.6, -0.8]));
+    }
+
+    /// 係数と閾値がFixの範囲を超える場合はパニックせずにエラーを返す．
+    #[test]
+    fn rejects_parameters_out_of_range() {
+        // 2/alphaの上限は128未満
+        assert!(AttitudeFilter::new(2.0 / 127.0, 0.2, 0.1, 0.5).is_ok());
+        assert!(AttitudeFilter::new(2.0 / 128.0, 0.2, 0.1, 0.5).is_err());
+        assert!(AttitudeFilter::new(0.0, 0.2, 0.1, 0.5).is_err());
+        assert!(AttitudeFilter::new(1.0, 128.0, 0.1, 0.5).is_err());
+        assert!(AttitudeFilter::new(1.0, 0.2, f64::NAN, 0.5).is_err());
+        assert!(AttitudeFilter::new(1.0, 0.2, 0.1, -200.0).is_err());
+    }
+
+    /// 地磁気の単位（大きさ）によらず同じ姿勢になる．
+    #[test]
+    fn mag_scale_invariant() {
+        let run = |mag: [f64; 3]| {
+            let mut filter = AttitudeFilter::new(1.0, 0.2, 0.1, 0.5).unwrap();
+            for _ in 0..500 {
+                filter.predict([ZERO; 3]);
+                filter.correct(to_fix_vec([1.0, 0.0, 9.7]), to_fix_vec(mag));
+            }
+            filter.q_f64()
+        };
+        let (a, b) = (run([0.3, 0.9, -0.2]), run([15.0, 45.0, -10.0]));
+        assert!((a.0 - b.0).abs() < 1e-4 && (0..3).all(|i| (a.1[i] - b.1[i]).abs() < 1e-4), "{:?} {:?}", a, b);
+    }
+}
@@ -0,0 +1,126 @@
+//! 時刻の異なる計測値の整列
+//!
+//! 角速度，加速度，地磁気が別々の時刻に届く場合（スマートフォンのセンサなど）に，
+//! 加速度と地磁気を前後の計測値から角速度の時刻に線形補間して1つのサンプルにまとめる．
+//! 最後に届いた値をそのまま組み合わせると，時刻のずれた計測値で補正することになるため．
+//!
+//! 角速度の時刻より後の加速度と地磁気が届くまではサンプルを返さないので，遅い方のセンサの周期だけ遅れる．
+
+use std::collections::VecDeque;
+
+use super::quat::Vector3;
+use super::source::ImuSample;
+
+/// 計測値の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Gyr,
+    Acc,
+    Mag,
+}
+
+/// 1つのセンサの時刻付きの計測値
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub time: f64,
+    pub channel: Channel,
+    pub value: Vector3<f64>,
+}
+
+/// 加速度と地磁気を角速度の時刻に補間する．
+///
+/// 各センサの計測値はそれぞれ時刻順に渡す（時刻が戻った計測値は捨てる）．
+#[derive(Debug, Clone, Default)]
+pub struct Aligner {
+    gyr: VecDeque<(f64, Vector3<f64>)>,         // まだ返していない角速度
+    slow: [VecDeque<(f64, Vector3<f64>)>; 2],   // 補間に使う加速度，地磁気
+    n_dropped: u64,
+}
+
+impl Aligner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 計測値を1つ加える．
+    pub fn push(&mut self, m: Measurement) {
+        let queue = match m.channel {
+            Channel::Gyr => &mut self.gyr,
+            Channel::Acc => &mut self.slow[0],
+            Channel::Mag => &mut self.slow[1],
+        };
+        match queue.back() {
+            Some(&(t, _)) if m.time <= t || m.time.is_nan() => self.n_dropped += 1,
+            _ => queue.push_back( (m.time, m.value) ),
+        }
+    }
+
+    /// 補間できるようになった角速度の時刻のサンプルを古い順に返す（無ければNone）．
+    ///
+    /// 最初の加速度・地磁気より前の角速度は補間できないので捨てる．
+    pub fn pop(&mut self) -> Option<ImuSample> {
+        loop {
+            let &(time, gyr) = self.gyr.front()?;
+            if self.slow.iter().any(|q| q.back().is_none_or(|&(t, _)| t < time)) {
+                return None;
+            }
+            self.gyr.pop_front();
+            match self.slow.each_mut().map(|q| interpolate(q, time)) {
+                [Some(acc), Some(mag)] => return Some( ImuSample { time, gyr, acc, mag } ),
+                _ => self.n_dropped += 1,
+            }
+        }
+    }
+
+    /// 時刻が戻っていたか，補間できなかったので捨てた計測値の数
+    pub fn n_dropped(&self) -> u64 {
+        self.n_dropped
+    }
+}
+
+/// 時刻tの値を前後の計測値から線形補間する（t以前の計測値が無ければNone）．
+///
+/// t以降の計測値が1つ以上あるものとし，補間に使わなくなった古い計測値は捨てる．
+fn interpolate(queue: &mut VecDeque<(f64, Vector3<f64>)>, t: f64) -> Option<Vector3<f64>> {
+    // 次の角速度の時刻はtより後なので，t以前の計測値は最後の1つだけ残せばよい
+    while queue.len() > 1 && queue[1].0 <= t {
+        queue.pop_front();
+    }
+    let (t0, v0) = queue[0];
+    if t0 > t {
+        return None;
+    }
+    if t0 == t {
+        return Some(v0);
+    }
+    let (t1, v1) = queue[1];
+    let r = (t - t0) / (t1 - t0);
+    Some( [0, 1, 2].map(|i| v0[i] + r * (v1[i] - v0[i])) )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_onto_gyro_time() {
+        let m = |time, channel, v| Measurement { time, channel, value: [v; 3] };
+        let mut aligner = Aligner::new();
+        // 角速度の周期の2倍で加速度，4倍で地磁気を計測する（最初の角速度は加速度より前なので捨てる）
+        for t in 0..=5 {
+            aligner.push(m(t as f64, Channel::Gyr, t as f64));
+        }
+        aligner.push(m(1.0, Channel::Acc, 10.0));
+        aligner.push(m(3.0, Channel::Acc, 30.0));
+        assert_eq!(aligner.pop(), None);  // 地磁気が届くまでは待つ
+        aligner.push(m(1.0, Channel::Mag, 100.0));
+        aligner.push(m(5.0, Channel::Mag, 500.0));
+
+        let aligned: Vec<_> = std::iter::from_fn(|| aligner.pop()).map(|s| (s.time, s.gyr[0], s.acc[0], s.mag[0])).collect();
+        assert_eq!(aligned, [(1.0, 1.0, 10.0, 100.0), (2.0, 2.0, 20.0, 200.0), (3.0, 3.0, 30.0, 300.0)]);
+        assert_eq!(aligner.n_dropped(), 1);  // 加速度が4以降に届くまでは残りを返さない
+
+        aligner.push(m(2.0, Channel::Acc, 0.0));  // 時刻が戻った計測値
+        assert_eq!(aligner.n_dropped(), 2);
+    }
+}
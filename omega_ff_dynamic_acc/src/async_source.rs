@@ -0,0 +1,285 @@
+//! 非同期ランタイム（tokio）向けの取得元（asyncフィーチャ）
+//!
+//! 受信はAsyncRecvとして抽象化し，パケットの解釈はsource::Decoderで同期の取得元と共通にする．
+//! tokioのUDP（UdpSocket），TCP（TcpStream），WebSocket（tokio-tungstenite）にはAsyncRecvを実装してあり，
+//! AsyncSource::udp，accept，connect，websocketで開ける．
+//! 他のランタイムのソケットも，包んだ型にAsyncRecvを実装すれば同じように使える．
+//!
+//! ```no_run
+//! # use omega_ff_dynamic_acc::async_source::{AsyncSensorSource, AsyncSource};
+//! # use omega_ff_dynamic_acc::source::PacketFormat;
+//! # async fn run() -> std::io::Result<()> {
+//! let mut source = AsyncSource::udp("0.0.0.0:5555", PacketFormat::Csv).await?;
+//! while let Some(sample) = source.next_sample().await {
+//!     println!("{:?}", sample.gyr);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::{self, Future};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use futures_util::Stream;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use super::source::{Decoder, ImuSample, PacketFormat};
+
+/// 非同期の取得元
+pub trait AsyncSensorSource {
+    /// 次のサンプルを返す（終わりならNone）．
+    fn next_sample(&mut self) -> impl Future<Output = Option<ImuSample>> + Send;
+}
+
+/// 非同期の受信
+pub trait AsyncRecv {
+    /// 受け取ったバイト列をbufに書いて長さを返す（終わりならOk(0)）．
+    ///
+    /// まだ届いていなければPendingを返し，届いたらcxのWakerで知らせる．
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>>;
+}
+
+/// 受信したバイト列の区切り方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    Datagram,   // 1回の受信が1つのパケット（UDP，WebSocket）
+    Stream,     // 区切りの無いバイト列（TCP）
+}
+
+/// AsyncRecvで受け取ったパケットを読む取得元
+///
+/// 受信のエラーか終わり（Ok(0)）でNoneを返す．
+pub struct AsyncSource<R> {
+    inner: R,
+    framing: Framing,
+    decoder: Decoder,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRecv> AsyncSource<R> {
+    /// 1回の受信が1つのパケット（UDPのデータグラム，WebSocketのメッセージ）の場合
+    pub fn datagram(inner: R, format: PacketFormat) -> Self {
+        Self { inner, framing: Framing::Datagram, decoder: Decoder::new(format), buf: vec![0; 65536] }
+    }
+
+    /// 区切りの無いバイト列（TCP）の場合（改行までを1行とする）
+    pub fn stream(inner: R, format: PacketFormat) -> Self {
+        Self { inner, framing: Framing::Stream, decoder: Decoder::new(format), buf: vec![0; 65536] }
+    }
+
+    /// 包んでいる受信
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    fn poll_sample(&mut self, cx: &mut Context<'_>) -> Poll<Option<ImuSample>> {
+        loop {
+            if let Some(sample) = self.decoder.pop() {
+                return Poll::Ready(Some(sample));
+            }
+            match self.inner.poll_recv(cx, &mut self.buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(0)) | Poll::Ready(Err(_)) => return Poll::Ready(None),
+                Poll::Ready(Ok(n)) => match self.framing {
+                    Framing::Datagram => self.decoder.push_packet(&self.buf[..n]),
+                    Framing::Stream => self.decoder.push_bytes(&self.buf[..n]),
+                },
+            }
+        }
+    }
+}
+
+impl<R: AsyncRecv + Send> AsyncSensorSource for AsyncSource<R> {
+    fn next_sample(&mut self) -> impl Future<Output = Option<ImuSample>> + Send {
+        future::poll_fn(|cx| self.poll_sample(cx))
+    }
+}
+
+impl AsyncSource<UdpSocket> {
+    /// addr（"0.0.0.0:5555"など）で受け取ったUDPのパケットから読む（1パケットに1行以上）．
+    pub async fn udp<A: ToSocketAddrs>(addr: A, format: PacketFormat) -> io::Result<Self> {
+        Ok( Self::datagram(UdpSocket::bind(addr).await?, format) )
+    }
+}
+
+impl AsyncSource<TcpStream> {
+    /// addrで接続を待ち，最初に接続してきた相手から読む．
+    pub async fn accept<A: ToSocketAddrs>(addr: A, format: PacketFormat) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr).await?.accept().await?;
+        Ok( Self::stream(stream, format) )
+    }
+
+    /// addr（テレメトリのサーバなど）に接続して読む．
+    pub async fn connect<A: ToSocketAddrs>(addr: A, format: PacketFormat) -> io::Result<Self> {
+        Ok( Self::stream(TcpStream::connect(addr).await?, format) )
+    }
+}
+
+impl AsyncSource<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+    /// WebSocketのサーバ（"ws://192.168.0.10:8080/imu"など）に接続し，
+    /// 受け取ったメッセージ（テキストかバイナリ）を1つのパケットとして読む．
+    pub async fn websocket(url: &str, format: PacketFormat) -> io::Result<Self> {
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.map_err(io::Error::other)?;
+        Ok( Self::datagram(ws, format) )
+    }
+}
+
+/// 1回の受信が1つのデータグラム（空のデータグラムは読み飛ばす）
+impl AsyncRecv for UdpSocket {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut buf = ReadBuf::new(buf);
+            ready!( UdpSocket::poll_recv(self, cx, &mut buf) )?;
+            if !buf.filled().is_empty() {
+                return Poll::Ready(Ok(buf.filled().len()));
+            }
+        }
+    }
+}
+
+impl AsyncRecv for TcpStream {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!( Pin::new(self).poll_read(cx, &mut buf) )?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+/// 1回の受信が1つのメッセージ（Ping，Pongと空のメッセージは読み飛ばし，Closeで終わり）
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRecv for WebSocketStream<S> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let message = match ready!( Pin::new(&mut *self).poll_next(cx) ) {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+                None => return Poll::Ready(Ok(0)),
+            };
+            let data = match &message {
+                Message::Text(text) => text.as_bytes(),
+                Message::Binary(data) => &data[..],
+                Message::Close(_) => return Poll::Ready(Ok(0)),
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
+            };
+            if data.len() > buf.len() {
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, "メッセージが受信バッファより長い")));
+            }
+            if !data.is_empty() {
+                buf[..data.len()].copy_from_slice(data);
+                return Poll::Ready(Ok(data.len()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::pin;
+    use std::task::Waker;
+
+    /// 受信の前に1回Pendingを返す模擬の受信
+    struct Chunks {
+        chunks: Vec<&'static [u8]>,
+        ready: bool,
+    }
+
+    impl AsyncRecv for Chunks {
+        fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            if !self.ready {
+                self.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            self.ready = false;
+            let Some(chunk) = self.chunks.pop() else { return Poll::Ready(Ok(0)) };
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Poll::Ready(Ok(chunk.len()))
+        }
+    }
+
+    /// 完了するまでポーリングする．
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn reads_chunks_asynchronously() {
+        // TCPでは行の途中で区切られて届くことがある（popで取り出すので逆順に並べる）
+        let chunks = vec![&b"9\n"[..], b"0.02,1,2,3,4,5,6,7,8,", b"0.00,1,2,3,4,5,6,7,8,9\n"];
+        let mut source = AsyncSource::stream(Chunks { chunks, ready: false }, PacketFormat::Csv);
+        let times: Vec<f64> = std::iter::from_fn(|| block_on(source.next_sample())).map(|s| s.time).collect();
+        assert_eq!(times, [0.0, 0.02]);
+
+        let chunks = vec![&b"0.02,1,2,3,4,5,6,7,8,9\n0.04,1,2,3,4,5,6,7,8,9"[..], b"0.00,1,2,3,4,5,6,7,8,9"];
+        let mut source = AsyncSource::datagram(Chunks { chunks, ready: false }, PacketFormat::Csv);
+        let times: Vec<f64> = std::iter::from_fn(|| block_on(source.next_sample())).map(|s| s.time).collect();
+        assert_eq!(times, [0.0, 0.02, 0.04]);
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap()
+    }
+
+    #[test]
+    fn reads_tokio_udp_and_tcp() {
+        use tokio::io::AsyncWriteExt;
+
+        runtime().block_on(async {
+            let mut source = AsyncSource::udp("127.0.0.1:0", PacketFormat::Csv).await.unwrap();
+            let addr = source.get_ref().local_addr().unwrap();
+            let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            sender.send_to(b"0.00,1,2,3,4,5,6,7,8,9\n0.02,1,2,3,4,5,6,7,8,9", addr).await.unwrap();
+            assert_eq!(source.next_sample().await.map(|s| s.time), Some(0.0));
+            assert_eq!(source.next_sample().await.map(|s| s.time), Some(0.02));
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut source = AsyncSource::connect(listener.local_addr().unwrap(), PacketFormat::Csv).await.unwrap();
+            let (mut server, _) = listener.accept().await.unwrap();
+            server.write_all(b"0.00,1,2,3,4,5,6,7,8,9\n0.02,1,2,").await.unwrap();
+            server.write_all(b"3,4,5,6,7,8,9\n").await.unwrap();
+            drop(server);
+            let mut times = Vec::new();
+            while let Some(sample) = source.next_sample().await {
+                times.push(sample.time);
+            }
+            assert_eq!(times, [0.0, 0.02]);
+        });
+    }
+
+    #[test]
+    fn reads_websocket_messages() {
+        use futures_util::SinkExt;
+
+        runtime().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("ws://{}/", listener.local_addr().unwrap());
+            let server = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                ws.send(Message::text("0.00,1,2,3,4,5,6,7,8,9")).await.unwrap();
+                ws.send(Message::Ping(Default::default())).await.unwrap();
+                ws.send(Message::binary(&b"0.02,1,2,3,4,5,6,7,8,9"[..])).await.unwrap();
+                ws.close(None).await.unwrap();
+            });
+
+            let mut source = AsyncSource::websocket(&url, PacketFormat::Csv).await.unwrap();
+            let mut times = Vec::new();
+            while let Some(sample) = source.next_sample().await {
+                times.push(sample.time);
+            }
+            assert_eq!(times, [0.0, 0.02]);
+            server.await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,255 @@
+//! 互いに独立な多数のフィルタをまとめて更新する（モンテカルロシミュレーション向け）
+//!
+//! フィルタの状態は時間方向に再帰的なのでサンプル方向には並列化できないが，試行ごとのフィルタは互いに独立なので，
+//! 状態を成分ごとの配列（Structure of Arrays）で持ち，同じ時刻の1サンプルで全てのフィルタを1つのループで更新する．
+//! AttitudeFilterを試行の数だけ並べるのと比べて，使わない機能の分岐や状態を読まずに済み，ループを自動ベクトル化しやすい．
+//!
+//! 対応しているのはAttitudeFilter::new()の設定（閾値による切り替え，get_q_gm()，積分項の制限無し）と
+//! with_detector()だけで，各フィルタはそれと同じ推定値になる．それ以外の機能を使う場合はAttitudeFilterを使う．
+
+use super::DT;
+use super::ahrs::{self, Detector, DisturbanceDetector, Sample, ACC_R, HYSTERESIS, MAG_R, MIN_NORM};
+use super::quat::{self, Quaternion, Vector3};
+
+#[derive(Debug, Clone)]
+pub struct FilterBank {
+    q: [Vec<f64>; 4],            // 姿勢推定値（成分ごと）
+    gyr_correct: [Vec<f64>; 3],  // 補正角速度（成分ごと）
+    gyr_integ: [Vec<f64>; 3],    // 補正角速度の積分項（成分ごと）
+    flag_acc_weak: Vec<bool>,    // ヒステリシス処理に使う変数
+    flag_acc_strong: Vec<bool>,  // ヒステリシス処理に使う変数
+    coef_gyr_c: f64,   // 補正角速度を計算するときのパラメータ
+    coef_integ: f64,   // 補正角速度の積分係数
+    thr_weak: f64,     // 弱い外乱判定の閾値
+    thr_strong: f64,   // 強い外乱判定の閾値
+    detector: Detector,
+    pub n_steps: u64,  // 補正ステップの実行回数
+}
+
+impl FilterBank {
+    /// AttitudeFilter::new()と同じパラメータのフィルタをn個作る．
+    ///
+    /// * n: フィルタの数
+    pub fn new(n: usize, alpha: f64, beta: f64, thr_weak: f64, thr_strong: f64) -> Self {
+        Self {
+            q: [vec![1.0; n], vec![0.0; n], vec![0.0; n], vec![0.0; n]],
+            gyr_correct: [vec![0.0; n], vec![0.0; n], vec![0.0; n]],
+            gyr_integ: [vec![0.0; n], vec![0.0; n], vec![0.0; n]],
+            flag_acc_weak: vec![false; n],
+            flag_acc_strong: vec![false; n],
+            coef_gyr_c: 2.0 / alpha,
+            coef_integ: beta,
+            thr_weak,
+            thr_strong,
+            detector: Detector::default(),
+            n_steps: 0,
+        }
+    }
+
+    /// 加速度外乱の判定に使う誤差関数を設定する（全てのフィルタで共通）．
+    pub fn with_detector(mut self, detector: Detector) -> Self {
+        self.detector = detector;
+        self
+    }
+
+    /// フィルタの数
+    pub fn len(&self) -> usize {
+        self.flag_acc_weak.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// i番目のフィルタの姿勢推定値
+    pub fn quaternion(&self, i: usize) -> Quaternion<f64> {
+        (self.q[0][i], [self.q[1][i], self.q[2][i], self.q[3][i]])
+    }
+
+    /// i番目のフィルタの姿勢推定値を設定する（試行ごとに初期姿勢を変える場合）．
+    pub fn set_quaternion(&mut self, i: usize, q: Quaternion<f64>) {
+        let q = quat::normalize(q);
+        self.q[0][i] = q.0;
+        for k in 0..3 {
+            self.q[k + 1][i] = q.1[k];
+        }
+    }
+
+    /// i番目のフィルタの角速度バイアスの推定値[rad/s]
+    pub fn gyro_bias(&self, i: usize) -> Vector3<f64> {
+        [0, 1, 2].map(|k| -self.coef_integ * self.gyr_integ[k][i])
+    }
+
+    /// i番目のフィルタが強い加速度外乱を検知しているか
+    pub fn is_disturbed(&self, i: usize) -> bool {
+        self.flag_acc_strong[i]
+    }
+
+    /// i番目のフィルタが弱い加速度外乱を検知しているか（強い外乱を検知している間はfalse）
+    pub fn is_weakly_disturbed(&self, i: usize) -> bool {
+        self.flag_acc_weak[i] && !self.flag_acc_strong[i]
+    }
+
+    /// 全てのフィルタで予測ステップと補正ステップを行う（AttitudeFilterのpredict()とcorrect()に相当）．
+    ///
+    /// * samples: 各フィルタの計測値（i番目をi番目のフィルタに使う．長さはフィルタの数と同じ）
+    pub fn step(&mut self, samples: &[Sample]) {
+        assert_eq!(samples.len(), self.len(), "計測値の数がフィルタの数と違います");
+        // 判定式の分岐をループの外に出す
+        match self.detector {
+            #[cfg(feature = "detector-e1")]
+            Detector::E1 => self.step_with(&ahrs::E1, samples),
+            #[cfg(feature = "detector-e2")]
+            Detector::E2 => self.step_with(&ahrs::E2, samples),
+            #[cfg(all(feature = "detector-e1", feature = "detector-e2"))]
+            Detector::Both => self.step_with(&Detector::Both, samples),
+        }
+        self.n_steps += 1;
+    }
+
+    /// 分岐を条件による値の選択だけにして，ループを自動ベクトル化できるようにしている．
+    fn step_with<D: DisturbanceDetector>(&mut self, detector: &D, samples: &[Sample]) {
+        let n = samples.len();
+        let [q0, q1, q2, q3] = self.q.each_mut().map(|v| &mut v[..n]);
+        let [c0, c1, c2] = self.gyr_correct.each_mut().map(|v| &mut v[..n]);
+        let [i0, i1, i2] = self.gyr_integ.each_mut().map(|v| &mut v[..n]);
+        let (flag_weak, flag_strong) = (&mut self.flag_acc_weak[..n], &mut self.flag_acc_strong[..n]);
+        let (thr_weak, thr_strong, coef_gyr_c, coef_integ) = (self.thr_weak, self.thr_strong, self.coef_gyr_c, self.coef_integ);
+        let mag_r = quat::normalize_vec(MAG_R);
+        for (i, s) in samples.iter().enumerate() {
+            let q = (q0[i], [q1[i], q2[i], q3[i]]);
+            let gyr_correct = [c0[i], c1[i], c2[i]];
+            let gyr_integ = [i0[i], i1[i], i2[i]];
+            let (weak, strong) = (flag_weak[i], flag_strong[i]);
+
+            // 予測ステップ（入力が不正な場合は姿勢を更新しない）
+            let omega = quat::add_vec(s.gyr, gyr_correct);
+            let dot = quat::dot_vec(q.1, omega);
+            let tmp = quat::add_vec(quat::scale_vec(q.0, omega), quat::cross_vec(q.1, omega));
+            let q_p = quat::scale_add(0.5 * DT, (-dot, tmp), q);
+            let norm = quat::norm(q_p);
+            let q_p = quat::scale(norm.recip(), q_p);
+            let q = if is_finite(s.gyr) { q_p } else { q };
+            // 発散したら初期状態に戻してから補正する
+            let diverged = is_finite(s.gyr) & !(norm.is_finite() & (norm > MIN_NORM));
+            let (q, gyr_correct, gyr_integ, weak, strong) = if diverged { INITIAL } else { (q, gyr_correct, gyr_integ, weak, strong) };
+
+            // 補正ステップ（AttitudeFilter::correct()の閾値による切り替えと同じ）
+            let acc_q = quat::frame_rotation(q, ACC_R);
+            let e = detector.error(s.acc, acc_q);
+            let band = (e > thr_weak) & (e <= thr_strong);
+            let hold_strong = band & strong & (e > thr_strong - thr_strong * HYSTERESIS);
+            let hold_weak = (e <= thr_weak) & weak & (e > thr_weak - thr_weak * HYSTERESIS);
+            let reject = (e > thr_strong) | hold_strong;
+            let coef = if (band & !hold_strong) | hold_weak { 0.5 } else { 1.0 };
+            let acc = if reject { acc_q } else { s.acc };
+            let weak_c = (weak & (reject | hold_weak)) | (band & !hold_strong);
+            let strong_c = reject | (hold_weak & strong);
+
+            // qからq_gmに到達するための角速度
+            let q_gm = q_gm(acc, s.mag, mag_r);
+            let term1 = quat::scale_vec(q.0, q_gm.1);
+            let term2 = quat::scale_vec(q_gm.0, q.1);
+            let term3 = quat::cross_vec(q_gm.1, q.1);
+            let dq = quat::add_vec(quat::sub_vec(term1, term2), term3);
+            let sign = if quat::dot(q, q_gm).is_sign_negative() { -1.0 } else { 1.0 };
+            let gyr_p = quat::scale_vec(sign * coef_gyr_c * coef, dq);
+            let gyr_integ_c = quat::scale_add_vec(DT, gyr_p, gyr_integ);
+            let gyr_correct_c = quat::scale_add_vec(coef_integ, gyr_integ_c, gyr_p);
+
+            let valid = is_finite(s.acc) & is_finite(s.mag);
+            let diverged = valid & !(is_finite(gyr_correct_c) & is_finite(gyr_integ_c));
+            let (q, gyr_correct, gyr_integ, weak, strong) = if diverged {
+                INITIAL
+            } else if valid {
+                (q, gyr_correct_c, gyr_integ_c, weak_c, strong_c)
+            } else {
+                (q, gyr_correct, gyr_integ, weak, strong)
+            };
+
+            (q0[i], q1[i], q2[i], q3[i]) = (q.0, q.1[0], q.1[1], q.1[2]);
+            (c0[i], c1[i], c2[i]) = (gyr_correct[0], gyr_correct[1], gyr_correct[2]);
+            (i0[i], i1[i], i2[i]) = (gyr_integ[0], gyr_integ[1], gyr_integ[2]);
+            (flag_weak[i], flag_strong[i]) = (weak, strong);
+        }
+    }
+}
+
+/// get_q_gm_with_reference()と同じ姿勢（mag_rは正規化しておく）．
+///
+/// 重力の向きACC_Rは鉛直なので外積の成分を展開し，ベクトルが反平行な場合の分岐を値の選択にしている．
+/// 呼び出しがループに残るとベクトル化されないので，必ずインライン展開する．
+#[inline(always)]
+fn q_gm(acc: Vector3<f64>, mag: Vector3<f64>, mag_r: Vector3<f64>) -> Quaternion<f64> {
+    // 加速度を鉛直に重ねる回転
+    let a = quat::normalize_vec(acc);
+    let s = (2.0 * (1.0 + a[2])).sqrt();
+    let q_g = select(s < f64::EPSILON, (1.0, [0.0; 3]), (0.5 * s, [a[1] / s, -a[0] / s, 0.0]));
+    // 水平面に射影した地磁気をmag_rに重ねる回転
+    let m = quat::vector_rotation(q_g, mag);
+    let m = quat::normalize_vec([m[0], m[1], 0.0]);
+    let s = (2.0 * (1.0 + quat::dot_vec(m, mag_r))).sqrt();
+    let q_e = select(s < f64::EPSILON, (1.0, [0.0; 3]), (0.5 * s, quat::scale_vec(s.recip(), quat::cross_vec(m, mag_r))));
+    quat::mul(q_e, q_g)
+}
+
+fn select(c: bool, a: Quaternion<f64>, b: Quaternion<f64>) -> Quaternion<f64> {
+    if c { a } else { b }
+}
+
+/// 発散したフィルタを戻す初期状態（姿勢，補正角速度，積分項，外乱判定のフラグ）
+const INITIAL: (Quaternion<f64>, Vector3<f64>, Vector3<f64>, bool, bool) = ((1.0, [0.0; 3]), [0.0; 3], [0.0; 3], false, false);
+
+fn is_finite(v: Vector3<f64>) -> bool {
+    v[0].is_finite() & v[1].is_finite() & v[2].is_finite()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ahrs::{AttitudeFilter, STANDARD_GRAVITY};
+
+    /// 試行ごとに姿勢と外乱の大きさを変えた計測値
+    fn sample(i: usize, n: usize) -> Sample {
+        let angle = 0.3 * i as f64;
+        let q = quat::from_axis_angle([1.0, 0.5 * i as f64, -0.2], angle);
+        let a_dr = match (i + n) % 4 {
+            0 => 0.0,
+            1 => 0.06,
+            2 => 0.15,
+            _ => 0.075,
+        } * STANDARD_GRAVITY;
+        Sample {
+            gyr: [0.01 * i as f64, -0.02, 0.005],
+            acc: quat::add_vec(quat::frame_rotation(q, ACC_R), [0.0, 0.0, a_dr]),
+            mag: quat::frame_rotation(q, MAG_R),
+        }
+    }
+
+    #[test]
+    fn matches_attitude_filter() {
+        const N: usize = 7;
+        let mut bank = FilterBank::new(N, 1.0, 0.2, 0.04, 0.08);
+        let mut filters = vec![AttitudeFilter::new(1.0, 0.2, 0.04, 0.08); N];
+        for n in 0..500 {
+            let mut samples: Vec<Sample> = (0..N).map(|i| sample(i, n / 10)).collect();
+            // 不正な入力を読み飛ばすのも同じ
+            if n == 100 {
+                samples[3].gyr[0] = f64::NAN;
+                samples[4].mag[2] = f64::INFINITY;
+            }
+            bank.step(&samples);
+            for (f, s) in filters.iter_mut().zip(&samples) {
+                f.predict(s.gyr);
+                f.correct(s.acc, s.mag);
+            }
+        }
+        assert_eq!(bank.n_steps, filters[0].n_steps);
+        for (i, f) in filters.iter().enumerate() {
+            assert!(quat::norm(quat::sub(bank.quaternion(i), f.q)) < 1e-9, "{}", i);
+            assert!(quat::norm_vec(quat::sub_vec(bank.gyro_bias(i), f.gyro_bias())) < 1e-9, "{}", i);
+            assert_eq!(bank.is_disturbed(i), f.is_disturbed(), "{}", i);
+            assert_eq!(bank.is_weakly_disturbed(i), f.is_weakly_disturbed(), "{}", i);
+        }
+    }
+}
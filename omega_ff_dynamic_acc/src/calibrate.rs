@@ -0,0 +1,118 @@
+//! 記録済みのセンサログからセンサの較正値を求める（calibrate-mag, calibrate-accサブコマンド）
+//!
+//! ログの形式はreplayと同じ．求めた較正値はCSVファイルに書き出し，--mag-cal, --acc-calで読み込んで使う．
+//!
+//! 較正値のファイルの形式（1行）：
+//! * 地磁気センサ：オフセットx,y,z, 補正行列（行優先で9個）
+//! * 加速度センサ：バイアスx,y,z, スケールx,y,z
+
+use std::fs;
+
+use omega_ff_dynamic_acc::{calibration::{AccCalibration, MagCalibration}, quat};
+use omega_ff_dynamic_acc::source::{ImuSample, SensorSource, CsvSource};
+use super::new_stationary_detector;
+use super::error::{Context, Error, Result};
+
+/// 地磁気センサの較正値の出力先
+const MAG_CAL_PATH: &str = "mag_calibration.csv";
+
+/// 加速度センサの較正値の出力先
+const ACC_CAL_PATH: &str = "acc_calibration.csv";
+
+/// * path: 機体を様々な向きに回しながら記録したセンサログのパス
+pub fn mag(path: &str) -> Result<()> {
+    let samples: Vec<quat::Vector3<f64>> = load(path)?
+        .map(|s| s.mag)
+        .collect();
+
+    let cal = MagCalibration::fit(&samples)
+        .ok_or(Error::Calibration("楕円体を当てはめられませんでした（様々な向きで記録したログを使ってください）"))?;
+
+    println!("{}サンプルから地磁気センサの較正値を求めました", samples.len());
+    println!("  オフセット: [{:.5}, {:.5}, {:.5}]", cal.offset[0], cal.offset[1], cal.offset[2]);
+    println!("  補正行列:");
+    for row in cal.matrix {
+        println!("    [{:.5}, {:.5}, {:.5}]", row[0], row[1], row[2]);
+    }
+    let (mean, std) = norm_stats(samples.iter().map(|m| quat::norm_vec(cal.apply(*m))));
+    println!("  較正後の大きさ: {:.5} ± {:.5}", mean, std);
+
+    let nums: Vec<String> = cal.offset.iter().chain(cal.matrix.iter().flatten()).map(|v| format!("{:.9}", v)).collect();
+    fs::write(MAG_CAL_PATH, format!("{}\n", nums.join(","))).context(MAG_CAL_PATH)?;
+    println!("{}に保存しました", MAG_CAL_PATH);
+    Ok(())
+}
+
+/// * path: 各軸を上向き・下向きにして（6姿勢），それぞれしばらく静止させながら記録したセンサログのパス
+pub fn acc(path: &str) -> Result<()> {
+    // 静止している間の加速度だけを使う
+    let mut stationary = new_stationary_detector();
+    let samples: Vec<quat::Vector3<f64>> = load(path)?
+        .map(|s| (s.gyr, s.acc))
+        .filter(|&(gyr, acc)| stationary.update(gyr, acc))
+        .map(|(_, acc)| acc)
+        .collect();
+
+    let cal = AccCalibration::fit(&samples).ok_or(Error::Calibration("6姿勢すべての静止区間が見つかりませんでした"))?;
+
+    println!("{}サンプル（静止中）から加速度センサの較正値を求めました", samples.len());
+    println!("  バイアス: [{:.5}, {:.5}, {:.5}]", cal.bias[0], cal.bias[1], cal.bias[2]);
+    println!("  スケール: [{:.5}, {:.5}, {:.5}]", cal.scale[0], cal.scale[1], cal.scale[2]);
+    let (mean, std) = norm_stats(samples.iter().map(|a| quat::norm_vec(cal.apply(*a))));
+    println!("  較正後の大きさ: {:.5} ± {:.5}", mean, std);
+
+    let nums: Vec<String> = cal.bias.iter().chain(&cal.scale).map(|v| format!("{:.9}", v)).collect();
+    fs::write(ACC_CAL_PATH, format!("{}\n", nums.join(","))).context(ACC_CAL_PATH)?;
+    println!("{}に保存しました", ACC_CAL_PATH);
+    Ok(())
+}
+
+/// calibrate-magで書き出した較正値を読み込む．
+pub fn load_mag(path: &str) -> Result<MagCalibration> {
+    let nums = read_nums(path, 12)?;
+    Ok( MagCalibration {
+        offset: [nums[0], nums[1], nums[2]],
+        matrix: [
+            [nums[3], nums[4], nums[5]],
+            [nums[6], nums[7], nums[8]],
+            [nums[9], nums[10], nums[11]],
+        ],
+    } )
+}
+
+/// calibrate-accで書き出した較正値を読み込む．
+pub fn load_acc(path: &str) -> Result<AccCalibration> {
+    let nums = read_nums(path, 6)?;
+    Ok( AccCalibration {
+        bias: [nums[0], nums[1], nums[2]],
+        scale: [nums[3], nums[4], nums[5]],
+    } )
+}
+
+/// 較正値のファイル（n個の数値を1行に並べたもの）を読み込む．
+fn read_nums(path: &str, n: usize) -> Result<Vec<f64>> {
+    let text = fs::read_to_string(path).context(path)?;
+    let nums: Option<Vec<f64>> = text.trim().split(',').map(|v| v.trim().parse().ok()).collect();
+    match nums {
+        Some(nums) if nums.len() == n => Ok(nums),
+        _ => Err( Error::format(path, format!("較正値のファイルの形式が正しくありません（{}個の数値をカンマ区切りで1行に並べてください）", n)) ),
+    }
+}
+
+/// 平均と標準偏差
+fn norm_stats(norms: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (mut sum, mut sum_sq, mut n) = (0.0, 0.0, 0.0);
+    for v in norms {
+        sum += v;
+        sum_sq += v * v;
+        n += 1.0;
+    }
+    let mean = sum / n;
+    (mean, (sum_sq / n - mean * mean).max(0.0).sqrt())
+}
+
+/// センサログのサンプルを順に返す．
+fn load(path: &str) -> Result<impl Iterator<Item = ImuSample>> {
+    let mut source = CsvSource::open(path).context(path)?;
+    Ok( std::iter::from_fn(move || source.next_sample()) )
+}
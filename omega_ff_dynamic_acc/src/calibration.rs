@@ -0,0 +1,429 @@
+//! センサの較正
+//!
+//! 地磁気センサのハードアイアン（オフセット）・ソフトアイアン（感度・軸の歪み）を，
+//! 様々な姿勢で集めた計測値に楕円体を当てはめて推定する．
+//! 加速度センサのバイアスとスケールは，各軸を上下に向けた6姿勢の静止時の計測値から推定する．
+//! 角速度センサのバイアスは，起動直後に静止している間の計測値の平均から推定する．
+
+use super::DT;
+use super::ahrs::{self, Sample, STANDARD_GRAVITY};
+use super::quat::{self, Quaternion, Vector3, DCM};
+
+/// 地磁気センサの較正値
+///
+/// 較正後の地磁気は`matrix (mag - offset)`で，大きさが1になる．
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MagCalibration {
+    pub offset: Vector3<f64>,  // ハードアイアン（楕円体の中心）
+    pub matrix: DCM<f64>,      // ソフトアイアンの補正行列（対称行列）
+}
+
+impl Default for MagCalibration {
+    /// 較正しない（オフセット0，単位行列）
+    fn default() -> Self {
+        Self {
+            offset: [0.0; 3],
+            matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+}
+
+impl MagCalibration {
+    /// 地磁気の計測値に楕円体を当てはめて較正値を求める．
+    ///
+    /// 計測値が少ない（9個未満）場合や，偏っていて楕円体が決まらない場合はNoneを返す．
+    ///
+    /// * samples: 機体を様々な向きに回しながら集めた地磁気の計測値
+    pub fn fit(samples: &[Vector3<f64>]) -> Option<Self> {
+        if samples.len() < 9 {
+            return None;
+        }
+
+        // 数値的に解きやすいように，重心を原点に移して大きさを1程度に揃える
+        let n = samples.len() as f64;
+        let mean = quat::scale_vec(n.recip(), samples.iter().fold([0.0; 3], |acc, v| quat::add_vec(acc, *v)));
+        let rms = ( samples.iter().map(|v| quat::dot_vec(quat::sub_vec(*v, mean), quat::sub_vec(*v, mean))).sum::<f64>() / n ).sqrt();
+        if !rms.is_normal() {
+            return None;
+        }
+
+        // 二次曲面 a x^2 + b y^2 + c z^2 + 2f yz + 2g xz + 2h xy + 2p x + 2q y + 2r z = 1 を最小二乗法で当てはめる
+        let mut ata = [[0.0; 9]; 9];
+        let mut atb = [0.0; 9];
+        for v in samples {
+            let [x, y, z] = quat::scale_vec(rms.recip(), quat::sub_vec(*v, mean));
+            let d = [x*x, y*y, z*z, 2.0*y*z, 2.0*x*z, 2.0*x*y, 2.0*x, 2.0*y, 2.0*z];
+            for i in 0..9 {
+                for j in 0..9 {
+                    ata[i][j] += d[i] * d[j];
+                }
+                atb[i] += d[i];
+            }
+        }
+        let [a, b, c, f, g, h, p, q, r] = solve(ata, atb)?;
+
+        // (x - center)^T M (x - center) = k の形に直す
+        let m = [[a, h, g], [h, b, f], [g, f, c]];
+        let center = quat::negate_vec( solve(m, [p, q, r])? );
+        let k = 1.0 + quat::dot_vec(center, quat::matrix_product(m, center));
+
+        // 元の座標に戻し，A = M / (k rms^2) の平方根を補正行列とする
+        let (eigval, eigvec) = sym_eigen(m);
+        let mut matrix = [[0.0; 3]; 3];
+        for (l, v) in eigval.iter().zip(eigvec) {
+            let l = l / (k * rms * rms);
+            if l.is_nan() || l <= 0.0 {
+                return None;  // 楕円体でない
+            }
+            for i in 0..3 {
+                for j in 0..3 {
+                    matrix[i][j] += l.sqrt() * v[i] * v[j];
+                }
+            }
+        }
+
+        Some(Self {
+            offset: quat::scale_add_vec(rms, center, mean),
+            matrix,
+        })
+    }
+
+    /// 地磁気の計測値を較正する．
+    pub fn apply(&self, mag: Vector3<f64>) -> Vector3<f64> {
+        quat::matrix_product(self.matrix, quat::sub_vec(mag, self.offset))
+    }
+}
+
+/// 加速度センサの較正値
+///
+/// 較正後の加速度は`scale ⊙ (acc - bias)`（⊙は要素ごとの積）．
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccCalibration {
+    pub bias: Vector3<f64>,   // バイアス[m/s^2]
+    pub scale: Vector3<f64>,  // スケール
+}
+
+impl Default for AccCalibration {
+    /// 較正しない（バイアス0，スケール1）
+    fn default() -> Self {
+        Self {
+            bias: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl AccCalibration {
+    /// 6姿勢（各軸を上向き・下向きにして静止させた状態）の計測値からバイアスとスケールを求める．
+    ///
+    /// 各計測値は絶対値が最大の成分の軸と符号で6姿勢に振り分ける．
+    /// いずれかの姿勢の計測値が無い場合はNoneを返す．
+    ///
+    /// * samples: 静止時の加速度の計測値[m/s^2]（順番や姿勢ごとの個数は問わない）
+    pub fn fit(samples: &[Vector3<f64>]) -> Option<Self> {
+        // 軸・向きごとの，その軸の成分の和とサンプル数（0:上向き，1:下向き）
+        let mut sum = [[0.0; 2]; 3];
+        let mut n = [[0usize; 2]; 3];
+        for v in samples {
+            let i = (0..3).max_by(|&i, &j| v[i].abs().total_cmp(&v[j].abs()))?;
+            let k = if v[i] >= 0.0 { 0 } else { 1 };
+            sum[i][k] += v[i];
+            n[i][k] += 1;
+        }
+
+        let mut cal = Self::default();
+        for i in 0..3 {
+            if n[i].contains(&0) {
+                return None;
+            }
+            let up = sum[i][0] / n[i][0] as f64;
+            let down = sum[i][1] / n[i][1] as f64;
+            cal.bias[i] = 0.5 * (up + down);
+            cal.scale[i] = 2.0 * STANDARD_GRAVITY / (up - down);
+        }
+        Some(cal)
+    }
+
+    /// 加速度の計測値を較正する．
+    pub fn apply(&self, acc: Vector3<f64>) -> Vector3<f64> {
+        quat::hadamard_vec(self.scale, quat::sub_vec(acc, self.bias))
+    }
+}
+
+/// 起動時の角速度センサのバイアス推定
+///
+/// 静止している間の計測値を平均し，指定した時間分溜まったら完了する．
+/// 途中で動いた場合はそれまでの計測値を捨ててやり直す．
+/// 完了後はgyro_bias()とattitude()で姿勢推定フィルタの状態を初期化する．
+#[derive(Debug, Clone)]
+pub struct StartupCalibrator {
+    n_required: usize,  // 平均するサンプル数
+    sum: Sample,        // 静止中の計測値の和
+    n: usize,           // 静止中のサンプル数
+}
+
+impl StartupCalibrator {
+    /// * duration: 計測値を平均する時間[s]
+    pub fn new(duration: f64) -> Self {
+        Self {
+            n_required: ((duration / DT).round() as usize).max(1),
+            sum: Sample { gyr: [0.0; 3], acc: [0.0; 3], mag: [0.0; 3] },
+            n: 0,
+        }
+    }
+
+    /// 計測値を加え，完了したらtrueを返す．
+    ///
+    /// * sample   : 計測値
+    /// * is_static: 静止しているかどうか（zupt::StationaryDetectorの出力など）
+    pub fn update(&mut self, sample: Sample, is_static: bool) -> bool {
+        if !is_static {
+            #[cfg(feature = "tracing")]
+            if self.n > 0 && !self.is_done() {
+                tracing::debug!(n = self.n, "静止していないので起動時のバイアス推定をやり直します");
+            }
+            self.sum = Sample { gyr: [0.0; 3], acc: [0.0; 3], mag: [0.0; 3] };
+            self.n = 0;
+            return false;
+        }
+        self.sum.gyr = quat::add_vec(self.sum.gyr, sample.gyr);
+        self.sum.acc = quat::add_vec(self.sum.acc, sample.acc);
+        self.sum.mag = quat::add_vec(self.sum.mag, sample.mag);
+        self.n += 1;
+        #[cfg(feature = "tracing")]
+        if self.n == self.n_required {
+            tracing::info!(gyro_bias = ?self.gyro_bias(), "起動時のバイアス推定が完了しました");
+        }
+        self.is_done()
+    }
+
+    /// 指定した時間分の計測値が溜まったかどうか
+    pub fn is_done(&self) -> bool {
+        self.n >= self.n_required
+    }
+
+    /// 角速度バイアスの推定値（静止中の角速度の平均）[rad/s]
+    pub fn gyro_bias(&self) -> Vector3<f64> {
+        quat::scale_vec((self.n.max(1) as f64).recip(), self.sum.gyr)
+    }
+
+    /// 静止中の加速度と地磁気の平均から求めた姿勢
+    pub fn attitude(&self) -> Quaternion<f64> {
+        ahrs::get_q_gm(self.sum.acc, self.sum.mag)
+    }
+}
+
+/// 地磁気センサのハードアイアン（オフセット）の逐次推定
+///
+/// 電池の電流の変化などでゆっくり変わるオフセットに追従するため，較正後の計測値が中心offset，半径radiusの球面に
+/// 乗るように，計測値ごとに確率的勾配法で中心と半径を更新する．補正ステップの前にupdate()で計測値からオフセットを除く．
+/// 機体の向きが変わらない間は中心と半径の変化を区別できないので，推定は様々な向きをとる間にしか進まない．
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HardIronTracker {
+    tau: f64,               // 推定の時定数[s]
+    offset: Vector3<f64>,   // オフセットの推定値
+    radius: f64,            // 球面の半径（0なら未初期化）
+}
+
+impl HardIronTracker {
+    /// * tau: 推定の時定数[s]（オフセットの変化より速く，姿勢の変化より十分遅くする）
+    pub fn new(tau: f64) -> Self {
+        Self { tau, offset: [0.0; 3], radius: 0.0 }
+    }
+
+    /// オフセットの初期値を与える（MagCalibration::fit()で求めた値など）．
+    pub fn with_offset(mut self, offset: Vector3<f64>) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// 計測値で推定値を更新し，オフセットを除いた地磁気を返す．
+    ///
+    /// 最初の計測値では，オフセットの初期値を除いた大きさを半径とする．
+    pub fn update(&mut self, mag: Vector3<f64>) -> Vector3<f64> {
+        let v = quat::sub_vec(mag, self.offset);
+        let norm = quat::norm_vec(v);
+        if !(norm > 0.0 && norm.is_finite()) {
+            return v;
+        }
+        if self.radius == 0.0 {
+            self.radius = norm;
+        }
+        // 球面からの距離eを小さくする向きに中心と半径を動かす
+        let k = (DT / self.tau).min(1.0);
+        let e = norm - self.radius;
+        self.offset = quat::scale_add_vec(k * e / norm, v, self.offset);
+        self.radius += k * e;
+        quat::sub_vec(mag, self.offset)
+    }
+
+    /// オフセットの推定値
+    pub fn offset(&self) -> Vector3<f64> {
+        self.offset
+    }
+
+    /// 球面の半径（オフセットを除いた地磁気の大きさ）の推定値
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+}
+
+/// 連立一次方程式 a x = b を解く（部分ピボット選択付きガウスの消去法）．
+///
+/// aが特異（に近い）場合はNoneを返す．
+fn solve<const N: usize>(mut a: [[f64; N]; N], mut b: [f64; N]) -> Option<[f64; N]> {
+    for k in 0..N {
+        let pivot = (k..N).max_by(|&i, &j| a[i][k].abs().total_cmp(&a[j][k].abs()))?;
+        if a[pivot][k].is_nan() || a[pivot][k].abs() <= 1e-12 {
+            return None;
+        }
+        a.swap(k, pivot);
+        b.swap(k, pivot);
+        let (upper, lower) = a.split_at_mut(k + 1);
+        for (i, row) in lower.iter_mut().enumerate() {
+            let coef = row[k] / upper[k][k];
+            for (x, y) in row[k..].iter_mut().zip(&upper[k][k..]) {
+                *x -= coef * y;
+            }
+            b[k + 1 + i] -= coef * b[k];
+        }
+    }
+
+    let mut x = [0.0; N];
+    for k in (0..N).rev() {
+        let sum: f64 = ((k + 1)..N).map(|j| a[k][j] * x[j]).sum();
+        x[k] = (b[k] - sum) / a[k][k];
+    }
+    Some(x)
+}
+
+/// 3x3の対称行列の固有値と固有ベクトルを求める（ヤコビ法）．
+fn sym_eigen(mut a: DCM<f64>) -> (Vector3<f64>, [Vector3<f64>; 3]) {
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];  // 列が固有ベクトル
+    for _ in 0..50 {
+        let off = a[0][1].abs() + a[0][2].abs() + a[1][2].abs();
+        if off < 1e-15 * (a[0][0].abs() + a[1][1].abs() + a[2][2].abs()) {
+            break;
+        }
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q] == 0.0 {
+                continue;
+            }
+            // a[p][q]を0にする回転角
+            let theta = 0.5 * (2.0 * a[p][q]).atan2(a[q][q] - a[p][p]);
+            let (s, c) = theta.sin_cos();
+            for row in a.iter_mut() {
+                let (akp, akq) = (row[p], row[q]);
+                row[p] = c * akp - s * akq;
+                row[q] = s * akp + c * akq;
+            }
+            let (upper, lower) = a.split_at_mut(q);
+            for (apk, aqk) in upper[p].iter_mut().zip(lower[0].iter_mut()) {
+                (*apk, *aqk) = (c * *apk - s * *aqk, s * *apk + c * *aqk);
+            }
+            for row in v.iter_mut() {
+                let (vkp, vkq) = (row[p], row[q]);
+                row[p] = c * vkp - s * vkq;
+                row[q] = s * vkp + c * vkq;
+            }
+        }
+    }
+    let eigvec = [
+        [v[0][0], v[1][0], v[2][0]],
+        [v[0][1], v[1][1], v[2][1]],
+        [v[0][2], v[1][2], v[2][2]],
+    ];
+    ([a[0][0], a[1][1], a[2][2]], eigvec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mag_fit_recovers_hard_and_soft_iron() {
+        let offset = [0.3, -0.2, 0.5];
+        let distortion = [[1.2, 0.1, 0.0], [0.1, 0.8, 0.05], [0.0, 0.05, 1.1]];
+
+        // 単位球上の点を歪ませて計測値とする
+        let mut samples = Vec::new();
+        for i in 0..20 {
+            for j in 0..10 {
+                let theta = std::f64::consts::PI * (j as f64 + 0.5) / 10.0;
+                let phi = 2.0 * std::f64::consts::PI * i as f64 / 20.0;
+                let m = [theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()];
+                samples.push( quat::add_vec(quat::matrix_product(distortion, m), offset) );
+            }
+        }
+
+        let cal = MagCalibration::fit(&samples).unwrap();
+        assert!(quat::norm_vec(quat::sub_vec(cal.offset, offset)) < 1e-9);
+        for m in samples {
+            assert!((quat::norm_vec(cal.apply(m)) - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn hard_iron_tracker_follows_offset() {
+        let offset = [0.3, -0.2, 0.1];
+        let mut tracker = HardIronTracker::new(5.0);
+        let mut mag = [0.0; 3];
+        // 機体を様々な向きに回し続ける
+        for n in 0..20000 {
+            let t = n as f64 * DT;
+            let (theta, phi) = (0.5 * t, 0.13 * t);
+            let m = [theta.cos() * phi.cos(), theta.sin() * phi.cos(), phi.sin()];
+            mag = tracker.update( quat::add_vec(m, offset) );
+        }
+        assert!(quat::norm_vec(quat::sub_vec(tracker.offset(), offset)) < 0.01, "{:?}", tracker.offset());
+        assert!((tracker.radius() - 1.0).abs() < 0.01);
+        assert!((quat::norm_vec(mag) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn startup_restarts_when_moved() {
+        let bias = [0.01, -0.02, 0.03];
+        let sample = Sample { gyr: bias, acc: [0.0, 0.0, STANDARD_GRAVITY], mag: [0.0, 1.0, 0.0] };
+        let mut startup = StartupCalibrator::new(10.0 * DT);
+
+        for _ in 0..9 {
+            assert!(!startup.update(sample, true));
+        }
+        assert!(!startup.update(sample, false));  // 動いたのでやり直し
+        for _ in 0..9 {
+            assert!(!startup.update(sample, true));
+        }
+        assert!(startup.update(sample, true));
+        assert!(quat::norm_vec(quat::sub_vec(startup.gyro_bias(), bias)) < 1e-12);
+
+        let mut filter = ahrs::AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        filter.set_gyro_bias(startup.gyro_bias());
+        assert!(quat::norm_vec(quat::sub_vec(filter.gyro_bias(), bias)) < 1e-12);
+    }
+
+    #[test]
+    fn acc_fit_recovers_bias_and_scale() {
+        let bias = [0.1, -0.2, 0.3];
+        let scale = [1.02, 0.98, 1.01];
+
+        // 各軸を上下に向けたときの計測値
+        let mut samples = Vec::new();
+        for i in 0..3 {
+            for sign in [1.0, -1.0] {
+                let mut g = [0.0; 3];
+                g[i] = sign * STANDARD_GRAVITY / scale[i];
+                samples.push( quat::add_vec(g, bias) );
+            }
+        }
+
+        let cal = AccCalibration::fit(&samples).unwrap();
+        for i in 0..3 {
+            assert!((cal.bias[i] - bias[i]).abs() < 1e-12);
+            assert!((cal.scale[i] - scale[i]).abs() < 1e-12);
+        }
+        assert!(AccCalibration::fit(&samples[..5]).is_none());
+    }
+}
@@ -0,0 +1,92 @@
+//! 複数のフィルタを同じ計測値で動かして比較する（--compare）
+//!
+//! ノイズの実現値が異なると推定誤差の差がフィルタの違いによるものか判断しにくいので，
+//! シナリオの計測値を一度だけ作って全てのフィルタに与える．
+//!
+//! compare_result.csvの1行の形式：
+//! * 時刻，四元数の真値（4列）
+//! * フィルタごとに：オイラー角の推定値（3列），角速度バイアスの推定値（3列），四元数の推定値（4列），姿勢誤差の回転角（1列）
+
+use std::fs;
+use std::io::BufWriter;
+
+use omega_ff_dynamic_acc::{ahrs, quat, estimator::AttitudeEstimator};
+
+use super::{Options, ALPHA, BETA, THR_WEAK, THR_STRONG, attitude_error, estimate_record, output};
+use super::options::parse_detector;
+use super::scenario::{self, Metrics};
+use super::error::{Context, Result};
+
+/// 比較結果の出力先
+const RESULT_PATH: &str = "compare_result.csv";
+
+/// 比較するフィルタの設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterSpec {
+    pub detector: ahrs::Detector,
+    pub alpha: f64,
+    pub beta: f64,
+    pub thr_weak: f64,
+    pub thr_strong: f64,
+}
+
+impl FilterSpec {
+    /// `<e1|e2|both>[:alpha[:beta[:thr_weak[:thr_strong]]]]`の形式の文字列から作る（省略したパラメータはシミュレーションと同じ値）．
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut fields = s.split(':');
+        let detector = parse_detector(fields.next()?)?;
+        let mut params = [ALPHA, BETA, THR_WEAK, THR_STRONG];
+        for (p, field) in params.iter_mut().zip(fields.by_ref()) {
+            *p = field.parse().ok()?;
+        }
+        if fields.next().is_some() {
+            return None;
+        }
+        let [alpha, beta, thr_weak, thr_strong] = params;
+        Some( Self { detector, alpha, beta, thr_weak, thr_strong } )
+    }
+
+    pub fn build(&self) -> ahrs::AttitudeFilter {
+        ahrs::AttitudeFilter::new(self.alpha, self.beta, self.thr_weak, self.thr_strong)
+            .with_detector(self.detector)
+    }
+
+    /// 結果の表示に使う名前
+    pub fn label(&self) -> String {
+        format!("{:?} (alpha={}, beta={}, thr={}/{})", self.detector, self.alpha, self.beta, self.thr_weak, self.thr_strong)
+    }
+}
+
+pub fn run(specs: &[FilterSpec], opts: &Options) -> Result<()> {
+    let steps = scenario::generate(&mut rand::thread_rng(), &opts.sim.noise);
+
+    let mut filters: Vec<Box<dyn AttitudeEstimator>> = specs.iter().map(|spec| {
+        Box::new(spec.build()) as Box<dyn AttitudeEstimator>
+    }).collect();
+    let mut errors = vec![Vec::with_capacity(steps.len()); filters.len()];
+
+    let mut file = output::CsvWriter::new( BufWriter::new( fs::File::create(RESULT_PATH).context(RESULT_PATH)? ) )
+        .with_columns(opts.output.columns)
+        .with_precision(opts.output.precision);
+    for step in &steps {
+        // 全てのフィルタに同じ計測値を与える
+        let results = filters.iter_mut().zip(errors.iter_mut()).map(|(filter, errors)| {
+            filter.update(&step.sample);
+            let (angle_err, _) = attitude_error(step.q, filter.quaternion());
+            errors.push(angle_err);
+            (estimate_record(filter.as_ref(), opts.output.euler), angle_err)
+        }).collect();
+        file.write(&output::CompareRecord { time: step.time, q_true: step.q, filters: results }).context(RESULT_PATH)?;
+    }
+    file.flush().context(RESULT_PATH)?;
+
+    for ((spec, filter), errors) in specs.iter().zip(&filters).zip(&errors) {
+        let metrics = Metrics::new(&steps, errors);
+        let bias_err = quat::norm_vec( quat::sub_vec(filter.gyro_bias(), scenario::GYR_BIAS) );
+        println!("{}", spec.label());
+        println!("  姿勢誤差（回転角）のRMS: 全体 {:.4} rad，外乱中 {:.4} rad", metrics.rms, metrics.rms_disturbance);
+        println!("  外乱後の収束時間: {:.2} s", metrics.recovery_time);
+        println!("  角速度バイアスの推定誤差（最終値）: {:.4} rad/s", bias_err);
+    }
+    Ok(())
+}
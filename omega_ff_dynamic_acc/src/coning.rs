@@ -0,0 +1,117 @@
+//! フィルタの更新より速い周期で計測した角速度の積算（コーニング補正）
+//!
+//! 複数の軸周りに同時に振動するような回転（コーニング運動）では，角速度を単純に足し合わせた角度増分は
+//! 実際の回転とずれ，ずれが一方向に溜まって姿勢がドリフトする（回転の非可換性による）．
+//! 角度増分を積算する際に，それまでの積算値と新しい増分の外積による補正項（コーニング補正）を加えて
+//! 更新周期の間の回転ベクトルを求め，AttitudeFilter::predict_delta_angle()に渡す．
+//!
+//! 補正項は1次（0.5 α × Δα）と，直前の増分を使う2サンプル補正（Δα_prev × Δα / 12）の和．
+
+use super::quat;
+use super::quat::Vector3;
+
+#[derive(Debug, Clone, Default)]
+pub struct ConingIntegrator {
+    alpha: Vector3<f64>,       // 更新周期の間の角度増分の単純な和[rad]
+    beta: Vector3<f64>,        // コーニング補正項の和[rad]
+    prev: Vector3<f64>,        // 直前の角度増分[rad]（更新周期をまたいで使う）
+    duration: f64,             // 積算した時間[s]
+}
+
+impl ConingIntegrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 角速度の計測値を1サンプル積算する．
+    ///
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    /// * dt : 計測周期[s]
+    pub fn add(&mut self, gyr: Vector3<f64>, dt: f64) {
+        self.add_delta_angle(quat::scale_vec(dt, gyr), dt);
+    }
+
+    /// 角度増分（センサが積分して出力する場合）を1サンプル積算する．
+    ///
+    /// * dalpha: 計測周期の間の角度増分[rad]
+    /// * dt    : 計測周期[s]
+    pub fn add_delta_angle(&mut self, dalpha: Vector3<f64>, dt: f64) {
+        let first = quat::scale_vec(0.5, quat::cross_vec(self.alpha, dalpha));
+        let second = quat::scale_vec(1.0 / 12.0, quat::cross_vec(self.prev, dalpha));
+        self.beta = quat::add_vec(self.beta, quat::add_vec(first, second));
+        self.alpha = quat::add_vec(self.alpha, dalpha);
+        self.prev = dalpha;
+        self.duration += dt;
+    }
+
+    /// 積算した時間[s]
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// 積算した回転ベクトル（コーニング補正済み）を返し，次の更新周期の積算を始める．
+    pub fn take(&mut self) -> Vector3<f64> {
+        let phi = quat::add_vec(self.alpha, self.beta);
+        self.alpha = [0.0; 3];
+        self.beta = [0.0; 3];
+        self.duration = 0.0;
+        phi
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DT;
+
+    /// 半頂角theta，角周波数omegaのコーニング運動の姿勢
+    fn coning_attitude(theta: f64, omega: f64, t: f64) -> quat::Quaternion<f64> {
+        let s = (0.5 * theta).sin();
+        ((0.5 * theta).cos(), [s * (omega * t).cos(), s * (omega * t).sin(), 0.0])
+    }
+
+    /// コーニング運動の角速度（機体座標系）をt0からt0 + dtまで積分する（シンプソン則）．
+    fn integrate_rate(theta: f64, omega: f64, t0: f64, dt: f64) -> Vector3<f64> {
+        let rate = |t: f64| {
+            // ω = 2 q^* ⊗ dq/dt
+            let s = (0.5 * theta).sin();
+            let dq = (0.0, [-s * omega * (omega * t).sin(), s * omega * (omega * t).cos(), 0.0]);
+            quat::scale_vec(2.0, quat::mul(quat::conj(coning_attitude(theta, omega, t)), dq).1)
+        };
+        let n = 16;
+        let h = dt / n as f64;
+        let mut sum = quat::add_vec(rate(t0), rate(t0 + dt));
+        for j in 1..n {
+            let w = if j % 2 == 1 { 4.0 } else { 2.0 };
+            sum = quat::scale_add_vec(w, rate(t0 + j as f64 * h), sum);
+        }
+        quat::scale_vec(h / 3.0, sum)
+    }
+
+    #[test]
+    fn reduces_coning_drift() {
+        let (theta, omega) = (0.1, 2.0 * std::f64::consts::PI * 5.0);
+        let n_sub = 10;
+        let dt = DT / n_sub as f64;
+        let mut integrator = ConingIntegrator::new();
+        let (mut q_plain, mut q_coning) = (coning_attitude(theta, omega, 0.0), coning_attitude(theta, omega, 0.0));
+        let n_steps = 500;
+        for k in 0..n_steps {
+            let mut sum = [0.0; 3];
+            for i in 0..n_sub {
+                // 計測周期の間の角速度の積分（センサが出力する角度増分に相当）
+                let t0 = (k * n_sub + i) as f64 * dt;
+                let dalpha = integrate_rate(theta, omega, t0, dt);
+                integrator.add_delta_angle(dalpha, dt);
+                sum = quat::add_vec(sum, dalpha);
+            }
+            assert!((integrator.duration() - DT).abs() < 1e-12);
+            q_plain = quat::normalize( quat::mul(q_plain, quat::from_rotation_vector(sum)) );
+            q_coning = quat::normalize( quat::mul(q_coning, quat::from_rotation_vector(integrator.take())) );
+        }
+        let q_true = coning_attitude(theta, omega, n_steps as f64 * DT);
+        let err = |q: quat::Quaternion<f64>| 2.0 * quat::dot(q, q_true).abs().min(1.0).acos();
+        let (err_plain, err_coning) = (err(q_plain), err(q_coning));
+        assert!(err_coning < 0.01 * err_plain, "{} {}", err_coning, err_plain);
+    }
+}
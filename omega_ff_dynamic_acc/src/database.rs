@@ -0,0 +1,120 @@
+//! 評価結果のSQLiteデータベースへの書き出し（--db）
+//!
+//! sweepの組み合わせごとの評価指標を，実行条件（シード，外乱判定式，ノイズの設定，gitのコミット）と一緒に記録する．
+//! 実行を重ねても同じファイルに追記するので，大量のCSVを管理する代わりにSQLで比較できる．
+//! 書き込みはrusqliteで行う（sqliteフィーチャが必要．SQLite本体もクレートと一緒にビルドするので，sqlite3コマンドは要らない）．
+//! 1回の実行の書き込みは1つのトランザクションにまとめ，finish()でコミットする（途中で失敗した実行は残らない）．
+//!
+//! テーブル：
+//! * runs(id, started, command, git_hash, seed, detector, noise)
+//! * results(run_id, alpha, beta, thr_weak, thr_strong, rms, rms_disturbance, recovery_time)
+//! * traces(run_id, result_id, time, angle_err)（--db-tracesを付けた場合だけ，組み合わせごとの姿勢誤差の時系列）
+
+#[cfg(feature = "sqlite")]
+use std::process::Command;
+
+use super::error::{Error, Result};
+
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY, started TEXT, command TEXT, git_hash TEXT, seed INTEGER, detector TEXT, noise TEXT
+);
+CREATE TABLE IF NOT EXISTS results (
+    id INTEGER PRIMARY KEY, run_id INTEGER REFERENCES runs(id),
+    alpha REAL, beta REAL, thr_weak REAL, thr_strong REAL, rms REAL, rms_disturbance REAL, recovery_time REAL
+);
+CREATE TABLE IF NOT EXISTS traces (
+    run_id INTEGER REFERENCES runs(id), result_id INTEGER REFERENCES results(id), time REAL, angle_err REAL
+);
+";
+
+/// 実行条件
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+pub struct RunInfo<'a> {
+    pub seed: u64,
+    pub detector: &'a str,
+    pub noise: &'a str,
+}
+
+pub struct ResultsDb {
+    #[cfg(feature = "sqlite")]
+    conn: rusqlite::Connection,
+    #[cfg(feature = "sqlite")]
+    run_id: i64,
+    #[cfg(feature = "sqlite")]
+    path: String,
+}
+
+#[cfg(feature = "sqlite")]
+impl ResultsDb {
+    /// データベースを開き（無ければ作る），実行条件を記録する．
+    pub fn open(path: &str, info: &RunInfo) -> Result<Self> {
+        let context = |source| Error::Database { target: path.to_string(), source };
+        let conn = rusqlite::Connection::open(path).map_err(context)?;
+        conn.execute_batch(SCHEMA).map_err(context)?;
+        conn.execute_batch("BEGIN").map_err(context)?;
+        let command: Vec<String> = std::env::args().collect();
+        conn.execute(
+            "INSERT INTO runs (started, command, git_hash, seed, detector, noise) VALUES (datetime('now'), ?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![command.join(" "), git_hash(), info.seed as i64, info.detector, info.noise],
+        ).map_err(context)?;
+        let run_id = conn.last_insert_rowid();
+        Ok( Self { conn, run_id, path: path.to_string() } )
+    }
+
+    /// 1つの組み合わせの評価指標と，姿勢誤差の時系列（(時刻, 回転角)，記録しない場合は空）を書き出す．
+    pub fn insert(&mut self, params: [f64; 4], metrics: [f64; 3], trace: &[(f64, f64)]) -> Result<()> {
+        self.try_insert(params, metrics, trace)
+            .map_err(|source| Error::Database { target: self.path.clone(), source })
+    }
+
+    fn try_insert(&self, params: [f64; 4], metrics: [f64; 3], trace: &[(f64, f64)]) -> rusqlite::Result<()> {
+        let [alpha, beta, thr_weak, thr_strong] = params.map(real);
+        let [rms, rms_disturbance, recovery_time] = metrics.map(real);
+        self.conn.prepare_cached(
+            "INSERT INTO results (run_id, alpha, beta, thr_weak, thr_strong, rms, rms_disturbance, recovery_time) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+        )?.execute(rusqlite::params![self.run_id, alpha, beta, thr_weak, thr_strong, rms, rms_disturbance, recovery_time])?;
+        let result_id = self.conn.last_insert_rowid();
+
+        let mut stmt = self.conn.prepare_cached("INSERT INTO traces (run_id, result_id, time, angle_err) VALUES (?1, ?2, ?3, ?4)")?;
+        for &(t, e) in trace {
+            stmt.execute(rusqlite::params![self.run_id, result_id, real(t), real(e)])?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        self.conn.execute_batch("COMMIT")
+            .map_err(|source| Error::Database { target: self.path, source })
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl ResultsDb {
+    pub fn open(_path: &str, _info: &RunInfo) -> Result<Self> {
+        Err( Error::Feature { option: "--db", feature: "sqlite" } )
+    }
+
+    pub fn insert(&mut self, _params: [f64; 4], _metrics: [f64; 3], _trace: &[(f64, f64)]) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// SQLの数値（NaNはNULL．無限大はそのまま記録する）
+#[cfg(feature = "sqlite")]
+fn real(v: f64) -> Option<f64> {
+    (!v.is_nan()).then_some(v)
+}
+
+#[cfg(feature = "sqlite")]
+/// 現在のgitのコミット（未コミットの変更があれば末尾に-dirtyを付ける）
+fn git_hash() -> Option<String> {
+    let out = Command::new("git").args(["describe", "--always", "--dirty"]).output().ok()?;
+    out.status.success().then(|| String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
@@ -0,0 +1,72 @@
+//! 実行時のエラー
+//!
+//! ファイルの読み書きや通信に失敗した場合や，読み込んだファイルの形式が正しくない場合もその場でpanicせず，
+//! main()まで返して表示し，終了コード1で終了する．
+//! 途中で失敗した場合も，それまでに書き出した分はフラッシュしてから閉じる．
+//! コマンドライン引数の誤りや，有効になっていないフィーチャが必要な引数も同じくエラーとして返す．
+
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// ファイル，デバイス，ソケットの読み書きに失敗した
+    #[error("{target}: {source}")]
+    Io { target: String, source: io::Error },
+    /// 読み込んだファイルの形式や内容が正しくない
+    #[error("{target}: {reason}")]
+    Format { target: String, reason: String },
+    /// 計測値から較正値を求められなかった
+    #[error("較正値を求められませんでした: {0}")]
+    Calibration(&'static str),
+    /// コマンドライン引数が正しくない
+    #[error("{0}")]
+    Argument(String),
+    /// 引数に必要なフィーチャを有効にせずにビルドした（必要なフィーチャを全て有効にしたビルドでは使わない）
+    #[allow(dead_code)]
+    #[error("{option}を使うには{feature}フィーチャを有効にしてビルドしてください")]
+    Feature { option: &'static str, feature: &'static str },
+    /// gRPCのサービスが止まった
+    #[cfg(feature = "serve")]
+    #[error("gRPC: {0}")]
+    Grpc(#[from] tonic::transport::Error),
+    /// 評価結果のデータベースの読み書きに失敗した
+    #[cfg(feature = "sqlite")]
+    #[error("{target}: {source}")]
+    Database { target: String, source: rusqlite::Error },
+    /// ウィンドウを開けなかった
+    #[cfg(feature = "window")]
+    #[error("ウィンドウ: {0}")]
+    Window(#[from] minifb::Error),
+    /// rerunのビューアに接続できなかった
+    #[cfg(feature = "rerun")]
+    #[error("rerun: {0}")]
+    Rerun(#[from] rerun::RecordingStreamError),
+    /// 起動前の確認（self-test）で問題が見つかった
+    #[error("起動前の確認で{0}個の問題が見つかりました")]
+    SelfTest(usize),
+}
+
+impl Error {
+    /// 形式の誤り（target: ファイル名）
+    pub fn format(target: &str, reason: impl Into<String>) -> Self {
+        Error::Format { target: target.to_string(), reason: reason.into() }
+    }
+
+    /// コマンドライン引数の誤り
+    pub fn argument(message: impl Into<String>) -> Self {
+        Error::Argument(message.into())
+    }
+}
+
+/// io::Resultに読み書きの対象（ファイル名やアドレス）を付けてResultにする．
+pub trait Context<T> {
+    fn context(self, target: &str) -> Result<T>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn context(self, target: &str) -> Result<T> {
+        self.map_err(|source| Error::Io { target: target.to_string(), source })
+    }
+}
@@ -0,0 +1,104 @@
+//! 姿勢推定フィルタ共通のインターフェース
+//!
+//! 実装の異なるフィルタ（f64版，固定小数点版など）を，シミュレーションやログ再生から同じように動かすためのトレイト．
+//! 入出力はすべてf64で受け渡す．
+
+use super::ahrs::{self, Sample};
+use super::quat::{Vector3, Quaternion};
+
+pub trait AttitudeEstimator {
+    /// 予測ステップ
+    ///
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    fn predict(&mut self, gyr: Vector3<f64>);
+
+    /// 補正ステップ
+    ///
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    /// * mag: 機体上のセンサで計測した地磁気（方向だけわかれば良いので単位不問）
+    fn correct(&mut self, acc: Vector3<f64>, mag: Vector3<f64>);
+
+    /// 姿勢推定値を返す．
+    fn quaternion(&self) -> Quaternion<f64>;
+
+    /// 角速度バイアスの推定値[rad/s]を返す．
+    fn gyro_bias(&self) -> Vector3<f64>;
+
+    /// 1サンプル分の予測・補正ステップを行う．
+    fn update(&mut self, sample: &Sample) {
+        self.predict(sample.gyr);
+        self.correct(sample.acc, sample.mag);
+    }
+}
+
+impl AttitudeEstimator for ahrs::AttitudeFilter {
+    fn predict(&mut self, gyr: Vector3<f64>) {
+        ahrs::AttitudeFilter::predict(self, gyr);
+    }
+
+    fn correct(&mut self, acc: Vector3<f64>, mag: Vector3<f64>) {
+        ahrs::AttitudeFilter::correct(self, acc, mag);
+    }
+
+    fn quaternion(&self) -> Quaternion<f64> {
+        self.q
+    }
+
+    fn gyro_bias(&self) -> Vector3<f64> {
+        ahrs::AttitudeFilter::gyro_bias(self)
+    }
+}
+
+#[cfg(feature = "fixed")]
+impl AttitudeEstimator for super::ahrs_fixed::AttitudeFilter {
+    fn predict(&mut self, gyr: Vector3<f64>) {
+        use super::ahrs_fixed::to_fix_vec;
+        super::ahrs_fixed::AttitudeFilter::predict(self, to_fix_vec(gyr));
+    }
+
+    fn correct(&mut self, acc: Vector3<f64>, mag: Vector3<f64>) {
+        use super::ahrs_fixed::to_fix_vec;
+        super::ahrs_fixed::AttitudeFilter::correct(self, to_fix_vec(acc), to_fix_vec(mag));
+    }
+
+    fn quaternion(&self) -> Quaternion<f64> {
+        self.q_f64()
+    }
+
+    fn gyro_bias(&self) -> Vector3<f64> {
+        super::ahrs_fixed::AttitudeFilter::gyro_bias(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// トレイト経由で計測値列を処理し，各サンプルでの姿勢推定値を返す．
+    fn run<E: AttitudeEstimator + ?Sized>(estimator: &mut E, samples: &[Sample]) -> Vec<Quaternion<f64>> {
+        samples.iter().map(|s| {
+            estimator.update(s);
+            estimator.quaternion()
+        }).collect()
+    }
+
+    #[test]
+    fn trait_matches_inherent_methods() {
+        let samples: Vec<Sample> = (0..200).map(|i| Sample {
+            gyr: [0.1, -0.05, 0.02],
+            acc: [0.0, 0.3 * (i % 7) as f64, ahrs::STANDARD_GRAVITY],
+            mag: ahrs::MAG_R,
+        }).collect();
+
+        let mut filter = ahrs::AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        let estimates: Vec<Quaternion<f64>> = samples.iter().map(|s| {
+            filter.predict(s.gyr);
+            filter.correct(s.acc, s.mag);
+            filter.q
+        }).collect();
+
+        let mut estimator: Box<dyn AttitudeEstimator> = Box::new(ahrs::AttitudeFilter::new(1.0, 0.2, 0.04, 0.08));
+        assert_eq!(run(estimator.as_mut(), &samples), estimates);
+        assert_eq!(estimator.gyro_bias(), filter.gyro_bias());
+    }
+}
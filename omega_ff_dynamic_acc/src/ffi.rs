@@ -0,0 +1,66 @@
+//! C言語から呼び出すためのAPI
+//! 
+//! ヘッダファイル（include/omega_ff_dynamic_acc.h）はcbindgenで生成する（APIを変えたらREADMEのコマンドで更新する）．
+
+use super::ahrs::AttitudeFilter;
+
+/// フィルタを生成する．不要になったら`omega_ff_destroy`で破棄すること．
+/// 
+/// 引数は`AttitudeFilter::new`と同じ．
+#[no_mangle]
+pub extern "C" fn omega_ff_create(alpha: f64, beta: f64, thr_weak: f64, thr_strong: f64) -> *mut AttitudeFilter {
+    Box::into_raw( Box::new( AttitudeFilter::new(alpha, beta, thr_weak, thr_strong) ) )
+}
+
+/// 予測ステップ
+/// 
+/// * gyr: 機体上で計測した角速度[rad/s]（要素数3）
+/// 
+/// # Safety
+/// `filter`は`omega_ff_create`で生成したもの，`gyr`は要素数3の配列を指していること．
+#[no_mangle]
+pub unsafe extern "C" fn omega_ff_predict(filter: *mut AttitudeFilter, gyr: *const f64) {
+    if filter.is_null() || gyr.is_null() {
+        return;
+    }
+    (*filter).predict( *(gyr as *const [f64; 3]) );
+}
+
+/// 補正ステップ
+/// 
+/// * acc: 機体上のセンサで計測した加速度[m/s^2]（要素数3）
+/// * mag: 機体上のセンサで計測した地磁気（要素数3）
+/// 
+/// # Safety
+/// `filter`は`omega_ff_create`で生成したもの，`acc`と`mag`は要素数3の配列を指していること．
+#[no_mangle]
+pub unsafe extern "C" fn omega_ff_correct(filter: *mut AttitudeFilter, acc: *const f64, mag: *const f64) {
+    if filter.is_null() || acc.is_null() || mag.is_null() {
+        return;
+    }
+    (*filter).correct( *(acc as *const [f64; 3]), *(mag as *const [f64; 3]) );
+}
+
+/// 姿勢推定値を`q`（要素数4，[q0, q1, q2, q3]の順）に書き込む．
+/// 
+/// # Safety
+/// `filter`は`omega_ff_create`で生成したもの，`q`は要素数4の配列を指していること．
+#[no_mangle]
+pub unsafe extern "C" fn omega_ff_get_quaternion(filter: *const AttitudeFilter, q: *mut f64) {
+    if filter.is_null() || q.is_null() {
+        return;
+    }
+    let q_hat = (*filter).q;
+    *(q as *mut [f64; 4]) = [q_hat.0, q_hat.1[0], q_hat.1[1], q_hat.1[2]];
+}
+
+/// フィルタを破棄する．NULLを渡した場合は何もしない．
+/// 
+/// # Safety
+/// `filter`は`omega_ff_create`で生成したもので，まだ破棄していないこと．
+#[no_mangle]
+pub unsafe extern "C" fn omega_ff_destroy(filter: *mut AttitudeFilter) {
+    if !filter.is_null() {
+        drop( Box::from_raw(filter) );
+    }
+}
@@ -0,0 +1,122 @@
+//! デスクトップのフライトシミュレータ（X-Plane，FlightGear）が送るUDPの出力の読み取り
+//!
+//! 実際の機体に近い軌跡で手軽に試せるように，機体の角速度と加速度をImuSampleに変換する．
+//! どちらも機体座標系はFRD（前・右・下）なので，このクレートの座標系（前・左・上）に合わせてy，z軸の符号を反転する
+//! （X-PlaneのG-loadsは右・上・後の向きなので並べ替える）．
+//! 地磁気は出力されないので，シミュレータの姿勢（ロール，ピッチ，真方位）からahrs::MAG_Rを機体座標系に回して作る．
+//!
+//! X-Planeは「Data Output」でUDPに送る項目のうち，次の番号を読む（他の番号は読み飛ばす）：
+//! * 1: Times（2番目の値totlを時刻[s]に使う）
+//! * 4: G-loads（norml，axial，sideを加速度に使う）
+//! * 16: Angular velocities（Q，P，R[rad/s]）
+//! * 17: Pitch, roll, & headings（pitch，roll，true hdg[deg]）
+//!
+//! FlightGearは同梱のプロトコル定義（flightgear/omega_ff.xml）で，1行に次の値をカンマ区切りで送る：
+//! 時刻[s], p, q, r[rad/s], 加速度x,y,z[ft/s^2]（/accelerations/pilot），ロール, ピッチ, 真方位[deg]
+
+use super::ahrs::{MAG_R, STANDARD_GRAVITY};
+use super::quat::{self, Quaternion, Vector3};
+use super::source::ImuSample;
+
+/// X-PlaneのDATAパケットの先頭（"DATA"と1バイト）の長さ
+const XPLANE_HEADER_LEN: usize = 5;
+
+/// X-PlaneのDATAパケットの1項目の長さ（番号と8つの値）
+const XPLANE_RECORD_LEN: usize = 36;
+
+/// X-Planeの項目番号
+const XPLANE_TIMES: i32 = 1;
+const XPLANE_G_LOADS: i32 = 4;
+const XPLANE_ANGULAR_VELOCITIES: i32 = 16;
+const XPLANE_ATTITUDE: i32 = 17;
+
+/// 1 ftの長さ[m]
+const FEET: f64 = 0.3048;
+
+/// X-PlaneのDATAパケットをサンプルに変換する（DATAパケットでないか，時刻と角速度が無ければNone）．
+///
+/// 加速度や姿勢の項目が無い場合，その計測値はNaNにする．
+pub fn parse_xplane(packet: &[u8]) -> Option<ImuSample> {
+    if !packet.starts_with(b"DATA") || packet.len() < XPLANE_HEADER_LEN {
+        return None;
+    }
+    let (mut time, mut gyr) = (None, None);
+    let (mut acc, mut mag) = ([f64::NAN; 3], [f64::NAN; 3]);
+    for record in packet[XPLANE_HEADER_LEN..].chunks_exact(XPLANE_RECORD_LEN) {
+        let index = i32::from_le_bytes(record[0..4].try_into().unwrap());
+        let v: [f64; 8] = std::array::from_fn(|i| f32::from_le_bytes(record[4 + 4 * i..8 + 4 * i].try_into().unwrap()) as f64);
+        match index {
+            XPLANE_TIMES => time = Some(v[1]),
+            // 機体座標系（右・上・後）のG
+            XPLANE_G_LOADS => acc = [-v[5], -v[6], v[4]].map(|g| g * STANDARD_GRAVITY),
+            XPLANE_ANGULAR_VELOCITIES => gyr = Some( [v[1], -v[0], -v[2]] ),
+            XPLANE_ATTITUDE => mag = magnetometer(v[1], v[0], v[2]),
+            _ => (),
+        }
+    }
+    Some( ImuSample { time: time?, gyr: gyr?, acc, mag } )
+}
+
+/// FlightGearの1行をサンプルに変換する（読めない行はNone）．
+pub fn parse_flightgear_line(line: &str) -> Option<ImuSample> {
+    let nums: Vec<f64> = line.split(',').map(|v| v.trim().parse().ok()).collect::<Option<_>>()?;
+    if nums.len() < 10 {
+        return None;
+    }
+    Some( ImuSample {
+        time: nums[0],
+        gyr: [nums[1], -nums[2], -nums[3]],
+        acc: [nums[4], -nums[5], -nums[6]].map(|a| a * FEET),
+        mag: magnetometer(nums[7], nums[8], nums[9]),
+    } )
+}
+
+/// シミュレータの姿勢での地磁気の計測値（伏角と偏角は無し）
+///
+/// * roll   : 右に傾けると正[deg]
+/// * pitch  : 機首上げが正[deg]
+/// * heading: 北から時計回りの真方位[deg]
+fn magnetometer(roll: f64, pitch: f64, heading: f64) -> Vector3<f64> {
+    quat::frame_rotation(attitude(roll, pitch, heading), MAG_R)
+}
+
+/// ロール，ピッチ，真方位[deg]から姿勢（機体座標系（前・左・上）→ENU）を求める．
+fn attitude(roll: f64, pitch: f64, heading: f64) -> Quaternion<f64> {
+    let yaw = quat::from_axis_angle([0.0, 0.0, 1.0], (90.0 - heading).to_radians());
+    let pitch = quat::from_axis_angle([0.0, 1.0, 0.0], -pitch.to_radians());
+    let roll = quat::from_axis_angle([1.0, 0.0, 0.0], roll.to_radians());
+    quat::mul(yaw, quat::mul(pitch, roll))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector3<f64>, b: Vector3<f64>) {
+        assert!(a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-6), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn parses_xplane_and_flightgear() {
+        // 北を向いて水平に飛んでいる
+        let mut packet = b"DATA*".to_vec();
+        for (index, v) in [(XPLANE_TIMES, [0.0, 12.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]), (3, [0.0; 8]),
+                           (4, [0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0]),
+                           (16, [0.25, 0.5, -0.125, 0.0, 0.0, 0.0, 0.0, 0.0]), (17, [0.0; 8])] {
+            packet.extend_from_slice(&index.to_le_bytes());
+            v.iter().for_each(|x: &f32| packet.extend_from_slice(&x.to_le_bytes()));
+        }
+        let s = parse_xplane(&packet).unwrap();
+        assert_eq!((s.time, s.gyr), (12.5, [0.5, -0.25, 0.125]));
+        assert_close(s.acc, [0.0, 0.0, STANDARD_GRAVITY]);
+        assert_close(s.mag, [1.0, 0.0, 0.0]);
+        assert_eq!(parse_xplane(b"RREF,"), None);
+
+        // 東を向いて右に90度傾いている（左の翼が上，北は機体の下側）
+        let s = parse_flightgear_line("3.0, 0.1, 0.2, 0.3, 0, -32.174, 0, 90, 0, 90").unwrap();
+        assert_eq!((s.time, s.gyr), (3.0, [0.1, -0.2, -0.3]));
+        assert_close(s.acc, [0.0, 32.174 * FEET, 0.0]);
+        assert_close(s.mag, [0.0, 0.0, -1.0]);
+        assert_eq!(parse_flightgear_line("3.0, 0.1"), None);
+    }
+}
@@ -0,0 +1,273 @@
+//! マイコンなどで実機のIMUドライバをSensorSourceとして使うためのアダプタ
+//!
+//! embedded-halのドライバはチップごとにAPIが違うので，ここでは角速度・加速度・地磁気を読む最小限のトレイトを決めておき，
+//! ドライバ側でそれを実装する（ドライバの読み取り関数を呼んで単位を揃えるだけの数行で済む）．
+//! 9軸のチップ（MPU-9250など）は1つの型で3つとも実装し，別々のチップは(ジャイロ, 加速度計, 地磁気センサ)の組として渡す．
+//!
+//! MPU-9250はembedded-hal 1.0のI2cトレイトで読むドライバ（Mpu9250）をここに用意してあるので，
+//! マイコンのHALやLinuxのi2c-dev（linux-embedded-hal）のI2Cをそのまま渡せる．
+//!
+//! サンプルの時刻はサンプリング周期（デフォルトはDT）ごとに進める（タイマ割り込みで一定周期で読む前提）．
+//! 読み取りに失敗したセンサの値はNaNにするので，フィルタはそのサンプルを不正な計測値として読み飛ばす．
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+
+use super::ahrs::STANDARD_GRAVITY;
+use super::source::{ImuSample, SensorSource};
+use super::DT;
+
+/// 角速度センサ
+pub trait Gyroscope {
+    type Error;
+
+    /// 角速度[rad/s]
+    fn read_gyro(&mut self) -> Result<[f32; 3], Self::Error>;
+}
+
+/// 加速度センサ
+pub trait Accelerometer {
+    type Error;
+
+    /// 加速度[m/s^2]
+    fn read_accel(&mut self) -> Result<[f32; 3], Self::Error>;
+}
+
+/// 地磁気センサ
+pub trait Magnetometer {
+    type Error;
+
+    /// 地磁気（単位は問わない，向きだけを使う）
+    fn read_mag(&mut self) -> Result<[f32; 3], Self::Error>;
+}
+
+/// 9軸のIMU（角速度・加速度・地磁気をまとめて読む）
+pub trait Imu {
+    /// 角速度・加速度・地磁気を読む（読み取りに失敗したセンサはNone）．
+    fn read(&mut self) -> [Option<[f32; 3]>; 3];
+}
+
+impl<T: Gyroscope + Accelerometer + Magnetometer> Imu for T {
+    fn read(&mut self) -> [Option<[f32; 3]>; 3] {
+        [self.read_gyro().ok(), self.read_accel().ok(), self.read_mag().ok()]
+    }
+}
+
+/// 別々のチップを組み合わせたIMU
+pub struct Separate<G, A, M> {
+    pub gyro: G,
+    pub accel: A,
+    pub mag: M,
+}
+
+impl<G: Gyroscope, A: Accelerometer, M: Magnetometer> Imu for Separate<G, A, M> {
+    fn read(&mut self) -> [Option<[f32; 3]>; 3] {
+        [self.gyro.read_gyro().ok(), self.accel.read_accel().ok(), self.mag.read_mag().ok()]
+    }
+}
+
+/// MPU-9250とAK8963のI2Cアドレス（AD0ピンがLの場合）
+pub const MPU9250_ADDR: u8 = 0x68;
+pub const AK8963_ADDR: u8 = 0x0C;
+
+/// 計測値の換算係数（±250 deg/s，±2 gの設定）
+const GYRO_SCALE: f32 = core::f32::consts::PI / 180.0 / 131.0;
+const ACCEL_SCALE: f32 = STANDARD_GRAVITY as f32 / 16384.0;
+const MAG_SCALE: f32 = 0.15;  // [uT/LSB]（16ビット出力）
+
+/// MPU-9250のドライバ（地磁気はバイパスモードで同じバスにつながるAK8963から読む）
+///
+/// ICM-20948はレジスタのバンク切り替えがあり配置も違うので，同じようにトレイトを実装した型を別に用意する．
+pub struct Mpu9250<I> {
+    i2c: I,
+    mag: [f32; 3],   // 最後に読んだ地磁気（新しい測定値が無い場合に返す）
+}
+
+impl<I: I2c> Mpu9250<I> {
+    /// スリープを解除し，測定範囲（±250 deg/s，±2 g）とAK8963の連続測定（100 Hz）を設定する．
+    pub fn new(mut i2c: I, delay: &mut impl DelayNs) -> Result<Self, I::Error> {
+        i2c.write(MPU9250_ADDR, &[0x6B, 0x00])?;  // PWR_MGMT_1: スリープ解除
+        delay.delay_ms(100);
+        i2c.write(MPU9250_ADDR, &[0x1B, 0x00])?;  // GYRO_CONFIG: ±250 deg/s
+        i2c.write(MPU9250_ADDR, &[0x1C, 0x00])?;  // ACCEL_CONFIG: ±2 g
+        i2c.write(MPU9250_ADDR, &[0x37, 0x02])?;  // INT_PIN_CFG: バイパスモード（AK8963を直接読む）
+        i2c.write(AK8963_ADDR, &[0x0A, 0x16])?;   // CNTL1: 16ビット，連続測定（100 Hz）
+        Ok( Self { i2c, mag: [f32::NAN; 3] } )
+    }
+
+    /// I2Cのバスを取り出す．
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    /// ビッグエンディアンの3軸の値
+    fn read_be(&mut self, reg: u8, scale: f32) -> Result<[f32; 3], I::Error> {
+        let mut buf = [0u8; 6];
+        self.i2c.write_read(MPU9250_ADDR, &[reg], &mut buf)?;
+        Ok( core::array::from_fn(|i| i16::from_be_bytes([buf[2 * i], buf[2 * i + 1]]) as f32 * scale) )
+    }
+}
+
+impl<I: I2c> Gyroscope for Mpu9250<I> {
+    type Error = I::Error;
+
+    fn read_gyro(&mut self) -> Result<[f32; 3], I::Error> {
+        self.read_be(0x43, GYRO_SCALE)  // GYRO_XOUT_H
+    }
+}
+
+impl<I: I2c> Accelerometer for Mpu9250<I> {
+    type Error = I::Error;
+
+    fn read_accel(&mut self) -> Result<[f32; 3], I::Error> {
+        self.read_be(0x3B, ACCEL_SCALE)  // ACCEL_XOUT_H
+    }
+}
+
+impl<I: I2c> Magnetometer for Mpu9250<I> {
+    type Error = I::Error;
+
+    /// 新しい測定値が無ければ前回の値を返し，磁気センサが飽和していればNaNを返す
+    /// （HalSourceではNaNの計測値として読み飛ばされる）．
+    fn read_mag(&mut self) -> Result<[f32; 3], I::Error> {
+        // ST1，HXL〜HZH（リトルエンディアン）とST2（読むと次の測定値に更新される）
+        let mut buf = [0u8; 8];
+        self.i2c.write_read(AK8963_ADDR, &[0x02], &mut buf)?;
+        if buf[0] & 0x01 == 0 {
+            return Ok(self.mag);  // ST1.DRDY: 新しい測定値が無い
+        }
+        self.mag = if buf[7] & 0x08 != 0 {
+            [f32::NAN; 3]  // ST2.HOFL: 磁気センサの飽和
+        } else {
+            let m: [f32; 3] = core::array::from_fn(|i| i16::from_le_bytes([buf[1 + 2 * i], buf[2 + 2 * i]]) as f32 * MAG_SCALE);
+            // AK8963の軸はx，yが入れ替わり，zが逆向き（加速度・角速度の軸に揃える）
+            [m[1], m[0], -m[2]]
+        };
+        Ok(self.mag)
+    }
+}
+
+/// IMUドライバから計測値を読む取得元
+pub struct HalSource<D> {
+    imu: D,
+    period: f64,     // サンプリング周期[s]
+    n_steps: u64,    // 読んだサンプル数
+    n_errors: u64,   // 読み取りに失敗したセンサの数の累計
+}
+
+impl<D: Imu> HalSource<D> {
+    pub fn new(imu: D) -> Self {
+        Self { imu, period: DT, n_steps: 0, n_errors: 0 }
+    }
+
+    /// サンプリング周期[s]を変える．
+    pub fn with_period(mut self, period: f64) -> Self {
+        self.period = period;
+        self
+    }
+
+    /// 読み取りに失敗したセンサの数の累計
+    pub fn n_errors(&self) -> u64 {
+        self.n_errors
+    }
+
+    /// ドライバを取り出す．
+    pub fn release(self) -> D {
+        self.imu
+    }
+}
+
+impl<D: Imu> SensorSource for HalSource<D> {
+    /// 実機のセンサに終わりは無いので常にSomeを返す．
+    fn next_sample(&mut self) -> Option<ImuSample> {
+        let [gyr, acc, mag] = self.imu.read().map(|v| match v {
+            Some(v) => v.map(f64::from),
+            None => {
+                self.n_errors += 1;
+                [f64::NAN; 3]
+            },
+        });
+        let time = self.n_steps as f64 * self.period;
+        self.n_steps += 1;
+        Some( ImuSample { time, gyr, acc, mag } )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Mock {
+        n: u32,
+    }
+
+    impl Gyroscope for Mock {
+        type Error = ();
+        fn read_gyro(&mut self) -> Result<[f32; 3], ()> {
+            Ok([0.1, 0.0, 0.0])
+        }
+    }
+
+    impl Accelerometer for Mock {
+        type Error = ();
+        fn read_accel(&mut self) -> Result<[f32; 3], ()> {
+            Ok([0.0, 0.0, 9.8])
+        }
+    }
+
+    impl Magnetometer for Mock {
+        type Error = ();
+        fn read_mag(&mut self) -> Result<[f32; 3], ()> {
+            // 2回に1回失敗する
+            self.n += 1;
+            if self.n.is_multiple_of(2) { Err(()) } else { Ok([0.0, 1.0, 0.0]) }
+        }
+    }
+
+    #[test]
+    fn reads_driver() {
+        let mut source = HalSource::new(Mock { n: 0 }).with_period(0.01);
+        let s = source.next_sample().unwrap();
+        assert_eq!((s.time, s.acc), (0.0, [0.0, 0.0, 9.8f32 as f64]));
+        let s = source.next_sample().unwrap();
+        assert_eq!(s.time, 0.01);
+        assert!(s.mag[0].is_nan());
+        assert_eq!(source.n_errors(), 1);
+
+        // 別々のチップの組み合わせ
+        let mut source = HalSource::new(Separate { gyro: Mock { n: 0 }, accel: Mock { n: 0 }, mag: Mock { n: 1 } });
+        assert!(source.next_sample().unwrap().mag[1].is_nan());
+    }
+
+    #[test]
+    fn reads_mpu9250_over_i2c() {
+        use embedded_hal_mock::eh1::delay::NoopDelay;
+        use embedded_hal_mock::eh1::i2c::{Mock as I2cMock, Transaction};
+
+        let expectations = [
+            Transaction::write(MPU9250_ADDR, vec![0x6B, 0x00]),
+            Transaction::write(MPU9250_ADDR, vec![0x1B, 0x00]),
+            Transaction::write(MPU9250_ADDR, vec![0x1C, 0x00]),
+            Transaction::write(MPU9250_ADDR, vec![0x37, 0x02]),
+            Transaction::write(AK8963_ADDR, vec![0x0A, 0x16]),
+            // 角速度 x = 131 LSB（1 deg/s）
+            Transaction::write_read(MPU9250_ADDR, vec![0x43], vec![0x00, 0x83, 0, 0, 0, 0]),
+            // 加速度 z = 16384 LSB（1 g）
+            Transaction::write_read(MPU9250_ADDR, vec![0x3B], vec![0, 0, 0, 0, 0x40, 0x00]),
+            // 地磁気 x = 100 LSB，z = -200 LSB（AK8963の軸，ST1.DRDY = 1，ST2.BITM = 1）
+            Transaction::write_read(AK8963_ADDR, vec![0x02], vec![0x01, 100, 0, 0, 0, 0x38, 0xFF, 0x10]),
+            // 新しい測定値が無い（ST1.DRDY = 0）
+            Transaction::write_read(AK8963_ADDR, vec![0x02], vec![0x00, 0, 0, 0, 0, 0, 0, 0x10]),
+            // 磁気センサの飽和（ST2.HOFL = 1）
+            Transaction::write_read(AK8963_ADDR, vec![0x02], vec![0x01, 0xFF, 0x7F, 0, 0, 0, 0, 0x18]),
+        ];
+        let mut imu = Mpu9250::new(I2cMock::new(&expectations), &mut NoopDelay).unwrap();
+        let [gyr, acc, mag] = imu.read().map(Option::unwrap);
+        assert!((gyr[0] - 1.0f32.to_radians()).abs() < 1e-6);
+        assert!((acc[2] - STANDARD_GRAVITY as f32).abs() < 1e-4);
+        assert_eq!(mag.map(f32::round), [0.0, 15.0, 30.0]);
+        assert_eq!(imu.read_mag().unwrap().map(f32::round), [0.0, 15.0, 30.0]);
+        assert!(imu.read_mag().unwrap().iter().all(|m| m.is_nan()));
+        imu.release().done();
+    }
+}
@@ -0,0 +1,111 @@
+//! 姿勢推定値を使った速度・位置の推測航法（ストラップダウン方式）
+//!
+//! 加速度を基準座標系に変換して重力を除き，速度と位置を積分する．
+//! 加速度センサのバイアスや姿勢誤差がそのまま積分されるので，補正無しでは時間とともに発散する．
+//! GNSSの位置・速度が得られる場合はcorrect_gnss()で補正する（疎結合）．
+//! 鉛直方向は気圧高度計の高度をcorrect_baro()で融合し，加速度のz軸バイアスも合わせて推定する．
+
+use super::DT;
+use super::ahrs::ACC_R;
+use super::quat::{self, Quaternion, Vector3};
+
+#[derive(Debug, Clone)]
+pub struct DeadReckoning {
+    pub vel: Vector3<f64>,  // 速度[m/s]（基準座標系）
+    pub pos: Vector3<f64>,  // 位置[m]（基準座標系）
+    gain_pos: f64,          // GNSSの位置による補正のゲイン
+    gain_vel: f64,          // GNSSの速度による補正のゲイン
+    pub acc_bias_z: f64,    // 基準座標系z軸方向の加速度バイアスの推定値[m/s^2]
+    gain_baro: [f64; 3],    // 気圧高度による補正のゲイン（高度，上昇率，加速度バイアス）
+}
+
+impl DeadReckoning {
+    /// * vel: 速度の初期値[m/s]
+    /// * pos: 位置の初期値[m]
+    pub fn new(vel: Vector3<f64>, pos: Vector3<f64>) -> Self {
+        Self {
+            vel,
+            pos,
+            gain_pos: 0.1,
+            gain_vel: 0.2,
+            acc_bias_z: 0.0,
+            gain_baro: Self::baro_gain(2.0),
+        }
+    }
+
+    /// GNSSで補正するときのゲインを設定する（デフォルトは位置0.1，速度0.2）．
+    ///
+    /// 1回の補正で推定値をGNSSの計測値に近づける割合なので，0〜1の範囲で指定する．
+    /// GNSSのノイズが大きいほど，また更新周期が短いほど小さくする．
+    pub fn with_gnss_gain(mut self, gain_pos: f64, gain_vel: f64) -> Self {
+        self.gain_pos = gain_pos;
+        self.gain_vel = gain_vel;
+        self
+    }
+
+    /// 気圧高度で補正するときの時定数を設定する（デフォルトは2秒）．
+    ///
+    /// 時定数より短い周期の高度変化は加速度の積分，長い周期の変化は気圧高度に従う．
+    ///
+    /// * tau: 時定数[s]
+    pub fn with_baro_time_constant(mut self, tau: f64) -> Self {
+        self.gain_baro = Self::baro_gain(tau);
+        self
+    }
+
+    /// 時定数tauの3次の相補フィルタ（3重極）となるゲインを計算する．
+    fn baro_gain(tau: f64) -> [f64; 3] {
+        [3.0 / tau * DT, 3.0 / (tau * tau) * DT, 1.0 / (tau * tau * tau) * DT]
+    }
+
+    /// 速度と位置を1ステップ進める．AttitudeFilterのcorrect()の後に呼ぶ．
+    ///
+    /// * q  : 姿勢推定値
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    pub fn update(&mut self, q: Quaternion<f64>, acc: Vector3<f64>) {
+        let mut acc_r = linear_acc(q, acc);
+        acc_r[2] -= self.acc_bias_z;
+
+        // 台形則で積分
+        let vel_prev = self.vel;
+        self.vel = quat::scale_add_vec(DT, acc_r, self.vel);
+        self.pos = quat::scale_add_vec(0.5 * DT, quat::add_vec(vel_prev, self.vel), self.pos);
+    }
+
+    /// GNSSの位置・速度で補正する．update()の後，GNSSの測位が得られたサンプルでだけ呼ぶ．
+    ///
+    /// * pos: GNSSで計測した位置[m]（基準座標系）
+    /// * vel: GNSSで計測した速度[m/s]（基準座標系）
+    pub fn correct_gnss(&mut self, pos: Vector3<f64>, vel: Vector3<f64>) {
+        let err_pos = quat::sub_vec(pos, self.pos);
+        let err_vel = quat::sub_vec(vel, self.vel);
+        self.pos = quat::scale_add_vec(self.gain_pos, err_pos, self.pos);
+        self.vel = quat::scale_add_vec(self.gain_vel, err_vel, self.vel);
+    }
+
+    /// 静止していることがわかっているときに速度を0にする（ZUPT）．update()の後に呼ぶ．
+    pub fn correct_zupt(&mut self) {
+        self.vel = [0.0; 3];
+    }
+
+    /// 気圧高度計の高度で鉛直方向（高度，上昇率，加速度バイアス）を補正する．update()の後に毎サンプル呼ぶ．
+    ///
+    /// * alt: 気圧高度[m]（基準座標系のz軸と同じく上向きを正とする）
+    pub fn correct_baro(&mut self, alt: f64) {
+        let err = alt - self.pos[2];
+        self.pos[2] += self.gain_baro[0] * err;
+        self.vel[2] += self.gain_baro[1] * err;
+        self.acc_bias_z -= self.gain_baro[2] * err;
+    }
+}
+
+impl Default for DeadReckoning {
+    fn default() -> Self {
+        Self::new([0.0; 3], [0.0; 3])
+    }
+}
+
+/// 計測した加速度を基準座標系に変換し，重力を除いた運動加速度を返す．
+pub fn linear_acc(q: Quaternion<f64>, acc: Vector3<f64>) -> Vector3<f64> {
+    quat::sub_vec(quat::vector_rotation(q, acc), ACC_R)
+}
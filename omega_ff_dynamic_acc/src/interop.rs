@@ -0,0 +1,160 @@
+//! 他の線形代数ライブラリ（nalgebra，glamなど）との変換（interopフィーチャ）
+//!
+//! このクレートの四元数は(w, [x, y, z])のタプル，ベクトルは[f64; 3]で，どちらもこのクレートで定義した型ではない．
+//! nalgebraの型もこのクレートの型ではないので，その間にFrom/Intoを直接実装することはできない（孤児ルール）．
+//! そこで，nalgebraやglamが配列との変換に使う並び（四元数は[x, y, z, w]）に並べ替える関数を用意する．
+//! nalgebraのベクトルは[f64; 3]のままVector3::from()，.into()で変換できる．
+//! 描画に使うglam（Quat，Vec3）やWebGLはf32なので，f32の配列にする関数も用意する．
+//!
+//! nalgebraフィーチャを有効にすると，UnitQuaternionとの変換関数（to_unit_quaternion，from_unit_quaternion）と，
+//! このクレートの型であるAttitudeFilterから姿勢推定値への`From`も使える．
+//! glamフィーチャでも同じく，f32のQuat，Vec3との変換関数（to_quat，from_quat，to_vec3，from_vec3）と`From`が使える．
+//!
+//! ```ignore
+//! let q: nalgebra::UnitQuaternion<f64> = interop::to_unit_quaternion(filter.q);  // または (&filter).into()
+//! let q_back = interop::from_unit_quaternion(&q);
+//! let bias = nalgebra::Vector3::from( filter.gyro_bias() );
+//!
+//! let rotation: glam::Quat = interop::to_quat(filter.q);  // または (&filter).into()
+//! let bias = interop::to_vec3( filter.gyro_bias() );
+//! ```
+
+use super::quat::{Quaternion, Vector3};
+#[cfg(any(feature = "nalgebra", feature = "glam"))]
+use super::ahrs::AttitudeFilter;
+
+/// 四元数を[x, y, z, w]の順の配列にする．
+pub fn to_xyzw(q: Quaternion<f64>) -> [f64; 4] {
+    [q.1[0], q.1[1], q.1[2], q.0]
+}
+
+/// [x, y, z, w]の順の配列を四元数にする（正規化はしない）．
+pub fn from_xyzw(a: [f64; 4]) -> Quaternion<f64> {
+    (a[3], [a[0], a[1], a[2]])
+}
+
+/// 四元数を[x, y, z, w]の順のf32の配列にする．
+pub fn to_xyzw_f32(q: Quaternion<f64>) -> [f32; 4] {
+    to_xyzw(q).map(|v| v as f32)
+}
+
+/// [x, y, z, w]の順のf32の配列を四元数にする（正規化はしない）．
+pub fn from_xyzw_f32(a: [f32; 4]) -> Quaternion<f64> {
+    from_xyzw( a.map(f64::from) )
+}
+
+/// ベクトルをf32の配列にする．
+pub fn to_f32(v: Vector3<f64>) -> [f32; 3] {
+    v.map(|v| v as f32)
+}
+
+/// 四元数をnalgebraのUnitQuaternionにする（正規化する）．
+#[cfg(feature = "nalgebra")]
+pub fn to_unit_quaternion(q: Quaternion<f64>) -> nalgebra::UnitQuaternion<f64> {
+    nalgebra::UnitQuaternion::new_normalize( nalgebra::Quaternion::new(q.0, q.1[0], q.1[1], q.1[2]) )
+}
+
+/// nalgebraのUnitQuaternionを四元数にする．
+#[cfg(feature = "nalgebra")]
+pub fn from_unit_quaternion(q: &nalgebra::UnitQuaternion<f64>) -> Quaternion<f64> {
+    (q.w, [q.i, q.j, q.k])
+}
+
+/// 姿勢推定値をnalgebraのUnitQuaternionとして取り出す．
+#[cfg(feature = "nalgebra")]
+impl From<&AttitudeFilter> for nalgebra::UnitQuaternion<f64> {
+    fn from(filter: &AttitudeFilter) -> Self {
+        to_unit_quaternion(filter.q)
+    }
+}
+
+/// 四元数をglamのQuat（f32）にする（正規化する）．
+#[cfg(feature = "glam")]
+pub fn to_quat(q: Quaternion<f64>) -> glam::Quat {
+    glam::Quat::from_array( to_xyzw_f32(q) ).normalize()
+}
+
+/// glamのQuatを四元数にする．
+#[cfg(feature = "glam")]
+pub fn from_quat(q: glam::Quat) -> Quaternion<f64> {
+    from_xyzw_f32( q.to_array() )
+}
+
+/// ベクトルをglamのVec3（f32）にする．
+#[cfg(feature = "glam")]
+pub fn to_vec3(v: Vector3<f64>) -> glam::Vec3 {
+    glam::Vec3::from_array( to_f32(v) )
+}
+
+/// glamのVec3をベクトルにする．
+#[cfg(feature = "glam")]
+pub fn from_vec3(v: glam::Vec3) -> Vector3<f64> {
+    v.to_array().map(f64::from)
+}
+
+/// 姿勢推定値をglamのQuatとして取り出す．
+#[cfg(feature = "glam")]
+impl From<&AttitudeFilter> for glam::Quat {
+    fn from(filter: &AttitudeFilter) -> Self {
+        to_quat(filter.q)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_quaternion_order() {
+        let q = (0.5, [0.5, -0.5, 0.5]);
+        assert_eq!(to_xyzw(q), [0.5, -0.5, 0.5, 0.5]);
+        assert_eq!(from_xyzw(to_xyzw(q)), q);
+        assert_eq!(from_xyzw_f32(to_xyzw_f32(q)), q);
+        assert_eq!(to_f32([0.25, -1.0, 2.0]), [0.25f32, -1.0, 2.0]);
+    }
+
+    #[test]
+    #[cfg(feature = "nalgebra")]
+    fn round_trips_through_nalgebra() {
+        use super::super::quat;
+
+        let q = quat::from_axis_angle(quat::normalize_vec([1.0, -2.0, 0.5]), 0.7);
+        let q_na = to_unit_quaternion(q);
+        let q_back = from_unit_quaternion(&q_na);
+        assert!(quat::norm(quat::sub(q, q_back)) < 1e-15);
+
+        // 回転の向き（q v q*）も同じ
+        let v = [0.3, -1.0, 2.0];
+        let v_na = q_na.transform_vector(&nalgebra::Vector3::from(v));
+        let v_q = quat::vector_rotation(q, v);
+        assert!(quat::norm_vec(quat::sub_vec(v_na.into(), v_q)) < 1e-12);
+
+        // フィルタの姿勢推定値から直接変換できる
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        filter.q = q;
+        let q_filter: nalgebra::UnitQuaternion<f64> = (&filter).into();
+        assert_eq!(q_filter, q_na);
+    }
+
+    #[test]
+    #[cfg(feature = "glam")]
+    fn round_trips_through_glam() {
+        use super::super::quat;
+
+        // f32を経由するので誤差はf32の精度程度
+        let q = quat::from_axis_angle(quat::normalize_vec([1.0, -2.0, 0.5]), 0.7);
+        let q_glam = to_quat(q);
+        assert!(quat::norm(quat::sub(q, from_quat(q_glam))) < 1e-6);
+
+        let v = [0.25, -1.0, 2.0];
+        assert_eq!(from_vec3(to_vec3(v)), v);
+
+        // 回転の向き（q v q*）も同じ
+        let v_glam = from_vec3(q_glam * to_vec3(v));
+        assert!(quat::norm_vec(quat::sub_vec(v_glam, quat::vector_rotation(q, v))) < 1e-5);
+
+        let mut filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        filter.q = q;
+        assert_eq!(glam::Quat::from(&filter), q_glam);
+    }
+}
@@ -0,0 +1,41 @@
+//! Ctrl-C（SIGINT，SIGTERM）による中断（signalフィーチャが必要）
+//!
+//! ログ再生・ライブ入力の途中で中断された場合も，それまでの推定結果と統計値を書き出し，途中状態を保存してから終わる．
+//! ハンドラは中断を要求されたことを記録するだけで，推定のループがサンプルごとに確認して抜ける．
+//! SA_RESTARTを付けないので，UDPやTCPの受信待ちはEINTRで戻って入力の終わりとして扱われる．
+//! 標準入力やシリアルポートは次の行が届いた時点で抜ける．
+//! 2回目のCtrl-Cは既定の動作に戻すので，すぐに強制終了する．
+//! signalフィーチャが無効な場合やUnix以外では何もしない（Ctrl-Cでそのまま終了する）．
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 中断を要求されたかどうか
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// SIGINTとSIGTERMのハンドラを設定する．
+#[cfg(all(unix, feature = "signal"))]
+pub fn install() {
+    extern "C" fn on_signal(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESETHAND;
+        libc::sigemptyset(&mut action.sa_mask);
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) < 0 {
+                eprintln!("シグナルのハンドラを設定できませんでした: {}", std::io::Error::last_os_error());
+            }
+        }
+    }
+}
+
+#[cfg(not(all(unix, feature = "signal")))]
+pub fn install() {}
+
+/// 中断を要求されていればtrue
+pub fn requested() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,45 @@
+//! 角速度をフィードバックする形で補正を行う姿勢推定フィルタ
+//! 
+//! 外乱検知式はE1，E2から選べる（ahrs::Detector）
+
+pub use quaternion_core as quat;
+
+pub mod ahrs;
+pub mod align;
+pub mod bank;
+pub mod calibration;
+pub mod coning;
+pub mod estimator;
+pub mod flightsim;
+pub mod ins;
+pub mod mavlink;
+pub mod noise_estimation;
+pub mod pipeline;
+pub mod redundant;
+pub mod resample;
+pub mod smoother;
+pub mod source;
+pub mod spike;
+pub mod synthetic;
+pub mod timestamp;
+pub mod wahba;
+pub mod zupt;
+#[cfg(feature = "fixed")]
+pub mod ahrs_fixed;
+#[cfg(feature = "hal")]
+pub mod hal;
+#[cfg(feature = "async")]
+pub mod async_source;
+#[cfg(feature = "interop")]
+pub mod interop;
+#[cfg(feature = "ahrs")]
+pub mod ahrs_compat;
+#[cfg(feature = "shm")]
+pub mod shm;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// サンプリング周期[s]
+pub const DT: f64 = 0.02;
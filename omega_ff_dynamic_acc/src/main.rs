@@ -0,0 +1,780 @@
+//! 姿勢推定フィルタのシミュレーション
+//! 
+//! 外乱検知式はE1，E2から選べる（ahrs::Detector）
+
+use std::fs;
+use std::io::{self, BufWriter};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use omega_ff_dynamic_acc::{ahrs, calibration, ins, noise_estimation, quat, redundant, spike, zupt, DT};
+use omega_ff_dynamic_acc::estimator::AttitudeEstimator;
+use omega_ff_dynamic_acc::source::{CsvSource, UdpSource, ReadSource, PacketFormat};
+
+use error::Context;
+use options::{FilterOptions, Live, Mode, Options};
+
+mod calibrate;
+mod compare;
+mod database;
+mod error;
+mod interrupt;
+mod mqtt;
+mod optimize;
+mod output;
+mod noise;
+mod options;
+mod progress;
+mod record;
+mod replay;
+mod rerun_log;
+mod scenario;
+mod score;
+#[cfg(feature = "serve")]
+mod service;
+mod shared;
+mod sweep;
+mod timing;
+mod trajectory;
+mod viewer;
+mod window;
+
+/// シミュレーション結果の出力先
+const RESULT_PATH: &str = "result.csv";
+
+const SIM_TIME: f64 = 30.0;
+const N: usize = (SIM_TIME / DT) as usize + 1;
+
+/// 角速度センサのノイズ分散
+const GYR_VAR: f64 = 0.0001;
+
+/// 加速度センサのノイズ分散
+const ACC_VAR: f64 = 0.01;
+
+/// 地磁気センサのノイズ分散
+const MAG_VAR: f64 = 0.01;
+
+/// GNSSの更新周波数[Hz]
+const GNSS_RATE: f64 = 5.0;
+
+/// GNSSの位置のノイズ分散[m^2]
+const GNSS_POS_VAR: f64 = 4.0;
+
+/// GNSSの速度のノイズ分散[(m/s)^2]
+const GNSS_VEL_VAR: f64 = 0.01;
+
+/// 気圧高度のノイズ分散[m^2]
+const BARO_VAR: f64 = 0.25;
+
+/// 模擬する計測値にノイズを加えるかどうか（センサごと）
+///
+/// バイアスや加速度外乱はそのまま加えるので，誤差の原因がノイズ・バイアス・外乱判定のどれにあるかを切り分けられる．
+#[derive(Debug, Clone, Copy)]
+struct SensorNoise {
+    gyr: bool,   // 角速度センサ
+    acc: bool,   // 加速度センサ
+    mag: bool,   // 地磁気センサ
+    gnss: bool,  // GNSSの位置・速度
+    baro: bool,  // 気圧高度
+}
+
+impl SensorNoise {
+    /// 全てのセンサにノイズを加える
+    const ALL: Self = Self { gyr: true, acc: true, mag: true, gnss: true, baro: true };
+
+    /// 全てのセンサのノイズを無くす
+    const NONE: Self = Self { gyr: false, acc: false, mag: false, gnss: false, baro: false };
+
+    /// ノイズを無効にしたセンサの分散は0にする．
+    fn var(enabled: bool, variance: f64) -> f64 {
+        if enabled { variance } else { 0.0 }
+    }
+}
+
+/// アンチワインドアップのパラメータ（バイアス推定値の上限[rad/s]，積分を止める姿勢の差[rad]）
+const MAX_BIAS: f64 = 0.1;
+const MAX_INTEG_ERR: f64 = 0.2;
+
+/// 可観測性による積分の停止のパラメータ（可観測性の移動平均の時定数[s]，積分をそのまま行う可観測性の下限）
+const OBS_TAU: f64 = 5.0;
+const OBS_THR: f64 = 0.5;
+
+/// 地磁気の大きさによる重み付けのパラメータ（重みを下げ始める相対誤差，大きさを学習する時定数[s]）
+const MAG_NORM_TOL: f64 = 0.1;
+const MAG_NORM_TAU: f64 = 10.0;
+
+/// 加速度バイアスの推定の時定数[s]
+const ACC_BIAS_TAU: f64 = 30.0;
+
+/// 静止検出のパラメータ（サンプル数，角速度・加速度の分散の閾値，角速度の平均の大きさの上限[rad/s]）
+const ZUPT_WINDOW: usize = 25;
+const ZUPT_THR_GYR_VAR: f64 = 0.001;
+const ZUPT_THR_ACC_VAR: f64 = 0.1;
+const ZUPT_MAX_RATE: f64 = 0.1;
+
+/// --zuptか--initを付けたシミュレーションで，開始時に静止させておく時間[s]
+const STATIC_TIME: f64 = 5.0;
+
+/// 冗長化したIMUの計測値を統合する際の，中央値との差の閾値（角速度[rad/s]，加速度[m/s^2]，地磁気）
+const IMU_THR_GYR: f64 = 0.06;
+const IMU_THR_ACC: f64 = 0.6;
+const IMU_THR_MAG: f64 = 0.6;
+
+/// --imusを付けたシミュレーションで，1台目の角速度センサが故障する時刻[s]と故障後に加わるバイアス[rad/s]
+const IMU_FAULT_TIME: f64 = 15.0;
+const IMU_FAULT_BIAS: [f64; 3] = [0.5, 0.0, 0.0];
+
+/// --gyro-spikesを付けたシミュレーションで，角速度に1サンプルだけの外れ値を加える間隔（サンプル数）と大きさ[rad/s]
+const GYRO_SPIKE_INTERVAL: usize = 97;
+const GYRO_SPIKE: [f64; 3] = [4.0, -6.0, 3.0];
+
+/// --gyro-failを付けたシミュレーションで，角速度センサが故障して計測値がNaNになる期間[s]
+const GYRO_FAIL_START: f64 = 22.0;
+const GYRO_FAIL_END: f64 = 26.0;
+
+/// 角速度の不正な入力がこの回数続いたら，角速度を使わないモードに切り替える
+const GYRO_FAULT_SAMPLES: u32 = 10;
+
+/// 角加速度を推定するオブザーバの帯域（固有角周波数）[rad/s]
+const ANG_ACCEL_WN: f64 = 5.0;
+
+/// 計測ノイズの分散を推定する指数移動平均の時定数（サンプル数）
+const NOISE_EST_WINDOW: usize = 500;
+
+/// 角速度の外れ値とみなす，直近3サンプルの中央値との差[rad/s]
+const GYRO_SPIKE_THR: f64 = 1.0;
+
+/// 姿勢推定フィルタのパラメータ（シミュレーションとログ再生で共通）
+const ALPHA: f64 = 1.0;
+const BETA: f64 = 0.2;
+const THR_WEAK: f64 = 0.04;
+const THR_STRONG: f64 = 0.08;
+
+/// チルトとヨー角を分離して補正する場合の，ヨー角の収束時間[s]
+const ALPHA_YAW: f64 = 1.0;
+
+fn main() {
+    let result = Options::parse().and_then(|opts| {
+        init_tracing(opts.log.as_deref())?;
+        run(&opts)
+    });
+    if let Err(e) = result {
+        eprintln!("エラー: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// 指定されたモードを実行する．
+fn run(opts: &Options) -> error::Result<()> {
+    match opts.mode {
+        Mode::CalibrateMag(ref path) => calibrate::mag(path),
+        Mode::CalibrateAcc(ref path) => calibrate::acc(path),
+        Mode::SelfTest => self_test(opts),
+        Mode::Serve(ref addr) => serve(addr, opts),
+        Mode::Live(ref live) => match live {
+            Live::Stdin => replay::stream(CsvSource::new(io::BufReader::new(io::stdin())), opts),
+            Live::Serial(dev) => replay::stream(CsvSource::open(dev).context(dev)?, opts),
+            Live::Udp(addr) => replay::stream(UdpSource::bind(addr.as_str(), None).context(addr)?, opts),
+            Live::Phone(addr) => {
+                let source = UdpSource::bind(addr.as_str(), None).context(addr)?.with_format(PacketFormat::Sensorstream);
+                replay::stream(source, opts)
+            },
+            Live::Hil(addr) => {
+                let source = UdpSource::bind(addr.as_str(), None).context(addr)?.with_format(PacketFormat::Hil);
+                replay::stream(source, opts)
+            },
+            Live::Mavlink(addr) => match addr.strip_prefix("tcp:") {
+                Some(addr) => replay::stream(ReadSource::accept(addr, PacketFormat::Mavlink).context(addr)?, opts),
+                None => replay::stream(UdpSource::bind(addr.as_str(), None).context(addr)?.with_format(PacketFormat::Mavlink), opts),
+            },
+            Live::FlightSim(addr, format) => {
+                let source = UdpSource::bind(addr.as_str(), None).context(addr)?.with_format(*format);
+                replay::stream(source, opts)
+            },
+            Live::Synthetic => {
+                let steps = scenario::generate(&mut rand::thread_rng(), &opts.sim.noise);
+                replay::stream(scenario::source(steps), opts)
+            },
+        },
+        Mode::Score(ref estimate, ref truth) => score::run(estimate, truth, &opts.eval.score_opts),
+        Mode::Sweep => sweep::run(&opts.eval.sweep_ranges, opts.eval.seed, opts),
+        Mode::Optimize => optimize::run(opts.eval.scenarios, opts.eval.seed, opts),
+        Mode::Compare(ref specs) => compare::run(specs, opts),
+        Mode::Replay(ref path) => replay::run(path, opts),
+        Mode::Simulate => simulate(opts),
+    }
+}
+
+/// 状態の変化を記録するイベントを標準エラー出力に書き出すように設定する．
+/// 
+/// * level: 書き出すイベントの詳細度（Noneならwarn）
+#[cfg(feature = "tracing")]
+fn init_tracing(level: Option<&str>) -> error::Result<()> {
+    let level: tracing::Level = level.unwrap_or("warn").parse()
+        .map_err(|_| error::Error::argument("--logにはerror，warn，info，debug，traceのいずれかを指定してください"))?;
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stderr()))
+        .init();
+    Ok(())
+}
+
+#[cfg(not(feature = "tracing"))]
+fn init_tracing(level: Option<&str>) -> error::Result<()> {
+    match level {
+        Some(_) => Err( error::Error::Feature { option: "--log", feature: "tracing" } ),
+        None => Ok(()),
+    }
+}
+
+/// コマンドライン引数に合わせて姿勢推定フィルタを作る．
+fn new_filter(opts: &FilterOptions) -> ahrs::AttitudeFilter {
+    let mut filter = ahrs::AttitudeFilter::new(ALPHA, BETA, THR_WEAK, THR_STRONG)
+        .with_detector(opts.detector)
+        .with_gain_schedule(opts.gain_schedule)
+        .with_attitude_solver(opts.attitude_solver)
+        .with_integral_leak(opts.leak)
+        .with_gyro_fault_detection(GYRO_FAULT_SAMPLES)
+        .with_substeps(opts.substeps)
+        .with_reset_callback(report_reset);
+    if opts.innovation_gate {
+        filter = filter.with_innovation_gate(ahrs::InnovationGate { acc_var: ACC_VAR, mag_var: MAG_VAR, chi2: ahrs::CHI2_3DOF_99 });
+    }
+    if opts.observability_freeze {
+        filter = filter.with_observability_freeze(OBS_TAU, OBS_THR);
+    }
+    if opts.directional_rejection {
+        filter = filter.with_directional_rejection();
+    }
+    if opts.mag_norm_gate {
+        filter = filter.with_mag_norm_gate(MAG_NORM_TOL, MAG_NORM_TAU);
+    }
+    if opts.estimate_acc_bias {
+        filter = filter.with_accel_bias_estimation(ACC_BIAS_TAU);
+    }
+    if opts.anti_windup {
+        filter = filter.with_integral_limit(MAX_BIAS).with_conditional_integration(MAX_INTEG_ERR);
+    }
+    if opts.decoupled {
+        filter = filter.with_decoupled_yaw(ALPHA_YAW);
+    }
+    if let Some(inclination) = opts.inclination {
+        filter = filter.with_inclination(inclination);
+    }
+    if opts.angular_acc {
+        filter = filter.with_angular_acceleration(ANG_ACCEL_WN);
+    }
+    filter
+}
+
+/// 計測値を受け取り推定値を返すサービスを起動する（--serve）．
+#[cfg(feature = "serve")]
+fn serve(addr: &str, opts: &Options) -> error::Result<()> {
+    service::run(addr, opts)
+}
+
+#[cfg(not(feature = "serve"))]
+fn serve(_addr: &str, _opts: &Options) -> error::Result<()> {
+    Err( error::Error::Feature { option: "--serve", feature: "serve" } )
+}
+
+/// 設定したフィルタで起動前の確認を行い，結果を表示する（問題があればエラーを返す）．
+fn self_test(opts: &Options) -> error::Result<()> {
+    let report = new_filter(&opts.filter).self_test();
+    println!("四元数のノルム: {:.9}", report.quaternion_norm);
+    println!("収束後の姿勢誤差: {:.3e} rad", report.convergence_error);
+    for problem in &report.problems {
+        println!("問題: {}", problem);
+    }
+    if !report.passed() {
+        return Err( error::Error::SelfTest(report.problems.len()) );
+    }
+    println!("問題はありません");
+    Ok(())
+}
+
+/// 角速度の外れ値の除去を作る（シミュレーション，ログ再生で共通）．
+fn new_spike_filter() -> spike::GyroSpikeFilter {
+    spike::GyroSpikeFilter::new(GYRO_SPIKE_THR)
+}
+
+/// 静止検出器を作る（シミュレーション，ログ再生，較正で共通）．
+fn new_stationary_detector() -> zupt::StationaryDetector {
+    zupt::StationaryDetector::new(ZUPT_WINDOW, ZUPT_THR_GYR_VAR, ZUPT_THR_ACC_VAR).with_max_rate(ZUPT_MAX_RATE)
+}
+
+/// 姿勢推定フィルタが発散して初期状態に戻ったことを知らせる．
+fn report_reset(health: ahrs::Health) {
+    eprintln!("姿勢推定フィルタを初期状態に戻しました（{:?}）", health);
+}
+
+/// CSVに書き出すオイラー角を計算する．
+/// 
+/// 回転順序によらず，Z, Y, X軸周りの角度の順に並べる（data_plot.pyの並びに合わせる）．
+fn euler_angles(q: quat::Quaternion<f64>, seq: ahrs::EulerSequence) -> quat::Vector3<f64> {
+    let angles = ahrs::to_euler_angles(q, seq);
+    match seq {
+        ahrs::EulerSequence::ZYX => angles,
+        ahrs::EulerSequence::XYZ => [angles[2], angles[1], angles[0]],
+    }
+}
+
+fn simulate(opts: &Options) -> error::Result<()> {
+    // CSVファイルにデータ保存（同一ファイルが存在したら上書き）
+    let mut file = output::CsvWriter::new( BufWriter::new( fs::File::create(RESULT_PATH).context(RESULT_PATH)? ) )
+        .with_columns(opts.output.columns)
+        .with_precision(opts.output.precision);
+
+    // ノイズに使う標準正規分布の乱数（記録した乱数を使うか，生成して記録する）
+    let mut randn = match (&opts.sim.load_noise, &opts.sim.dump_noise) {
+        (Some(path), _) => noise::NoiseSource::load(path)?,
+        (None, Some(path)) => noise::NoiseSource::random().with_dump(path)?,
+        (None, None) => noise::NoiseSource::random(),
+    };
+
+    // 姿勢推定フィルタ
+    let mut filter = new_filter(&opts.filter);
+
+    // 推測航法（機体は回転するだけなので速度・位置の真値は0）
+    let mut dr = ins::DeadReckoning::default();
+    let mut stationary = new_stationary_detector();
+    let mut startup = opts.input.init.map(calibration::StartupCalibrator::new);
+    let mut timer = opts.input.profile.then(timing::StepTimer::new);
+    let mut imus = (opts.sim.imus > 1).then(|| {
+        redundant::ImuArray::new(imu_mounts(opts.sim.imus), IMU_THR_GYR, IMU_THR_ACC, IMU_THR_MAG)
+    });
+    let mut spikes = opts.input.reject_spikes.then(new_spike_filter);
+    let mut noise_est = opts.input.estimate_noise.then(new_noise_estimator);
+    let mut recorder = opts.output.record.as_deref().map(record::Recorder::create).transpose()?;
+    let mut plot = opts.output.plotjuggler.as_deref().map(|addr| output::JsonUdpSender::connect(addr).context(addr)).transpose()?;
+    let mut mqtt = opts.output.mqtt.as_deref().map(|addr| mqtt::MqttPublisher::connect(addr, &opts.output.mqtt_topic).context(addr)).transpose()?;
+    let mut shm = opts.output.shm.as_deref().map(shared::SharedStatePublisher::create).transpose()?;
+    let view = opts.output.viewer.map(viewer::Viewer::start).transpose()?;
+    let mut rerun = opts.output.rerun.as_deref().map(rerun_log::RerunLogger::connect).transpose()?;
+    let mut window = opts.output.window.then(window::Window::open).transpose()?;
+    let mut n_faults = vec![0; opts.sim.imus];  // IMUごとに計測値を除いた回数
+    let gnss_interval = (1.0 / (GNSS_RATE * DT)).round() as usize;  // GNSSの更新間隔（サンプル数）
+    // 軌跡を与えた場合は軌跡の長さだけ実行する
+    let n_steps = opts.sim.trajectory.as_ref().map_or(N, |traj| (traj.duration() / DT) as usize + 1);
+    let mut progress = progress::Progress::new("シミュレーション", Some(n_steps));
+
+    // 固定小数点版のフィルタ（f64版との誤差を評価する）
+    #[cfg(feature = "fixed")]
+    let mut filter_fix = omega_ff_dynamic_acc::ahrs_fixed::AttitudeFilter::new(ALPHA, BETA, THR_WEAK, THR_STRONG)
+        .map_err(error::Error::argument)?
+        .with_detector(opts.filter.detector);
+    #[cfg(feature = "fixed")]
+    let mut fix_err = FixedError::new();
+
+    let mut q = (1.0, [0.0; 3]);
+    let mut q_gyro = q;  // 角速度の積分だけで求めた姿勢（補正の効果と比べる）
+    //q = quat::normalize((0.0, [1.0, -0.5, 1.5]));  // 初期値をずらす
+    let gyr_bias = [-0.02, 0.01, 0.05];
+    // 基準座標系上における地磁気（伏角を与えた場合は下向きの成分を持つ）
+    let mag_r = match opts.filter.inclination {
+        Some(inc) => [0.0, inc.cos(), -inc.sin()],
+        None => ahrs::MAG_R,
+    };
+    let mut a_dr = [0.0; 3];  // センサに直接加わる加速度外乱
+    let mut gyr_prev = [0.0; 3];     // 前のステップの角速度の真値（角加速度の真値を求める）
+    let mut rate_prev = None;        // 前のステップのバイアスを除いた角速度（単純な差分と比べる）
+    let mut ang_acc_err = (Vec::new(), Vec::new());  // 角加速度の誤差（オブザーバ，単純な差分）
+
+    let start = Instant::now();
+    // ---- Loop start ---- //
+    for t in 0..n_steps {
+        let time = t as f64 * DT;
+        randn.begin_step(time)?;
+
+        // 角速度の真値と加速度外乱（軌跡を与えた場合は軌跡の値）
+        let gyr = if let Some(ref traj) = opts.sim.trajectory {
+            let (gyr, acc) = traj.at(time);
+            a_dr = acc;
+            gyr
+        } else {
+            // 加速度外乱印加
+            if (10.0..=20.0).contains(&time) {
+                //a_dr[0] = 0.5 * (time * 5.0).sin() + 1.0;
+                a_dr[0] = 3.0;
+            } else {
+                a_dr[0] = 0.0;
+            }
+            if (opts.input.zupt || opts.input.init.is_some()) && time < STATIC_TIME {
+                [0.0; 3]
+            } else {
+                [0.1; 3]
+            }
+        };
+
+        // 積分
+        q = integrate(q, gyr, DT);
+
+        // 計測値生成（ノイズ無し）
+        let mut acc_b = quat::frame_rotation(q, ahrs::ACC_R);
+        let mag_b = quat::frame_rotation(q, mag_r);
+
+        // 外乱を加える
+        acc_b = quat::add_vec(acc_b, a_dr);
+
+        // 機体が前進している場合は向心加速度が加わる
+        if let Some(speed) = opts.filter.speed {
+            acc_b = quat::add_vec(acc_b, quat::cross_vec(gyr, [speed, 0.0, 0.0]));
+        }
+
+        // ノイズを加える（冗長化したIMUの場合は各IMUの計測値を統合する）
+        let truth = ahrs::Sample { gyr: quat::add_vec(gyr, gyr_bias), acc: acc_b, mag: mag_b };
+        let ahrs::Sample { gyr: mut gyr_b, acc: acc_b, mag: mag_b } = match imus {
+            Some(ref mut imus) => {
+                let fused = measure_imus(imus, &mut randn, &opts.sim.noise, time, truth);
+                for (n, &fault) in n_faults.iter_mut().zip(imus.faults()) {
+                    *n += fault as usize;
+                }
+                fused
+            },
+            None => measure(&mut randn, &opts.sim.noise, truth),
+        };
+
+        // 角速度の外れ値
+        if opts.sim.gyro_spikes && t % GYRO_SPIKE_INTERVAL == GYRO_SPIKE_INTERVAL / 2 {
+            gyr_b = quat::add_vec(gyr_b, GYRO_SPIKE);
+        }
+        // 角速度センサの故障
+        if opts.sim.gyro_fail && (GYRO_FAIL_START..GYRO_FAIL_END).contains(&time) {
+            gyr_b = [f64::NAN; 3];
+        }
+        if let Some(ref mut recorder) = recorder {
+            recorder.write(time, &ahrs::Sample { gyr: gyr_b, acc: acc_b, mag: mag_b })?;
+        }
+        if let Some(ref mut spikes) = spikes {
+            gyr_b = spikes.update(gyr_b);
+        }
+
+        // 補正せずに角速度の計測値を積分しただけの姿勢（不正な計測値の間は保持する）
+        if gyr_b.iter().all(|v| v.is_finite()) {
+            q_gyro = integrate(q_gyro, gyr_b, DT);
+        }
+
+        // 推定
+        let still = (opts.input.zupt || startup.is_some()) && stationary.update(gyr_b, acc_b);
+        let is_static = opts.input.zupt && still;
+        step(&mut filter, &opts.filter, &mut timer, gyr_b, acc_b, mag_b, is_static, DT);
+        update_startup(&mut startup, &mut filter, ahrs::Sample { gyr: gyr_b, acc: acc_b, mag: mag_b }, still);
+        update_noise_estimate(&mut noise_est, &mut filter, acc_b, mag_b);
+        dr.update(filter.q, acc_b);
+        if is_static {
+            dr.correct_zupt();
+        }
+        if opts.sim.gnss && t % gnss_interval == 0 {
+            let pos_gnss = add_noise(&mut randn, SensorNoise::var(opts.sim.noise.gnss, GNSS_POS_VAR), [0.0; 3]);
+            let vel_gnss = add_noise(&mut randn, SensorNoise::var(opts.sim.noise.gnss, GNSS_VEL_VAR), [0.0; 3]);
+            dr.correct_gnss(pos_gnss, vel_gnss);
+        }
+        if opts.sim.baro {
+            let alt = SensorNoise::var(opts.sim.noise.baro, BARO_VAR).sqrt() * randn.sample();
+            dr.correct_baro(alt);
+        }
+        #[cfg(feature = "fixed")]
+        if fixed_comparable(opts) {
+            fix_err.update(&filter, &mut filter_fix, ahrs::Sample { gyr: gyr_b, acc: acc_b, mag: mag_b });
+        }
+
+        // 角加速度の推定値の誤差（オブザーバと単純な差分）
+        let angular_acc = filter.angular_acceleration();
+        if let Some(ang_acc) = angular_acc {
+            let ang_acc_true = quat::scale_vec(DT.recip(), quat::sub_vec(gyr, gyr_prev));
+            // 角速度が不正な間（--gyro-fail）は比べない
+            let rate = Some( filter.angular_rate(gyr_b) ).filter(|r| r.iter().all(|v| v.is_finite()));
+            if let (Some(rate), Some(prev)) = (rate, rate_prev) {
+                let diff = quat::scale_vec(DT.recip(), quat::sub_vec(rate, prev));
+                ang_acc_err.0.push( quat::norm_vec(quat::sub_vec(ang_acc, ang_acc_true)) );
+                ang_acc_err.1.push( quat::norm_vec(quat::sub_vec(diff, ang_acc_true)) );
+            }
+            rate_prev = rate;
+        }
+        gyr_prev = gyr;
+
+        // ---------- データ書き込み ---------- //
+        let (angle_err, axis_err) = attitude_error(q, filter.q);
+        progress.add_error(angle_err);
+        let record = output::SimulationRecord {
+            time: t as f64 * DT,
+            euler_true: euler_angles(q, opts.output.euler),
+            euler: euler_angles(filter.q, opts.output.euler),
+            euler_gyro: euler_angles(q_gyro, opts.output.euler),
+            bias_true: gyr_bias,
+            bias: filter.gyro_bias(),
+            q_true: q,
+            q: filter.q,
+            disturbance_true: a_dr,
+            disturbance: filter.linear_acceleration(acc_b),
+            e: filter.disturbance_error(acc_b),
+            angle_err,
+            axis_err,
+            vel: dr.vel,
+            pos: dr.pos,
+            angular_acc,
+            gyro_free: filter.is_gyro_free(),
+        };
+        file.write(&record).context(RESULT_PATH)?;
+        if let Some(ref mut plot) = plot {
+            plot.send(&record);
+        }
+        if let Some(ref mut mqtt) = mqtt {
+            mqtt.publish(record.time, &filter, opts);
+        }
+        if let Some(ref mut shm) = shm {
+            shm.publish(record.time, &filter);
+        }
+        if let Some(ref view) = view {
+            view.publish(&viewer::Frame::new(record.time, Some(q), &filter));
+        }
+        if let Some(ref mut window) = window {
+            window.show(&viewer::Frame::new(record.time, Some(q), &filter));
+        }
+        if let Some(ref mut rerun) = rerun {
+            rerun.log(record.time, Some(q), &filter, acc_b);
+        }
+        // ------------------------------------ //
+
+        if opts.sim.realtime {
+            file.flush().context(RESULT_PATH)?;
+            sleep_until(start, (t + 1) as f64 * DT);
+        }
+        progress.step();
+    }
+    progress.finish();
+    file.flush().context(RESULT_PATH)?;
+    randn.finish()?;
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+
+    #[cfg(feature = "fixed")]
+    if fixed_comparable(opts) {
+        fix_err.report();
+    }
+    if imus.is_some() {
+        for (i, n) in n_faults.iter().enumerate() {
+            eprintln!("IMU {}: 計測値を除いた回数 {}", i, n);
+        }
+    }
+    if let Some(spikes) = spikes {
+        eprintln!("角速度の外れ値を置き換えた回数 {}", spikes.n_rejected());
+    }
+    if opts.filter.angular_acc {
+        let (obs, diff) = (score::ErrorStats::new(&ang_acc_err.0), score::ErrorStats::new(&ang_acc_err.1));
+        eprintln!("角加速度の誤差 [rad/s^2]（オブザーバ）   RMS {:.4}, 最大 {:.4}", obs.rms, obs.max);
+        eprintln!("角加速度の誤差 [rad/s^2]（単純な差分）   RMS {:.4}, 最大 {:.4}", diff.rms, diff.max);
+    }
+    if let Some(est) = noise_est {
+        report_noise_estimate(&est);
+    }
+    if let Some(mut timer) = timer {
+        timer.report();
+    }
+    Ok(())
+}
+
+/// 角速度gyrでdt秒積分した姿勢を返す（q = q + 0.5*Δt*q*ω）．
+fn integrate(q: quat::Quaternion<f64>, gyr: quat::Vector3<f64>, dt: f64) -> quat::Quaternion<f64> {
+    let tmp0 = quat::scale_vec(q.0, gyr);
+    let dot = quat::dot_vec(q.1, gyr);
+    let cross = quat::cross_vec(q.1, gyr);
+    let tmp1 = (-dot, quat::add_vec(tmp0, cross));
+    quat::normalize( quat::scale_add(0.5 * dt, tmp1, q) )
+}
+
+/// 開始時刻startからelapsed秒経つまで待つ．
+/// 
+/// 1ステップごとにDT秒ずつ待つと処理時間の分だけ遅れが溜まるので，開始時刻からの経過時間で合わせる．
+fn sleep_until(start: Instant, elapsed: f64) {
+    let wait = Duration::from_secs_f64(elapsed).saturating_sub( start.elapsed() );
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
+
+/// 姿勢誤差 q_err = q_true^-1 ⊗ q_hat の回転角[rad]（0〜π）と回転軸（機体座標系の単位ベクトル）を返す．
+/// 
+/// オイラー角の差と違って，角度の折り返しやジンバルロックの影響を受けない．誤差が0のときの回転軸は0ベクトル．
+fn attitude_error(q_true: quat::Quaternion<f64>, q_hat: quat::Quaternion<f64>) -> (f64, quat::Vector3<f64>) {
+    let mut q_err = quat::mul(quat::conj(q_true), q_hat);
+    // 同じ回転を表す2つの四元数のうち，回転角がπ以下になる方を使う
+    if q_err.0 < 0.0 {
+        q_err = quat::negate(q_err);
+    }
+    let norm = quat::norm_vec(q_err.1);
+    let axis = if norm > 0.0 { quat::scale_vec(norm.recip(), q_err.1) } else { [0.0; 3] };
+    (2.0 * norm.atan2(q_err.0), axis)
+}
+
+/// 計測ノイズの分散の推定器を作る（初期値はシミュレーションのノイズ分散）．
+fn new_noise_estimator() -> noise_estimation::NoiseEstimator {
+    noise_estimation::NoiseEstimator::new(ACC_VAR, MAG_VAR, NOISE_EST_WINDOW)
+}
+
+/// 計測ノイズの分散の推定を進め，イノベーションの検定に反映する．
+///
+/// 加速度の大きさが重力加速度に近く（E1が弱い外乱の閾値以下），補正が正常に行われたステップだけを使う．
+fn update_noise_estimate(
+    est: &mut Option<noise_estimation::NoiseEstimator>, filter: &mut ahrs::AttitudeFilter,
+    acc: quat::Vector3<f64>, mag: quat::Vector3<f64>
+) {
+    if let Some(est) = est {
+        // 判定式のフィーチャによらず，E1と同じ式で加速度の大きさを調べる
+        let e1 = (quat::norm_vec(acc) - ahrs::STANDARD_GRAVITY).abs() / ahrs::STANDARD_GRAVITY;
+        let quiet = filter.health() == ahrs::Health::Ok && e1 <= THR_WEAK;
+        est.update(filter, acc, mag, quiet);
+        filter.set_noise_variance(est.acc_var(), est.mag_var());
+    }
+}
+
+/// 推定した計測ノイズの分散を表示する．
+fn report_noise_estimate(est: &noise_estimation::NoiseEstimator) {
+    eprintln!("推定した計測ノイズの分散（{}サンプル）", est.n_samples());
+    eprintln!("  加速度: {:.5} (m/s^2)^2", est.acc_var());
+    eprintln!("  地磁気: {:.5}", est.mag_var());
+}
+
+/// 起動時のバイアス推定を進め，完了したら姿勢推定フィルタの姿勢と積分項を初期化する．
+fn update_startup(
+    startup: &mut Option<calibration::StartupCalibrator>, filter: &mut ahrs::AttitudeFilter,
+    sample: ahrs::Sample, is_static: bool
+) {
+    if let Some(cal) = startup {
+        if cal.update(sample, is_static) {
+            let bias = cal.gyro_bias();
+            filter.q = cal.attitude();
+            filter.set_gyro_bias(bias);
+            eprintln!("起動時のバイアス推定が完了しました: [{:.5}, {:.5}, {:.5}] rad/s", bias[0], bias[1], bias[2]);
+            *startup = None;
+        }
+    }
+}
+
+/// 予測ステップと，コマンドライン引数に合わせた補正ステップを行う（timerがあれば処理時間を記録する）．
+/// 
+/// * dt: 前回のステップからの経過時間[s]（シミュレーションではDT）
+#[allow(clippy::too_many_arguments)]
+fn step(
+    filter: &mut ahrs::AttitudeFilter, opts: &FilterOptions, timer: &mut Option<timing::StepTimer>,
+    gyr: quat::Vector3<f64>, acc: quat::Vector3<f64>, mag: quat::Vector3<f64>, is_static: bool, dt: f64
+) {
+    let t0 = Instant::now();
+    filter.predict_with_dt(gyr, dt);
+    let t1 = Instant::now();
+    correct(filter, opts, gyr, acc, mag, is_static);
+    let t2 = Instant::now();
+
+    if let Some(timer) = timer {
+        timer.record(t1 - t0, t2 - t1);
+    }
+}
+
+/// コマンドライン引数に合わせた補正ステップを行う．
+fn correct(
+    filter: &mut ahrs::AttitudeFilter, opts: &FilterOptions,
+    gyr: quat::Vector3<f64>, acc: quat::Vector3<f64>, mag: quat::Vector3<f64>, is_static: bool
+) {
+    if opts.no_mag {
+        filter.correct_acc_only(acc);
+    } else if is_static {
+        filter.correct_stationary(gyr, acc, mag);
+    } else if let Some(speed) = opts.speed {
+        filter.correct_with_velocity(acc, mag, gyr, [speed, 0.0, 0.0]);
+    } else {
+        filter.correct(acc, mag);
+    }
+}
+
+/// 固定小数点版と比較できる設定かどうか（固定小数点版に無い補正を使う場合は比較しない）
+#[cfg(feature = "fixed")]
+fn fixed_comparable(opts: &Options) -> bool {
+    !opts.filter.no_mag && !opts.input.zupt && opts.filter.speed.is_none() && !opts.filter.decoupled && !opts.filter.directional_rejection && !opts.filter.mag_norm_gate
+        && !opts.filter.estimate_acc_bias && !opts.filter.anti_windup && !opts.filter.observability_freeze && !opts.filter.innovation_gate && opts.filter.inclination.is_none() && opts.filter.leak == 0.0
+        && opts.filter.gain_schedule == ahrs::GainSchedule::Switching && opts.filter.attitude_solver == ahrs::AttitudeSolver::GetQGm
+        && opts.filter.substeps == 1
+}
+
+/// 基準のフィルタと別のフィルタ（固定小数点版など）の姿勢推定値の差（回転角）を集計する．
+#[cfg(feature = "fixed")]
+struct FixedError {
+    sum_sq: f64,  // 二乗和
+    max: f64,     // 最大値
+    n: usize,     // サンプル数
+}
+
+#[cfg(feature = "fixed")]
+impl FixedError {
+    fn new() -> Self {
+        Self { sum_sq: 0.0, max: 0.0, n: 0 }
+    }
+
+    /// 比較するフィルタestimatorを1サンプル分進め，更新済みの基準のフィルタとの差を記録する．
+    fn update(&mut self, reference: &impl AttitudeEstimator, estimator: &mut impl AttitudeEstimator, sample: ahrs::Sample) {
+        estimator.update(&sample);
+        let (angle, _) = attitude_error(reference.quaternion(), estimator.quaternion());
+        self.sum_sq += angle * angle;
+        self.max = self.max.max(angle);
+        self.n += 1;
+    }
+
+    fn report(&self) {
+        println!("固定小数点版とf64版の姿勢推定値の差（回転角）");
+        println!("  RMS: {:.4e} rad", (self.sum_sq / self.n as f64).sqrt());
+        println!("  Max: {:.4e} rad", self.max);
+    }
+}
+
+/// 姿勢推定フィルタのオイラー角，角速度バイアス，四元数の推定値
+fn estimate_record(estimator: &(impl AttitudeEstimator + ?Sized), seq: ahrs::EulerSequence) -> output::Estimate {
+    let q = estimator.quaternion();
+    output::Estimate { euler: euler_angles(q, seq), bias: estimator.gyro_bias(), q }
+}
+
+/// 真値にノイズを加えて計測値を作る（noiseで無効にしたセンサには加えない）．
+fn measure(randn: &mut noise::NoiseSource, noise: &SensorNoise, truth: ahrs::Sample) -> ahrs::Sample {
+    ahrs::Sample {
+        gyr: add_noise(randn, SensorNoise::var(noise.gyr, GYR_VAR), truth.gyr),
+        acc: add_noise(randn, SensorNoise::var(noise.acc, ACC_VAR), truth.acc),
+        mag: add_noise(randn, SensorNoise::var(noise.mag, MAG_VAR), truth.mag),
+    }
+}
+
+/// n台のIMUの取付姿勢（[1, 1, 1]軸周りに等間隔に回転させて取り付ける）
+fn imu_mounts(n: usize) -> Vec<quat::Quaternion<f64>> {
+    (0..n).map(|i| {
+        let angle = 2.0 * std::f64::consts::PI * i as f64 / n as f64;
+        quat::from_axis_angle([1.0, 1.0, 1.0], angle)
+    }).collect()
+}
+
+/// 冗長化したIMUの計測値を模擬し，統合した計測値を返す．
+/// 
+/// 各IMUのセンサ座標系で独立なノイズを加える．IMU_FAULT_TIME秒以降は1台目の角速度センサが故障する．
+fn measure_imus(imus: &mut redundant::ImuArray, randn: &mut noise::NoiseSource, noise: &SensorNoise, time: f64, truth: ahrs::Sample) -> ahrs::Sample {
+    let samples: Vec<ahrs::Sample> = imus.mounts().iter().enumerate().map(|(i, &mount)| {
+        let mut s = measure(randn, noise, ahrs::Sample {
+            gyr: quat::frame_rotation(mount, truth.gyr),
+            acc: quat::frame_rotation(mount, truth.acc),
+            mag: quat::frame_rotation(mount, truth.mag),
+        });
+        if i == 0 && time >= IMU_FAULT_TIME {
+            s.gyr = quat::add_vec(s.gyr, IMU_FAULT_BIAS);
+        }
+        s
+    }).collect();
+    imus.fuse(&samples)
+}
+
+/// ベクトルxにノイズを加える．
+fn add_noise(randn: &mut noise::NoiseSource, variance: f64, x: quat::Vector3<f64>) -> quat::Vector3<f64> {
+    let mut noisy = [0.0; 3];
+
+    let tmp = variance.sqrt();
+    for i in 0..3 {
+        noisy[i] = x[i] + randn.sample() * tmp;
+    }
+    noisy
+}
\ No newline at end of file
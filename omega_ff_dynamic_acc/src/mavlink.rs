@@ -0,0 +1,214 @@
+//! MAVLinkのHIL_SENSORメッセージの受信（Gazebo，jMAVSim，PX4 SITLなどの機体シミュレータとの接続）
+//!
+//! 機体の運動を模擬するシミュレータはPX4に向けてHIL_SENSOR（#107）でIMUと地磁気の計測値を送る．
+//! そのバイト列を受け取り，一定角速度の簡単な軌跡ではなく機体の運動モデルで生成した計測値でフィルタを検証できるようにする．
+//! MAVLink v1，v2のどちらのフレームも読み，CRCが合わないフレームと他のメッセージは読み飛ばす（署名は確認しない）．
+//!
+//! MAVLinkの機体座標系はFRD（前・右・下）なので，このクレートの座標系（前・左・上）に合わせてy，z軸の符号を反転する．
+//! 地磁気の単位はgaussのまま渡す（正規化して使うので単位は問わない）．
+//! fields_updatedで更新されていないセンサは直前の値を使う（まだ一度も届いていなければNaN）．
+
+use std::collections::VecDeque;
+
+use super::quat::Vector3;
+use super::source::ImuSample;
+
+/// HIL_SENSORのメッセージID
+pub const MSG_ID_HIL_SENSOR: u32 = 107;
+
+/// HIL_SENSORのCRC_EXTRA
+const HIL_SENSOR_CRC_EXTRA: u8 = 108;
+
+/// HIL_SENSORのペイロードの長さ（拡張フィールドidを除く）
+const HIL_SENSOR_LEN: usize = 64;
+
+/// フレームの先頭
+const STX_V1: u8 = 0xFE;
+const STX_V2: u8 = 0xFD;
+
+/// v2の署名付きフレームのフラグと署名の長さ
+const IFLAG_SIGNED: u8 = 0x01;
+const SIGNATURE_LEN: usize = 13;
+
+/// fields_updatedのビット（加速度，角速度，地磁気のx,y,z）
+const UPDATED_ACC: u32 = 0b111;
+const UPDATED_GYR: u32 = 0b111 << 3;
+const UPDATED_MAG: u32 = 0b111 << 6;
+
+/// MAVLinkのバイト列からHIL_SENSORを取り出す．
+///
+/// 読み書きは行わないので，UDPでもTCPでも受け取ったバイト列をpush()で渡す．
+#[derive(Debug, Clone, Default)]
+pub struct HilSensorDecoder {
+    buf: Vec<u8>,                   // まだ読み終えていないバイト列
+    last: [Option<Vector3<f64>>; 3],  // 直前の角速度，加速度，地磁気
+    pending: VecDeque<ImuSample>,
+    n_crc_errors: u64,
+}
+
+impl HilSensorDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 受け取ったバイト列を加える（フレームの途中で区切られていてもよい）．
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+        loop {
+            // フレームの先頭まで読み飛ばす
+            let start = self.buf.iter().position(|&b| b == STX_V1 || b == STX_V2).unwrap_or(self.buf.len());
+            self.buf.drain(..start);
+            match Frame::parse(&self.buf) {
+                Parsed::Incomplete => return,
+                Parsed::Frame(frame) if frame.crc_ok(HIL_SENSOR_CRC_EXTRA) => {
+                    let (len, sample) = (frame.len, self.decode(&frame.payload));
+                    self.pending.push_back(sample);
+                    self.buf.drain(..len);
+                },
+                parsed => {
+                    // 他のメッセージは中身を確かめられないので，値がたまたまSTXだった場合も含めて1バイトずつ進めて探し直す
+                    if matches!(parsed, Parsed::Frame(_)) {
+                        self.n_crc_errors += 1;
+                    }
+                    self.buf.remove(0);
+                },
+            }
+        }
+    }
+
+    /// 次のサンプル（取り出せたものが無ければNone）
+    pub fn pop(&mut self) -> Option<ImuSample> {
+        self.pending.pop_front()
+    }
+
+    /// CRCが合わなかったHIL_SENSORのフレームの数
+    pub fn n_crc_errors(&self) -> u64 {
+        self.n_crc_errors
+    }
+
+    fn decode(&mut self, payload: &[u8]) -> ImuSample {
+        let f32_at = |i: usize| f32::from_le_bytes(payload[i..i + 4].try_into().unwrap()) as f64;
+        // FRDからFLUに変換する
+        let vector = |i: usize| [f32_at(i), -f32_at(i + 4), -f32_at(i + 8)];
+        let time_usec = u64::from_le_bytes(payload[0..8].try_into().unwrap());
+        let fields_updated = u32::from_le_bytes(payload[60..64].try_into().unwrap());
+        for (k, (offset, bits)) in [(20, UPDATED_GYR), (8, UPDATED_ACC), (32, UPDATED_MAG)].into_iter().enumerate() {
+            if fields_updated & bits != 0 {
+                self.last[k] = Some( vector(offset) );
+            }
+        }
+        let [gyr, acc, mag] = self.last.map(|v| v.unwrap_or([f64::NAN; 3]));
+        ImuSample { time: time_usec as f64 * 1e-6, gyr, acc, mag }
+    }
+}
+
+/// bufの先頭を読んだ結果
+enum Parsed<'a> {
+    Incomplete,         // まだ全て届いていない
+    Other,              // HIL_SENSOR以外のメッセージ（か先頭がたまたまSTXだった）
+    Frame(Frame<'a>),   // HIL_SENSOR（CRCは未確認）
+}
+
+/// HIL_SENSORのフレーム
+struct Frame<'a> {
+    len: usize,         // 署名を含むフレーム全体の長さ
+    header: &'a [u8],   // CRCの計算に含めるヘッダとペイロード（STXを除く）
+    payload: Vec<u8>,   // v2で省かれた末尾の0を補ったペイロード
+    crc: u16,
+}
+
+impl<'a> Frame<'a> {
+    /// bufの先頭のフレームを読む（bufは空かSTXから始まる）．
+    fn parse(buf: &'a [u8]) -> Parsed<'a> {
+        let Some(&stx) = buf.first() else { return Parsed::Incomplete };
+        let v1 = stx == STX_V1;
+        let header_len = if v1 { 5 } else { 9 };
+        let Some(header) = buf.get(1..1 + header_len) else { return Parsed::Incomplete };
+        let payload_len = header[0] as usize;
+        let (msg_id, extra) = if v1 {
+            (header[4] as u32, 0)
+        } else {
+            let signed = header[1] & IFLAG_SIGNED != 0;
+            (u32::from_le_bytes([header[6], header[7], header[8], 0]), if signed { SIGNATURE_LEN } else { 0 })
+        };
+        // 拡張フィールド（1バイト）を含めた長さより長ければHIL_SENSORではない
+        if msg_id != MSG_ID_HIL_SENSOR || payload_len > HIL_SENSOR_LEN + 1 {
+            return Parsed::Other;
+        }
+        let crc_at = 1 + header_len + payload_len;
+        let len = crc_at + 2 + extra;
+        if buf.len() < len {
+            return Parsed::Incomplete;
+        }
+        let mut payload = buf[1 + header_len..crc_at].to_vec();
+        payload.resize(HIL_SENSOR_LEN + 1, 0);
+        Parsed::Frame( Frame {
+            len,
+            header: &buf[1..crc_at],
+            payload,
+            crc: u16::from_le_bytes([buf[crc_at], buf[crc_at + 1]]),
+        } )
+    }
+
+    fn crc_ok(&self, crc_extra: u8) -> bool {
+        crc16(self.header.iter().chain([crc_extra].iter())) == self.crc
+    }
+}
+
+/// MAVLinkのCRC（CRC-16/MCRF4XX）
+fn crc16<'a>(bytes: impl Iterator<Item = &'a u8>) -> u16 {
+    bytes.fold(0xFFFF, |crc: u16, &b| {
+        let mut tmp = b ^ (crc as u8);
+        tmp ^= tmp << 4;
+        let tmp = tmp as u16;
+        (crc >> 8) ^ (tmp << 8) ^ (tmp << 3) ^ (tmp >> 4)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// HIL_SENSORのv2のフレームを作る（末尾の0は省く）．
+    fn frame_v2(time_usec: u64, gyr: [f32; 3], acc: [f32; 3], mag: [f32; 3], fields_updated: u32) -> Vec<u8> {
+        let mut payload = time_usec.to_le_bytes().to_vec();
+        for v in acc.iter().chain(&gyr).chain(&mag).chain(&[0.0; 4]) {
+            payload.extend_from_slice(&v.to_le_bytes());
+        }
+        payload.extend_from_slice(&fields_updated.to_le_bytes());
+        while payload.last() == Some(&0) {
+            payload.pop();
+        }
+        let mut frame = vec![STX_V2, payload.len() as u8, 0, 0, 0, 1, 200, MSG_ID_HIL_SENSOR as u8, 0, 0];
+        frame.extend_from_slice(&payload);
+        let crc = crc16(frame[1..].iter().chain([HIL_SENSOR_CRC_EXTRA].iter()));
+        frame.extend_from_slice(&crc.to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn decodes_hil_sensor() {
+        assert_eq!(crc16(b"123456789".iter()), 0x6F91);
+
+        let all = UPDATED_ACC | UPDATED_GYR | UPDATED_MAG;
+        let mut bytes = vec![0x55, STX_V1];  // フレームの前の不要なバイト
+        bytes.extend( frame_v2(1_000_000, [0.5, 0.25, -0.125], [0.0, 0.0, -9.75], [0.25, 0.0, 0.5], all) );
+        // 他のメッセージ（HEARTBEAT）は読み飛ばす
+        bytes.extend([STX_V1, 9, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend( frame_v2(1_020_000, [0.0; 3], [1.0, 0.0, 0.0], [9.0; 3], UPDATED_ACC) );
+        let mut corrupted = frame_v2(1_040_000, [0.0; 3], [0.0; 3], [0.0; 3], all);
+        corrupted[12] ^= 1;
+        bytes.extend(corrupted);
+
+        let mut decoder = HilSensorDecoder::new();
+        decoder.push(&bytes[..30]);
+        assert_eq!(decoder.pop(), None);
+        decoder.push(&bytes[30..]);
+        let s = decoder.pop().unwrap();
+        assert_eq!((s.time, s.gyr, s.acc, s.mag), (1.0, [0.5, -0.25, 0.125], [0.0, 0.0, 9.75], [0.25, -0.0, -0.5]));
+        // 更新されていない角速度と地磁気は直前の値を使う
+        let s = decoder.pop().unwrap();
+        assert_eq!((s.time, s.gyr, s.acc, s.mag), (1.02, [0.5, -0.25, 0.125], [1.0, -0.0, -0.0], [0.25, -0.0, -0.5]));
+        assert_eq!((decoder.pop(), decoder.n_crc_errors()), (None, 1));
+    }
+}
@@ -0,0 +1,174 @@
+//! 推定値と状態のMQTTでの配信（--mqtt）
+//!
+//! 試験装置のデータバスがMQTTの場合に，各ステップの推定値をブローカに送る．
+//! MQTT 3.1.1のCONNECTとQoS 0のPUBLISHだけを標準ライブラリで書いている（keep aliveは0で，PINGは送らない）．
+//!
+//! トピック（prefixは--mqtt-topic，デフォルトはomega_ff）と内容（JSON）：
+//! * prefix/quaternion：time, w, x, y, z
+//! * prefix/euler：time, x, y, z[rad]（--eulerの回転順序，並びはCSVの出力と同じ）
+//! * prefix/bias：time, x, y, z[rad/s]
+//! * prefix/status：time, weak, strong（外乱判定），gyro_free（推定モード），health（ahrs::Health）
+//!
+//! 値がNaNや無限大の場合はnullを送る．ブローカへの書き込みが詰まって時間切れになった場合も接続が切れたとみなす．
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use omega_ff_dynamic_acc::{ahrs, quat};
+
+use super::{euler_angles, Options};
+
+/// トピックのデフォルトの接頭辞
+pub const DEFAULT_TOPIC: &str = "omega_ff";
+
+/// ブローカとの読み書きの時間切れ（ブローカが受け取らなくなっても推定のループを止めないため）
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+pub struct MqttPublisher {
+    stream: TcpStream,
+    prefix: String,
+    connected: bool,   // 送れなくなったら以降は送らない
+}
+
+impl MqttPublisher {
+    /// * addr  : ブローカのアドレス（"localhost:1883"など）
+    /// * prefix: トピックの接頭辞
+    pub fn connect(addr: &str, prefix: &str) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout( Some(TIMEOUT) )?;
+        stream.set_write_timeout( Some(TIMEOUT) )?;
+
+        // CONNECT（プロトコル名，レベル4，clean session，keep alive 0，クライアントID）
+        let mut body = Vec::new();
+        put_string(&mut body, "MQTT");
+        body.extend_from_slice(&[4, 0x02, 0, 0]);
+        put_string(&mut body, &format!("omega_ff_dynamic_acc-{}", std::process::id()));
+        stream.write_all( &packet(0x10, &body) )?;
+
+        // CONNACK
+        let mut ack = [0; 4];
+        stream.read_exact(&mut ack)?;
+        if ack[0] != 0x20 || ack[3] != 0 {
+            return Err( io::Error::other(format!("ブローカに接続を拒否されました（コード {}）", ack[3])) );
+        }
+        Ok( Self { stream, prefix: prefix.to_string(), connected: true } )
+    }
+
+    /// 1ステップ分の推定値と状態を送る（ブローカとの接続が切れても推定は止めない）．
+    pub fn publish(&mut self, time: f64, filter: &ahrs::AttitudeFilter, opts: &Options) {
+        if !self.connected {
+            return;
+        }
+        let q = filter.q;
+        let time = number(time, 3);
+        let vector = |v: quat::Vector3<f64>| format!(
+            "{{\"time\":{},\"x\":{},\"y\":{},\"z\":{}}}", time, number(v[0], 7), number(v[1], 7), number(v[2], 7)
+        );
+        let messages = [
+            ("quaternion", format!(
+                "{{\"time\":{},\"w\":{},\"x\":{},\"y\":{},\"z\":{}}}",
+                time, number(q.0, 7), number(q.1[0], 7), number(q.1[1], 7), number(q.1[2], 7)
+            )),
+            ("euler", vector( euler_angles(q, opts.output.euler) )),
+            ("bias", vector( filter.gyro_bias() )),
+            ("status", format!(
+                "{{\"time\":{},\"weak\":{},\"strong\":{},\"gyro_free\":{},\"health\":\"{:?}\"}}",
+                time, filter.is_weakly_disturbed(), filter.is_disturbed(), filter.is_gyro_free(), filter.health()
+            )),
+        ];
+        for (topic, payload) in messages {
+            let mut body = Vec::new();
+            put_string(&mut body, &format!("{}/{}", self.prefix, topic));
+            body.extend_from_slice( payload.as_bytes() );
+            // 時間切れで途中まで書いた場合もパケットの区切りが分からなくなるので，以降は送らない
+            if self.stream.write_all( &packet(0x30, &body) ).is_err() {
+                eprintln!("MQTTのブローカとの接続が切れた（または応答が無い）ので，以降は送りません");
+                self.connected = false;
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for MqttPublisher {
+    fn drop(&mut self) {
+        // DISCONNECT
+        let _ = self.stream.write_all(&[0xE0, 0]);
+    }
+}
+
+/// JSONの数値（NaNや無限大はJSONで表せないのでnull）
+fn number(x: f64, precision: usize) -> String {
+    if x.is_finite() {
+        format!("{:.*}", precision, x)
+    } else {
+        "null".to_string()
+    }
+}
+
+/// 固定ヘッダ（種類と残りの長さ）を付けたパケット
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    // 残りの長さは7ビットずつの可変長
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        packet.push( if len > 0 { byte | 0x80 } else { byte } );
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// 長さ（2バイト）を前に付けたUTF-8の文字列
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice( &(s.len() as u16).to_be_bytes() );
+    buf.extend_from_slice( s.as_bytes() );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_length() {
+        // 7ビットずつの可変長の桁が変わる境目
+        for (len, header) in [
+            (0, vec![0]),
+            (127, vec![0x7F]),
+            (128, vec![0x80, 0x01]),
+            (16383, vec![0xFF, 0x7F]),
+            (16384, vec![0x80, 0x80, 0x01]),
+        ] {
+            let body = vec![0xAB; len];
+            let p = packet(0x30, &body);
+            assert_eq!(p[0], 0x30);
+            assert_eq!(&p[1..1 + header.len()], &header[..], "len = {}", len);
+            assert_eq!(&p[1 + header.len()..], &body[..]);
+        }
+    }
+
+    #[test]
+    fn string_length() {
+        for len in [0, 127, 128, 16383, 16384] {
+            let s = "a".repeat(len);
+            let mut buf = Vec::new();
+            put_string(&mut buf, &s);
+            assert_eq!(&buf[..2], &(len as u16).to_be_bytes());
+            assert_eq!(buf.len(), 2 + len);
+        }
+    }
+
+    #[test]
+    fn non_finite_is_null() {
+        assert_eq!(number(0.5, 3), "0.500");
+        assert_eq!(number(f64::NAN, 7), "null");
+        assert_eq!(number(f64::INFINITY, 7), "null");
+        assert_eq!(number(f64::NEG_INFINITY, 3), "null");
+    }
+}
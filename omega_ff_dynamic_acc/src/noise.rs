@@ -0,0 +1,139 @@
+//! シミュレーションで計測値に加えるノイズの生成・記録・再生
+//!
+//! 生成した標準正規分布の乱数をファイルに書き出しておき（--dump-noise），後の実行で同じ乱数を読み込んで使う（--load-noise）．
+//! フィルタの実装を変えた前後でも，ビット単位で同じ計測値で比較できる．
+//!
+//! ファイルの1行の形式（1ステップ分）：時刻[s], そのステップで使った乱数（使った順）
+//!
+//! 乱数は分散を掛ける前の値を記録するので，--no-noiseなどでノイズの大きさを変えても同じファイルを使える．
+//! ただし，1ステップで使う乱数の数（--imus，--gnss，--baroの有無）は記録したときと同じでなければならない．
+//! 数が合わない場合やファイルの形式が正しくない場合は，そのステップの終わりにエラーを返す．
+
+use std::fs;
+use std::io::{Write, BufRead, BufReader, BufWriter};
+
+use rand::distributions::{Distribution, Normal};
+
+use super::error::{Context, Error, Result};
+
+/// 乱数の数が記録したときと合わない場合のエラーの説明
+const COUNT_MISMATCH: &str = "ノイズのファイルの乱数の数が合いません（--imus，--gnss，--baroを記録したときと同じにしてください）";
+
+enum Mode {
+    /// 乱数を生成する（dumpがSomeなら書き出す）
+    Random { randn: Normal, dump: Option<(BufWriter<fs::File>, String)> },
+    /// 記録した乱数を読み込む
+    Replay { lines: std::io::Lines<BufReader<fs::File>>, path: String, short: bool },  // short: 乱数が足りなかった
+}
+
+pub struct NoiseSource {
+    mode: Mode,
+    time: f64,         // 現在のステップの時刻[s]
+    values: Vec<f64>,  // 現在のステップの乱数（Replayでは残りを逆順に持つ）
+}
+
+impl NoiseSource {
+    /// 乱数を生成する．
+    pub fn random() -> Self {
+        Self {
+            mode: Mode::Random { randn: Normal::new(0.0, 1.0), dump: None },  // 平均値:0，標準偏差:1
+            time: 0.0,
+            values: Vec::new(),
+        }
+    }
+
+    /// 生成した乱数をpathに書き出す．
+    pub fn with_dump(mut self, path: &str) -> Result<Self> {
+        if let Mode::Random { ref mut dump, .. } = self.mode {
+            *dump = Some( (BufWriter::new( fs::File::create(path).context(path)? ), path.to_string()) );
+        }
+        Ok(self)
+    }
+
+    /// pathに記録した乱数を読み込んで使う．
+    pub fn load(path: &str) -> Result<Self> {
+        Ok( Self {
+            mode: Mode::Replay { lines: BufReader::new( fs::File::open(path).context(path)? ).lines(), path: path.to_string(), short: false },
+            time: 0.0,
+            values: Vec::new(),
+        } )
+    }
+
+    /// 時刻timeのステップを始める（記録する場合は前のステップの乱数を書き出す）．
+    pub fn begin_step(&mut self, time: f64) -> Result<()> {
+        self.check_count()?;
+        match self.mode {
+            Mode::Random { .. } => {
+                self.write_step()?;
+                self.values.clear();
+            },
+            Mode::Replay { ref mut lines, ref path, .. } => {
+                let line = match lines.next() {
+                    Some(line) => line.context(path)?,
+                    None => return Err( Error::format(path, format!("{:.3} s: ノイズのファイルのステップ数が足りません", time)) ),
+                };
+                let nums: Option<Vec<f64>> = line.split(',').map(|v| v.trim().parse().ok()).collect();
+                let Some((&t, values)) = nums.as_deref().and_then(|nums| nums.split_first()) else {
+                    return Err( Error::format(path, format!("{:.3} s: ノイズのファイルの形式が正しくありません", time)) );
+                };
+                if (t - time).abs() >= 1e-9 {
+                    return Err( Error::format(path, format!("ノイズのファイルの時刻が一致しません（{} s, {} s）", t, time)) );
+                }
+                self.values = values.iter().rev().copied().collect();
+            },
+        }
+        self.time = time;
+        Ok(())
+    }
+
+    /// 標準正規分布に従う乱数を1つ返す．
+    pub fn sample(&mut self) -> f64 {
+        match self.mode {
+            Mode::Random { ref randn, .. } => {
+                let v = randn.sample(&mut rand::thread_rng());
+                self.values.push(v);
+                v
+            },
+            Mode::Replay { ref mut short, .. } => {
+                // 足りない場合は0を返しておき，ステップの終わりにエラーを返す
+                self.values.pop().unwrap_or_else(|| {
+                    *short = true;
+                    0.0
+                })
+            },
+        }
+    }
+
+    /// 最後のステップの乱数を書き出す．
+    pub fn finish(&mut self) -> Result<()> {
+        self.check_count()?;
+        self.write_step()?;
+        self.values.clear();
+        if let Mode::Random { dump: Some((ref mut file, ref path)), .. } = self.mode {
+            file.flush().context(path)?;
+        }
+        Ok(())
+    }
+
+    /// 記録した乱数を過不足なく使ったか確かめる（生成する場合は何もしない）．
+    fn check_count(&self) -> Result<()> {
+        match self.mode {
+            Mode::Replay { ref path, short, .. } if short || !self.values.is_empty() => {
+                Err( Error::format(path, format!("{:.3} s: {}", self.time, COUNT_MISMATCH)) )
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn write_step(&mut self) -> Result<()> {
+        if let Mode::Random { dump: Some((ref mut file, ref path)), .. } = self.mode {
+            if self.values.is_empty() {
+                return Ok(());
+            }
+            // {}で書き出した浮動小数点数は読み込むと元の値に戻る
+            let values: Vec<String> = self.values.iter().map(|v| v.to_string()).collect();
+            file.write_all( format!("{:.3},{}\n", self.time, values.join(",")).as_bytes() ).context(path)?;
+        }
+        Ok(())
+    }
+}
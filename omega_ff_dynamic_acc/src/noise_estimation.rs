@@ -0,0 +1,119 @@
+//! 計測ノイズの分散のオンライン推定
+//!
+//! 外乱が無いとみなせる間のイノベーション（AttitudeFilter::acc_innovation()，mag_innovation()）の二乗の
+//! 指数移動平均から，加速度センサと地磁気センサのノイズ分散（各軸）を推定する．
+//! 推定値はAttitudeFilter::set_noise_variance()でイノベーションの検定に使えるので，
+//! 実機のセンサに合わせてノイズ分散を決め打ちする必要が無くなる．
+//!
+//! 外乱の判定をすり抜けたサンプル（大きさが重力加速度と同じになる加速度外乱など）の影響を抑えるため，
+//! 各サンプルのイノベーションの二乗は現在の推定値のカイ二乗分布の上側1%点で頭打ちにする．
+//! 頭打ちにしても推定値は少しずつ大きくなれるので，初期値が小さすぎても真値に近づく．
+//!
+//! イノベーションには推定姿勢の誤差も含まれるので，推定値は真のノイズ分散よりやや大きくなる．
+
+use super::ahrs::{AttitudeFilter, CHI2_3DOF_99};
+use super::quat::{self, Vector3};
+
+/// 自由度1のカイ二乗分布の上側1%点
+const CHI2_1DOF_99: f64 = 6.635;
+
+#[derive(Debug, Clone)]
+pub struct NoiseEstimator {
+    acc_var: f64,   // 加速度センサのノイズ分散の推定値（各軸）[(m/s^2)^2]
+    mag_var: f64,   // 地磁気センサのノイズ分散の推定値（各軸）
+    window: f64,    // 指数移動平均の時定数（サンプル数）
+    n: u64,         // 推定に使ったサンプル数
+}
+
+impl NoiseEstimator {
+    /// * acc_var: 加速度センサのノイズ分散の初期値
+    /// * mag_var: 地磁気センサのノイズ分散の初期値
+    /// * window : 指数移動平均の時定数（サンプル数）
+    pub fn new(acc_var: f64, mag_var: f64, window: usize) -> Self {
+        Self { acc_var, mag_var, window: window as f64, n: 0 }
+    }
+
+    /// 外乱が無いとみなせる（quiet）ときの計測値で分散の推定値を更新する．
+    ///
+    /// 推定に使ったサンプル数が時定数に満たない間は単純平均で更新するので，初期値の影響はすぐに無くなる．
+    ///
+    /// * filter: 補正ステップ後の姿勢推定フィルタ
+    /// * acc   : 機体上のセンサで計測した加速度[m/s^2]
+    /// * mag   : 機体上のセンサで計測した地磁気
+    /// * quiet : 外乱が無いとみなせるか（静止検出や，ノイズ分散に依存しない誤差関数の判定などで決める）
+    pub fn update(&mut self, filter: &AttitudeFilter, acc: Vector3<f64>, mag: Vector3<f64>, quiet: bool) {
+        if !quiet {
+            return;
+        }
+        let r_acc = filter.acc_innovation(acc);
+        let r_mag = filter.mag_innovation(mag);
+        if !(r_acc.iter().chain(&r_mag).all(|v| v.is_finite())) {
+            return;
+        }
+        self.n += 1;
+        let k = 1.0 / (self.n as f64).min(self.window);
+        // 加速度は3自由度，地磁気は1自由度（水平面内で予測した向きと直交する成分）
+        let acc_sq = quat::dot_vec(r_acc, r_acc).min(CHI2_3DOF_99 * self.acc_var);
+        let mag_sq = quat::dot_vec(r_mag, r_mag).min(CHI2_1DOF_99 * self.mag_var);
+        self.acc_var += k * (acc_sq / 3.0 - self.acc_var);
+        self.mag_var += k * (mag_sq - self.mag_var);
+    }
+
+    /// 加速度センサのノイズ分散の推定値（各軸）[(m/s^2)^2]
+    pub fn acc_var(&self) -> f64 {
+        self.acc_var
+    }
+
+    /// 地磁気センサのノイズ分散の推定値（各軸）
+    pub fn mag_var(&self) -> f64 {
+        self.mag_var
+    }
+
+    /// 推定に使ったサンプル数
+    pub fn n_samples(&self) -> u64 {
+        self.n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ahrs::{ACC_R, MAG_R};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use rand::distributions::{Distribution, Normal};
+
+    #[test]
+    fn converges_to_true_variance() {
+        let (acc_var, mag_var) = (0.04, 0.0025);
+        let filter = AttitudeFilter::new(1.0, 0.2, 0.04, 0.08);
+        let mut rng = StdRng::seed_from_u64(1);
+        let randn = Normal::new(0.0, 1.0);
+        let mut noise = |var: f64, x: Vector3<f64>| x.map(|v| v + var.sqrt() * randn.sample(&mut rng));
+
+        // 初期値が1桁違っていても（大きくても小さくても）真値に近づく
+        for scale in [10.0, 0.1] {
+            let mut est = NoiseEstimator::new(scale * acc_var, scale * mag_var, 500);
+            for _ in 0..5000 {
+                est.update(&filter, noise(acc_var, ACC_R), noise(mag_var, MAG_R), true);
+            }
+            assert!((est.acc_var() / acc_var - 1.0).abs() < 0.2, "acc_var = {}", est.acc_var());
+            assert!((est.mag_var() / mag_var - 1.0).abs() < 0.2, "mag_var = {}", est.mag_var());
+        }
+        let mut est = NoiseEstimator::new(acc_var, mag_var, 500);
+        for _ in 0..5000 {
+            est.update(&filter, noise(acc_var, ACC_R), noise(mag_var, MAG_R), true);
+        }
+
+        // 外乱中のサンプルは使わない
+        est.update(&filter, [5.0, 0.0, 0.0], MAG_R, false);
+        assert_eq!(est.n_samples(), 5000);
+
+        // 外乱の判定をすり抜けた外れ値が混ざっても大きくずれない
+        for i in 0..5000 {
+            let acc = if i % 10 == 0 { quat::add_vec(ACC_R, [3.0, 0.0, 0.0]) } else { noise(acc_var, ACC_R) };
+            est.update(&filter, acc, noise(mag_var, MAG_R), true);
+        }
+        assert!((est.acc_var() / acc_var - 1.0).abs() < 0.5, "acc_var = {}", est.acc_var());
+    }
+}
@@ -0,0 +1,146 @@
+//! フィルタのパラメータを自動で調整する（optimizeサブコマンド）
+//!
+//! 固定シードのシナリオ（scenario.rs）を複数のシードで実行し，評価関数の平均が最小になる
+//! alpha, beta, thr_weak, thr_strongをNelder-Mead法で探す．
+//! パラメータは正でなければならないので，対数をとった空間で探索する．
+//!
+//! 評価関数 = 外乱中の姿勢誤差のRMS[rad] + RECOVERY_WEIGHT * 外乱後の収束時間[s]
+
+use std::io::{self, Write};
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use super::{Options, ALPHA, BETA, THR_WEAK, THR_STRONG};
+use super::compare::FilterSpec;
+use super::error::{self, Context};
+use super::scenario::{self, Metrics, Step};
+
+/// 結果の出力先（エラーの表示用）
+const STDOUT: &str = "標準出力";
+
+/// 収束時間の重み[rad/s]（収束が1秒遅れることを，姿勢誤差のRMSが0.01 rad大きいことと同等とみなす）
+const RECOVERY_WEIGHT: f64 = 0.01;
+
+/// 収束しなかった場合の収束時間[s]（外乱が終わってからシナリオの終わりまでの時間）
+const RECOVERY_MAX: f64 = 10.0;
+
+/// Nelder-Mead法の最大反復回数
+const MAX_ITER: usize = 200;
+
+/// 単体の頂点の評価関数の差がこれより小さくなったら終了する
+const TOLERANCE: f64 = 1e-6;
+
+/// 初期単体の大きさ（対数空間での幅）
+const INITIAL_STEP: f64 = 0.3;
+
+/// 評価関数
+fn cost(m: &Metrics) -> f64 {
+    m.rms_disturbance + RECOVERY_WEIGHT * m.recovery_time.min(RECOVERY_MAX)
+}
+
+/// * n_scenarios: 評価に使うシナリオの数（シードをseedから1ずつ変える）
+pub fn run(n_scenarios: usize, seed: u64, opts: &Options) -> error::Result<()> {
+    let scenarios: Vec<Vec<Step>> = (0..n_scenarios as u64).map(|i| {
+        scenario::generate(&mut StdRng::seed_from_u64(seed + i), &opts.sim.noise)
+    }).collect();
+
+    let spec = |x: &[f64; 4]| {
+        let [alpha, beta, thr_weak, thr_strong] = x.map(f64::exp);
+        FilterSpec { detector: opts.filter.detector, alpha, beta, thr_weak, thr_strong }
+    };
+    let objective = |x: &[f64; 4]| {
+        let spec = spec(x);
+        // 弱い外乱の閾値は強い外乱の閾値より小さくなければならない
+        if spec.thr_weak >= spec.thr_strong {
+            return f64::INFINITY;
+        }
+        let sum: f64 = scenarios.iter().map(|steps| cost( &scenario::evaluate(&mut spec.build(), steps) )).sum();
+        sum / n_scenarios as f64
+    };
+
+    let x0 = [ALPHA, BETA, THR_WEAK, THR_STRONG].map(f64::ln);
+    let cost0 = objective(&x0);
+    let (x, cost, n_iter) = nelder_mead(objective, x0);
+    let best = spec(&x);
+
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}個のシナリオで評価しました（外乱判定式：{:?}，反復回数：{}）", n_scenarios, opts.filter.detector, n_iter).context(STDOUT)?;
+    writeln!(out, "  評価関数: {:.5} -> {:.5}", cost0, cost).context(STDOUT)?;
+    writeln!(out, "推奨するパラメータ").context(STDOUT)?;
+    writeln!(out, "  alpha      = {:.4}", best.alpha).context(STDOUT)?;
+    writeln!(out, "  beta       = {:.4}", best.beta).context(STDOUT)?;
+    writeln!(out, "  thr_weak   = {:.4}", best.thr_weak).context(STDOUT)?;
+    writeln!(out, "  thr_strong = {:.4}", best.thr_strong).context(STDOUT)?;
+    out.flush().context(STDOUT)
+}
+
+/// Nelder-Mead法で関数fの最小値を探し，最小値を与える点，最小値，反復回数を返す．
+fn nelder_mead<const N: usize>(f: impl Fn(&[f64; N]) -> f64, x0: [f64; N]) -> ([f64; N], f64, usize) {
+    // 初期単体：x0と，x0から各軸方向にINITIAL_STEPずらした点
+    let mut simplex: Vec<([f64; N], f64)> = (0..=N).map(|i| {
+        let mut x = x0;
+        if i > 0 {
+            x[i - 1] += INITIAL_STEP;
+        }
+        (x, f(&x))
+    }).collect();
+
+    // 2点を結ぶ直線上の点（a + t*(b - a)）
+    let lerp = |a: &[f64; N], b: &[f64; N], t: f64| -> [f64; N] {
+        std::array::from_fn(|i| a[i] + t * (b[i] - a[i]))
+    };
+
+    let mut n_iter = 0;
+    while n_iter < MAX_ITER {
+        simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+        if simplex[N].1 - simplex[0].1 < TOLERANCE {
+            break;
+        }
+        n_iter += 1;
+
+        // 最悪の点を除いた重心
+        let centroid: [f64; N] = std::array::from_fn(|i| {
+            simplex[..N].iter().map(|(x, _)| x[i]).sum::<f64>() / N as f64
+        });
+        let (worst, f_worst) = simplex[N];
+
+        // 反射
+        let reflected = lerp(&centroid, &worst, -1.0);
+        let f_reflected = f(&reflected);
+        if f_reflected < simplex[0].1 {
+            // 拡大
+            let expanded = lerp(&centroid, &worst, -2.0);
+            let f_expanded = f(&expanded);
+            simplex[N] = if f_expanded < f_reflected { (expanded, f_expanded) } else { (reflected, f_reflected) };
+            continue;
+        }
+        if f_reflected < simplex[N - 1].1 {
+            simplex[N] = (reflected, f_reflected);
+            continue;
+        }
+
+        // 収縮（反射点が最悪の点より良ければ外側，そうでなければ内側）
+        let (contracted, f_contracted) = if f_reflected < f_worst {
+            let x = lerp(&centroid, &worst, -0.5);
+            (x, f(&x))
+        } else {
+            let x = lerp(&centroid, &worst, 0.5);
+            (x, f(&x))
+        };
+        if f_contracted < f_worst.min(f_reflected) {
+            simplex[N] = (contracted, f_contracted);
+            continue;
+        }
+
+        // 縮小：最良の点に向かって全ての点を半分に近づける
+        let best = simplex[0].0;
+        for (x, fx) in simplex[1..].iter_mut() {
+            *x = lerp(&best, x, 0.5);
+            *fx = f(x);
+        }
+    }
+
+    simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+    (simplex[0].0, simplex[0].1, n_iter)
+}
@@ -0,0 +1,630 @@
+//! コマンドライン引数
+//!
+//! 引数はモード（Mode）と，姿勢推定フィルタ・入力・出力・シミュレーション・評価ごとの設定に分けて持つ．
+//! 各設定の構造体が自分の引数を解析し，どれにも当てはまらない引数はモードとして解析する．
+
+use std::env;
+use std::str::FromStr;
+
+use omega_ff_dynamic_acc::{ahrs, calibration, timestamp};
+use omega_ff_dynamic_acc::source::{GapPolicy, PacketFormat};
+use omega_ff_dynamic_acc::pipeline::OverflowPolicy;
+
+use super::{calibrate, compare, error, mqtt, output, replay, score, sweep, trajectory, SensorNoise};
+
+/// ライブ入力の取得元（--stream, --serial, --udp, --phone, --hil, --mavlink, --xplane, --flightgear, --synthetic）
+#[derive(Debug, Clone)]
+pub enum Live {
+    Stdin,           // 標準入力
+    Serial(String),  // シリアルポートのデバイス
+    Udp(String),     // UDPで受信するアドレス
+    Phone(String),   // スマートフォンのアプリのパケットを受信するアドレス
+    Hil(String),     // HIL形式のパケットを受信するアドレス
+    Mavlink(String), // MAVLinkのHIL_SENSORを受信するアドレス（tcp:で始まればTCPで接続を待つ）
+    FlightSim(String, PacketFormat),  // フライトシミュレータ（X-Plane，FlightGear）の出力を受信するアドレス
+    Synthetic,       // シミュレーションの標準設定のシナリオ
+}
+
+/// 実行するモード（指定できるのは1つだけ）
+pub enum Mode {
+    Simulate,                        // シミュレーション（引数無し）
+    Replay(String),                  // --replay
+    Live(Live),                      // --streamなどのライブ入力
+    CalibrateMag(String),            // calibrate-mag
+    CalibrateAcc(String),            // calibrate-acc
+    SelfTest,                        // self-test
+    Serve(String),                   // --serve（待ち受けるアドレス）
+    Score(String, String),           // score（推定結果のファイル，真値のファイル）
+    Sweep,                           // sweep
+    Optimize,                        // optimize
+    Compare(Vec<compare::FilterSpec>),  // --compare
+}
+
+/// 姿勢推定フィルタの外乱判定と補正の設定
+pub struct FilterOptions {
+    pub detector: ahrs::Detector,
+    pub gain_schedule: ahrs::GainSchedule,
+    pub attitude_solver: ahrs::AttitudeSolver,
+    pub inclination: Option<f64>,
+    pub no_mag: bool,
+    pub decoupled: bool,
+    pub anti_windup: bool,
+    pub observability_freeze: bool,
+    pub innovation_gate: bool,
+    pub directional_rejection: bool,
+    pub mag_norm_gate: bool,
+    pub estimate_acc_bias: bool,
+    pub leak: f64,
+    pub substeps: u32,
+    pub angular_acc: bool,
+    pub speed: Option<f64>,
+}
+
+/// 計測値の入力と，推定の前後に行う処理の設定（シミュレーション・ログ再生で共通）
+pub struct InputOptions {
+    pub pipeline: Option<OverflowPolicy>,
+    pub gaps: GapPolicy,
+    pub log_dt: bool,
+    pub resume: bool,
+    pub smooth: bool,
+    pub mag_cal: Option<calibration::MagCalibration>,
+    pub acc_cal: Option<calibration::AccCalibration>,
+    pub track_hard_iron: bool,
+    pub mag_ref: Option<replay::MagReference>,
+    pub reject_spikes: bool,
+    pub zupt: bool,
+    pub init: Option<f64>,
+    pub estimate_noise: bool,
+    pub profile: bool,
+}
+
+/// 推定結果の書き出しと送信の設定
+pub struct OutputOptions {
+    pub columns: output::Columns,
+    pub precision: usize,
+    pub euler: ahrs::EulerSequence,
+    pub time_format: timestamp::TimeFormat,
+    pub record: Option<String>,
+    pub plotjuggler: Option<String>,
+    pub mqtt: Option<String>,
+    pub mqtt_topic: String,
+    pub shm: Option<String>,
+    pub viewer: Option<u16>,
+    pub rerun: Option<String>,
+    pub window: bool,
+}
+
+/// シミュレーションで模擬する運動と計測値の設定
+pub struct SimOptions {
+    pub trajectory: Option<trajectory::Trajectory>,
+    pub noise: SensorNoise,
+    pub dump_noise: Option<String>,
+    pub load_noise: Option<String>,
+    pub realtime: bool,
+    pub gnss: bool,
+    pub baro: bool,
+    pub imus: usize,
+    pub gyro_spikes: bool,
+    pub gyro_fail: bool,
+}
+
+/// 推定結果の評価（score，--truth，sweep，optimize）の設定
+pub struct EvalOptions {
+    pub score_opts: score::ScoreOptions,
+    pub truth: Option<score::Trace>,
+    pub sweep_ranges: sweep::SweepRanges,
+    pub seed: u64,
+    pub db: Option<String>,
+    pub db_traces: bool,
+    pub scenarios: usize,
+}
+
+/// コマンドライン引数
+///
+/// 引数無しで実行した場合はシミュレーションを行う．モード（calibrate-mag，self-test，--replay，--stream，sweepなど）は1つだけ指定できる．
+/// * `calibrate-mag <ログファイル>`: 記録済みのセンサログから地磁気センサの較正値を求める
+/// * `--mag-cal <較正値のファイル>`: calibrate-magで求めた較正値で地磁気を較正してから補正する（ログ再生・--streamのみ）
+/// * `calibrate-acc <ログファイル>`: 6姿勢で静止させたセンサログから加速度センサの較正値を求める
+/// * `--acc-cal <較正値のファイル>`: calibrate-accで求めた較正値で加速度を較正してから使う（ログ再生・--streamのみ）
+/// * `self-test`: 他の引数で設定したフィルタで起動前の確認（パラメータ，四元数のノルム，合成した計測値での収束）を行い，結果を表示する（問題があれば終了コード1）
+/// * `--replay <ログファイル>`: 記録済みのセンサログを再生して姿勢推定を行う
+/// * `--trajectory <ファイル>`: シミュレーションの角速度と並進加速度の真値を軌跡のファイル（時刻, 角速度x,y,z, 並進加速度x,y,z）から読む（標準の回転と加速度外乱の代わりに使い，軌跡の長さだけ実行する）
+/// * `--track-hard-iron`: 地磁気のオフセット（ハードアイアン）の変化に逐次追従して除いてから補正し，推定値をhard_iron.csvに書き出す（ログ再生・--streamのみ）
+/// * `--mag-ref <ファイル>`: 基準座標系上における地磁気の時系列（時刻, x, y, z）．ログ再生・--streamで各時刻の値に切り替えて補正する
+/// * `--stream`: 標準入力からセンサログの形式のサンプルを読み，推定結果を標準出力に書き出す
+/// * `--serial <デバイス>`: --streamと同じく，シリアルポート（/dev/ttyUSB0など，ボーレートはsttyで設定しておく）から読む
+/// * `--udp <アドレス>`: --streamと同じく，アドレス（0.0.0.0:5555など）で受け取ったUDPのパケットから読む（1パケットに1行以上）
+/// * `--phone <アドレス>`: --udpと同じく，スマートフォンのアプリ（Sensorstream IMU+GPSなど）が送るパケットから読む
+/// * `--hil <アドレス>`: --udpと同じく，HILベンチや他のシミュレータが送るバイナリ形式（source::PacketFormat::Hil）のパケットから読む
+/// * `--mavlink <アドレス>`: 機体シミュレータ（Gazebo，jMAVSim，PX4 SITLなど）が送るMAVLinkのHIL_SENSORから読む（UDPで受信する．tcp:0.0.0.0:4560のようにtcp:を付けるとTCPで接続を待つ）
+/// * `--xplane <アドレス>`: X-PlaneのData Output（UDP，項目1，4，16，17）から読む（地磁気は姿勢から作る）
+/// * `--flightgear <アドレス>`: FlightGearがflightgear/omega_ff.xmlのプロトコルで送るUDPのパケットから読む（地磁気は姿勢から作る）
+/// * `--synthetic`: --streamと同じく，シミュレーションの標準設定のシナリオで模擬した計測値を読む
+/// * `--pipeline <block|drop-newest|drop-oldest>`: --streamなどのライブ入力の読み取りと推定結果の書き出しを別のスレッドで行い，間のキューが一杯になったら待つか新しい方か古い方を捨てる（最後にキューの統計を表示する）
+/// * `--serve <ポート|アドレス:ポート>`: gRPCで計測値を受け取り推定値を返すサービス（proto/omega_ff.protoのPushImuSample，GetAttitude，StreamAttitude）を起動する（serveフィーチャが必要．ポートだけなら127.0.0.1で待ち受ける）
+/// * `--plotjuggler <アドレス>`: シミュレーション・ログ再生（--streamなどを含む）の推定結果を1サンプルずつJSONにしてUDPで送る（PlotJugglerのUDPサーバで受け取る，デフォルトのポートは9870）
+/// * `--mqtt <アドレス>`: シミュレーション・ログ再生の推定値（四元数，オイラー角，バイアス）と状態（外乱判定，推定モード，Health）をMQTTのブローカ（localhost:1883など）に送る
+/// * `--mqtt-topic <接頭辞>`: --mqttで送るトピックの接頭辞（デフォルトはomega_ff，接頭辞/quaternionなどに送る）
+/// * `--shm <名前>`: シミュレーション・ログ再生の最新の推定値をPOSIX共有メモリ（/omega_ffなど）にシーケンスロックで書き出す（shmフィーチャが必要）
+/// * `--viewer <ポート>`: シミュレーション・ログ再生の姿勢の真値と推定値をブラウザ（http://localhost:ポート/）でリアルタイムに3次元表示する（シミュレーションは--realtimeと併用する）
+/// * `--window`: --viewerと同じ表示をネイティブのウィンドウで行う（windowフィーチャが必要）
+/// * `--rerun <アドレス>`: シミュレーション・ログ再生の姿勢の真値と推定値，推定した並進加速度，外乱判定をrerunのビューア（127.0.0.1:9876など）に送る（rerunフィーチャが必要）
+/// * `--record <ファイル>`: シミュレーション・--stream・ログ再生で推定に使った計測値をそのままバイナリで記録する（--replayで再生できる）
+/// * `--resume`: ログ再生を前回中断したところから再開する
+/// * `--log-dt`: ログ再生（--streamを含む）でDTの代わりにログの時刻の差を予測ステップの経過時間に使う（サンプリング周期が一定でない実機のログ向け）
+/// * `--gaps <skip|hold|interpolate>`: ログ再生（--streamを含む）でサンプリング周期の1.5倍以上空いた欠損の扱い（デフォルトはskipで埋めない，holdは直前の計測値，interpolateは前後の線形補間でDTごとに埋める．時刻が戻ったサンプルはどれでも捨てる）
+/// * `--time-format <seconds|iso8601>`: ログ再生の結果に書き出す時刻の書式（デフォルトはseconds，iso8601はUTCのUnix時間として書き出す）
+/// * `--smooth`: ログ再生の最後に後ろ向きにも推定し，前向きと合成した姿勢を別のファイルに書き出す
+/// * `--detector <e1|e2|both>`: 加速度外乱の判定に使う誤差関数（デフォルトはe1，bothはどちらか一方でも外乱とみなせば外乱とする．有効にしたフィーチャの判定式だけ使える）
+/// * `--columns <種類,...>`: シミュレーション・ログ再生・--compareの結果に書き出す列の種類（truth，estimate，bias，quaternion，disturbance，error，dr，otherから選ぶ，デフォルトは全て，時刻は常に書き出す）
+/// * `--precision <n>`: 結果の数値の小数点以下の桁数（デフォルトは7，時刻は3桁のまま）
+/// * `--euler <zyx|xyz>`: 出力するオイラー角の回転順序（デフォルトはzyx）
+/// * `--no-mag`: 地磁気を使わずに加速度だけで補正する（ヨー角は補正しない）
+/// * `--inclination <deg>`: 地磁気の伏角[deg]を与え，鉛直成分も含めた地磁気で姿勢を計算する（シミュレーションでは模擬する地磁気にも伏角を付ける）
+/// * `--attitude-solver <gm|triad|triad-mag|davenport|svd>`: 補正の目標にする姿勢の計算方法（デフォルトはgm，triadは加速度，triad-magは地磁気を主ベクトルとするTRIAD法．triad-magは--inclinationと併用する．davenportは外乱判定の結果とノイズ分散で重み付けする．svdは特異値分解で解く）
+/// * `--decoupled`: 加速度でチルト，地磁気でヨー角を別々に補正する
+/// * `--gain-schedule <switching|sigmoid|huber|cauchy>`: 加速度外乱に応じた補正ゲインの変え方（デフォルトはswitching）
+/// * `--innovation-gate`: 誤差関数の閾値の代わりに，加速度と地磁気のイノベーションのカイ二乗検定（棄却率1%，分散はACC_VAR，MAG_VAR）で外乱を判定する
+/// * `--estimate-noise`: 外乱が無いとみなせる間のイノベーションから加速度・地磁気のノイズ分散を推定し，--innovation-gateの検定に使う
+/// * `--directional-rejection`: 強い加速度外乱の間，加速度全体ではなく残差が最大の軸の成分だけを棄却する
+/// * `--mag-norm-gate`: 外乱の無い間に地磁気の大きさを学習し，大きさが外れた地磁気の補正を弱める
+/// * `--estimate-acc-bias`: 外乱の無い間の加速度の残差から加速度バイアスを推定し，計測値から除いて補正に使う
+/// * `--anti-windup`: 積分項の制限と条件付き積分を有効にする
+/// * `--observability-freeze`: 角速度バイアスの軸ごとの可観測性を監視し，可観測性の低い軸の積分を止める
+/// * `--leak <k>`: 積分項の減衰率[1/s]（デフォルトは0で減衰しない）
+/// * `--realtime`: シミュレーションの1ステップごとに実時間でDT秒待つ（結果を逐次ファイルに書き出す）
+/// * `--profile`: predict()とcorrect()の1ステップごとの処理時間を計測し，最後にパーセンタイルを表示する
+/// * `--gnss`: 模擬したGNSSの位置・速度で推測航法を補正する（シミュレーションのみ）
+/// * `--zupt`: 静止を検出したらZUPTで補正する（シミュレーションでは開始からSTATIC_TIME秒間静止させる）
+/// * `--init <s>`: 起動後に静止しているs秒間の計測値の平均で，姿勢と角速度バイアスの推定値を初期化する（シミュレーションでは開始からSTATIC_TIME秒間静止させる）
+/// * `--speed <m/s>`: 機体x軸方向の速度．遠心力を補償して補正する（シミュレーションでは計測値に向心加速度を加える）
+/// * `--imus <n>`: 取付姿勢の異なるn台のIMUを模擬し，多数決で統合した計測値で推定する（シミュレーションのみ）
+/// * `--baro`: 模擬した気圧高度で推測航法の鉛直方向を補正する（シミュレーションのみ）
+/// * `--no-noise`: 模擬する計測値のノイズを全て無くす（バイアスと加速度外乱はそのまま，sweep・optimize・--compareのシナリオにも適用する）
+/// * `--no-gyr-noise`, `--no-acc-noise`, `--no-mag-noise`, `--no-gnss-noise`, `--no-baro-noise`: 指定したセンサのノイズだけを無くす
+/// * `--dump-noise <ファイル>`: シミュレーションで生成したノイズの乱数を書き出す
+/// * `--load-noise <ファイル>`: --dump-noiseで書き出した乱数をノイズに使う（同じ計測値でフィルタを比較できる）
+/// * `--gyro-spikes`: 角速度にGYRO_SPIKE_INTERVALサンプルごとに1サンプルだけの外れ値を加える（シミュレーションのみ）
+/// * `--reject-spikes`: 角速度の外れ値を直近3サンプルの中央値で置き換えてから推定する
+/// * `--substeps <m>`: predict()の積分をm回に分割する（デフォルトは1）
+/// * `--angular-acc`: バイアスの推定値を除いた角速度から角加速度も推定する（シミュレーションでは推測航法の後の3列に書き出し，最後に単純な差分との誤差を比べる）
+/// * `--gyro-fail`: GYRO_FAIL_STARTからGYRO_FAIL_ENDまで角速度をNaNにする（シミュレーションのみ，その間は加速度と地磁気だけで推定する）
+/// * `sweep`: 固定シードのシナリオでパラメータの全ての組み合わせを評価する（外乱判定式は--detectorで指定）
+/// * `--alpha`, `--beta`, `--thr-weak`, `--thr-strong <a,b,c|start:stop:step>`: sweepで変えるパラメータの候補（指定しなければシミュレーションと同じ値に固定）
+/// * `--db <ファイル>`: sweepの評価指標を実行条件（シード，外乱判定式，ノイズの設定，gitのコミット）と一緒にSQLiteのデータベースに追記する（sqliteフィーチャが必要）
+/// * `--db-traces`: --dbに組み合わせごとの姿勢誤差の時系列も記録する
+/// * `--seed <n>`: sweep，optimizeで使う乱数のシード
+/// * `optimize`: 固定シードのシナリオで評価関数（外乱中の姿勢誤差と収束時間）が最小になるパラメータを探す（外乱判定式は--detectorで指定）
+/// * `--scenarios <n>`: optimizeで評価に使うシナリオの数（シードを1ずつ変える，デフォルトは3）
+/// * `score <推定結果のファイル> <真値のファイル>`: 推定結果を外部の真値（時刻, qw, qx, qy, qz）と比較する（真値は推定結果の時刻に補間する）
+/// * `--truth <真値のファイル>`: ログ再生（--streamを含む）で真値（時刻, qw, qx, qy, qz）と比較し，姿勢誤差を書き出す
+/// * `--q-col <n>`: scoreで推定結果のファイルの四元数の列（デフォルトは7でreplay_result.csvと同じ）
+/// * `--time-offset <s>`: score，--truthで真値の時刻に加える時間[s]
+/// * `--align-frame`: scoreで基準座標系の違い（一定の回転）を推定して除いてから比較する
+/// * `--log <error|warn|info|debug|trace>`: 状態の変化を記録するイベントの詳細度（tracingフィーチャが必要，デフォルトはwarn）
+/// * `--compare <spec>,<spec>,...`: 同じ計測値で複数のフィルタを動かして比較する（specは`<e1|e2|both>[:alpha[:beta[:thr_weak[:thr_strong]]]]`，他の補正の設定は使わない）
+pub struct Options {
+    pub mode: Mode,
+    pub filter: FilterOptions,
+    pub input: InputOptions,
+    pub output: OutputOptions,
+    pub sim: SimOptions,
+    pub eval: EvalOptions,
+    pub log: Option<String>,
+}
+
+/// 解析中のコマンドライン引数の列
+struct Args<I> {
+    iter: I,
+}
+
+impl<I: Iterator<Item = String>> Args<I> {
+    /// 次の引数を返す（無ければmsgのエラーを返す）．
+    fn value(&mut self, msg: &str) -> error::Result<String> {
+        self.iter.next().ok_or_else(|| error::Error::argument(msg))
+    }
+
+    /// 次の引数を数値などとして解析して返す（無いか解析できなければmsgのエラーを返す）．
+    fn parse<T: FromStr>(&mut self, msg: &str) -> error::Result<T> {
+        self.iter.next().and_then(|v| v.parse().ok()).ok_or_else(|| error::Error::argument(msg))
+    }
+
+    /// 次の引数を1以上の整数として解析して返す．
+    fn positive<T: FromStr + PartialOrd + Default>(&mut self, msg: &str) -> error::Result<T> {
+        self.iter.next().and_then(|v| v.parse().ok()).filter(|n| *n > T::default()).ok_or_else(|| error::Error::argument(msg))
+    }
+
+    /// 次の引数を候補の名前から選び，対応する値を返す．
+    fn choice<T: Copy>(&mut self, choices: &[(&str, T)], msg: &str) -> error::Result<T> {
+        let name = self.iter.next();
+        choices.iter().find(|(n, _)| Some(*n) == name.as_deref()).map(|&(_, v)| v).ok_or_else(|| error::Error::argument(msg))
+    }
+
+    /// 次の引数をfで解析して返す（Noneならmsgのエラーを返す）．
+    fn parse_with<T>(&mut self, f: impl FnOnce(&str) -> Option<T>, msg: &str) -> error::Result<T> {
+        self.iter.next().as_deref().and_then(f).ok_or_else(|| error::Error::argument(msg))
+    }
+}
+
+impl Default for FilterOptions {
+    fn default() -> Self {
+        Self {
+            detector: ahrs::Detector::default(),
+            gain_schedule: ahrs::GainSchedule::Switching,
+            attitude_solver: ahrs::AttitudeSolver::GetQGm,
+            inclination: None,
+            no_mag: false,
+            decoupled: false,
+            anti_windup: false,
+            observability_freeze: false,
+            innovation_gate: false,
+            directional_rejection: false,
+            mag_norm_gate: false,
+            estimate_acc_bias: false,
+            leak: 0.0,
+            substeps: 1,
+            angular_acc: false,
+            speed: None,
+        }
+    }
+}
+
+impl FilterOptions {
+    /// argがフィルタの設定なら解析してtrueを返す．
+    fn parse_arg<I: Iterator<Item = String>>(&mut self, arg: &str, args: &mut Args<I>) -> error::Result<bool> {
+        match arg {
+            "--detector" => {
+                self.detector = args.parse_with(parse_detector, "--detectorにはe1かe2かboth（有効にしたフィーチャの判定式）を指定してください")?;
+            },
+            "--gain-schedule" => {
+                self.gain_schedule = args.choice(&[
+                    ("switching", ahrs::GainSchedule::Switching),
+                    ("sigmoid", ahrs::GainSchedule::Sigmoid),
+                    ("huber", ahrs::GainSchedule::Huber),
+                    ("cauchy", ahrs::GainSchedule::Cauchy),
+                ], "--gain-scheduleにはswitching，sigmoid，huber，cauchyのいずれかを指定してください")?;
+            },
+            "--attitude-solver" => {
+                self.attitude_solver = args.choice(&[
+                    ("gm", ahrs::AttitudeSolver::GetQGm),
+                    ("triad", ahrs::AttitudeSolver::Triad),
+                    ("triad-mag", ahrs::AttitudeSolver::TriadMag),
+                    ("davenport", ahrs::AttitudeSolver::Davenport),
+                    ("svd", ahrs::AttitudeSolver::Svd),
+                ], "--attitude-solverにはgm，triad，triad-mag，davenport，svdのいずれかを指定してください")?;
+            },
+            "--inclination" => {
+                let deg: f64 = args.parse("--inclinationの後に伏角[deg]を指定してください")?;
+                self.inclination = Some( deg.to_radians() );
+            },
+            "--no-mag" => self.no_mag = true,
+            "--decoupled" => self.decoupled = true,
+            "--anti-windup" => self.anti_windup = true,
+            "--observability-freeze" => self.observability_freeze = true,
+            "--innovation-gate" => self.innovation_gate = true,
+            "--directional-rejection" => self.directional_rejection = true,
+            "--mag-norm-gate" => self.mag_norm_gate = true,
+            "--estimate-acc-bias" => self.estimate_acc_bias = true,
+            "--leak" => self.leak = args.parse("--leakの後に減衰率[1/s]を指定してください")?,
+            "--substeps" => self.substeps = args.positive("--substepsの後に積分の分割数を指定してください")?,
+            "--angular-acc" => self.angular_acc = true,
+            "--speed" => self.speed = Some( args.parse("--speedの後に速度[m/s]を指定してください")? ),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Default for InputOptions {
+    fn default() -> Self {
+        Self {
+            pipeline: None,
+            gaps: GapPolicy::Skip,
+            log_dt: false,
+            resume: false,
+            smooth: false,
+            mag_cal: None,
+            acc_cal: None,
+            track_hard_iron: false,
+            mag_ref: None,
+            reject_spikes: false,
+            zupt: false,
+            init: None,
+            estimate_noise: false,
+            profile: false,
+        }
+    }
+}
+
+impl InputOptions {
+    /// argが入力の設定なら解析してtrueを返す（較正値などのファイルを読めなければエラーを返す）．
+    fn parse_arg<I: Iterator<Item = String>>(&mut self, arg: &str, args: &mut Args<I>) -> error::Result<bool> {
+        match arg {
+            "--pipeline" => {
+                self.pipeline = Some( args.choice(&[
+                    ("block", OverflowPolicy::Block),
+                    ("drop-newest", OverflowPolicy::DropNewest),
+                    ("drop-oldest", OverflowPolicy::DropOldest),
+                ], "--pipelineにはblockかdrop-newestかdrop-oldestを指定してください")? );
+            },
+            "--gaps" => {
+                self.gaps = args.choice(&[
+                    ("skip", GapPolicy::Skip),
+                    ("hold", GapPolicy::HoldLast),
+                    ("interpolate", GapPolicy::Interpolate),
+                ], "--gapsにはskipかholdかinterpolateを指定してください")?;
+            },
+            "--log-dt" => self.log_dt = true,
+            "--resume" => self.resume = true,
+            "--smooth" => self.smooth = true,
+            "--mag-cal" => {
+                let path = args.value("--mag-calの後に較正値のファイルを指定してください")?;
+                self.mag_cal = Some( calibrate::load_mag(&path)? );
+            },
+            "--acc-cal" => {
+                let path = args.value("--acc-calの後に較正値のファイルを指定してください")?;
+                self.acc_cal = Some( calibrate::load_acc(&path)? );
+            },
+            "--track-hard-iron" => self.track_hard_iron = true,
+            "--mag-ref" => {
+                let path = args.value("--mag-refの後に基準の磁場のファイルを指定してください")?;
+                self.mag_ref = Some( replay::MagReference::load(&path)? );
+            },
+            "--reject-spikes" => self.reject_spikes = true,
+            "--zupt" => self.zupt = true,
+            "--init" => self.init = Some( args.parse("--initの後に静止させておく時間[s]を指定してください")? ),
+            "--estimate-noise" => self.estimate_noise = true,
+            "--profile" => self.profile = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            columns: output::Columns::ALL,
+            precision: output::DEFAULT_PRECISION,
+            euler: ahrs::EulerSequence::ZYX,
+            time_format: timestamp::TimeFormat::Seconds,
+            record: None,
+            plotjuggler: None,
+            mqtt: None,
+            mqtt_topic: mqtt::DEFAULT_TOPIC.to_string(),
+            shm: None,
+            viewer: None,
+            rerun: None,
+            window: false,
+        }
+    }
+}
+
+impl OutputOptions {
+    /// argが出力の設定なら解析してtrueを返す．
+    fn parse_arg<I: Iterator<Item = String>>(&mut self, arg: &str, args: &mut Args<I>) -> error::Result<bool> {
+        match arg {
+            "--columns" => {
+                self.columns = args.parse_with(output::Columns::parse, "--columnsにはtruth，estimate，bias，quaternion，disturbance，error，dr，otherをカンマ区切りで指定してください")?;
+            },
+            "--precision" => self.precision = args.parse("--precisionの後に小数点以下の桁数を指定してください")?,
+            "--euler" => {
+                self.euler = args.choice(&[
+                    ("zyx", ahrs::EulerSequence::ZYX),
+                    ("xyz", ahrs::EulerSequence::XYZ),
+                ], "--eulerにはzyxかxyzを指定してください")?;
+            },
+            "--time-format" => {
+                self.time_format = args.choice(&[
+                    ("seconds", timestamp::TimeFormat::Seconds),
+                    ("iso8601", timestamp::TimeFormat::Iso8601),
+                ], "--time-formatにはsecondsかiso8601を指定してください")?;
+            },
+            "--record" => self.record = Some( args.value("--recordの後に記録先のファイルを指定してください")? ),
+            "--plotjuggler" => self.plotjuggler = Some( args.value("--plotjugglerの後に送り先のアドレスを指定してください")? ),
+            "--mqtt" => self.mqtt = Some( args.value("--mqttの後にブローカのアドレスを指定してください")? ),
+            "--mqtt-topic" => self.mqtt_topic = args.value("--mqtt-topicの後にトピックの接頭辞を指定してください")?,
+            "--shm" => self.shm = Some( args.value("--shmの後に共有メモリの名前を指定してください")? ),
+            "--viewer" => self.viewer = Some( args.parse("--viewerの後にポート番号を指定してください")? ),
+            "--window" => self.window = true,
+            "--rerun" => self.rerun = Some( args.value("--rerunの後にビューアのアドレスを指定してください")? ),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Default for SimOptions {
+    fn default() -> Self {
+        Self {
+            trajectory: None,
+            noise: SensorNoise::ALL,
+            dump_noise: None,
+            load_noise: None,
+            realtime: false,
+            gnss: false,
+            baro: false,
+            imus: 1,
+            gyro_spikes: false,
+            gyro_fail: false,
+        }
+    }
+}
+
+impl SimOptions {
+    /// argがシミュレーションの設定なら解析してtrueを返す（軌跡のファイルを読めなければエラーを返す）．
+    fn parse_arg<I: Iterator<Item = String>>(&mut self, arg: &str, args: &mut Args<I>) -> error::Result<bool> {
+        match arg {
+            "--trajectory" => {
+                let path = args.value("--trajectoryの後に軌跡のファイルを指定してください")?;
+                self.trajectory = Some( trajectory::Trajectory::load(&path)? );
+            },
+            "--no-noise" => self.noise = SensorNoise::NONE,
+            "--no-gyr-noise" => self.noise.gyr = false,
+            "--no-acc-noise" => self.noise.acc = false,
+            "--no-mag-noise" => self.noise.mag = false,
+            "--no-gnss-noise" => self.noise.gnss = false,
+            "--no-baro-noise" => self.noise.baro = false,
+            "--dump-noise" => self.dump_noise = Some( args.value("--dump-noiseの後に出力先のファイルを指定してください")? ),
+            "--load-noise" => self.load_noise = Some( args.value("--load-noiseの後にノイズのファイルを指定してください")? ),
+            "--realtime" => self.realtime = true,
+            "--gnss" => self.gnss = true,
+            "--baro" => self.baro = true,
+            "--imus" => self.imus = args.positive("--imusの後にIMUの台数を指定してください")?,
+            "--gyro-spikes" => self.gyro_spikes = true,
+            "--gyro-fail" => self.gyro_fail = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self {
+            score_opts: score::ScoreOptions::default(),
+            truth: None,
+            sweep_ranges: sweep::SweepRanges::default(),
+            seed: sweep::SEED,
+            db: None,
+            db_traces: false,
+            scenarios: 3,
+        }
+    }
+}
+
+impl EvalOptions {
+    /// argが評価の設定なら解析してtrueを返す（真値のファイルを読めなければエラーを返す）．
+    fn parse_arg<I: Iterator<Item = String>>(&mut self, arg: &str, args: &mut Args<I>) -> error::Result<bool> {
+        match arg {
+            "--truth" => {
+                let path = args.value("--truthの後に真値のファイルを指定してください")?;
+                self.truth = Some( score::Trace::load(&path, 1)? );
+            },
+            "--q-col" => self.score_opts.q_col = args.positive("--q-colの後に四元数の列を指定してください")?,
+            "--time-offset" => self.score_opts.time_offset = args.parse("--time-offsetの後に時間[s]を指定してください")?,
+            "--align-frame" => self.score_opts.align_frame = true,
+            "--alpha" | "--beta" | "--thr-weak" | "--thr-strong" => {
+                let values = args.parse_with(sweep::parse_values, &format!("{}の後に候補（0.5,1,2または0.5:2:0.25）を指定してください", arg))?;
+                let ranges = &mut self.sweep_ranges;
+                match arg {
+                    "--alpha" => ranges.alpha = values,
+                    "--beta" => ranges.beta = values,
+                    "--thr-weak" => ranges.thr_weak = values,
+                    _ => ranges.thr_strong = values,
+                }
+            },
+            "--seed" => self.seed = args.parse("--seedの後に乱数のシードを指定してください")?,
+            "--db" => self.db = Some( args.value("--dbの後にデータベースのファイルを指定してください")? ),
+            "--db-traces" => self.db_traces = true,
+            "--scenarios" => self.scenarios = args.positive("--scenariosの後にシナリオの数を指定してください")?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl Options {
+    /// 引数を解析し，指定されたファイル（較正値，軌跡，真値など）を読み込む．
+    ///
+    /// 引数が正しくない場合やファイルを読めない場合はエラーを返す．
+    pub fn parse() -> error::Result<Self> {
+        Self::parse_from(env::args().skip(1))
+    }
+
+    /// 引数の列（プログラム名を除く）を解析する．
+    fn parse_from(args: impl IntoIterator<Item = String>) -> error::Result<Self> {
+        let mut opts = Options {
+            mode: Mode::Simulate,
+            filter: FilterOptions::default(),
+            input: InputOptions::default(),
+            output: OutputOptions::default(),
+            sim: SimOptions::default(),
+            eval: EvalOptions::default(),
+            log: None,
+        };
+
+        let mut args = Args { iter: args.into_iter() };
+        while let Some(arg) = args.iter.next() {
+            let arg = arg.as_str();
+            if opts.filter.parse_arg(arg, &mut args)?
+                || opts.input.parse_arg(arg, &mut args)?
+                || opts.output.parse_arg(arg, &mut args)?
+                || opts.sim.parse_arg(arg, &mut args)?
+                || opts.eval.parse_arg(arg, &mut args)? {
+                continue;
+            }
+            if arg == "--log" {
+                opts.log = Some( args.value("--logの後にイベントの詳細度を指定してください")? );
+                continue;
+            }
+            let mode = parse_mode(arg, &mut args)?;
+            if !matches!(opts.mode, Mode::Simulate) {
+                return Err( error::Error::argument(format!("モードは1つだけ指定してください（{}）", arg)) );
+            }
+            opts.mode = mode;
+        }
+        if opts.filter.attitude_solver == ahrs::AttitudeSolver::TriadMag && opts.filter.inclination.is_none() {
+            return Err( error::Error::argument("--attitude-solver triad-magには--inclinationも指定してください") );
+        }
+        Ok(opts)
+    }
+}
+
+/// argをモードとして解析する（モードでもなければ不明な引数のエラーを返す）．
+fn parse_mode<I: Iterator<Item = String>>(arg: &str, args: &mut Args<I>) -> error::Result<Mode> {
+    Ok( match arg {
+        "calibrate-mag" => Mode::CalibrateMag( args.value("calibrate-magの後にログファイルを指定してください")? ),
+        "calibrate-acc" => Mode::CalibrateAcc( args.value("calibrate-accの後にログファイルを指定してください")? ),
+        "self-test" => Mode::SelfTest,
+        "--replay" => Mode::Replay( args.value("--replayの後にログファイルを指定してください")? ),
+        "--stream" => Mode::Live(Live::Stdin),
+        "--serial" => Mode::Live(Live::Serial( args.value("--serialの後にデバイスを指定してください")? )),
+        "--udp" => Mode::Live(Live::Udp( args.value("--udpの後に受信するアドレスを指定してください")? )),
+        "--phone" => Mode::Live(Live::Phone( args.value("--phoneの後に受信するアドレスを指定してください")? )),
+        "--hil" => Mode::Live(Live::Hil( args.value("--hilの後に受信するアドレスを指定してください")? )),
+        "--mavlink" => Mode::Live(Live::Mavlink( args.value("--mavlinkの後に受信するアドレスを指定してください")? )),
+        "--xplane" => {
+            let addr = args.value("--xplaneの後に受信するアドレスを指定してください")?;
+            Mode::Live(Live::FlightSim(addr, PacketFormat::XPlane))
+        },
+        "--flightgear" => {
+            let addr = args.value("--flightgearの後に受信するアドレスを指定してください")?;
+            Mode::Live(Live::FlightSim(addr, PacketFormat::FlightGear))
+        },
+        "--synthetic" => Mode::Live(Live::Synthetic),
+        "--serve" => {
+            let addr = args.value("--serveの後に待ち受けるポートかアドレス:ポートを指定してください")?;
+            // ポートだけの場合は同じマシンからの接続だけを受け付ける
+            Mode::Serve( match addr.parse::<u16>() {
+                Ok(port) => format!("127.0.0.1:{}", port),
+                Err(_) => addr,
+            } )
+        },
+        "score" => {
+            let estimate = args.value("scoreの後に推定結果のファイルを指定してください")?;
+            let truth = args.value("scoreの後に真値のファイルを指定してください")?;
+            Mode::Score(estimate, truth)
+        },
+        "sweep" => Mode::Sweep,
+        "optimize" => Mode::Optimize,
+        "--compare" => {
+            let specs = args.parse_with(|v| v.split(',').map(compare::FilterSpec::parse).collect(), "--compareの後に比較するフィルタ（e1:1.0:0.2,e2など）を指定してください")?;
+            Mode::Compare(specs)
+        },
+        _ => return Err( error::Error::argument(format!("不明な引数です: {}", arg)) ),
+    } )
+}
+
+/// 外乱判定式の名前（e1，e2，both）から判定式を返す（フィーチャが無効な判定式はNone）．
+pub fn parse_detector(name: &str) -> Option<ahrs::Detector> {
+    match name {
+        #[cfg(feature = "detector-e1")]
+        "e1" => Some(ahrs::Detector::E1),
+        #[cfg(feature = "detector-e2")]
+        "e2" => Some(ahrs::Detector::E2),
+        #[cfg(all(feature = "detector-e1", feature = "detector-e2"))]
+        "both" => Some(ahrs::Detector::Both),
+        _ => None,
+    }
+}
@@ -0,0 +1,437 @@
+//! 推定結果の書き出し（CSV，PlotJuggler向けのJSON）
+//!
+//! 1行分の値を出力の種類ごとのレコード（列の並びを型で決めた構造体）にまとめ，CsvWriterやJsonUdpSenderで書き出す．
+//! 列の区切りと行末はRowがまとめて付けるので，列を足したり省いたりしても区切りがずれない．
+//! 数値の書式は時刻が小数点以下3桁，それ以外が7桁（値が無い列は空にする）．
+//! ログの時刻の列はCsvWriter::with_time_formatでISO 8601にもできる．
+//! 書き出す列の種類（Columns）と小数点以下の桁数はCsvWriter::with_columns，with_precisionで絞れる（時刻の列は常に書き出す）．
+
+use std::io::{self, Write};
+use std::net::UdpSocket;
+
+use omega_ff_dynamic_acc::quat::{Quaternion, Vector3};
+use omega_ff_dynamic_acc::timestamp::TimeFormat;
+
+/// 書き出す列の種類の集合
+///
+/// 列の種類はセルの名前で決める（名前が_trueで終わる真値の列は，TRUTHとその値の種類の両方を含む場合だけ書き出す）．
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Columns(u8);
+
+impl Columns {
+    pub const TRUTH: Self = Self(1 << 0);         // 真値（euler_true，q_trueなど）
+    pub const ESTIMATE: Self = Self(1 << 1);      // オイラー角
+    pub const BIAS: Self = Self(1 << 2);          // 角速度バイアス
+    pub const QUATERNION: Self = Self(1 << 3);    // 四元数
+    pub const DISTURBANCE: Self = Self(1 << 4);   // 加速度外乱と外乱検出の誤差関数
+    pub const ERROR: Self = Self(1 << 5);         // 真値との姿勢誤差
+    pub const DEAD_RECKONING: Self = Self(1 << 6);  // 推測航法の速度・位置
+    pub const OTHER: Self = Self(1 << 7);         // その他（角加速度，推定モードなど）
+    pub const ALL: Self = Self(u8::MAX);
+
+    /// カンマ区切りの種類の名前（truth,estimate,bias,quaternion,disturbance,error,dr,other）を読む．
+    pub fn parse(list: &str) -> Option<Self> {
+        list.split(',').try_fold(Self(0), |columns, name| {
+            let c = match name.trim() {
+                "truth" => Self::TRUTH,
+                "estimate" => Self::ESTIMATE,
+                "bias" => Self::BIAS,
+                "quaternion" => Self::QUATERNION,
+                "disturbance" => Self::DISTURBANCE,
+                "error" => Self::ERROR,
+                "dr" => Self::DEAD_RECKONING,
+                "other" => Self::OTHER,
+                _ => return None,
+            };
+            Some( Self(columns.0 | c.0) )
+        })
+    }
+
+    fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// 名前がnameのセルを書き出すかどうか
+    fn includes(self, name: &str) -> bool {
+        let base = name.split('/').next().unwrap_or(name);
+        let kind = base.strip_suffix("_true");
+        if kind.is_some() && !self.contains(Self::TRUTH) {
+            return false;
+        }
+        match kind.unwrap_or(base) {
+            "time" => true,
+            "euler" | "euler_gyro" => self.contains(Self::ESTIMATE),
+            "bias" => self.contains(Self::BIAS),
+            "q" => self.contains(Self::QUATERNION),
+            "disturbance" | "e" => self.contains(Self::DISTURBANCE),
+            "angle_err" | "axis_err" => self.contains(Self::ERROR),
+            "vel" | "pos" => self.contains(Self::DEAD_RECKONING),
+            _ => self.contains(Self::OTHER),
+        }
+    }
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// 数値の小数点以下の桁数のデフォルト
+pub const DEFAULT_PRECISION: usize = 7;
+
+/// 1行分のセル（名前と値の文字列）
+///
+/// 名前はCSVには書き出さず，JSONのキーに使う（ベクトルは名前/x，名前/yのように要素ごとに分ける）．
+#[derive(Debug)]
+pub struct Row {
+    cells: Vec<(String, String)>,
+    prefix: String,   // 名前の前に付ける文字列（複数のフィルタの推定値を並べる場合など）
+    time_format: TimeFormat,  // timestamp()の書式
+    columns: Columns,         // 書き出す列の種類
+    precision: usize,         // value()の小数点以下の桁数
+}
+
+impl Default for Row {
+    fn default() -> Self {
+        Self {
+            cells: Vec::new(),
+            prefix: String::new(),
+            time_format: TimeFormat::default(),
+            columns: Columns::ALL,
+            precision: DEFAULT_PRECISION,
+        }
+    }
+}
+
+impl Row {
+    fn push(&mut self, name: &str, text: String) -> &mut Self {
+        if self.columns.includes(name) {
+            self.cells.push( (format!("{}{}", self.prefix, name), text) );
+        }
+        self
+    }
+
+    /// 時刻・時間[s]
+    pub fn time(&mut self, name: &str, t: f64) -> &mut Self {
+        self.push(name, format!("{:.3}", t))
+    }
+
+    /// ログの時刻（Unix時間などの絶対時刻になりうるもの）
+    pub fn timestamp(&mut self, name: &str, t: f64) -> &mut Self {
+        let text = self.time_format.format(t);
+        self.push(name, text)
+    }
+
+    pub fn value(&mut self, name: &str, v: f64) -> &mut Self {
+        let text = format!("{:.*}", self.precision, v);
+        self.push(name, text)
+    }
+
+    pub fn vector(&mut self, name: &str, v: Vector3<f64>) -> &mut Self {
+        for (axis, v) in ["x", "y", "z"].iter().zip(v) {
+            self.value(&format!("{}/{}", name, axis), v);
+        }
+        self
+    }
+
+    /// 四元数（w, x, y, z）
+    pub fn quaternion(&mut self, name: &str, q: Quaternion<f64>) -> &mut Self {
+        self.value(&format!("{}/w", name), q.0).vector(name, q.1)
+    }
+
+    /// 値が無い列
+    pub fn empty(&mut self, name: &str) -> &mut Self {
+        self.push(name, String::new())
+    }
+
+    /// 値が無ければ3つの空の列
+    pub fn optional_vector(&mut self, name: &str, v: Option<Vector3<f64>>) -> &mut Self {
+        match v {
+            Some(v) => self.vector(name, v),
+            None => {
+                for axis in ["x", "y", "z"] {
+                    self.empty(&format!("{}/{}", name, axis));
+                }
+                self
+            },
+        }
+    }
+
+    /// 0か1で書き出すフラグ
+    pub fn flag(&mut self, name: &str, b: bool) -> &mut Self {
+        self.push(name, (b as u8).to_string())
+    }
+
+    /// 書式を指定しない値（パラメータなど，入力された値をそのまま書き出す）
+    pub fn text(&mut self, name: &str, v: impl ToString) -> &mut Self {
+        self.push(name, v.to_string())
+    }
+
+    /// 名前の前にprefixを付けてセルを追加する．
+    pub fn scoped(&mut self, prefix: &str, fill: impl FnOnce(&mut Self)) -> &mut Self {
+        let len = self.prefix.len();
+        self.prefix.push_str(prefix);
+        fill(self);
+        self.prefix.truncate(len);
+        self
+    }
+
+    /// CSVの1行（行末の改行を含む）
+    fn to_csv(&self) -> String {
+        let texts: Vec<&str> = self.cells.iter().map(|(_, text)| text.as_str()).collect();
+        format!("{}\n", texts.join(","))
+    }
+
+    /// JSONのオブジェクト（値が無い列は省き，NaNなどはnullにする）
+    fn to_json(&self) -> String {
+        let members: Vec<String> = self.cells.iter()
+            .filter(|(_, text)| !text.is_empty())
+            .map(|(name, text)| {
+                let value = if text.parse::<f64>().is_ok_and(f64::is_finite) { text.as_str() } else { "null" };
+                format!("\"{}\":{}", name, value)
+            })
+            .collect();
+        format!("{{{}}}", members.join(","))
+    }
+}
+
+/// 1行分の値
+pub trait Record {
+    /// 列の順にセルを追加する．
+    fn fill(&self, row: &mut Row);
+}
+
+/// レコードをCSVの1行ずつ書き出す．
+pub struct CsvWriter<W> {
+    inner: W,
+    row: Row,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, row: Row::default() }
+    }
+
+    /// ログの時刻の列の書式（デフォルトは秒）
+    pub fn with_time_format(mut self, format: TimeFormat) -> Self {
+        self.row.time_format = format;
+        self
+    }
+
+    /// 書き出す列の種類（デフォルトは全て）
+    pub fn with_columns(mut self, columns: Columns) -> Self {
+        self.row.columns = columns;
+        self
+    }
+
+    /// 数値の小数点以下の桁数（デフォルトはDEFAULT_PRECISION，時刻の列は変えない）
+    pub fn with_precision(mut self, precision: usize) -> Self {
+        self.row.precision = precision;
+        self
+    }
+
+    pub fn write(&mut self, record: &impl Record) -> io::Result<()> {
+        self.row.cells.clear();
+        record.fill(&mut self.row);
+        self.inner.write_all( self.row.to_csv().as_bytes() )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// レコードをJSONにして1つずつUDPで送る（PlotJugglerのUDPサーバ（JSON）で受け取ってグラフにする）．
+///
+/// 時刻はキー"time"に入れるので，PlotJuggler側でタイムスタンプのフィールドとして指定する．
+pub struct JsonUdpSender {
+    socket: UdpSocket,
+    row: Row,
+}
+
+impl JsonUdpSender {
+    /// * addr: 送り先のアドレス（PlotJugglerのデフォルトは127.0.0.1:9870）
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        Ok( Self { socket, row: Row::default() } )
+    }
+
+    /// 受け取る側が起動していなくても推定は止めない（送れなかったレコードは捨てる）．
+    pub fn send(&mut self, record: &impl Record) {
+        self.row.cells.clear();
+        record.fill(&mut self.row);
+        let _ = self.socket.send( self.row.to_json().as_bytes() );
+    }
+}
+
+/// 姿勢推定フィルタの推定値（オイラー角，角速度バイアス，四元数）
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    pub euler: Vector3<f64>,
+    pub bias: Vector3<f64>,
+    pub q: Quaternion<f64>,
+}
+
+impl Record for Estimate {
+    fn fill(&self, row: &mut Row) {
+        row.vector("euler", self.euler).vector("bias", self.bias).quaternion("q", self.q);
+    }
+}
+
+/// シミュレーションの結果（result.csv）
+#[derive(Debug, Clone, Copy)]
+pub struct SimulationRecord {
+    pub time: f64,
+    pub euler_true: Vector3<f64>,            // オイラー角の真値
+    pub euler: Vector3<f64>,                 // オイラー角の推定値
+    pub euler_gyro: Vector3<f64>,            // 角速度の積分だけで求めたオイラー角（補正なし，最後の3列）
+    pub bias_true: Vector3<f64>,             // 角速度バイアスの真値
+    pub bias: Vector3<f64>,                  // 角速度バイアスの推定値
+    pub q_true: Quaternion<f64>,             // 四元数の真値
+    pub q: Quaternion<f64>,                  // 四元数の推定値
+    pub disturbance_true: Vector3<f64>,      // 加速度外乱の真値
+    pub disturbance: Vector3<f64>,           // 加速度外乱の推定値（重力を除いた加速度）
+    pub e: f64,                              // 外乱検出の誤差関数
+    pub angle_err: f64,                      // 姿勢誤差（真値から推定値への回転の角度と軸）
+    pub axis_err: Vector3<f64>,
+    pub vel: Vector3<f64>,                   // 推測航法の速度・位置
+    pub pos: Vector3<f64>,
+    pub angular_acc: Option<Vector3<f64>>,   // 角加速度の推定値（推定しない場合は空）
+    pub gyro_free: bool,                     // 推定モード（1: 角速度を使わないモード）
+}
+
+impl Record for SimulationRecord {
+    fn fill(&self, row: &mut Row) {
+        row.time("time", self.time)
+            .vector("euler_true", self.euler_true).vector("euler", self.euler)
+            .vector("bias_true", self.bias_true).vector("bias", self.bias)
+            .quaternion("q_true", self.q_true).quaternion("q", self.q)
+            .vector("disturbance_true", self.disturbance_true).vector("disturbance", self.disturbance)
+            .value("e", self.e)
+            .value("angle_err", self.angle_err).vector("axis_err", self.axis_err)
+            .vector("vel", self.vel).vector("pos", self.pos)
+            .optional_vector("angular_acc", self.angular_acc)
+            .flag("gyro_free", self.gyro_free)
+            .vector("euler_gyro", self.euler_gyro);
+    }
+}
+
+/// 外部の真値との比較
+#[derive(Debug, Clone, Copy)]
+pub enum TruthError {
+    /// 真値を与えていない（列を書き出さない）
+    None,
+    /// 真値の記録の範囲外（列を空にする）
+    OutOfRange,
+    /// 姿勢誤差（回転角，回転軸）
+    Error(f64, Vector3<f64>),
+}
+
+/// ログ再生の結果（replay_result.csv，--streamの出力）
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayRecord {
+    pub time: f64,
+    pub estimate: Estimate,
+    pub e: f64,                 // 外乱検出の誤差関数
+    pub truth: TruthError,      // 真値との姿勢誤差
+    pub vel: Vector3<f64>,      // 推測航法の速度・位置
+    pub pos: Vector3<f64>,
+    pub gyro_free: bool,        // 推定モード（1: 角速度を使わないモード）
+}
+
+impl Record for ReplayRecord {
+    fn fill(&self, row: &mut Row) {
+        row.timestamp("time", self.time);
+        self.estimate.fill(row);
+        row.value("e", self.e);
+        match self.truth {
+            TruthError::None => (),
+            TruthError::OutOfRange => { row.empty("angle_err").optional_vector("axis_err", None); },
+            TruthError::Error(angle, axis) => { row.value("angle_err", angle).vector("axis_err", axis); },
+        }
+        row.vector("vel", self.vel).vector("pos", self.pos).flag("gyro_free", self.gyro_free);
+    }
+}
+
+/// 平滑化した姿勢（replay_smoothed.csv）
+#[derive(Debug, Clone, Copy)]
+pub struct SmoothedRecord {
+    pub time: f64,
+    pub euler: Vector3<f64>,
+    pub q: Quaternion<f64>,
+}
+
+impl Record for SmoothedRecord {
+    fn fill(&self, row: &mut Row) {
+        row.timestamp("time", self.time).vector("euler", self.euler).quaternion("q", self.q);
+    }
+}
+
+/// 地磁気のハードアイアンの推定値（hard_iron.csv）
+#[derive(Debug, Clone, Copy)]
+pub struct HardIronRecord {
+    pub time: f64,
+    pub offset: Vector3<f64>,  // オフセットの推定値
+    pub radius: f64,           // オフセットを除いた地磁気の大きさの推定値
+}
+
+impl Record for HardIronRecord {
+    fn fill(&self, row: &mut Row) {
+        row.timestamp("time", self.time).vector("offset", self.offset).value("radius", self.radius);
+    }
+}
+
+/// 同じ計測値で動かした複数のフィルタの比較（compare_result.csv）
+#[derive(Debug, Clone)]
+pub struct CompareRecord {
+    pub time: f64,
+    pub q_true: Quaternion<f64>,
+    pub filters: Vec<(Estimate, f64)>,   // フィルタごとの推定値と姿勢誤差（回転角）
+}
+
+impl Record for CompareRecord {
+    fn fill(&self, row: &mut Row) {
+        row.time("time", self.time).quaternion("q_true", self.q_true);
+        for (i, (estimate, angle_err)) in self.filters.iter().enumerate() {
+            row.scoped(&format!("{}/", i), |row| {
+                estimate.fill(row);
+                row.value("angle_err", *angle_err);
+            });
+        }
+    }
+}
+
+/// 推定結果と外部の真値の比較（score_result.csv）
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreRecord {
+    pub time: f64,
+    pub angle_err: f64,
+    pub axis_err: Vector3<f64>,
+}
+
+impl Record for ScoreRecord {
+    fn fill(&self, row: &mut Row) {
+        row.time("time", self.time).value("angle_err", self.angle_err).vector("axis_err", self.axis_err);
+    }
+}
+
+/// パラメータの組み合わせの評価結果（sweep_result.csv）
+#[derive(Debug, Clone, Copy)]
+pub struct SweepRecord {
+    pub alpha: f64,
+    pub beta: f64,
+    pub thr_weak: f64,
+    pub thr_strong: f64,
+    pub rms: f64,               // 姿勢誤差のRMS[rad]
+    pub rms_disturbance: f64,   // 加速度外乱中の姿勢誤差のRMS[rad]
+    pub recovery_time: f64,     // 外乱後の収束時間[s]
+}
+
+impl Record for SweepRecord {
+    fn fill(&self, row: &mut Row) {
+        row.text("alpha", self.alpha).text("beta", self.beta).text("thr_weak", self.thr_weak).text("thr_strong", self.thr_strong)
+            .value("rms", self.rms).value("rms_disturbance", self.rms_disturbance).time("recovery_time", self.recovery_time);
+    }
+}
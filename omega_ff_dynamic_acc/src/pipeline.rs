@@ -0,0 +1,252 @@
+//! ライブ入力のスレッド分割（読み取り → 推定 → 書き出し）
+//!
+//! シリアルポートやUDPの読み取りと推定を同じスレッドで行うと，推定や書き出しが遅れた間に
+//! OSの受信バッファが溢れてサンプルを取りこぼす．読み取りと書き出しを別のスレッドに分け，
+//! 上限付きのキューでつなぐ．キューが一杯になった場合の扱いはOverflowPolicyで選ぶ．
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use super::source::{ImuSample, SensorSource};
+
+/// キューが一杯になった場合の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// 空くまで書き込む側を待たせる（取りこぼしは取得元のバッファで起こる）
+    #[default]
+    Block,
+    /// 新しく来た方を捨てる
+    DropNewest,
+    /// 一番古いものを捨てて新しく来た方を入れる（遅れを溜めない）
+    DropOldest,
+}
+
+/// キューの統計
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueStats {
+    pub n_pushed: u64,    // 入れようとした数
+    pub n_dropped: u64,   // 一杯で捨てた数
+    pub max_len: usize,   // 溜まった数の最大値
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    stats: QueueStats,
+}
+
+/// スレッド間の上限付きのキュー
+struct Queue<T> {
+    state: Mutex<State<T>>,
+    changed: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl<T> Queue<T> {
+    fn new(capacity: usize, policy: OverflowPolicy) -> Arc<Self> {
+        assert!(capacity > 0);
+        let state = State { items: VecDeque::with_capacity(capacity), closed: false, stats: QueueStats::default() };
+        Arc::new( Self { state: Mutex::new(state), changed: Condvar::new(), capacity, policy } )
+    }
+
+    /// 1つ入れる（閉じられていればfalseを返す）．
+    fn push(&self, item: T) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if self.policy == OverflowPolicy::Block {
+            while state.items.len() >= self.capacity && !state.closed {
+                state = self.changed.wait(state).unwrap();
+            }
+        }
+        if state.closed {
+            return false;
+        }
+        state.stats.n_pushed += 1;
+        if state.items.len() >= self.capacity {
+            state.stats.n_dropped += 1;
+            match self.policy {
+                OverflowPolicy::DropOldest => { state.items.pop_front(); },
+                _ => return true,
+            }
+        }
+        state.items.push_back(item);
+        state.stats.max_len = state.stats.max_len.max( state.items.len() );
+        self.changed.notify_all();
+        true
+    }
+
+    /// 1つ取り出す（空なら待ち，閉じられていて空ならNone）．
+    fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.changed.notify_all();
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// 閉じる（残っているものは取り出せる）．
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+
+    fn stats(&self) -> QueueStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+/// 別のスレッドで取得元を読み続け，キューに溜めたサンプルを返す取得元
+///
+/// 取得元が終わったらキューに残ったサンプルを返してから終わる．
+pub struct ThreadedSource {
+    queue: Arc<Queue<ImuSample>>,
+}
+
+impl ThreadedSource {
+    /// * capacity: キューに溜めるサンプル数の上限
+    pub fn spawn<S: SensorSource + Send + 'static>(mut source: S, capacity: usize, policy: OverflowPolicy) -> Self {
+        let queue = Queue::new(capacity, policy);
+        let shared = Arc::clone(&queue);
+        thread::spawn(move || {
+            while let Some(sample) = source.next_sample() {
+                if !shared.push(sample) {
+                    break;
+                }
+            }
+            shared.close();
+        });
+        Self { queue }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+}
+
+impl SensorSource for ThreadedSource {
+    fn next_sample(&mut self) -> Option<ImuSample> {
+        self.queue.pop()
+    }
+}
+
+impl Drop for ThreadedSource {
+    fn drop(&mut self) {
+        // 読み取りのスレッドは次のサンプルを読んだところで終わる
+        self.queue.close();
+    }
+}
+
+/// 書き込んだ内容を別のスレッドで書き出すWrite
+///
+/// 1回のwriteを1つの単位としてキューに入れる（捨てる場合もwrite単位なので，1行ずつwrite_allで書く）．
+/// 書き出し先のエラー（パイプが閉じられたなど）は，その後の書き込みでBrokenPipeとして返す．
+pub struct ThreadedWriter {
+    queue: Arc<Queue<Vec<u8>>>,
+    handle: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl ThreadedWriter {
+    /// * capacity: キューに溜める書き込み（CSVなら行）の数の上限
+    pub fn spawn<W: Write + Send + 'static>(mut inner: W, capacity: usize, policy: OverflowPolicy) -> Self {
+        let queue = Queue::<Vec<u8>>::new(capacity, policy);
+        let shared = Arc::clone(&queue);
+        let handle = thread::spawn(move || {
+            let result = (|| {
+                while let Some(buf) = shared.pop() {
+                    inner.write_all(&buf)?;
+                    // 溜まっている分を書き終えたら出力する
+                    if shared.state.lock().unwrap().items.is_empty() {
+                        inner.flush()?;
+                    }
+                }
+                inner.flush()
+            })();
+            shared.close();
+            result
+        });
+        Self { queue, handle: Some(handle) }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    /// 溜まっている分を全て書き出して終わる（書き出し先のエラーを返す）．
+    pub fn finish(mut self) -> io::Result<()> {
+        self.queue.close();
+        self.handle.take().unwrap().join().unwrap()
+    }
+}
+
+impl Write for ThreadedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.queue.push( buf.to_vec() ) {
+            Ok(buf.len())
+        } else {
+            Err( io::Error::from(io::ErrorKind::BrokenPipe) )
+        }
+    }
+
+    /// 書き出しのスレッドが溜まった分を書いたら出力するので，何もしない．
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ThreadedWriter {
+    fn drop(&mut self) {
+        self.queue.close();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::IterSource;
+
+    #[test]
+    fn passes_samples_between_threads() {
+        let sample = |i: usize| ImuSample { time: i as f64, gyr: [0.0; 3], acc: [0.0; 3], mag: [0.0; 3] };
+        let mut source = ThreadedSource::spawn(IterSource( (0..100).map(sample) ), 4, OverflowPolicy::Block);
+        let times: Vec<f64> = std::iter::from_fn(|| source.next_sample()).map(|s| s.time).collect();
+        assert_eq!(times, (0..100).map(|i| i as f64).collect::<Vec<_>>());
+        assert_eq!(source.stats().n_dropped, 0);
+        assert!(source.stats().max_len <= 4);
+
+        // 取り出さない間に来たものは方針に合わせて捨てる
+        let queue = Queue::new(2, OverflowPolicy::DropOldest);
+        (0..5).for_each(|i| { queue.push(i); });
+        queue.close();
+        assert_eq!((queue.pop(), queue.pop(), queue.pop()), (Some(3), Some(4), None));
+        let queue = Queue::new(2, OverflowPolicy::DropNewest);
+        (0..5).for_each(|i| { queue.push(i); });
+        assert_eq!((queue.pop(), queue.pop(), queue.stats().n_dropped), (Some(0), Some(1), 3));
+
+        // 書き出しのスレッドを介しても全ての行を順に書く
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.lock().unwrap().write(buf) }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+        let out = Shared::default();
+        let mut writer = ThreadedWriter::spawn(out.clone(), 2, OverflowPolicy::Block);
+        for i in 0..100 {
+            writeln!(writer, "{}", i).unwrap();
+        }
+        writer.finish().unwrap();
+        let expected: String = (0..100).map(|i| format!("{}\n", i)).collect();
+        assert_eq!(String::from_utf8(out.0.lock().unwrap().clone()).unwrap(), expected);
+    }
+}
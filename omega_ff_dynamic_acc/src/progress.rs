@@ -0,0 +1,79 @@
+//! 時間のかかる処理の進捗表示
+//!
+//! 標準エラー出力が端末の場合だけ，INTERVAL秒ごとに処理済みの数，残り時間の見積もり，
+//! 姿勢誤差のRMS（真値がわかる場合）を1行で上書き表示する．パイプやファイルに出力している場合は何も表示しない．
+
+use std::io::{self, IsTerminal};
+use std::time::{Duration, Instant};
+
+/// 表示を更新する間隔
+const INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct Progress {
+    label: &'static str,   // 処理の名前
+    total: Option<usize>,  // 全体の数（わからなければNone）
+    n: usize,              // 処理済みの数
+    sum_sq: f64,           // 姿勢誤差の二乗和
+    n_err: usize,          // 姿勢誤差を記録した数
+    start: Instant,        // 開始時刻
+    last: Instant,         // 最後に表示を更新した時刻
+    enabled: bool,         // 表示するかどうか
+}
+
+impl Progress {
+    /// * label: 処理の名前
+    /// * total: 全体の数（わからなければNone）
+    pub fn new(label: &'static str, total: Option<usize>) -> Self {
+        let now = Instant::now();
+        Self {
+            label,
+            total,
+            n: 0,
+            sum_sq: 0.0,
+            n_err: 0,
+            start: now,
+            last: now,
+            enabled: io::stderr().is_terminal(),
+        }
+    }
+
+    /// 1つ処理したことを記録する（INTERVAL秒ごとに表示を更新する）．
+    pub fn step(&mut self) {
+        self.n += 1;
+        if self.enabled && self.last.elapsed() >= INTERVAL {
+            self.last = Instant::now();
+            self.print();
+        }
+    }
+
+    /// 姿勢誤差（回転角）[rad]を記録する．
+    pub fn add_error(&mut self, angle_err: f64) {
+        self.sum_sq += angle_err * angle_err;
+        self.n_err += 1;
+    }
+
+    /// 最終的な状況を表示して改行する．
+    pub fn finish(&self) {
+        if self.enabled {
+            self.print();
+            eprintln!();
+        }
+    }
+
+    fn print(&self) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let mut line = match self.total {
+            Some(total) => {
+                let ratio = self.n as f64 / total.max(1) as f64;
+                let remaining = if self.n > 0 { elapsed * (1.0 - ratio) / ratio } else { 0.0 };
+                format!("{}: {}/{} ({:.1}%)  経過 {:.0} s  残り {:.0} s", self.label, self.n, total, 100.0 * ratio, elapsed, remaining)
+            },
+            None => format!("{}: {}  経過 {:.0} s", self.label, self.n, elapsed),
+        };
+        if self.n_err > 0 {
+            line += &format!("  姿勢誤差のRMS {:.4} rad", (self.sum_sq / self.n_err as f64).sqrt());
+        }
+        // 前の表示より短い場合に備えて行末まで消す
+        eprint!("\r{}\x1b[K", line);
+    }
+}
@@ -0,0 +1,92 @@
+//! センサの計測値の記録（--record）と再生
+//!
+//! シミュレーションやライブ入力（--stream）の計測値を，ノイズや外れ値を含めてそのままバイナリで記録する．
+//! 記録したファイルは--replayに渡すと同じ計測値で推定をやり直せる（CSVのログと違って丸め誤差が無い）．
+//!
+//! ファイルの形式：MAGICの後に，1サンプルごとに時刻[s], 角速度x,y,z, 加速度x,y,z, 地磁気x,y,z（f64，リトルエンディアン）
+//! 記録が途中で途切れている場合（ライブ入力を強制終了した場合など），最後の不完全なサンプルは読み飛ばす．
+//! 読み取りに失敗した場合はそのエラーを返して終わる．
+
+use std::fs;
+use std::io::{self, Read, Write, BufReader, BufWriter};
+
+use omega_ff_dynamic_acc::{ahrs, source::ImuSample};
+use super::error::{Context, Error, Result};
+
+/// ファイルの先頭に書く識別子
+const MAGIC: &[u8; 8] = b"OFFREC1\0";
+
+/// 1サンプルの値の数
+const N_VALUES: usize = 10;
+
+pub struct Recorder {
+    file: BufWriter<fs::File>,
+    path: String,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self> {
+        let mut file = BufWriter::new( fs::File::create(path).context(path)? );
+        file.write_all(MAGIC).context(path)?;
+        Ok( Self { file, path: path.to_string() } )
+    }
+
+    /// 1サンプル分の計測値を書き出す．
+    pub fn write(&mut self, time: f64, s: &ahrs::Sample) -> Result<()> {
+        let values = [time].into_iter().chain(s.gyr).chain(s.acc).chain(s.mag);
+        for v in values {
+            self.file.write_all( &v.to_le_bytes() ).context(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// バッファの内容を書き出す（ライブ入力を強制終了しても記録が残るように）．
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush().context(&self.path)
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+/// pathが記録したファイルかどうか（先頭がMAGICか）
+pub fn is_recording(path: &str) -> bool {
+    let mut head = [0u8; 8];
+    fs::File::open(path).and_then(|mut f| f.read_exact(&mut head)).is_ok() && &head == MAGIC
+}
+
+/// 記録したサンプルの数
+pub fn count(path: &str) -> Result<usize> {
+    let len = fs::metadata(path).context(path)?.len() as usize;
+    Ok( len.saturating_sub(MAGIC.len()) / (8 * N_VALUES) )
+}
+
+/// 記録したファイルを読み，サンプルを順に返す（読み取りに失敗したらエラーを返して終わる）．
+pub fn load(path: &str) -> Result<impl Iterator<Item = Result<ImuSample>>> {
+    let mut file = BufReader::new( fs::File::open(path).context(path)? );
+    let mut head = [0u8; 8];
+    file.read_exact(&mut head).context(path)?;
+    if &head != MAGIC {
+        return Err( Error::format(path, "記録したファイルではありません") );
+    }
+    let path = path.to_string();
+    let mut failed = false;
+    Ok( std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        let mut buf = [0u8; 8 * N_VALUES];
+        match file.read_exact(&mut buf) {
+            Ok(()) => {
+                let v: Vec<f64> = buf.chunks_exact(8).map(|b| f64::from_le_bytes(b.try_into().unwrap())).collect();
+                Some( Ok( ImuSample { time: v[0], gyr: [v[1], v[2], v[3]], acc: [v[4], v[5], v[6]], mag: [v[7], v[8], v[9]] } ) )
+            },
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => {
+                failed = true;
+                Some( Err(e).context(&path) )
+            },
+        }
+    }) )
+}
@@ -0,0 +1,156 @@
+//! 冗長化した複数のIMU（角速度・加速度・地磁気センサの組）の計測値を統合する
+//!
+//! 各IMUの計測値を取付姿勢で機体座標系に揃えてから，計測値の種類ごとに各軸の中央値を求め，
+//! 中央値から閾値以上離れたセンサを除いて平均する（多数決）．
+//! 除いたセンサは故障の疑いがあるものとして記録する．
+//!
+//! IMU同士の位置の差（レバーアーム）による加速度の違いは考慮しないので，
+//! 各IMUは近くに取り付けるか，その分だけ加速度の閾値を大きくしておく．
+//! 故障したセンサを特定するには3台以上必要（2台では平均するだけになる）．
+
+use super::ahrs::Sample;
+use super::quat::{self, Quaternion, Vector3};
+
+#[derive(Debug, Clone)]
+pub struct ImuArray {
+    mounts: Vec<Quaternion<f64>>,  // 各IMUの取付姿勢（機体座標系から見たセンサ座標系の姿勢）
+    thr: [f64; 3],                 // 中央値との差の閾値（角速度，加速度，地磁気）
+    faults: Vec<bool>,             // 直前のfuse()で除いた計測値があったセンサ
+}
+
+impl ImuArray {
+    /// * mounts : 各IMUの取付姿勢（センサ座標系での計測値vはvector_rotation(mount, v)で機体座標系に戻る）
+    /// * thr_gyr: 中央値との差（ノルム）の閾値[rad/s]
+    /// * thr_acc: 中央値との差（ノルム）の閾値[m/s^2]
+    /// * thr_mag: 中央値との差（ノルム）の閾値
+    pub fn new(mounts: Vec<Quaternion<f64>>, thr_gyr: f64, thr_acc: f64, thr_mag: f64) -> Self {
+        assert!(!mounts.is_empty(), "IMUを1台以上指定してください");
+        let n = mounts.len();
+        Self {
+            mounts,
+            thr: [thr_gyr, thr_acc, thr_mag],
+            faults: vec![false; n],
+        }
+    }
+
+    /// 各IMUの取付姿勢
+    pub fn mounts(&self) -> &[Quaternion<f64>] {
+        &self.mounts
+    }
+
+    /// 直前のfuse()で，いずれかの計測値を除いたセンサならtrue．
+    pub fn faults(&self) -> &[bool] {
+        &self.faults
+    }
+
+    /// 各IMUの計測値（センサ座標系）を統合し，機体座標系の計測値を返す．
+    ///
+    /// * samples: IMUごとの計測値（new()で渡した取付姿勢と同じ順に並べる）
+    pub fn fuse(&mut self, samples: &[Sample]) -> Sample {
+        assert_eq!(samples.len(), self.mounts.len(), "IMUの台数と計測値の数が一致しません");
+
+        // 機体座標系に揃える
+        let mut body = [Vec::with_capacity(samples.len()), Vec::with_capacity(samples.len()), Vec::with_capacity(samples.len())];
+        for (s, mount) in samples.iter().zip(&self.mounts) {
+            body[0].push( quat::vector_rotation(*mount, s.gyr) );
+            body[1].push( quat::vector_rotation(*mount, s.acc) );
+            body[2].push( quat::vector_rotation(*mount, s.mag) );
+        }
+
+        self.faults.fill(false);
+        Sample {
+            gyr: self.vote(&body[0], self.thr[0]),
+            acc: self.vote(&body[1], self.thr[1]),
+            mag: self.vote(&body[2], self.thr[2]),
+        }
+    }
+
+    /// 中央値から閾値以上離れた計測値を除いて平均する．
+    ///
+    /// 全ての計測値が離れている場合（2台の計測値が食い違った場合など）は，どれが正しいか分からないので全て平均する．
+    fn vote(&mut self, vs: &[Vector3<f64>], thr: f64) -> Vector3<f64> {
+        let med = median_vec(vs);
+
+        let mut sum = [0.0; 3];
+        let mut n = 0;
+        for (i, v) in vs.iter().enumerate() {
+            if quat::norm_vec( quat::sub_vec(*v, med) ) < thr {
+                sum = quat::add_vec(sum, *v);
+                n += 1;
+            } else {
+                self.faults[i] = true;
+            }
+        }
+
+        if n == 0 {
+            sum = vs.iter().fold([0.0; 3], |acc, v| quat::add_vec(acc, *v));
+            n = vs.len();
+        }
+        quat::scale_vec((n as f64).recip(), sum)
+    }
+}
+
+/// 各軸の中央値（偶数個の場合は中央の2つの平均）
+fn median_vec(vs: &[Vector3<f64>]) -> Vector3<f64> {
+    let mut med = [0.0; 3];
+    let mut buf: Vec<f64> = Vec::with_capacity(vs.len());
+    for (i, m) in med.iter_mut().enumerate() {
+        buf.clear();
+        buf.extend( vs.iter().map(|v| v[i]) );
+        buf.sort_by(f64::total_cmp);
+        let n = buf.len();
+        *m = if n % 2 == 1 {
+            buf[n / 2]
+        } else {
+            0.5 * (buf[n / 2 - 1] + buf[n / 2])
+        };
+    }
+    med
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_PI_2;
+
+    fn mounts() -> Vec<Quaternion<f64>> {
+        vec![
+            (1.0, [0.0; 3]),
+            quat::from_axis_angle([0.0, 0.0, 1.0], FRAC_PI_2),
+            quat::from_axis_angle([1.0, 0.0, 0.0], FRAC_PI_2),
+        ]
+    }
+
+    /// 機体座標系の計測値から各IMUの計測値を作る
+    fn measure(mounts: &[Quaternion<f64>], s: Sample) -> Vec<Sample> {
+        mounts.iter().map(|m| Sample {
+            gyr: quat::frame_rotation(*m, s.gyr),
+            acc: quat::frame_rotation(*m, s.acc),
+            mag: quat::frame_rotation(*m, s.mag),
+        }).collect()
+    }
+
+    #[test]
+    fn mounts_are_aligned_to_body_frame() {
+        let mut imus = ImuArray::new(mounts(), 0.1, 0.5, 0.1);
+        let truth = Sample { gyr: [0.1, -0.2, 0.3], acc: [1.0, 2.0, 9.0], mag: [0.0, 0.6, -0.8] };
+        let fused = imus.fuse( &measure(imus.mounts(), truth) );
+
+        for (a, b) in [(fused.gyr, truth.gyr), (fused.acc, truth.acc), (fused.mag, truth.mag)] {
+            assert!(quat::norm_vec(quat::sub_vec(a, b)) < 1e-12);
+        }
+        assert_eq!(imus.faults(), &[false; 3]);
+    }
+
+    #[test]
+    fn faulty_sensor_is_excluded() {
+        let mut imus = ImuArray::new(mounts(), 0.1, 0.5, 0.1);
+        let truth = Sample { gyr: [0.1, -0.2, 0.3], acc: [0.0, 0.0, 9.8], mag: [0.0, 1.0, 0.0] };
+        let mut samples = measure(imus.mounts(), truth);
+        samples[1].gyr[0] += 1.0;  // 2台目の角速度センサが故障
+
+        let fused = imus.fuse(&samples);
+        assert!(quat::norm_vec(quat::sub_vec(fused.gyr, truth.gyr)) < 1e-12);
+        assert_eq!(imus.faults(), &[false, true, false]);
+    }
+}
@@ -0,0 +1,432 @@
+//! 記録済みのセンサログを再生して姿勢推定を行う
+//!
+//! ログの形式（CSV，数値として読めない行は読み飛ばす）：
+//! 時刻[s], 角速度x,y,z[rad/s], 加速度x,y,z[m/s^2], 地磁気x,y,z
+//!
+//! ログはサンプリング周期DTで記録されているものとする（--log-dtを付けた場合は時刻の差を予測ステップの経過時間に使う）．
+//! 時刻は秒（相対時刻またはUnix時間）かISO 8601で，--time-formatで結果の時刻の列の書式を選ぶ．
+//! stream()ではライブ入力（標準入力，シリアルポート，UDP，模擬した計測値．source.rs）のサンプルを順に読み，
+//! 推定結果を標準出力に書き出す．ログの再生もライブ入力も同じestimate()で推定する．
+//! --smoothを付けた場合は，最後に後ろ向きにも推定して平滑化した姿勢を別のファイルに書き出す．
+//! --truthで真値を与えた場合は，各時刻の姿勢誤差を外乱検出の誤差関数の後に書き出し，最後に統計値を表示する
+//! （真値の記録の範囲外の時刻は姿勢誤差の列を空にする）．
+//! --mag-refで基準の磁場の時系列を与えた場合は，各時刻の値に切り替えながら推定する．
+//! 欠損したサンプルは--gapsの方法で埋め（source::GapFiller），順序の入れ替わったサンプルは捨てる．
+//! --recordで記録したバイナリのファイル（record.rs）もログとして再生できる（先頭の識別子で判別する）．
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write, BufWriter, BufRead, BufReader};
+use std::rc::Rc;
+
+use omega_ff_dynamic_acc::{ahrs, calibration, ins, quat, smoother::Smoother, DT};
+use omega_ff_dynamic_acc::source::{ImuSample, SensorSource, CsvSource, IterSource, GapFiller, GAP_RATIO};
+use omega_ff_dynamic_acc::pipeline::{ThreadedSource, ThreadedWriter, QueueStats};
+
+use super::error::{self, Context};
+use super::progress::Progress;
+use super::{interrupt, mqtt, record, rerun_log, shared, viewer, window};
+use super::score::ErrorStats;
+use super::{Options, attitude_error, new_filter, new_spike_filter, new_stationary_detector, new_noise_estimator, step, correct, update_startup, update_noise_estimate, report_noise_estimate, euler_angles, estimate_record, output, timing};
+
+/// 推定結果の出力先
+const RESULT_PATH: &str = "replay_result.csv";
+
+/// ライブ入力の推定結果の出力先（エラーの表示に使う）
+const STDOUT: &str = "標準出力";
+
+/// 平滑化した姿勢の出力先
+const SMOOTHED_PATH: &str = "replay_smoothed.csv";
+
+/// 地磁気のハードアイアンの推定値の出力先（--track-hard-iron）
+const HARD_IRON_PATH: &str = "hard_iron.csv";
+
+/// 地磁気のハードアイアンの推定の時定数[s]
+const HARD_IRON_TAU: f64 = 60.0;
+
+/// --pipelineのスレッド間のキューの長さ（サンプル数，行数）
+const PIPELINE_CAPACITY: usize = 256;
+
+/// 途中状態の保存先
+#[cfg(feature = "serde")]
+const STATE_PATH: &str = "replay_state.json";
+
+/// 途中状態を保存する間隔（サンプル数）
+#[cfg(feature = "serde")]
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// 基準座標系上における地磁気の時系列（長い軌跡を位置に応じた磁場のモデルで補正する場合など）
+///
+/// ファイルの形式（CSV，数値として読めない行は読み飛ばす）：時刻[s], 地磁気x,y,z（基準座標系）
+#[derive(Debug, Clone)]
+pub struct MagReference {
+    times: Vec<f64>,                    // 時刻[s]（単調増加）
+    fields: Vec<quat::Vector3<f64>>,    // 基準座標系上における地磁気
+}
+
+impl MagReference {
+    pub fn load(path: &str) -> error::Result<Self> {
+        let file = BufReader::new( fs::File::open(path).context(path)? );
+        let (times, fields): (Vec<f64>, Vec<_>) = file.lines()
+            .map_while(Result::ok)
+            .filter_map(|line| {
+                let nums: Vec<f64> = line.split(',').map(|v| v.trim().parse().ok()).collect::<Option<_>>()?;
+                (nums.len() >= 4).then(|| (nums[0], [nums[1], nums[2], nums[3]]))
+            })
+            .unzip();
+        if times.is_empty() {
+            return Err( error::Error::format(path, "基準の磁場のファイルにデータがありません") );
+        }
+        if !times.windows(2).all(|w| w[0] < w[1]) {
+            return Err( error::Error::format(path, "時刻は単調増加でなければなりません") );
+        }
+        Ok( Self { times, fields } )
+    }
+
+    /// 時刻tの時点で最新の値（最初の時刻より前なら最初の値）
+    pub fn at(&self, t: f64) -> quat::Vector3<f64> {
+        let i = self.times.partition_point(|&ti| ti <= t);
+        self.fields[i.saturating_sub(1)]
+    }
+}
+
+/// * path: センサログのパス
+/// * opts: コマンドライン引数（resumeが有効なら前回保存した途中状態から再開する）
+pub fn run(path: &str, opts: &Options) -> error::Result<()> {
+    interrupt::install();
+    let recorded = record::is_recording(path);
+
+    let (mut filter, mut file) = if opts.input.resume {
+        resume_state()?
+    } else {
+        let filter = new_filter(&opts.filter);
+        let file = BufWriter::new( fs::File::create(RESULT_PATH).context(RESULT_PATH)? );
+        (filter, file)
+    };
+    // 記録したファイルの読み取りのエラー（そこまでで推定を止め，結果を書き出してから返す）
+    let read_error = Rc::new(RefCell::new(None));
+    let source: Box<dyn SensorSource> = if recorded {
+        Box::new( IterSource(until_error(record::load(path)?, Rc::clone(&read_error))) )
+    } else {
+        Box::new( CsvSource::open(path).context(path)? )
+    };
+    let mut source = GapFiller::new(source, DT, opts.input.gaps);
+    // 再開する場合は処理済みのサンプル（欠損を埋めたものを含む）を読み飛ばす
+    let n_skip = filter.n_steps as usize;
+    for _ in 0..n_skip {
+        source.next_sample();
+    }
+    // 平滑化する場合は前向きの推定結果を記録しておく（再開した場合は再開後の分だけ平滑化する）
+    let mut smoother = opts.input.smooth.then(Smoother::new);
+    // 進捗表示のために行数を数えておく（数値として読めない行も含むので目安）
+    let n_lines = if recorded {
+        record::count(path)?
+    } else {
+        BufReader::new( fs::File::open(path).context(path)? ).lines().count()
+    };
+    let mut progress = Progress::new("ログ再生", Some( n_lines.saturating_sub(n_skip) ));
+    let result = estimate(&mut source, &mut filter, &mut file, opts, true, smoother.as_mut(), Some(&mut progress));
+    progress.finish();
+    report_gaps(&source);
+
+    // 途中で失敗した場合も，それまでの推定結果は書き出しておく
+    let flushed = file.flush().context(RESULT_PATH);
+    result?;
+    flushed?;
+    if let Some(e) = read_error.take() {
+        return Err(e);
+    }
+    #[cfg(feature = "serde")]
+    filter.save_state(STATE_PATH).context(STATE_PATH)?;
+
+    if let Some(smoother) = smoother {
+        write_smoothed(&smoother, filter, opts).context(SMOOTHED_PATH)?;
+    }
+    Ok(())
+}
+
+/// 最初のエラーの手前までのサンプルを返す（エラーはerrorに残す）．
+fn until_error(
+    samples: impl Iterator<Item = error::Result<ImuSample>>, error: Rc<RefCell<Option<error::Error>>>
+) -> impl Iterator<Item = ImuSample> {
+    samples.map_while(move |s| s.map_err(|e| *error.borrow_mut() = Some(e)).ok())
+}
+
+/// 後ろ向きに推定して平滑化した姿勢を書き出す．
+/// 
+/// 形式：時刻, オイラー角（3個）, 四元数（4個）
+fn write_smoothed(smoother: &Smoother, filter: ahrs::AttitudeFilter, opts: &Options) -> io::Result<()> {
+    // 後ろ向きの推定ではZUPTを使わない
+    let smoothed = smoother.smooth(filter, |f, s| correct(f, &opts.filter, s.gyr, s.acc, s.mag, false));
+
+    let mut file = output::CsvWriter::new( BufWriter::new( fs::File::create(SMOOTHED_PATH)? ) )
+        .with_time_format(opts.output.time_format)
+        .with_columns(opts.output.columns)
+        .with_precision(opts.output.precision);
+    for (&time, q) in smoother.times().iter().zip(smoothed) {
+        file.write(&output::SmoothedRecord { time, euler: euler_angles(q, opts.output.euler), q })?;
+    }
+    file.flush()
+}
+
+/// ライブ入力からサンプルを読み，推定結果を標準出力に書き出す．
+/// 
+/// 入力が終わるか，出力先のパイプが閉じられるか，Ctrl-Cで中断されたら（signalフィーチャ．interrupt.rs）終了する．
+/// --pipelineを付けた場合は読み取りと書き出しを別のスレッドで行う（pipeline.rs）．
+pub fn stream<S: SensorSource + Send + 'static>(source: S, opts: &Options) -> error::Result<()> {
+    interrupt::install();
+    let mut filter = new_filter(&opts.filter);
+    let result = match opts.input.pipeline {
+        Some(policy) => {
+            let mut source = GapFiller::new(ThreadedSource::spawn(source, PIPELINE_CAPACITY, policy), DT, opts.input.gaps);
+            let mut out = ThreadedWriter::spawn(io::stdout(), PIPELINE_CAPACITY, policy);
+            let result = estimate(&mut source, &mut filter, &mut out, opts, false, None, None);
+            report_gaps(&source);
+            report_queue("読み取り", source.get_ref().stats());
+            report_queue("書き出し", out.stats());
+            result.and( out.finish().context(STDOUT) )
+        },
+        None => {
+            let mut source = GapFiller::new(source, DT, opts.input.gaps);
+            let result = estimate(&mut source, &mut filter, &mut io::stdout().lock(), opts, false, None, None);
+            report_gaps(&source);
+            result
+        },
+    };
+    match result {
+        Err(error::Error::Io { source, .. }) if source.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// スレッド間のキューで捨てた数と溜まった数の最大値を表示する．
+fn report_queue(name: &str, stats: QueueStats) {
+    eprintln!("{}のキュー：{}個中{}個を捨てました（溜まった数の最大値 {}）", name, stats.n_pushed, stats.n_dropped, stats.max_len);
+}
+
+/// 欠損を埋めたサンプルと捨てたサンプルの数を表示する．
+fn report_gaps<S: SensorSource>(source: &GapFiller<S>) {
+    if source.n_filled() > 0 || source.n_unfilled() > 0 || source.n_dropped() > 0 {
+        eprintln!("欠損を埋めたサンプル {}，長すぎるので埋めなかった欠損 {}，順序が入れ替わっていたので捨てたサンプル {}",
+            source.n_filled(), source.n_unfilled(), source.n_dropped());
+    }
+}
+
+/// サンプルを順に処理して推定結果を書き出す．
+/// 
+/// * checkpoint: 途中状態を保存するかどうか（serdeフィーチャが無効な場合は無視する）
+/// * smoother  : 平滑化のために各ステップの推定結果を記録する
+/// * progress  : 進捗表示
+#[cfg_attr(not(feature = "serde"), allow(unused_variables))]
+fn estimate<W: Write>(
+    mut source: impl SensorSource, filter: &mut ahrs::AttitudeFilter, file: &mut W,
+    opts: &Options, checkpoint: bool, mut smoother: Option<&mut Smoother>, mut progress: Option<&mut Progress>
+) -> error::Result<()> {
+    // 推定結果の出力先（ログ再生はファイル，ライブ入力は標準出力）
+    let target = if checkpoint { RESULT_PATH } else { STDOUT };
+    // 推測航法（途中状態には含めないので，再開した場合は速度・位置0から積分し直す）
+    let mut dr = ins::DeadReckoning::default();
+    let mut stationary = new_stationary_detector();
+    // 起動時のバイアス推定（再開した場合は行わない）
+    let mut startup = opts.input.init.filter(|_| filter.n_steps == 0).map(calibration::StartupCalibrator::new);
+    let mut timer = opts.input.profile.then(timing::StepTimer::new);
+    let mut spikes = opts.input.reject_spikes.then(new_spike_filter);
+    let mut noise_est = opts.input.estimate_noise.then(new_noise_estimator);
+    let mut recorder = opts.output.record.as_deref().map(record::Recorder::create).transpose()?;
+    let mut out = output::CsvWriter::new(file)
+        .with_time_format(opts.output.time_format)
+        .with_columns(opts.output.columns)
+        .with_precision(opts.output.precision);
+    let mut plot = opts.output.plotjuggler.as_deref().map(|addr| output::JsonUdpSender::connect(addr).context(addr)).transpose()?;
+    let mut mqtt = opts.output.mqtt.as_deref().map(|addr| mqtt::MqttPublisher::connect(addr, &opts.output.mqtt_topic).context(addr)).transpose()?;
+    let mut shm = opts.output.shm.as_deref().map(shared::SharedStatePublisher::create).transpose()?;
+    let view = opts.output.viewer.map(viewer::Viewer::start).transpose()?;
+    let mut rerun = opts.output.rerun.as_deref().map(rerun_log::RerunLogger::connect).transpose()?;
+    let mut window = opts.output.window.then(window::Window::open).transpose()?;
+    // 地磁気のハードアイアンの追従と，推定値の記録
+    let mut hard_iron = if opts.input.track_hard_iron {
+        let log = output::CsvWriter::new( BufWriter::new( fs::File::create(HARD_IRON_PATH).context(HARD_IRON_PATH)? ) )
+            .with_time_format(opts.output.time_format)
+            .with_precision(opts.output.precision);
+        Some( (calibration::HardIronTracker::new(HARD_IRON_TAU), log) )
+    } else {
+        None
+    };
+    // 真値と比較した姿勢誤差（回転角）
+    let mut errors = Vec::new();
+    // 前のサンプルの時刻（--log-dt）
+    let mut prev_time: Option<f64> = None;
+
+    while let Some(sample) = source.next_sample() {
+        let time = sample.time;
+        let ahrs::Sample { mut gyr, mut acc, mut mag } = sample.sample();
+        if let Some(ref mut recorder) = recorder {
+            recorder.write(time, &sample.sample())?;
+            // ライブ入力（stream()）は強制終了されることがあるので毎サンプル書き出す
+            if !checkpoint {
+                recorder.flush()?;
+            }
+        }
+        if let Some(ref mut spikes) = spikes {
+            gyr = spikes.update(gyr);
+        }
+        if let Some(ref cal) = opts.input.acc_cal {
+            acc = cal.apply(acc);
+        }
+        if let Some(ref cal) = opts.input.mag_cal {
+            mag = cal.apply(mag);
+        }
+        if let Some((ref mut tracker, ref mut log)) = hard_iron {
+            mag = tracker.update(mag);
+            log.write(&output::HardIronRecord { time, offset: tracker.offset(), radius: tracker.radius() }).context(HARD_IRON_PATH)?;
+        }
+
+        // 推定
+        if let Some(ref mag_ref) = opts.input.mag_ref {
+            filter.set_reference_field(mag_ref.at(time));
+        }
+        let still = (opts.input.zupt || startup.is_some()) && stationary.update(gyr, acc);
+        let is_static = opts.input.zupt && still;
+        // 最初のサンプルや，欠損を埋めずに読み飛ばした（--gaps skip）場合はDTとみなす
+        let dt = match prev_time {
+            Some(prev) if opts.input.log_dt && time - prev <= GAP_RATIO * DT => time - prev,
+            _ => DT,
+        };
+        prev_time = Some(time);
+        step(filter, &opts.filter, &mut timer, gyr, acc, mag, is_static, dt);
+        update_startup(&mut startup, filter, ahrs::Sample { gyr, acc, mag }, still);
+        update_noise_estimate(&mut noise_est, filter, acc, mag);
+        if let Some(ref mut smoother) = smoother {
+            smoother.record(time, filter, ahrs::Sample { gyr, acc, mag });
+        }
+        if filter.health() == ahrs::Health::InvalidInput {
+            eprintln!("{:.3} s: 不正な計測値を読み飛ばしました", time);
+        }
+        dr.update(filter.q, acc);
+        if is_static {
+            dr.correct_zupt();
+        }
+
+        // ---------- データ書き込み ---------- //
+        // 真値との姿勢誤差（回転角，回転軸）
+        let q_true = opts.eval.truth.as_ref().and_then(|truth| truth.at(time - opts.eval.score_opts.time_offset));
+        let truth = match opts.eval.truth {
+            Some(_) => match q_true {
+                Some(q_true) => {
+                    let (angle_err, axis) = attitude_error(q_true, filter.q);
+                    errors.push(angle_err);
+                    if let Some(ref mut progress) = progress {
+                        progress.add_error(angle_err);
+                    }
+                    output::TruthError::Error(angle_err, axis)
+                },
+                None => output::TruthError::OutOfRange,
+            },
+            None => output::TruthError::None,
+        };
+        let record = output::ReplayRecord {
+            time,
+            estimate: estimate_record(&*filter, opts.output.euler),
+            e: filter.disturbance_error(acc),
+            truth,
+            vel: dr.vel,
+            pos: dr.pos,
+            gyro_free: filter.is_gyro_free(),
+        };
+        out.write(&record).context(target)?;
+        if let Some(ref mut plot) = plot {
+            plot.send(&record);
+        }
+        if let Some(ref mut mqtt) = mqtt {
+            mqtt.publish(time, filter, opts);
+        }
+        if let Some(ref mut shm) = shm {
+            shm.publish(time, filter);
+        }
+        if let Some(ref view) = view {
+            view.publish(&viewer::Frame::new(time, q_true, filter));
+        }
+        if let Some(ref mut window) = window {
+            window.show(&viewer::Frame::new(time, q_true, filter));
+        }
+        if let Some(ref mut rerun) = rerun {
+            rerun.log(time, q_true, filter, acc);
+        }
+        // ------------------------------------ //
+
+        // 途中状態の保存（推定結果を書き出してから状態を保存する）
+        #[cfg(feature = "serde")]
+        if checkpoint && filter.n_steps.is_multiple_of(CHECKPOINT_INTERVAL) {
+            out.flush().context(target)?;
+            filter.save_state(STATE_PATH).context(STATE_PATH)?;
+        }
+        if let Some(ref mut progress) = progress {
+            progress.step();
+        }
+        // 中断された場合は，ここまでの推定結果と統計値を書き出して終わる
+        if interrupt::requested() {
+            break;
+        }
+    }
+
+    if interrupt::requested() {
+        eprintln!("中断しました（{}サンプル目まで）", filter.n_steps);
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+    if let Some((tracker, mut log)) = hard_iron {
+        log.flush().context(HARD_IRON_PATH)?;
+        let offset = tracker.offset();
+        eprintln!("地磁気のオフセットの推定値: [{:.5}, {:.5}, {:.5}]", offset[0], offset[1], offset[2]);
+    }
+    if let Some(mut timer) = timer {
+        timer.report();
+    }
+    if let Some(spikes) = spikes {
+        eprintln!("角速度の外れ値を置き換えた回数 {}", spikes.n_rejected());
+    }
+    if let Some(est) = noise_est {
+        report_noise_estimate(&est);
+    }
+    if opts.eval.truth.is_some() {
+        if errors.is_empty() {
+            eprintln!("推定結果と真値の時刻が重なっていません");
+        } else {
+            let stats = ErrorStats::new(&errors);
+            eprintln!("真値との比較（{}サンプル）", errors.len());
+            eprintln!("  姿勢誤差（回転角）のRMS: {:.4} rad", stats.rms);
+            eprintln!("  平均: {:.4} rad", stats.mean);
+            eprintln!("  最大: {:.4} rad", stats.max);
+        }
+    }
+    Ok(())
+}
+
+/// 保存した途中状態を読み込み，推定結果のファイルを保存時点まで巻き戻す．
+#[cfg(feature = "serde")]
+fn resume_state() -> error::Result<(ahrs::AttitudeFilter, BufWriter<fs::File>)> {
+    let filter = ahrs::AttitudeFilter::load_state(STATE_PATH).context(STATE_PATH)?;
+
+    // 状態の保存後に書き出した分は捨てる
+    let n = filter.n_steps as usize;
+    let lines: Vec<String> = BufReader::new( fs::File::open(RESULT_PATH).context(RESULT_PATH)? )
+        .lines()
+        .take(n)
+        .collect::<Result<_, _>>()
+        .context(RESULT_PATH)?;
+    if lines.len() != n {
+        return Err( error::Error::format(RESULT_PATH, "推定結果のファイルが途中状態と一致しません") );
+    }
+
+    let mut file = BufWriter::new( fs::File::create(RESULT_PATH).context(RESULT_PATH)? );
+    for line in lines {
+        file.write_all( format!("{}\n", line).as_bytes() ).context(RESULT_PATH)?;
+    }
+    Ok( (filter, file) )
+}
+
+#[cfg(not(feature = "serde"))]
+fn resume_state() -> error::Result<(ahrs::AttitudeFilter, BufWriter<fs::File>)> {
+    Err( error::Error::Feature { option: "--resume", feature: "serde" } )
+}
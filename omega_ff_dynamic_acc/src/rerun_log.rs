@@ -0,0 +1,95 @@
+//! 推定値と外乱判定のrerunへの記録（--rerun，rerunフィーチャが必要）
+//!
+//! rerun_view.pyと同じ内容（姿勢の真値と推定値の直方体，誤差関数と外乱判定のフラグの時系列）を，
+//! 結果のファイルを介さずに推定しながらrerunのビューアに送る．
+//! 推定した並進加速度（加速度外乱）は機体に固定した矢印で表示する．
+//!
+//! エンティティ：
+//! * world/truth，world/estimate：姿勢の真値（灰色）と推定値（弱い外乱：黄，強い外乱：赤）
+//! * world/estimate/disturbance：推定した並進加速度[m/s^2]
+//! * disturbance/e，disturbance/weak，disturbance/strong：誤差関数と外乱判定のフラグ
+//! * attitude_error：真値との姿勢誤差（回転角）[rad]，gyro_free：推定モード
+
+use omega_ff_dynamic_acc::{ahrs, quat};
+
+use super::error::{Error, Result};
+
+pub struct RerunLogger {
+    #[cfg(feature = "rerun")]
+    rec: rerun::RecordingStream,
+    #[cfg(feature = "rerun")]
+    connected: bool,   // 送れなくなったら以降は送らない
+}
+
+#[cfg(feature = "rerun")]
+impl RerunLogger {
+    /// * addr: rerunのビューアのアドレス（ビューアはデフォルトで127.0.0.1:9876で待ち受ける）
+    pub fn connect(addr: &str) -> Result<Self> {
+        let addr = addr.parse()
+            .map_err(|_| Error::argument(format!("--rerunにはビューアのアドレス（127.0.0.1:9876など）を指定してください: {}", addr)))?;
+        let rec = rerun::RecordingStreamBuilder::new("omega_ff_dynamic_acc")
+            .connect_tcp_opts(addr, rerun::default_flush_timeout())?;
+        Ok( Self { rec, connected: true } )
+    }
+
+    /// 1ステップ分の姿勢と外乱判定を送る（ビューアとの接続が切れても推定は止めない）．
+    ///
+    /// * q_true: 姿勢の真値（ログ再生では無い場合がある）
+    /// * acc   : このステップの加速度の計測値
+    pub fn log(&mut self, time: f64, q_true: Option<quat::Quaternion<f64>>, filter: &ahrs::AttitudeFilter, acc: quat::Vector3<f64>) {
+        if !self.connected {
+            return;
+        }
+        if let Err(e) = self.try_log(time, q_true, filter, acc) {
+            eprintln!("rerunに送れなくなったので，以降は送りません（{}）", e);
+            self.connected = false;
+        }
+    }
+
+    fn try_log(
+        &self, time: f64, q_true: Option<quat::Quaternion<f64>>, filter: &ahrs::AttitudeFilter, acc: quat::Vector3<f64>
+    ) -> rerun::RecordingStreamResult<()> {
+        /// 機体を表す直方体の大きさの半分
+        const HALF_SIZE: [f32; 3] = [1.0, 0.5, 0.2];
+
+        let rec = &self.rec;
+        let log_body = |path: &str, q: quat::Quaternion<f64>, color: rerun::Color| {
+            let xyzw = [q.1[0], q.1[1], q.1[2], q.0].map(|v| v as f32);
+            rec.log(path, &rerun::Transform3D::from_rotation(rerun::Quaternion::from_xyzw(xyzw)))?;
+            rec.log(format!("{}/body", path), &rerun::Boxes3D::from_half_sizes([HALF_SIZE]).with_colors([color]))
+        };
+        let flag = |set: bool| if set { 1.0 } else { 0.0 };
+
+        rec.set_time_seconds("time", time);
+        if let Some(q_true) = q_true {
+            log_body("world/truth", q_true, rerun::Color::from_rgb(128, 128, 128))?;
+            let (angle_err, _) = super::attitude_error(q_true, filter.q);
+            rec.log("attitude_error", &rerun::Scalar::new(angle_err))?;
+        }
+        // 外乱中は推定値の色を変える（強い外乱：赤，弱い外乱：黄）
+        let color = if filter.is_disturbed() {
+            rerun::Color::from_rgb(255, 0, 0)
+        } else if filter.is_weakly_disturbed() {
+            rerun::Color::from_rgb(255, 200, 0)
+        } else {
+            rerun::Color::from_rgb(0, 160, 255)
+        };
+        log_body("world/estimate", filter.q, color)?;
+        let a = filter.linear_acceleration(acc).map(|v| v as f32);
+        rec.log("world/estimate/disturbance", &rerun::Arrows3D::from_vectors([a]).with_colors([rerun::Color::from_rgb(255, 0, 255)]))?;
+
+        rec.log("disturbance/e", &rerun::Scalar::new(filter.disturbance_error(acc)))?;
+        rec.log("disturbance/weak", &rerun::Scalar::new(flag(filter.is_weakly_disturbed())))?;
+        rec.log("disturbance/strong", &rerun::Scalar::new(flag(filter.is_disturbed())))?;
+        rec.log("gyro_free", &rerun::Scalar::new(flag(filter.is_gyro_free())))
+    }
+}
+
+#[cfg(not(feature = "rerun"))]
+impl RerunLogger {
+    pub fn connect(_addr: &str) -> Result<Self> {
+        Err( Error::Feature { option: "--rerun", feature: "rerun" } )
+    }
+
+    pub fn log(&mut self, _time: f64, _q_true: Option<quat::Quaternion<f64>>, _filter: &ahrs::AttitudeFilter, _acc: quat::Vector3<f64>) {}
+}
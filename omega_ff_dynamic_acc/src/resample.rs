@@ -0,0 +1,64 @@
+//! 姿勢の時系列の補間・リサンプリング
+//!
+//! モーションキャプチャなど，推定値と異なる周期で記録した姿勢の真値と比較するために，
+//! 前後のサンプルを球面線形補間（slerp）して任意の時刻の姿勢を求める．
+
+use super::quat::{self, Quaternion};
+
+/// 時刻tの姿勢を前後のサンプルから補間して返す．
+///
+/// timesは単調増加でなければならない．tが記録の範囲外の場合はNoneを返す（外挿はしない）．
+///
+/// * times: 各サンプルの時刻[s]
+/// * qs   : 各サンプルの姿勢（単位四元数）
+/// * t    : 補間する時刻[s]
+pub fn interpolate(times: &[f64], qs: &[Quaternion<f64>], t: f64) -> Option<Quaternion<f64>> {
+    assert_eq!(times.len(), qs.len());
+    // tより後の最初のサンプル
+    let i = times.partition_point(|&ti| ti <= t);
+    if i == 0 {
+        return None;
+    }
+    if i == times.len() {
+        // 最後のサンプルと同じ時刻なら補間せずに返す
+        return (times[i - 1] == t).then(|| qs[i - 1]);
+    }
+    let (t0, t1) = (times[i - 1], times[i]);
+    let ratio = (t - t0) / (t1 - t0);
+    Some( quat::slerp(qs[i - 1], qs[i], ratio) )
+}
+
+/// new_timesの各時刻の姿勢を補間して返す（記録の範囲外の時刻はNone）．
+///
+/// * times    : 各サンプルの時刻[s]（単調増加）
+/// * qs       : 各サンプルの姿勢（単位四元数）
+/// * new_times: 補間する時刻[s]
+pub fn resample(times: &[f64], qs: &[Quaternion<f64>], new_times: &[f64]) -> Vec<Option<Quaternion<f64>>> {
+    new_times.iter().map(|&t| interpolate(times, qs, t)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_constant_rotation() {
+        // z軸周りに一定の角速度で回転する姿勢を0.1秒ごとに記録し，途中の時刻で補間する
+        let rate = 0.8;
+        let times: Vec<f64> = (0..=10).map(|i| i as f64 * 0.1).collect();
+        let qs: Vec<Quaternion<f64>> = times.iter().map(|&t| quat::from_axis_angle([0.0, 0.0, 1.0], rate * t)).collect();
+
+        for t in [0.0, 0.03, 0.45, 0.999, 1.0] {
+            let q = interpolate(&times, &qs, t).unwrap();
+            let q_true = quat::from_axis_angle([0.0, 0.0, 1.0], rate * t);
+            assert!(quat::dot(q, q_true).abs() > 1.0 - 1e-12, "t = {}", t);
+        }
+        assert_eq!(resample(&times, &qs, &[-0.01, 1.01]), vec![None, None]);
+
+        // 同じ回転を表す符号の異なる四元数が混ざっていても最短経路で補間する
+        let mut qs_flipped = qs.clone();
+        qs_flipped[5] = quat::negate(qs_flipped[5]);
+        let q = interpolate(&times, &qs_flipped, 0.45).unwrap();
+        assert!(quat::dot(q, quat::from_axis_angle([0.0, 0.0, 1.0], rate * 0.45)).abs() > 1.0 - 1e-12);
+    }
+}
@@ -0,0 +1,75 @@
+//! フィルタの比較・評価に使うシナリオ
+//!
+//! シミュレーションの標準設定（一定角速度で回転，10〜20秒に加速度外乱）と同じ真値と計測値の列をライブラリの
+//! syntheticモジュールで作る．計測値を先に作っておくので，複数のフィルタに全く同じノイズの計測値を与えられる．
+
+use omega_ff_dynamic_acc::{estimator::AttitudeEstimator, synthetic};
+use omega_ff_dynamic_acc::source::{ImuSample, IterSource, SensorSource};
+use rand::Rng;
+
+use super::{N, GYR_VAR, ACC_VAR, MAG_VAR, SensorNoise, attitude_error};
+
+pub use omega_ff_dynamic_acc::synthetic::{Step, GYR_BIAS, DISTURBANCE};
+
+/// 収束したとみなす姿勢誤差（回転角）[rad]
+const CONVERGED_ERR: f64 = 0.05;
+
+/// シナリオの真値と計測値を作る（ノイズはrngから生成する，synthetic::generate()を参照）．
+pub fn generate<R: Rng>(rng: &mut R, noise: &SensorNoise) -> Vec<Step> {
+    let var = synthetic::NoiseVariance {
+        gyr: SensorNoise::var(noise.gyr, GYR_VAR),
+        acc: SensorNoise::var(noise.acc, ACC_VAR),
+        mag: SensorNoise::var(noise.mag, MAG_VAR),
+    };
+    synthetic::generate(rng, N, var)
+}
+
+/// シナリオの計測値を順に返す取得元（--synthetic）
+pub fn source(steps: Vec<Step>) -> impl SensorSource {
+    IterSource( steps.into_iter().map(|s| ImuSample { time: s.time, gyr: s.sample.gyr, acc: s.sample.acc, mag: s.sample.mag }) )
+}
+
+/// シナリオの計測値でフィルタを動かし，評価指標を計算する．
+pub fn evaluate(estimator: &mut (impl AttitudeEstimator + ?Sized), steps: &[Step]) -> Metrics {
+    Metrics::new(steps, &errors(estimator, steps))
+}
+
+/// シナリオの計測値でフィルタを動かし，各ステップの姿勢誤差（回転角）[rad]を返す．
+pub fn errors(estimator: &mut (impl AttitudeEstimator + ?Sized), steps: &[Step]) -> Vec<f64> {
+    steps.iter().map(|step| {
+        estimator.update(&step.sample);
+        attitude_error(step.q, estimator.quaternion()).0
+    }).collect()
+}
+
+/// 姿勢推定値の評価指標
+#[derive(Debug, Clone, Copy)]
+pub struct Metrics {
+    pub rms: f64,              // 姿勢誤差（回転角）のRMS[rad]
+    pub rms_disturbance: f64,  // 加速度外乱を加えている間の姿勢誤差のRMS[rad]
+    pub recovery_time: f64,    // 加速度外乱が終わってから姿勢誤差がCONVERGED_ERR以下に収まるまでの時間[s]（収まらなければ無限大）
+}
+
+impl Metrics {
+    /// 各ステップの姿勢誤差（回転角）[rad]から評価指標を計算する．
+    pub fn new(steps: &[Step], errors: &[f64]) -> Self {
+        let rms = |iter: &mut dyn Iterator<Item = f64>| {
+            let (sum_sq, n) = iter.fold((0.0, 0), |(s, n), e| (s + e * e, n + 1));
+            (sum_sq / n as f64).sqrt()
+        };
+        // 外乱が終わった後，最後にCONVERGED_ERRを超えていたステップの次で収束したとみなす
+        let end = *DISTURBANCE.end();
+        let recovery_time = match steps.iter().zip(errors).rposition(|(s, &e)| s.time > end && e > CONVERGED_ERR) {
+            Some(i) if i + 1 == steps.len() => f64::INFINITY,
+            Some(i) => steps[i + 1].time - end,
+            None => 0.0,
+        };
+        Self {
+            rms: rms(&mut errors.iter().copied()),
+            rms_disturbance: rms(&mut steps.iter().zip(errors)
+                .filter(|(s, _)| DISTURBANCE.contains(&s.time))
+                .map(|(_, &e)| e)),
+            recovery_time,
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! 推定結果を外部の真値（モーションキャプチャなど）と比較する（scoreサブコマンド）
+//!
+//! 真値を推定結果の各時刻に球面線形補間して姿勢誤差を計算し，統計値を表示する．
+//! 真値の読み込みと補間（Trace）は，ログ再生で真値と比較する場合（--truth）にも使う．
+//! 真値の記録の範囲外の時刻は比較しない．
+//!
+//! 真値のファイルの形式（CSV，数値として読めない行は読み飛ばす）：時刻[s], qw, qx, qy, qz
+//!
+//! 推定結果のファイルは時刻が先頭の列にあるCSVで，四元数の列の位置を--q-colで指定する
+//! （デフォルトはreplay_result.csvに合わせて7，replay_smoothed.csvなら4）．
+//!
+//! score_result.csvの1行の形式：時刻, 姿勢誤差の回転角[rad], 回転軸x,y,z
+
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter};
+
+use omega_ff_dynamic_acc::{quat, resample};
+
+use super::{attitude_error, output};
+use super::error::{self, Context};
+
+/// 比較結果の出力先
+const RESULT_PATH: &str = "score_result.csv";
+
+/// 時刻と姿勢の列（外部の真値など）
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub times: Vec<f64>,                  // 時刻[s]（単調増加）
+    pub qs: Vec<quat::Quaternion<f64>>,   // 姿勢
+}
+
+impl Trace {
+    /// CSVファイルから時刻と四元数の列を読み込む（数値として読めない行は読み飛ばす）．
+    ///
+    /// * q_col: 四元数（qw）が入っている列（続く3列がqx, qy, qz）
+    pub fn load(path: &str, q_col: usize) -> error::Result<Self> {
+        let file = BufReader::new( fs::File::open(path).context(path)? );
+        let (times, qs): (Vec<f64>, Vec<_>) = file.lines()
+            .map_while(Result::ok)
+            .filter_map(|line| {
+                let nums: Vec<f64> = line.split(',').map(|v| v.trim().parse().ok()).collect::<Option<_>>()?;
+                let q = nums.get(q_col..q_col + 4)?;
+                Some( (nums[0], quat::normalize((q[0], [q[1], q[2], q[3]]))) )
+            })
+            .unzip();
+        if times.is_empty() {
+            return Err( error::Error::format(path, "時刻と四元数の行がありません") );
+        }
+        if !times.windows(2).all(|w| w[0] < w[1]) {
+            return Err( error::Error::format(path, "時刻は単調増加でなければなりません") );
+        }
+        Ok( Self { times, qs } )
+    }
+
+    /// 時刻tの姿勢を前後のサンプルから補間して返す（記録の範囲外ならNone）．
+    pub fn at(&self, t: f64) -> Option<quat::Quaternion<f64>> {
+        resample::interpolate(&self.times, &self.qs, t)
+    }
+}
+
+/// 姿勢誤差（回転角）の統計値
+pub struct ErrorStats {
+    pub rms: f64,   // RMS[rad]
+    pub mean: f64,  // 平均[rad]
+    pub max: f64,   // 最大値[rad]
+}
+
+impl ErrorStats {
+    pub fn new(errors: &[f64]) -> Self {
+        let n = errors.len() as f64;
+        Self {
+            rms: (errors.iter().map(|e| e * e).sum::<f64>() / n).sqrt(),
+            mean: errors.iter().sum::<f64>() / n,
+            max: errors.iter().fold(0.0_f64, |a, &b| a.max(b)),
+        }
+    }
+}
+
+/// 比較の設定
+#[derive(Debug, Clone)]
+pub struct ScoreOptions {
+    pub q_col: usize,       // 推定結果のファイルで四元数（qw）が入っている列
+    pub time_offset: f64,   // 真値の時刻に加える時間[s]（記録機器の時刻のずれ，--truthでも使う）
+    pub align_frame: bool,  // 基準座標系の違い（一定の回転）を推定して除いてから比較する
+}
+
+impl Default for ScoreOptions {
+    fn default() -> Self {
+        Self { q_col: 7, time_offset: 0.0, align_frame: false }
+    }
+}
+
+/// * estimate: 推定結果のファイルのパス
+/// * truth   : 真値のファイルのパス
+pub fn run(estimate: &str, truth: &str, opts: &ScoreOptions) -> error::Result<()> {
+    let est = Trace::load(estimate, opts.q_col)?;
+    let truth = Trace::load(truth, 1)?;
+
+    // 推定結果の時刻に真値を補間する
+    let pairs: Vec<(f64, quat::Quaternion<f64>, quat::Quaternion<f64>)> = est.times.iter().zip(&est.qs)
+        .filter_map(|(&t, &q_est)| Some((t, truth.at(t - opts.time_offset)?, q_est)))
+        .collect();
+    if pairs.is_empty() {
+        return Err( error::Error::format(estimate, "推定結果と真値の時刻が重なっていません") );
+    }
+
+    // 基準座標系の違い：q_est = q_frame ⊗ q_true とみなしてq_frameの平均を求め，真値に掛けておく
+    let q_frame = if opts.align_frame {
+        let q_frame = mean_rotation(pairs.iter().map(|(_, q_true, q_est)| quat::mul(*q_est, quat::conj(*q_true))));
+        let (angle, axis) = attitude_error((1.0, [0.0; 3]), q_frame);
+        println!("基準座標系の違い: {:.4} rad（回転軸 [{:.4}, {:.4}, {:.4}]）", angle, axis[0], axis[1], axis[2]);
+        q_frame
+    } else {
+        (1.0, [0.0; 3])
+    };
+
+    let mut file = output::CsvWriter::new( BufWriter::new( fs::File::create(RESULT_PATH).context(RESULT_PATH)? ) );
+    let mut errors = Vec::with_capacity(pairs.len());
+    for (t, q_true, q_est) in pairs {
+        let (angle, axis) = attitude_error(quat::mul(q_frame, q_true), q_est);
+        errors.push(angle);
+        file.write(&output::ScoreRecord { time: t, angle_err: angle, axis_err: axis }).context(RESULT_PATH)?;
+    }
+    file.flush().context(RESULT_PATH)?;
+
+    let stats = ErrorStats::new(&errors);
+    println!("{}サンプルを比較しました（推定結果 {}サンプル中）", errors.len(), est.times.len());
+    println!("  姿勢誤差（回転角）のRMS: {:.4} rad", stats.rms);
+    println!("  平均: {:.4} rad", stats.mean);
+    println!("  最大: {:.4} rad", stats.max);
+    Ok(())
+}
+
+/// 姿勢の平均（最初の四元数と同じ符号に揃えて足し合わせ，正規化する）
+///
+/// ばらつきが小さい場合の近似．姿勢が1つも無ければ単位四元数を返す．
+fn mean_rotation(qs: impl Iterator<Item = quat::Quaternion<f64>>) -> quat::Quaternion<f64> {
+    let mut sum: Option<quat::Quaternion<f64>> = None;
+    for q in qs {
+        sum = Some( match sum {
+            Some(s) if quat::dot(s, q).is_sign_negative() => quat::sub(s, q),
+            Some(s) => quat::add(s, q),
+            None => q,
+        } );
+    }
+    sum.map_or((1.0, [0.0; 3]), quat::normalize)
+}
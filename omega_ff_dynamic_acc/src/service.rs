@@ -0,0 +1,146 @@
+//! 外部のプロセスから計測値を受け取り，推定値を返すgRPCのサービス（--serve，serveフィーチャが必要）
+//!
+//! Pythonの地上局など，Rustのライブラリをリンクしないプログラムから推定器を使うためのもの．
+//! サービスの定義はproto/omega_ff.proto（ビルド時にtonicとprostでコードを生成する）：
+//! * `PushImuSample`：計測値で推定し，推定値を返す
+//! * `GetAttitude`：最新の推定値を返す
+//! * `StreamAttitude`：推定値を更新のたびに送り続ける
+//!
+//! 認証は無いので，既定では同じマシン（127.0.0.1）からの接続だけを受け付ける．
+//! フィルタは推定用のスレッドが1つだけ持ち，RPCからはチャネルで計測値を渡す．
+//! 複数の送り手が同時に送る場合は時刻が前後しうる．
+
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::thread;
+
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, oneshot, watch};
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use omega_ff_dynamic_acc::{ahrs, quat, source, DT};
+
+use super::{Options, new_filter, step, estimate_record};
+use super::error::{self, Context};
+
+mod proto {
+    tonic::include_proto!("omega_ff");
+}
+
+use proto::attitude_estimator_server::{AttitudeEstimator, AttitudeEstimatorServer};
+
+/// StreamAttitudeで送り待ちにできる推定値の数（これより遅れた購読者は古い推定値を読み飛ばす）
+const STREAM_CAPACITY: usize = 1024;
+
+/// 推定用のスレッドに渡す計測値と，推定値の返し先
+type Push = (source::ImuSample, oneshot::Sender<proto::Attitude>);
+
+fn attitude(time: f64, filter: &ahrs::AttitudeFilter, opts: &Options) -> proto::Attitude {
+    let estimate = estimate_record(filter, opts.output.euler);
+    let (w, [x, y, z]) = estimate.q;
+    proto::Attitude {
+        time,
+        q: Some( proto::Quaternion { w, x, y, z } ),
+        euler: Some( vector(estimate.euler) ),
+        gyro_bias: Some( vector(estimate.bias) ),
+        weak: filter.is_weakly_disturbed(),
+        strong: filter.is_disturbed(),
+        healthy: filter.health() == ahrs::Health::Ok,
+    }
+}
+
+fn vector([x, y, z]: quat::Vector3<f64>) -> proto::Vector3 {
+    proto::Vector3 { x, y, z }
+}
+
+/// 受け取った計測値（角速度，加速度，地磁気のどれかが無ければINVALID_ARGUMENT）
+fn imu_sample(sample: proto::ImuSample) -> Result<source::ImuSample, Status> {
+    let field = |v: Option<proto::Vector3>, name: &str| {
+        v.map(|v| [v.x, v.y, v.z]).ok_or_else(|| Status::invalid_argument(format!("{}がありません", name)))
+    };
+    Ok( source::ImuSample {
+        time: sample.time,
+        gyr: field(sample.gyr, "gyr")?,
+        acc: field(sample.acc, "acc")?,
+        mag: field(sample.mag, "mag")?,
+    } )
+}
+
+/// 計測値で推定し，最新の推定値と購読者に送る（RPCの送り手が全て無くなったら終わる）．
+fn estimator(
+    rx: mpsc::Receiver<Push>, latest: watch::Sender<proto::Attitude>, updates: broadcast::Sender<proto::Attitude>, opts: &Options
+) {
+    let mut filter = new_filter(&opts.filter);
+    let mut prev_time = None;
+    for (sample, reply) in rx {
+        // 時刻の差をdtに使うのは--log-dtを付けた場合だけ（ログ再生と同じ）
+        let dt = match prev_time {
+            Some(prev) if opts.input.log_dt && sample.time > prev => sample.time - prev,
+            _ => DT,
+        };
+        prev_time = Some(sample.time);
+        step(&mut filter, &opts.filter, &mut None, sample.gyr, sample.acc, sample.mag, false, dt);
+
+        let attitude = attitude(sample.time, &filter, opts);
+        // 購読者がいない，返し先のRPCが取り消されたなどで送れなくても推定は続ける
+        let _ = updates.send(attitude);
+        latest.send_replace(attitude);
+        let _ = reply.send(attitude);
+    }
+}
+
+struct Service {
+    push: mpsc::Sender<Push>,
+    latest: watch::Receiver<proto::Attitude>,
+    updates: broadcast::Sender<proto::Attitude>,
+}
+
+#[tonic::async_trait]
+impl AttitudeEstimator for Service {
+    async fn push_imu_sample(&self, request: Request<proto::ImuSample>) -> Result<Response<proto::Attitude>, Status> {
+        let sample = imu_sample(request.into_inner())?;
+        let (tx, rx) = oneshot::channel();
+        self.push.send((sample, tx)).map_err(|_| Status::unavailable("推定を終了しました"))?;
+        let attitude = rx.await.map_err(|_| Status::unavailable("推定を終了しました"))?;
+        Ok( Response::new(attitude) )
+    }
+
+    async fn get_attitude(&self, _request: Request<proto::GetAttitudeRequest>) -> Result<Response<proto::Attitude>, Status> {
+        Ok( Response::new( *self.latest.borrow() ) )
+    }
+
+    type StreamAttitudeStream = Pin<Box<dyn Stream<Item = Result<proto::Attitude, Status>> + Send>>;
+
+    async fn stream_attitude(
+        &self, _request: Request<proto::StreamAttitudeRequest>
+    ) -> Result<Response<Self::StreamAttitudeStream>, Status> {
+        // 遅れて読み飛ばした分（Lagged）は送らない
+        let stream = BroadcastStream::new( self.updates.subscribe() ).filter_map(|attitude| attitude.ok().map(Ok));
+        Ok( Response::new( Box::pin(stream) ) )
+    }
+}
+
+/// * addr: 待ち受けるアドレス（127.0.0.1:50051など．0.0.0.0にすると他のマシンからも使える）
+pub fn run(addr: &str, opts: &Options) -> error::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().context("tokio")?;
+    let listener = runtime.block_on( TcpListener::bind(addr) ).context(addr)?;
+    eprintln!("{} でgRPCの要求を受け付けます（PushImuSample，GetAttitude，StreamAttitude）", addr);
+
+    let (push, rx) = mpsc::channel();
+    let (latest_tx, latest) = watch::channel( attitude(0.0, &new_filter(&opts.filter), opts) );
+    let (updates, _) = broadcast::channel(STREAM_CAPACITY);
+    let service = Service { push, latest, updates: updates.clone() };
+
+    // サーバが止まるとserviceが破棄され，推定用のスレッドも終わる
+    thread::scope(|scope| {
+        scope.spawn(|| estimator(rx, latest_tx, updates, opts));
+        runtime.block_on(
+            tonic::transport::Server::builder()
+                .add_service( AttitudeEstimatorServer::new(service) )
+                .serve_with_incoming( TcpListenerStream::new(listener) )
+        )
+    })?;
+    Ok(())
+}
@@ -0,0 +1,46 @@
+//! 最新の推定値の共有メモリへの書き出し（--shm，shmフィーチャが必要）
+
+use omega_ff_dynamic_acc::ahrs;
+
+use super::error::Result;
+#[cfg(feature = "shm")]
+use super::error::Context;
+#[cfg(not(feature = "shm"))]
+use super::error::Error;
+#[cfg(feature = "shm")]
+use omega_ff_dynamic_acc::shm;
+
+pub struct SharedStatePublisher {
+    #[cfg(feature = "shm")]
+    writer: shm::ShmWriter,
+}
+
+impl SharedStatePublisher {
+    /// * name: 共有メモリの名前（/omega_ffなど）
+    #[cfg(feature = "shm")]
+    pub fn create(name: &str) -> Result<Self> {
+        Ok( Self { writer: shm::ShmWriter::create(name).context(name)? } )
+    }
+
+    #[cfg(not(feature = "shm"))]
+    pub fn create(_name: &str) -> Result<Self> {
+        Err( Error::Feature { option: "--shm", feature: "shm" } )
+    }
+
+    #[cfg(feature = "shm")]
+    pub fn publish(&mut self, time: f64, filter: &ahrs::AttitudeFilter) {
+        let flags = [
+            (filter.is_weakly_disturbed(), shm::FLAG_WEAK),
+            (filter.is_disturbed(), shm::FLAG_STRONG),
+            (filter.is_gyro_free(), shm::FLAG_GYRO_FREE),
+            (filter.health() == ahrs::Health::Ok, shm::FLAG_HEALTHY),
+        ].iter().filter(|(set, _)| *set).fold(0, |flags, (_, bit)| flags | bit);
+        let state = shm::SharedState { time, n_steps: filter.n_steps, flags, ..Default::default() }
+            .with_quaternion(filter.q)
+            .with_bias(filter.gyro_bias());
+        self.writer.write(&state);
+    }
+
+    #[cfg(not(feature = "shm"))]
+    pub fn publish(&mut self, _time: f64, _filter: &ahrs::AttitudeFilter) {}
+}
@@ -0,0 +1,235 @@
+//! POSIX共有メモリへの最新の推定値の書き出し（shmフィーチャ）
+//!
+//! 同じマシンの制御プロセスが，ソケットを介さずにマイクロ秒単位の遅れで姿勢を読めるようにする．
+//! 共有メモリは先頭の識別子（u64，MAGIC），シーケンス番号（u64）とSharedState（repr(C)）だけで，シーケンスロックで読み書きする：
+//! 書き込み中は番号が奇数になるので，読む側は番号が偶数かつ読む前後で同じになるまで読み直す．
+//! 読む側は開くときに大きさと識別子を確かめ，配置の違う共有メモリ（古い版など）は読まない．
+//! C言語などから読む場合もこの配置（リトルエンディアン，各値8バイト）と手順に合わせる．
+
+use std::ffi::CString;
+use std::io;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+use super::quat::{Quaternion, Vector3};
+
+/// 共有メモリの先頭に置く識別子（"OMGFF"と配置の版．配置を変えたら末尾の版を上げる）
+pub const MAGIC: u64 = u64::from_le_bytes(*b"OMGFF\0\0\x01");
+
+/// SharedState::flagsのビット
+pub const FLAG_WEAK: u64 = 1 << 0;        // 弱い外乱を検知している
+pub const FLAG_STRONG: u64 = 1 << 1;      // 強い外乱を検知している
+pub const FLAG_GYRO_FREE: u64 = 1 << 2;   // 角速度を使わないモード
+pub const FLAG_HEALTHY: u64 = 1 << 3;     // 直前の計測値を使えた（ahrs::Health::Ok）
+
+/// 共有メモリに置く推定値
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SharedState {
+    pub time: f64,          // 時刻[s]
+    pub q: [f64; 4],        // 姿勢（w, x, y, z）
+    pub bias: [f64; 3],     // 角速度バイアスの推定値[rad/s]
+    pub n_steps: u64,       // フィルタのステップ数
+    pub flags: u64,         // FLAG_*の論理和
+}
+
+impl SharedState {
+    pub fn quaternion(&self) -> Quaternion<f64> {
+        (self.q[0], [self.q[1], self.q[2], self.q[3]])
+    }
+
+    pub fn with_quaternion(mut self, q: Quaternion<f64>) -> Self {
+        self.q = [q.0, q.1[0], q.1[1], q.1[2]];
+        self
+    }
+
+    pub fn with_bias(mut self, bias: Vector3<f64>) -> Self {
+        self.bias = bias;
+        self
+    }
+}
+
+/// 共有メモリの配置
+#[repr(C)]
+struct Segment {
+    magic: AtomicU64,
+    seq: AtomicU64,
+    state: SharedState,
+}
+
+/// 共有メモリの対応付け
+struct Mapping {
+    ptr: NonNull<Segment>,
+}
+
+impl Mapping {
+    /// * create: 無ければ作って大きさを合わせる（書き込む側）
+    ///
+    /// 読む側は大きさが足りない場合と識別子が違う場合にInvalidDataを返す．
+    fn open(name: &str, create: bool) -> io::Result<Self> {
+        let c_name = CString::new(name).map_err(io::Error::other)?;
+        let size = std::mem::size_of::<Segment>();
+        unsafe {
+            let flags = if create { libc::O_RDWR | libc::O_CREAT } else { libc::O_RDONLY };
+            let fd = libc::shm_open(c_name.as_ptr(), flags, 0o644);
+            if fd < 0 {
+                return Err( io::Error::last_os_error() );
+            }
+            if create && libc::ftruncate(fd, size as libc::off_t) < 0 {
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                return Err(err);
+            }
+            if !create {
+                // 大きさが足りないまま対応付けると，読んだときにSIGBUSになる
+                let mut stat: libc::stat = std::mem::zeroed();
+                if libc::fstat(fd, &mut stat) < 0 {
+                    let err = io::Error::last_os_error();
+                    libc::close(fd);
+                    return Err(err);
+                }
+                if (stat.st_size as u64) < size as u64 {
+                    libc::close(fd);
+                    return Err( io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("共有メモリの大きさが足りません（{}バイト，{}バイト必要）", stat.st_size, size)
+                    ) );
+                }
+            }
+            let prot = if create { libc::PROT_READ | libc::PROT_WRITE } else { libc::PROT_READ };
+            let ptr = libc::mmap(ptr::null_mut(), size, prot, libc::MAP_SHARED, fd, 0);
+            libc::close(fd);
+            if ptr == libc::MAP_FAILED {
+                return Err( io::Error::last_os_error() );
+            }
+            let mapping = Self { ptr: NonNull::new_unchecked(ptr.cast()) };
+            if create {
+                mapping.magic().store(MAGIC, Ordering::Release);
+            } else if mapping.magic().load(Ordering::Acquire) != MAGIC {
+                return Err( io::Error::new(io::ErrorKind::InvalidData, "共有メモリの識別子が違います（配置の版が違うか，書き込む側が初期化していません）") );
+            }
+            Ok(mapping)
+        }
+    }
+
+    fn magic(&self) -> &AtomicU64 {
+        unsafe { &(*self.ptr.as_ptr()).magic }
+    }
+
+    fn seq(&self) -> &AtomicU64 {
+        unsafe { &(*self.ptr.as_ptr()).seq }
+    }
+
+    fn state(&self) -> *mut SharedState {
+        unsafe { ptr::addr_of_mut!( (*self.ptr.as_ptr()).state ) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), std::mem::size_of::<Segment>()); }
+    }
+}
+
+/// 共有メモリに書き込む側（書き込むプロセスは1つだけにする）
+pub struct ShmWriter {
+    mapping: Mapping,
+    name: String,
+}
+
+impl ShmWriter {
+    /// * name: 共有メモリの名前（"/omega_ff"のように/で始める，Linuxでは/dev/shmの下に作られる）
+    pub fn create(name: &str) -> io::Result<Self> {
+        Ok( Self { mapping: Mapping::open(name, true)?, name: name.to_string() } )
+    }
+
+    pub fn write(&mut self, state: &SharedState) {
+        let seq = self.mapping.seq();
+        let s = seq.load(Ordering::Relaxed);
+        seq.store(s.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        unsafe { ptr::write_volatile(self.mapping.state(), *state); }
+        seq.store(s.wrapping_add(2), Ordering::Release);
+    }
+
+    /// 共有メモリを削除する（読んでいるプロセスの対応付けはそのまま残る）．
+    pub fn unlink(self) -> io::Result<()> {
+        let c_name = CString::new(self.name.as_str()).map_err(io::Error::other)?;
+        if unsafe { libc::shm_unlink(c_name.as_ptr()) } < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+        Ok(())
+    }
+}
+
+/// 共有メモリを読む側
+pub struct ShmReader {
+    mapping: Mapping,
+}
+
+impl ShmReader {
+    pub fn open(name: &str) -> io::Result<Self> {
+        Ok( Self { mapping: Mapping::open(name, false)? } )
+    }
+
+    /// 最新の推定値とシーケンス番号（書き込みのたびに2増える，まだ書き込まれていなければ0）を返す．
+    pub fn read(&self) -> (SharedState, u64) {
+        let seq = self.mapping.seq();
+        loop {
+            let s1 = seq.load(Ordering::Acquire);
+            if s1 % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let state = unsafe { ptr::read_volatile(self.mapping.state()) };
+            fence(Ordering::Acquire);
+            if seq.load(Ordering::Relaxed) == s1 {
+                return (state, s1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_and_reads_latest_state() {
+        let name = format!("/omega_ff_test_{}", std::process::id());
+        let mut writer = ShmWriter::create(&name).unwrap();
+        let reader = ShmReader::open(&name).unwrap();
+        assert_eq!(reader.read().1, 0);
+
+        for i in 1..=3 {
+            let state = SharedState { time: i as f64 * 0.02, n_steps: i, flags: FLAG_HEALTHY, ..Default::default() }
+                .with_quaternion((1.0, [0.0; 3]));
+            writer.write(&state);
+            assert_eq!(reader.read(), (state, 2 * i));
+        }
+        writer.unlink().unwrap();
+        assert!(ShmReader::open(&name).is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_segments() {
+        // 小さすぎる共有メモリと，識別子の違う共有メモリ
+        let open = |name: &str, size: usize, first: u64| unsafe {
+            let c_name = CString::new(name).unwrap();
+            let fd = libc::shm_open(c_name.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0o644);
+            assert!(fd >= 0);
+            assert_eq!(libc::ftruncate(fd, size as libc::off_t), 0);
+            assert_eq!(libc::pwrite(fd, first.to_le_bytes().as_ptr().cast(), 8, 0), 8);
+            libc::close(fd);
+            c_name
+        };
+        let size = std::mem::size_of::<Segment>();
+        for (suffix, size, first) in [("short", size - 8, MAGIC), ("magic", size, 0), ("version", size, MAGIC + (1 << 56))] {
+            let name = format!("/omega_ff_test_{}_{}", std::process::id(), suffix);
+            let c_name = open(&name, size, first);
+            let err = ShmReader::open(&name).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", suffix);
+            unsafe { libc::shm_unlink(c_name.as_ptr()); }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! 記録済みの推定結果を後ろ向きにもう一度推定して平滑化する（事後解析用）
+//!
+//! 前向きの推定中に各ステップの姿勢と計測値を記録しておき，最後のステップから時間を遡って
+//! 同じフィルタで推定し直す（角速度の符号を反転させて積分する）．
+//! 前向きと後ろ向きの推定値は，強い加速度外乱で補正を止めてからの経過時間（角速度の積分だけで
+//! 推定している時間）が短い方を重視して球面線形補間で合成する（RTSスムーザと同じく，誤差の小さい方を信頼する）．
+
+use super::DT;
+use super::ahrs::{AttitudeFilter, Sample};
+use super::quat::{self, Quaternion};
+
+#[derive(Debug, Clone, Default)]
+pub struct Smoother {
+    times: Vec<f64>,                 // 時刻[s]
+    samples: Vec<Sample>,            // 計測値
+    forward: Vec<Quaternion<f64>>,   // 前向きの姿勢推定値
+    coast: Vec<f64>,                 // 前向きの推定で補正を止めてからの経過時間[s]
+}
+
+impl Smoother {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 記録したステップ数
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    /// 何も記録していなければtrue
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// 記録した時刻
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    /// 前向きの推定を1ステップ行った後に呼び，姿勢推定値と計測値を記録する．
+    ///
+    /// * time  : 時刻[s]
+    /// * filter: 予測・補正ステップを行った後のフィルタ
+    /// * sample: このステップで使った計測値
+    pub fn record(&mut self, time: f64, filter: &AttitudeFilter, sample: Sample) {
+        let coast = match self.coast.last() {
+            Some(&c) if filter.is_disturbed() => c + DT,
+            _ => 0.0,
+        };
+        self.times.push(time);
+        self.samples.push(sample);
+        self.forward.push(filter.q);
+        self.coast.push(coast);
+    }
+
+    /// 後ろ向きに推定し，前向きの推定値と合成した姿勢推定値を記録した順に返す．
+    ///
+    /// * filter : 前向きの推定を終えた状態のフィルタ（パラメータと最後の状態を引き継ぐ）
+    /// * correct: 補正ステップ（計測値は記録したものをそのまま渡す）
+    pub fn smooth(&self, mut filter: AttitudeFilter, mut correct: impl FnMut(&mut AttitudeFilter, &Sample)) -> Vec<Quaternion<f64>> {
+        let n = self.len();
+        let mut smoothed = self.forward.clone();
+        if n == 0 {
+            return smoothed;
+        }
+
+        // 時間を遡ると角速度とバイアスの符号が反転する
+        let bias = filter.gyro_bias();
+        filter.set_gyro_bias( quat::negate_vec(bias) );
+
+        let mut coast_b = 0.0;
+        for k in (0..(n - 1)).rev() {
+            // k+1番目の角速度でk+1からkに戻り，k番目の計測値で補正する
+            filter.predict( quat::negate_vec(self.samples[k + 1].gyr) );
+            correct(&mut filter, &self.samples[k]);
+            coast_b = if filter.is_disturbed() { coast_b + DT } else { 0.0 };
+
+            // 誤差は補正を止めてからの経過時間に比例して大きくなるとみなし，その二乗で重み付けする
+            let var_f = (self.coast[k] + DT).powi(2);
+            let var_b = (coast_b + DT).powi(2);
+            smoothed[k] = quat::slerp(self.forward[k], filter.q, var_f / (var_f + var_b));
+        }
+        smoothed
+    }
+}
@@ -0,0 +1,595 @@
+//! 計測値の取得元（シミュレーション，ログファイル，シリアルポート，UDPなど）
+//!
+//! 取得元をSensorSourceの後ろに隠しておけば，推定のループは取得元によらず1つ書くだけで済む．
+//! 各取得元はサンプルを時刻付きのImuSampleとして返し，終わり（ファイルの終端，タイムアウトなど）でNoneを返す．
+//! 実機のログの欠損や順序の入れ替わりはGapFillerで取得元を包んで処理する．
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use super::ahrs::Sample;
+use super::align::{Aligner, Channel, Measurement};
+use super::flightsim;
+use super::mavlink::HilSensorDecoder;
+use super::quat::Vector3;
+use super::timestamp;
+
+/// 時刻付きの1サンプル分の計測値
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImuSample {
+    pub time: f64,          // 時刻[s]（ログの時刻がUnix時間ならUnix時間）
+    pub gyr: Vector3<f64>,  // 角速度[rad/s]
+    pub acc: Vector3<f64>,  // 加速度[m/s^2]
+    pub mag: Vector3<f64>,  // 地磁気
+}
+
+impl ImuSample {
+    /// 時刻を除いた計測値
+    pub fn sample(&self) -> Sample {
+        Sample { gyr: self.gyr, acc: self.acc, mag: self.mag }
+    }
+}
+
+/// 計測値の取得元
+pub trait SensorSource {
+    /// 次のサンプルを返す（終わりならNone）．
+    fn next_sample(&mut self) -> Option<ImuSample>;
+}
+
+impl<S: SensorSource + ?Sized> SensorSource for Box<S> {
+    fn next_sample(&mut self) -> Option<ImuSample> {
+        (**self).next_sample()
+    }
+}
+
+impl<S: SensorSource + ?Sized> SensorSource for &mut S {
+    fn next_sample(&mut self) -> Option<ImuSample> {
+        (**self).next_sample()
+    }
+}
+
+/// イテレータをそのまま取得元として使う（記録済みのサンプルの列など）．
+pub struct IterSource<I>(pub I);
+
+impl<I: Iterator<Item = ImuSample>> SensorSource for IterSource<I> {
+    fn next_sample(&mut self) -> Option<ImuSample> {
+        self.0.next()
+    }
+}
+
+/// CSVの1行をサンプルに変換する（時刻, 角速度x,y,z, 加速度x,y,z, 地磁気x,y,z）．
+///
+/// 時刻は秒（相対時刻またはUnix時間）とISO 8601のどちらでもよい（timestamp::parse）．
+/// ヘッダ行など，数値として読めない行や値が足りない行はNoneを返す．
+pub fn parse_csv_line(line: &str) -> Option<ImuSample> {
+    let mut fields = line.split(',');
+    let time = timestamp::parse( fields.next()? )?;
+    let nums: Vec<f64> = fields.map(|v| v.trim().parse::<f64>()).collect::<Result<_, _>>().ok()?;
+    (nums.len() >= 9).then(|| ImuSample {
+        time,
+        gyr: [nums[0], nums[1], nums[2]],
+        acc: [nums[3], nums[4], nums[5]],
+        mag: [nums[6], nums[7], nums[8]],
+    })
+}
+
+/// CSV形式のテキストを1行ずつ読む取得元（ログファイル，標準入力，シリアルポート）
+///
+/// 数値として読めない行は読み飛ばす．
+pub struct CsvSource<R> {
+    reader: R,
+    line: String,
+}
+
+impl<R: BufRead> CsvSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, line: String::new() }
+    }
+}
+
+impl CsvSource<BufReader<fs::File>> {
+    /// ファイルを開く．
+    ///
+    /// シリアルポートのデバイスファイル（/dev/ttyUSB0など）もそのまま開ける（ボーレートなどはsttyで設定しておく）．
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok( Self::new( BufReader::new( fs::File::open(path)? ) ) )
+    }
+}
+
+impl<R: BufRead> SensorSource for CsvSource<R> {
+    fn next_sample(&mut self) -> Option<ImuSample> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {
+                    if let Some(s) = parse_csv_line(&self.line) {
+                        return Some(s);
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// UDPのパケットの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketFormat {
+    /// ログと同じCSV（1つのパケットに1行以上）
+    Csv,
+    /// スマートフォンのアプリ（Sensorstream IMU+GPSなど）の形式：時刻[s], (センサ番号, x, y, z)の繰り返し
+    ///
+    /// センサ番号は3が加速度[m/s^2]，4が角速度[rad/s]，5が地磁気[uT]で，他の番号（GPSなど）は読み飛ばす．
+    /// センサごとに更新の周期が違うので，加速度と地磁気は前後の値から角速度の時刻に補間する（align::Aligner）．
+    Sensorstream,
+    /// HILベンチや他のシミュレータ向けのバイナリ形式（リトルエンディアン，1つのパケットにHIL_PACKET_LENバイトの1つ以上）
+    ///
+    /// | オフセット | 型 | 内容 |
+    /// |---|---|---|
+    /// | 0 | [u8; 4] | 識別子HIL_MAGIC（"OFFH"） |
+    /// | 4 | u32 | フラグ（HIL_*_INVALIDの論理和） |
+    /// | 8 | f64 | 時刻[s] |
+    /// | 16 | f32 × 3 | 角速度x,y,z[rad/s] |
+    /// | 28 | f32 × 3 | 加速度x,y,z[m/s^2] |
+    /// | 40 | f32 × 3 | 地磁気x,y,z |
+    ///
+    /// フラグで無効にしたセンサの値はNaNとして渡す（フィルタは不正な計測値として扱う）．
+    Hil,
+    /// MAVLinkのHIL_SENSORメッセージ（Gazebo，jMAVSim，PX4 SITLなど．mavlink::HilSensorDecoder）
+    Mavlink,
+    /// X-PlaneのData OutputのDATAパケット（flightsim::parse_xplane）
+    XPlane,
+    /// FlightGearのプロトコル定義flightgear/omega_ff.xmlで送る行（flightsim::parse_flightgear_line）
+    FlightGear,
+}
+
+/// HIL形式のパケットの識別子
+pub const HIL_MAGIC: [u8; 4] = *b"OFFH";
+
+/// HIL形式のパケットの長さ[byte]
+pub const HIL_PACKET_LEN: usize = 52;
+
+/// HIL形式のフラグのビット
+pub const HIL_GYR_INVALID: u32 = 1 << 0;   // 角速度が無効
+pub const HIL_ACC_INVALID: u32 = 1 << 1;   // 加速度が無効
+pub const HIL_MAG_INVALID: u32 = 1 << 2;   // 地磁気が無効
+
+/// サンプルをHIL形式のパケットにする（送る側で使う）．
+pub fn encode_hil(sample: &ImuSample, flags: u32) -> [u8; HIL_PACKET_LEN] {
+    let mut packet = [0; HIL_PACKET_LEN];
+    packet[0..4].copy_from_slice(&HIL_MAGIC);
+    packet[4..8].copy_from_slice(&flags.to_le_bytes());
+    packet[8..16].copy_from_slice(&sample.time.to_le_bytes());
+    for (i, v) in sample.gyr.iter().chain(&sample.acc).chain(&sample.mag).enumerate() {
+        packet[16 + 4 * i..20 + 4 * i].copy_from_slice(&(*v as f32).to_le_bytes());
+    }
+    packet
+}
+
+/// HIL形式のパケットをサンプルに変換する（長さか識別子が違えばNone）．
+pub fn decode_hil(packet: &[u8]) -> Option<ImuSample> {
+    if packet.len() != HIL_PACKET_LEN || packet[0..4] != HIL_MAGIC {
+        return None;
+    }
+    let flags = u32::from_le_bytes(packet[4..8].try_into().unwrap());
+    let time = f64::from_le_bytes(packet[8..16].try_into().unwrap());
+    let vector = |offset: usize, invalid: u32| -> Vector3<f64> {
+        if flags & invalid != 0 {
+            return [f64::NAN; 3];
+        }
+        [0, 1, 2].map(|i| f32::from_le_bytes(packet[offset + 4 * i..offset + 4 * i + 4].try_into().unwrap()) as f64)
+    };
+    Some( ImuSample { time, gyr: vector(16, HIL_GYR_INVALID), acc: vector(28, HIL_ACC_INVALID), mag: vector(40, HIL_MAG_INVALID) } )
+}
+
+/// Sensorstream形式のセンサ番号
+const SENSORSTREAM_ACC: i32 = 3;
+const SENSORSTREAM_GYR: i32 = 4;
+const SENSORSTREAM_MAG: i32 = 5;
+
+/// Sensorstream形式の1行を計測値に変換する（読めない行は空）．
+fn parse_sensorstream_line(line: &str) -> Vec<Measurement> {
+    let Ok(nums) = line.split(',').map(|v| v.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>() else {
+        return Vec::new();
+    };
+    let Some((&time, rest)) = nums.split_first() else {
+        return Vec::new();
+    };
+    rest.chunks_exact(4).filter_map(|chunk| {
+        let channel = match chunk[0] as i32 {
+            SENSORSTREAM_GYR => Channel::Gyr,
+            SENSORSTREAM_ACC => Channel::Acc,
+            SENSORSTREAM_MAG => Channel::Mag,
+            _ => return None,
+        };
+        Some( Measurement { time, channel, value: [chunk[1], chunk[2], chunk[3]] } )
+    }).collect()
+}
+
+/// 受け取ったバイト列をサンプルに変換する．
+///
+/// 読み書きは行わないので，同期（UdpSource）と非同期（async_source.rs）のどちらの取得元でも使う．
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    format: PacketFormat,
+    partial: Vec<u8>,               // 改行がまだ届いていない行（push_bytes）
+    aligner: Aligner,               // 角速度の時刻への補間（Sensorstream形式）
+    mavlink: HilSensorDecoder,      // MAVLinkのフレームの読み取り（Mavlink形式）
+    pending: VecDeque<ImuSample>,   // まだ返していないサンプル
+}
+
+impl Decoder {
+    pub fn new(format: PacketFormat) -> Self {
+        Self { format, partial: Vec::new(), aligner: Aligner::new(), mavlink: HilSensorDecoder::new(), pending: VecDeque::new() }
+    }
+
+    /// パケット（UDPのデータグラム，WebSocketのメッセージなど）を1つ加える（1つに1行以上）．
+    pub fn push_packet(&mut self, packet: &[u8]) {
+        if self.format == PacketFormat::Mavlink {
+            self.push_bytes(packet);
+            return;
+        }
+        if self.format == PacketFormat::Hil {
+            self.pending.extend( packet.chunks_exact(HIL_PACKET_LEN).filter_map(decode_hil) );
+            return;
+        }
+        if self.format == PacketFormat::XPlane {
+            self.pending.extend( flightsim::parse_xplane(packet) );
+            return;
+        }
+        for line in String::from_utf8_lossy(packet).lines() {
+            self.push_line(line);
+        }
+    }
+
+    /// 区切りの無いバイト列（TCPなど）を加える（改行までを1行とし，残りは次に持ち越す）．
+    ///
+    /// HIL形式では識別子から始まるHIL_PACKET_LENバイトずつ区切る（先頭が識別子でなければ次の識別子まで読み飛ばす）．
+    /// MAVLink形式ではパケットも区切りの無いバイト列として，フレームごとに区切る．
+    /// X-Plane形式はUDPでしか送られないので，渡されたバイト列を1つのパケットとして読む．
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        if self.format == PacketFormat::XPlane {
+            self.push_packet(bytes);
+            return;
+        }
+        if self.format == PacketFormat::Mavlink {
+            self.mavlink.push(bytes);
+            self.pending.extend( std::iter::from_fn(|| self.mavlink.pop()) );
+            return;
+        }
+        self.partial.extend_from_slice(bytes);
+        if self.format == PacketFormat::Hil {
+            while self.partial.len() >= HIL_PACKET_LEN {
+                match decode_hil(&self.partial[..HIL_PACKET_LEN]) {
+                    Some(sample) => {
+                        self.pending.push_back(sample);
+                        self.partial.drain(..HIL_PACKET_LEN);
+                    },
+                    None => {
+                        // 識別子が見つからなければ，続きと合わせて識別子になりうる末尾の3バイトだけを残す
+                        let start = self.partial.windows(HIL_MAGIC.len()).skip(1).position(|w| w == HIL_MAGIC)
+                            .map_or(self.partial.len() + 1 - HIL_MAGIC.len(), |i| i + 1);
+                        self.partial.drain(..start);
+                    },
+                }
+            }
+            return;
+        }
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            self.push_line( &String::from_utf8_lossy(&line) );
+        }
+    }
+
+    /// 次のサンプル（変換できたものが無ければNone）
+    pub fn pop(&mut self) -> Option<ImuSample> {
+        self.pending.pop_front()
+    }
+
+    fn push_line(&mut self, line: &str) {
+        match self.format {
+            PacketFormat::Csv => self.pending.extend( parse_csv_line(line) ),
+            PacketFormat::FlightGear => self.pending.extend( flightsim::parse_flightgear_line(line) ),
+            PacketFormat::Hil | PacketFormat::Mavlink | PacketFormat::XPlane => unreachable!(),
+            PacketFormat::Sensorstream => {
+                for m in parse_sensorstream_line(line) {
+                    self.aligner.push(m);
+                }
+                self.pending.extend( std::iter::from_fn(|| self.aligner.pop()) );
+            },
+        }
+    }
+}
+
+/// UDPで受け取ったサンプルを読む取得元
+///
+/// timeoutの間パケットが届かなければ終わりとみなす．
+pub struct UdpSource {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+    decoder: Decoder,
+}
+
+impl UdpSource {
+    /// * addr   : 受信するアドレス（"0.0.0.0:5555"など）
+    /// * timeout: パケットを待つ時間の上限（Noneなら待ち続ける）
+    pub fn bind<A: ToSocketAddrs>(addr: A, timeout: Option<Duration>) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(timeout)?;
+        Ok( Self { socket, buf: vec![0; 65536], decoder: Decoder::new(PacketFormat::Csv) } )
+    }
+
+    /// パケットの形式を変える（デフォルトはCSV）．
+    pub fn with_format(mut self, format: PacketFormat) -> Self {
+        self.decoder = Decoder::new(format);
+        self
+    }
+
+    /// 受信しているアドレス
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl SensorSource for UdpSource {
+    fn next_sample(&mut self) -> Option<ImuSample> {
+        loop {
+            if let Some(sample) = self.decoder.pop() {
+                return Some(sample);
+            }
+            let n = self.socket.recv(&mut self.buf).ok()?;
+            self.decoder.push_packet(&self.buf[..n]);
+        }
+    }
+}
+
+/// TCPなどの区切りの無いバイト列から読む取得元
+///
+/// 読み取りのエラーか終わり（接続が閉じられたなど）でNoneを返す．
+pub struct ReadSource<R> {
+    reader: R,
+    buf: Vec<u8>,
+    decoder: Decoder,
+}
+
+impl<R: Read> ReadSource<R> {
+    pub fn new(reader: R, format: PacketFormat) -> Self {
+        Self { reader, buf: vec![0; 65536], decoder: Decoder::new(format) }
+    }
+}
+
+impl ReadSource<TcpStream> {
+    /// addrで接続を待ち，最初に接続してきた相手から読む．
+    pub fn accept<A: ToSocketAddrs>(addr: A, format: PacketFormat) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Ok( Self::new(stream, format) )
+    }
+}
+
+impl<R: Read> SensorSource for ReadSource<R> {
+    fn next_sample(&mut self) -> Option<ImuSample> {
+        loop {
+            if let Some(sample) = self.decoder.pop() {
+                return Some(sample);
+            }
+            match self.reader.read(&mut self.buf) {
+                Ok(0) | Err(_) => return None,
+                Ok(n) => self.decoder.push_bytes(&self.buf[..n]),
+            }
+        }
+    }
+}
+
+/// サンプリング周期の何倍以上間隔が空いたら欠損とみなすか
+pub const GAP_RATIO: f64 = 1.5;
+
+/// 欠損を埋める間隔の上限[s]（デフォルト）
+pub const MAX_FILL: f64 = 1.0;
+
+/// 欠損したサンプルの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GapPolicy {
+    /// 何もせずに欠損の後のサンプルを返す
+    #[default]
+    Skip,
+    /// 欠損の前のサンプルの計測値をサンプリング周期ごとに繰り返す
+    HoldLast,
+    /// 欠損の前後のサンプルを線形補間してサンプリング周期ごとに返す
+    Interpolate,
+}
+
+/// 欠損したサンプルを埋め，順序の入れ替わったサンプルを捨てる取得元
+///
+/// 前に返したサンプルより時刻が進んでいない（または時刻が不正な）サンプルは，遅れて届いたものとして捨てる．
+/// 間隔がmax_fillより長い欠損（記録の中断や時刻の飛びなど）は埋めずに，GapPolicy::Skipと同じく欠損の後のサンプルを返す．
+pub struct GapFiller<S> {
+    source: S,
+    period: f64,
+    policy: GapPolicy,
+    max_fill: f64,                // 欠損を埋める間隔の上限[s]
+    last: Option<ImuSample>,      // 最後に返したサンプル
+    pending: Option<ImuSample>,   // 欠損の後のサンプル（欠損を埋め終わったら返す）
+    n_filled: u64,
+    n_unfilled: u64,
+    n_dropped: u64,
+}
+
+impl<S: SensorSource> GapFiller<S> {
+    /// * period: サンプリング周期[s]
+    pub fn new(source: S, period: f64, policy: GapPolicy) -> Self {
+        Self { source, period, policy, max_fill: MAX_FILL, last: None, pending: None, n_filled: 0, n_unfilled: 0, n_dropped: 0 }
+    }
+
+    /// 欠損を埋める間隔の上限を設定する（デフォルトはMAX_FILL）．
+    /// 
+    /// * max_fill: これより長い欠損は埋めない[s]
+    pub fn with_max_fill(mut self, max_fill: f64) -> Self {
+        self.max_fill = max_fill;
+        self
+    }
+
+    /// 包んでいる取得元
+    pub fn get_ref(&self) -> &S {
+        &self.source
+    }
+
+    /// 欠損を埋めるために作ったサンプルの数
+    pub fn n_filled(&self) -> u64 {
+        self.n_filled
+    }
+
+    /// 間隔がmax_fillより長かったので埋めなかった欠損の数
+    pub fn n_unfilled(&self) -> u64 {
+        self.n_unfilled
+    }
+
+    /// 順序が入れ替わっていたので捨てたサンプルの数
+    pub fn n_dropped(&self) -> u64 {
+        self.n_dropped
+    }
+}
+
+impl<S: SensorSource> SensorSource for GapFiller<S> {
+    fn next_sample(&mut self) -> Option<ImuSample> {
+        let next = match self.pending.take() {
+            Some(next) => next,
+            None => loop {
+                let s = self.source.next_sample()?;
+                match self.last {
+                    // 時刻が無限大のサンプルは欠損を埋め終わらないので捨てる
+                    Some(last) if s.time <= last.time || !s.time.is_finite() => self.n_dropped += 1,
+                    None if !s.time.is_finite() => self.n_dropped += 1,
+                    _ => break s,
+                }
+            },
+        };
+
+        if let Some(last) = self.last {
+            let gap = next.time - last.time;
+            if self.policy != GapPolicy::Skip && gap > self.max_fill {
+                self.n_unfilled += 1;
+            } else if self.policy != GapPolicy::Skip && gap > GAP_RATIO * self.period {
+                let time = last.time + self.period;
+                let filled = match self.policy {
+                    GapPolicy::Interpolate => {
+                        let r = (time - last.time) / (next.time - last.time);
+                        let lerp = |a: Vector3<f64>, b: Vector3<f64>| [0, 1, 2].map(|i| a[i] + r * (b[i] - a[i]));
+                        ImuSample { time, gyr: lerp(last.gyr, next.gyr), acc: lerp(last.acc, next.acc), mag: lerp(last.mag, next.mag) }
+                    },
+                    _ => ImuSample { time, ..last },
+                };
+                self.pending = Some(next);
+                self.last = Some(filled);
+                self.n_filled += 1;
+                return Some(filled);
+            }
+        }
+        self.last = Some(next);
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_csv_and_udp() {
+        let text = "time,gx,gy,gz,ax,ay,az,mx,my,mz\n0.00,1,2,3,4,5,6,7,8,9\nbad\n0.02,1,2,3,4,5,6,7,8,9,extra\n";
+        let mut csv = CsvSource::new(text.as_bytes());
+        assert_eq!(csv.next_sample().map(|s| s.acc), Some([4.0, 5.0, 6.0]));
+        assert_eq!(csv.next_sample(), None);  // 数値でない列を含む行は読み飛ばす
+        assert_eq!(parse_csv_line("1970-01-01T00:00:01.5Z,1,2,3,4,5,6,7,8,9").map(|s| s.time), Some(1.5));
+
+        // TCPなどの区切りの無いバイト列は改行までを1行とする
+        let mut decoder = Decoder::new(PacketFormat::Csv);
+        decoder.push_bytes(b"0.00,1,2,3,4,5,6,7,8,9\n0.02,1,2,");
+        assert_eq!(decoder.pop().map(|s| s.time), Some(0.0));
+        assert_eq!(decoder.pop(), None);
+        decoder.push_bytes(b"3,4,5,6,7,8,9\n");
+        assert_eq!(decoder.pop().map(|s| s.time), Some(0.02));
+
+        let mut udp = UdpSource::bind("127.0.0.1:0", Some(Duration::from_millis(200))).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"0.00,1,2,3,4,5,6,7,8,9\n0.02,0,0,0,0,0,9.8,0,1,0\n", udp.local_addr().unwrap()).unwrap();
+        assert_eq!(udp.next_sample().map(|s| s.time), Some(0.0));
+        assert_eq!(udp.next_sample().map(|s| s.acc), Some([0.0, 0.0, 9.8]));
+        assert_eq!(udp.next_sample(), None);  // タイムアウト
+
+        // Sensorstream形式（GPS（1）は読み飛ばし，加速度と地磁気は角速度の時刻に補間する）
+        let mut udp = UdpSource::bind("127.0.0.1:0", Some(Duration::from_millis(200))).unwrap().with_format(PacketFormat::Sensorstream);
+        let addr = udp.local_addr().unwrap();
+        sender.send_to(b"1.00, 3, 0.1, 0.2, 9.8, 1, 35.0, 139.0, 10.0", addr).unwrap();
+        sender.send_to(b"1.02, 3, 0.1, 0.2, 9.7, 4, 0.01, 0.02, 0.03, 5, 30.0, 5.0, -40.0", addr).unwrap();
+        sender.send_to(b"1.04, 4, 0.04, 0.05, 0.06", addr).unwrap();
+        sender.send_to(b"1.06, 3, 0.3, 0.4, 9.9, 5, 32.0, 5.0, -40.0", addr).unwrap();
+        let s = udp.next_sample().unwrap();
+        assert_eq!((s.time, s.acc, s.gyr, s.mag), (1.02, [0.1, 0.2, 9.7], [0.01, 0.02, 0.03], [30.0, 5.0, -40.0]));
+        let s = udp.next_sample().unwrap();
+        assert_eq!((s.time, s.gyr), (1.04, [0.04, 0.05, 0.06]));
+        assert!((s.acc[0] - 0.2).abs() < 1e-9 && (s.acc[2] - 9.8).abs() < 1e-9 && (s.mag[0] - 31.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn decodes_hil_packets() {
+        let sample = ImuSample { time: 12.5, gyr: [0.5, -0.25, 0.125], acc: [0.0, 0.0, 9.75], mag: [1.0, 0.0, -0.5] };
+        assert_eq!(decode_hil(&encode_hil(&sample, 0)), Some(sample));
+        let s = decode_hil(&encode_hil(&sample, HIL_MAG_INVALID)).unwrap();
+        assert!(s.mag.iter().all(|v| v.is_nan()) && s.acc == sample.acc);
+
+        // 1つのデータグラムに複数のパケット，TCPでは途中で区切られたり前に不要なバイトが付いたりする
+        let packets = [encode_hil(&sample, 0), encode_hil(&ImuSample { time: 12.52, ..sample }, 0)].concat();
+        let mut decoder = Decoder::new(PacketFormat::Hil);
+        decoder.push_packet(&packets);
+        assert_eq!((decoder.pop().map(|s| s.time), decoder.pop().map(|s| s.time)), (Some(12.5), Some(12.52)));
+        decoder.push_bytes(b"xx");
+        decoder.push_bytes(&packets[..30]);
+        assert_eq!(decoder.pop(), None);
+        decoder.push_bytes(&packets[30..]);
+        assert_eq!((decoder.pop().map(|s| s.time), decoder.pop().map(|s| s.time)), (Some(12.5), Some(12.52)));
+
+        // 長い不要なバイト列はまとめて読み飛ばし，識別子の途中で区切られた場合は続きと合わせて読む
+        decoder.push_bytes(&vec![b'O'; 100_000]);
+        assert!(decoder.partial.len() < HIL_MAGIC.len());
+        decoder.push_bytes(b"xxOF");
+        decoder.push_bytes(&packets[2..]);
+        assert_eq!((decoder.pop().map(|s| s.time), decoder.pop().map(|s| s.time)), (Some(12.5), Some(12.52)));
+        decoder.push_bytes(&[&[b'x'; 100][..], b"OFF"].concat());
+        assert_eq!(decoder.partial, b"OFF");
+        decoder.push_bytes(&packets[3..HIL_PACKET_LEN]);
+        assert_eq!(decoder.pop().map(|s| s.time), Some(12.5));
+        assert_eq!(decoder.pop(), None);
+    }
+
+    #[test]
+    fn fills_gaps() {
+        // 0.06と0.08が欠損し，0.04が遅れて届いたログ
+        let sample = |time: f64, v: f64| ImuSample { time, gyr: [v; 3], acc: [v; 3], mag: [v; 3] };
+        let log = || IterSource( vec![sample(0.0, 0.0), sample(0.02, 1.0), sample(0.10, 5.0), sample(0.04, 9.0), sample(0.12, 6.0)].into_iter() );
+        let read = |policy| {
+            let mut gaps = GapFiller::new(log(), 0.02, policy);
+            let samples: Vec<(f64, f64)> = std::iter::from_fn(|| gaps.next_sample()).map(|s| ((s.time * 100.0).round(), s.gyr[0])).collect();
+            (samples, gaps.n_filled(), gaps.n_dropped())
+        };
+
+        assert_eq!(read(GapPolicy::Skip), (vec![(0.0, 0.0), (2.0, 1.0), (10.0, 5.0), (12.0, 6.0)], 0, 1));
+        let (samples, n_filled, _) = read(GapPolicy::HoldLast);
+        assert_eq!(samples[2..5], [(4.0, 1.0), (6.0, 1.0), (8.0, 1.0)]);
+        assert_eq!(n_filled, 3);
+        let (samples, ..) = read(GapPolicy::Interpolate);
+        let values: Vec<f64> = samples.iter().map(|&(_, v)| (v * 1e6).round() / 1e6).collect();
+        assert_eq!(values, [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        // 上限より長い欠損は埋めずにそのまま返し，時刻が無限大のサンプルは捨てる
+        let log = IterSource( vec![sample(0.0, 0.0), sample(0.02, 1.0), sample(f64::INFINITY, 2.0), sample(1e9, 3.0), sample(1e9 + 0.06, 4.0)].into_iter() );
+        let mut gaps = GapFiller::new(log, 0.02, GapPolicy::Interpolate).with_max_fill(1.0);
+        let times: Vec<f64> = std::iter::from_fn(|| gaps.next_sample()).map(|s| s.time).collect();
+        assert_eq!(times.len(), 6);
+        assert_eq!(times[2], 1e9);
+        assert_eq!((gaps.n_filled(), gaps.n_unfilled(), gaps.n_dropped()), (2, 1, 1));
+    }
+}
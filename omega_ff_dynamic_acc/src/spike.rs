@@ -0,0 +1,90 @@
+//! 角速度の外れ値（1サンプルだけのスパイク）の除去
+//!
+//! 角速度は予測ステップで積分されるだけでなく，補正の積分項（バイアス推定値）にも影響するので，
+//! 1サンプルでも大きな外れ値が入ると姿勢とバイアス推定値が後まで狂う．
+//! 直近3サンプルの中央値との差が閾値を超えた軸は中央値で置き換えてからpredict()に渡す．
+//!
+//! 閾値を超える本当の角速度の急変は1サンプル遅れて反映される．2サンプル以上続く外れ値は除去できない．
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use super::quat::Vector3;
+
+/// 中央値をとるサンプル数
+const WINDOW: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct GyroSpikeFilter {
+    thr: f64,                        // 中央値との差の閾値[rad/s]
+    buf: VecDeque<Vector3<f64>>,     // 直近の角速度（置き換える前の値）
+    n_rejected: usize,               // 置き換えたサンプルの数
+}
+
+impl GyroSpikeFilter {
+    /// * thr: 中央値との差の閾値[rad/s]（ノイズと想定する最大の角加速度による変化より十分大きくする）
+    pub fn new(thr: f64) -> Self {
+        Self {
+            thr,
+            buf: VecDeque::with_capacity(WINDOW + 1),
+            n_rejected: 0,
+        }
+    }
+
+    /// 角速度を追加し，外れ値を中央値で置き換えた角速度を返す（WINDOWサンプル溜まるまではそのまま返す）．
+    ///
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    pub fn update(&mut self, gyr: Vector3<f64>) -> Vector3<f64> {
+        self.buf.push_back(gyr);
+        if self.buf.len() > WINDOW {
+            self.buf.pop_front();
+        }
+        if self.buf.len() < WINDOW {
+            return gyr;
+        }
+
+        let mut out = gyr;
+        let mut rejected = false;
+        for (i, v) in out.iter_mut().enumerate() {
+            let mut xs = [self.buf[0][i], self.buf[1][i], self.buf[2][i]];
+            xs.sort_by(f64::total_cmp);
+            let median = xs[1];
+            // NaNも外れ値とみなす
+            if matches!((*v - median).abs().partial_cmp(&self.thr), Some(Ordering::Greater) | None) {
+                *v = median;
+                rejected = true;
+            }
+        }
+        self.n_rejected += rejected as usize;
+        out
+    }
+
+    /// これまでに外れ値を置き換えたサンプルの数
+    pub fn n_rejected(&self) -> usize {
+        self.n_rejected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_single_spike() {
+        let mut f = GyroSpikeFilter::new(0.5);
+        let gyr = [0.1, -0.2, 0.05];
+        for k in 0..20 {
+            let mut x = gyr;
+            if k == 10 {
+                x[1] += 8.0;
+            }
+            assert_eq!(f.update(x), gyr, "k = {}", k);
+        }
+        assert_eq!(f.n_rejected(), 1);
+
+        // 閾値を超える急変は1サンプル遅れて反映される
+        let step = [0.1, 2.0, 0.05];
+        assert_eq!(f.update(step), gyr);
+        assert_eq!(f.update(step), step);
+    }
+}
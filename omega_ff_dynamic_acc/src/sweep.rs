@@ -0,0 +1,129 @@
+//! フィルタのパラメータを格子状に変えて評価する（sweepサブコマンド）
+//!
+//! 固定シードのシナリオ（scenario.rs）を全ての組み合わせで実行し，評価指標をsweep_result.csvに書き出す．
+//! 各パラメータは`--alpha 0.5,1,2`のようにカンマ区切りで列挙するか，`--alpha 0.5:2:0.25`（開始:終了:刻み幅）で範囲を指定する．
+//! 指定しなかったパラメータはシミュレーションと同じ値に固定する．
+//!
+//! --dbを付けた場合は評価指標を実行条件と一緒にSQLiteのデータベースにも追記する（database.rs）．
+//!
+//! sweep_result.csvの1行の形式：
+//! alpha, beta, thr_weak, thr_strong, 姿勢誤差のRMS[rad], 外乱中の姿勢誤差のRMS[rad], 外乱後の収束時間[s]（収束しなければinf）
+
+use std::fs;
+use std::io::BufWriter;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use super::{Options, ALPHA, BETA, THR_WEAK, THR_STRONG, output};
+use super::database::{ResultsDb, RunInfo};
+use super::compare::FilterSpec;
+use super::progress::Progress;
+use super::scenario::{self, Metrics};
+use super::error::{Context, Result};
+
+/// 結果の出力先
+const RESULT_PATH: &str = "sweep_result.csv";
+
+/// 乱数のシード（デフォルト）
+pub const SEED: u64 = 20200501;
+
+/// 結果として表示する組み合わせの数
+const N_BEST: usize = 10;
+
+/// 各パラメータの候補
+#[derive(Debug, Clone)]
+pub struct SweepRanges {
+    pub alpha: Vec<f64>,
+    pub beta: Vec<f64>,
+    pub thr_weak: Vec<f64>,
+    pub thr_strong: Vec<f64>,
+}
+
+impl Default for SweepRanges {
+    fn default() -> Self {
+        Self {
+            alpha: vec![ALPHA],
+            beta: vec![BETA],
+            thr_weak: vec![THR_WEAK],
+            thr_strong: vec![THR_STRONG],
+        }
+    }
+}
+
+/// `a,b,c`（列挙）または`start:stop:step`（範囲，stopを含む）の形式の文字列をパラメータの候補に変換する．
+pub fn parse_values(s: &str) -> Option<Vec<f64>> {
+    let nums: Vec<f64> = s.split([',', ':']).map(|v| v.trim().parse().ok()).collect::<Option<_>>()?;
+    if !s.contains(':') {
+        return Some(nums);
+    }
+    match nums[..] {
+        [start, stop, step] if step > 0.0 && start <= stop => {
+            // 刻み幅の丸め誤差でstopを取りこぼさないように，個数を先に決める
+            let n = ((stop - start) / step + 1e-9).floor() as usize + 1;
+            // 表示したときに0.30000000000000004のようにならないように丸める
+            Some( (0..n).map(|i| ((start + step * i as f64) * 1e9).round() / 1e9).collect() )
+        },
+        _ => None,
+    }
+}
+
+pub fn run(ranges: &SweepRanges, seed: u64, opts: &Options) -> Result<()> {
+    let steps = scenario::generate(&mut StdRng::seed_from_u64(seed), &opts.sim.noise);
+
+    let total = ranges.alpha.len() * ranges.beta.len() * ranges.thr_weak.len() * ranges.thr_strong.len();
+    let mut progress = Progress::new("sweep", Some(total));
+    let mut results: Vec<(FilterSpec, Metrics)> = Vec::new();
+    let noise = format!("{:?}", opts.sim.noise);
+    let mut db = opts.eval.db.as_deref().map(|path| {
+        ResultsDb::open(path, &RunInfo { seed, detector: &format!("{:?}", opts.filter.detector), noise: &noise })
+    }).transpose()?;
+    for &alpha in &ranges.alpha {
+        for &beta in &ranges.beta {
+            for &thr_weak in &ranges.thr_weak {
+                for &thr_strong in &ranges.thr_strong {
+                    progress.step();
+                    // 弱い外乱の閾値は強い外乱の閾値より小さくなければならない
+                    if thr_weak >= thr_strong {
+                        continue;
+                    }
+                    let spec = FilterSpec { detector: opts.filter.detector, alpha, beta, thr_weak, thr_strong };
+                    let errors = scenario::errors(&mut spec.build(), &steps);
+                    let metrics = Metrics::new(&steps, &errors);
+                    if let Some(ref mut db) = db {
+                        let trace: Vec<(f64, f64)> = if opts.eval.db_traces {
+                            steps.iter().map(|s| s.time).zip(errors).collect()
+                        } else {
+                            Vec::new()
+                        };
+                        db.insert([alpha, beta, thr_weak, thr_strong], [metrics.rms, metrics.rms_disturbance, metrics.recovery_time], &trace)?;
+                    }
+                    results.push((spec, metrics));
+                }
+            }
+        }
+    }
+    progress.finish();
+    if let Some(db) = db {
+        db.finish()?;
+    }
+
+    let mut file = output::CsvWriter::new( BufWriter::new( fs::File::create(RESULT_PATH).context(RESULT_PATH)? ) );
+    for (spec, m) in &results {
+        file.write(&output::SweepRecord {
+            alpha: spec.alpha, beta: spec.beta, thr_weak: spec.thr_weak, thr_strong: spec.thr_strong,
+            rms: m.rms, rms_disturbance: m.rms_disturbance, recovery_time: m.recovery_time,
+        }).context(RESULT_PATH)?;
+    }
+    file.flush().context(RESULT_PATH)?;
+
+    // 姿勢誤差のRMSが小さい順に表示する
+    results.sort_by(|a, b| a.1.rms.total_cmp(&b.1.rms));
+    println!("{}通りの組み合わせを評価しました（外乱判定式：{:?}，シード：{}）", results.len(), opts.filter.detector, seed);
+    println!("  alpha     beta      thr_weak  thr_strong  RMS[rad]  外乱中[rad]  収束時間[s]");
+    for (spec, m) in results.iter().take(N_BEST) {
+        println!("  {:<8}  {:<8}  {:<8}  {:<10}  {:.4}    {:.4}       {:.2}",
+            spec.alpha, spec.beta, spec.thr_weak, spec.thr_strong, m.rms, m.rms_disturbance, m.recovery_time);
+    }
+    Ok(())
+}
@@ -0,0 +1,68 @@
+//! 標準シナリオ（一定角速度で回転，10〜20秒に加速度外乱）の真値と計測値の列を作る
+//!
+//! 実行ファイルのフィルタ比較・評価（--compare，--sweep，--optimize）と，
+//! 推定結果の回帰テスト（tests/golden_trace.rs）で同じシナリオを使う．
+
+use std::ops::RangeInclusive;
+
+use rand::Rng;
+use rand::distributions::{Distribution, Normal};
+
+use super::{ahrs, quat, DT};
+
+/// 角速度の真値[rad/s]
+pub const GYR: [f64; 3] = [0.1; 3];
+
+/// 角速度バイアスの真値[rad/s]
+pub const GYR_BIAS: [f64; 3] = [-0.02, 0.01, 0.05];
+
+/// 加速度外乱を加える時間[s]
+pub const DISTURBANCE: RangeInclusive<f64> = 10.0..=20.0;
+
+/// 加速度外乱の大きさ（機体座標系x軸方向）[m/s^2]
+pub const DISTURBANCE_ACC: f64 = 3.0;
+
+/// センサごとのノイズの分散（0ならノイズ無し）
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseVariance {
+    pub gyr: f64,
+    pub acc: f64,
+    pub mag: f64,
+}
+
+/// 1ステップ分の真値と計測値
+pub struct Step {
+    pub time: f64,                  // 時刻[s]
+    pub q: quat::Quaternion<f64>,   // 姿勢の真値
+    pub sample: ahrs::Sample,       // 計測値
+}
+
+/// シナリオのnステップ分の真値と計測値を作る（ノイズはrngから生成する）．
+///
+/// 分散を0にしたセンサにも乱数を引くので，他のセンサのノイズは同じシードなら変わらない．
+pub fn generate<R: Rng>(rng: &mut R, n: usize, var: NoiseVariance) -> Vec<Step> {
+    let randn = Normal::new(0.0, 1.0);
+    let mut add_noise = |variance: f64, x: quat::Vector3<f64>| {
+        let sd = variance.sqrt();
+        x.map(|v| v + randn.sample(rng) * sd)
+    };
+
+    let mut q = (1.0, [0.0; 3]);
+    (0..n).map(|t| {
+        let time = t as f64 * DT;
+
+        // 積分（q = q + 0.5*Δt*q*ω）
+        q = quat::normalize( quat::scale_add(0.5 * DT, quat::mul(q, (0.0, GYR)), q) );
+
+        let mut acc = quat::frame_rotation(q, ahrs::ACC_R);
+        if DISTURBANCE.contains(&time) {
+            acc[0] += DISTURBANCE_ACC;
+        }
+        let sample = ahrs::Sample {
+            gyr: add_noise(var.gyr, quat::add_vec(GYR, GYR_BIAS)),
+            acc: add_noise(var.acc, acc),
+            mag: add_noise(var.mag, quat::frame_rotation(q, ahrs::MAG_R)),
+        };
+        Step { time, q, sample }
+    }).collect()
+}
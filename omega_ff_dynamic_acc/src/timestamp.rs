@@ -0,0 +1,119 @@
+//! 実機のログの時刻（Unix時間・ISO 8601）の読み書き
+//!
+//! 時刻はどちらもUnix時間（1970-01-01T00:00:00Zからの秒数）のf64として扱う．
+//! 相対時刻（記録開始からの秒数）のログはそのままの値で扱う．
+//! ISO 8601は`YYYY-MM-DDTHH:MM:SS[.fff][Z|±HH:MM]`の形式に対応する（Tの代わりに空白も可，時差が無ければUTC）．
+
+/// 出力する時刻の書式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeFormat {
+    /// 秒（小数点以下3桁）
+    #[default]
+    Seconds,
+    /// ISO 8601（UTC，ミリ秒まで）
+    Iso8601,
+}
+
+impl TimeFormat {
+    pub fn format(self, t: f64) -> String {
+        match self {
+            TimeFormat::Seconds => format!("{:.3}", t),
+            TimeFormat::Iso8601 => to_iso8601(t),
+        }
+    }
+}
+
+/// 数値（秒）またはISO 8601の時刻を読む．
+pub fn parse(s: &str) -> Option<f64> {
+    let s = s.trim();
+    s.parse::<f64>().ok().or_else(|| parse_iso8601(s))
+}
+
+/// ISO 8601の時刻をUnix時間[s]に変換する．
+pub fn parse_iso8601(s: &str) -> Option<f64> {
+    let b = s.as_bytes();
+    if b.len() < 19 || b[4] != b'-' || b[7] != b'-' || !(b[10] == b'T' || b[10] == b' ') || b[13] != b':' || b[16] != b':' {
+        return None;
+    }
+    let num = |r: std::ops::Range<usize>| s.get(r)?.parse::<i64>().ok();
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, min, sec) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+
+    // 秒の小数部と時差
+    let rest = &s[19..];
+    let zone_at = rest.find(['Z', '+', '-']).unwrap_or(rest.len());
+    let (frac, zone) = rest.split_at(zone_at);
+    let frac = match frac {
+        "" => 0.0,
+        f if f.starts_with('.') && f.len() > 1 && f[1..].bytes().all(|c| c.is_ascii_digit()) => format!("0{}", f).parse().ok()?,
+        _ => return None,
+    };
+    let offset = match zone {
+        "" | "Z" => 0,
+        z if z.len() == 6 && z.as_bytes()[3] == b':' => {
+            let h: i64 = z[1..3].parse().ok()?;
+            let m: i64 = z[4..6].parse().ok()?;
+            let sign = if z.starts_with('-') { -1 } else { 1 };
+            sign * (h * 3600 + m * 60)
+        },
+        _ => return None,
+    };
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + min * 60 + sec - offset;
+    Some(secs as f64 + frac)
+}
+
+/// Unix時間[s]をISO 8601（UTC，ミリ秒まで）の文字列にする．
+pub fn to_iso8601(t: f64) -> String {
+    let ms = (t * 1000.0).round() as i64;
+    let (secs, ms) = (ms.div_euclid(1000), ms.rem_euclid(1000));
+    let (days, sod) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day, sod / 3600, sod / 60 % 60, sod % 60, ms)
+}
+
+/// 1970-01-01からの日数（グレゴリオ暦）
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// days_from_civil()の逆変換
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + (month <= 2) as i64, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_iso8601() {
+        assert_eq!(parse("12.5"), Some(12.5));
+        assert_eq!(parse("1970-01-01T00:00:00Z"), Some(0.0));
+        assert_eq!(parse("2024-02-29T12:34:56.25Z"), Some(1709210096.25));
+        assert_eq!(parse("2024-02-29 21:34:56.25+09:00"), Some(1709210096.25));
+        assert_eq!(parse("2024-13-01T00:00:00Z"), None);
+        assert_eq!(parse("2024-02-29T12:34:56.Z"), None);
+
+        let t = 1709210096.25;
+        assert_eq!(to_iso8601(t), "2024-02-29T12:34:56.250Z");
+        assert_eq!(parse(&to_iso8601(-0.5)), Some(-0.5));
+        assert_eq!(TimeFormat::Seconds.format(t), "1709210096.250");
+    }
+}
@@ -0,0 +1,49 @@
+//! predict()とcorrect()の処理時間の計測
+//!
+//! 1ステップごとの処理時間を記録しておき，最後にパーセンタイルを表示する．
+//! 組み込み向けに処理時間を見積もるためのものなので，--releaseでビルドして使う．
+
+use std::time::Duration;
+
+pub struct StepTimer {
+    predict: Vec<Duration>,
+    correct: Vec<Duration>,
+}
+
+impl StepTimer {
+    pub fn new() -> Self {
+        Self {
+            predict: Vec::new(),
+            correct: Vec::new(),
+        }
+    }
+
+    /// 1ステップ分の処理時間を記録する．
+    pub fn record(&mut self, predict: Duration, correct: Duration) {
+        self.predict.push(predict);
+        self.correct.push(correct);
+    }
+
+    /// 処理時間のパーセンタイルを標準エラー出力に表示する（--streamの出力と混ざらないようにする）．
+    pub fn report(&mut self) {
+        eprintln!("1ステップあたりの処理時間 [ns]（{}ステップ）", self.predict.len());
+        eprintln!("           {:>8} {:>8} {:>8} {:>8}", "p50", "p90", "p99", "max");
+        report_line("predict", &mut self.predict);
+        report_line("correct", &mut self.correct);
+    }
+}
+
+fn report_line(name: &str, durations: &mut [Duration]) {
+    if durations.is_empty() {
+        return;
+    }
+    durations.sort_unstable();
+    let percentile = |p: f64| {
+        let i = ((durations.len() - 1) as f64 * p).round() as usize;
+        durations[i].as_nanos()
+    };
+    eprintln!(
+        "  {:<8} {:>8} {:>8} {:>8} {:>8}",
+        name, percentile(0.5), percentile(0.9), percentile(0.99), percentile(1.0)
+    );
+}
@@ -0,0 +1,69 @@
+//! シミュレーションの真値の軌跡（--trajectory）
+//!
+//! 実機で記録した運動をそのまま再現し，センサのモデル（ノイズ，バイアス，故障など）を変えて推定を試せるようにする．
+//!
+//! ファイルの形式（CSV，数値として読めない行は読み飛ばす）：
+//! 時刻[s], 角速度x,y,z[rad/s], 並進加速度x,y,z[m/s^2]（省略した場合は0）
+//!
+//! 角速度と並進加速度は機体座標系の真値で，並進加速度は加速度外乱として重力加速度に加える．
+//! 時刻は秒（相対時刻またはUnix時間）かISO 8601で，最初の行を0秒とする．
+//! シミュレーションの各時刻の値は前後の行から線形補間する．
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+
+use omega_ff_dynamic_acc::{quat::Vector3, timestamp};
+
+use super::error::{Context, Error, Result};
+
+/// 真値の軌跡
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    times: Vec<f64>,            // 最初の行からの時刻[s]（単調増加）
+    gyr: Vec<Vector3<f64>>,     // 角速度[rad/s]
+    acc: Vec<Vector3<f64>>,     // 並進加速度[m/s^2]
+}
+
+impl Trajectory {
+    pub fn load(path: &str) -> Result<Self> {
+        let file = BufReader::new( fs::File::open(path).context(path)? );
+        let mut traj = Self { times: Vec::new(), gyr: Vec::new(), acc: Vec::new() };
+        for line in file.lines().map_while(std::io::Result::ok) {
+            let mut cols = line.split(',');
+            let Some(time) = cols.next().and_then(timestamp::parse) else { continue };
+            let Some(nums) = cols.map(|v| v.trim().parse().ok()).collect::<Option<Vec<f64>>>() else { continue };
+            if nums.len() < 3 {
+                continue;
+            }
+            traj.times.push(time);
+            traj.gyr.push([nums[0], nums[1], nums[2]]);
+            traj.acc.push(if nums.len() >= 6 { [nums[3], nums[4], nums[5]] } else { [0.0; 3] });
+        }
+        if traj.times.is_empty() {
+            return Err( Error::format(path, "軌跡のファイルにデータがありません") );
+        }
+        if !traj.times.windows(2).all(|w| w[0] < w[1]) {
+            return Err( Error::format(path, "時刻は単調増加でなければなりません") );
+        }
+        let t0 = traj.times[0];
+        traj.times.iter_mut().for_each(|t| *t -= t0);
+        Ok(traj)
+    }
+
+    /// 最後の行の時刻[s]（load()で空のファイルは拒否するので，常に1行以上ある）
+    pub fn duration(&self) -> f64 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+
+    /// 時刻tの角速度と並進加速度（範囲外なら最初か最後の値）
+    pub fn at(&self, t: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let i = self.times.partition_point(|&ti| ti <= t);
+        if i == 0 || i == self.times.len() {
+            let i = i.saturating_sub(1);
+            return (self.gyr[i], self.acc[i]);
+        }
+        let r = (t - self.times[i - 1]) / (self.times[i] - self.times[i - 1]);
+        let lerp = |v: &[Vector3<f64>]| [0, 1, 2].map(|k| v[i - 1][k] + r * (v[i][k] - v[i - 1][k]));
+        (lerp(&self.gyr), lerp(&self.acc))
+    }
+}
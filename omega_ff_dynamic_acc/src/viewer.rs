@@ -0,0 +1,91 @@
+//! 姿勢の真値と推定値をブラウザでリアルタイムに3次元表示する（--viewer）
+//!
+//! 標準ライブラリだけで小さなHTTPサーバを立て，www/viewer.htmlを返す．
+//! 各ステップの姿勢はServer-Sent Events（/events）でページに送り，ページ側で座標軸を描画する．
+//! 推定値の座標軸は外乱判定のフラグに応じて色を変える（弱い外乱：黄，強い外乱：赤）．
+//!
+//! ページを開いていない間に送ったステップは捨てる（推定は止めない）．
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+use omega_ff_dynamic_acc::{ahrs, quat};
+
+use super::error::{self, Context};
+
+/// 表示するページ
+const PAGE: &str = include_str!("../www/viewer.html");
+
+/// 1ステップ分の表示内容
+pub struct Frame {
+    pub time: f64,
+    pub q_true: Option<quat::Quaternion<f64>>,   // 姿勢の真値（ログ再生では無い場合がある）
+    pub q: quat::Quaternion<f64>,                // 姿勢の推定値
+    pub weak: bool,                              // 弱い外乱を検知しているか
+    pub strong: bool,                            // 強い外乱を検知しているか
+}
+
+impl Frame {
+    pub fn new(time: f64, q_true: Option<quat::Quaternion<f64>>, filter: &ahrs::AttitudeFilter) -> Self {
+        Self { time, q_true, q: filter.q, weak: filter.is_weakly_disturbed(), strong: filter.is_disturbed() }
+    }
+
+    fn to_json(&self) -> String {
+        let q = |q: quat::Quaternion<f64>| format!("[{:.6},{:.6},{:.6},{:.6}]", q.0, q.1[0], q.1[1], q.1[2]);
+        let q_true = self.q_true.map_or("null".to_string(), q);
+        format!("{{\"time\":{:.3},\"q_true\":{},\"q\":{},\"weak\":{},\"strong\":{}}}",
+            self.time, q_true, q(self.q), self.weak, self.strong)
+    }
+}
+
+pub struct Viewer {
+    clients: Arc<Mutex<Vec<mpsc::Sender<String>>>>,
+}
+
+impl Viewer {
+    /// * port: 待ち受けるポート（http://localhost:port/ を開く）
+    pub fn start(port: u16) -> error::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).context(&format!("127.0.0.1:{}", port))?;
+        eprintln!("http://localhost:{}/ で姿勢を表示します", port);
+        let clients = Arc::new( Mutex::new( Vec::new() ) );
+        let shared = Arc::clone(&clients);
+        thread::spawn(move || {
+            for stream in listener.incoming().map_while(Result::ok) {
+                let clients = Arc::clone(&shared);
+                thread::spawn(move || { let _ = serve(stream, &clients); });
+            }
+        });
+        Ok( Self { clients } )
+    }
+
+    /// 開いている全てのページに送る．
+    pub fn publish(&self, frame: &Frame) {
+        let event = format!("data: {}\n\n", frame.to_json());
+        // ページを閉じたクライアントは除く
+        self.clients.lock().unwrap().retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+/// 1つの接続を処理する（/eventsならページを閉じるまでイベントを送り続ける）．
+fn serve(mut stream: TcpStream, clients: &Mutex<Vec<mpsc::Sender<String>>>) -> std::io::Result<()> {
+    let mut request = String::new();
+    BufReader::new( stream.try_clone()? ).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    if path == "/events" {
+        let (tx, rx) = mpsc::channel();
+        clients.lock().unwrap().push(tx);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n")?;
+        for event in rx {
+            stream.write_all( event.as_bytes() )?;
+        }
+        Ok(())
+    } else {
+        stream.write_all( format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            PAGE.len(), PAGE
+        ).as_bytes() )
+    }
+}
@@ -0,0 +1,221 @@
+//! 複数のベクトルの組から姿勢を求める（Wahba問題）
+//!
+//! 機体座標系で計測したベクトルb_kと，同じベクトルを基準座標系で表したr_kの組から，
+//! b_kをr_kに重ねる回転（機体座標系→基準座標系）を求める．
+//! 計測値から直接姿勢を計算するので，フィルタの補正ステップで推定姿勢を引き寄せる目標として使う．
+
+use super::quat;
+use super::quat::{Vector3, Quaternion, DCM};
+
+/// ヤコビ法の最大の反復回数（対角化の掃引回数）
+const MAX_SWEEPS: usize = 50;
+
+/// TRIAD法：機体座標系のベクトルb1, b2を基準座標系のr1, r2に重ねる回転を求める．
+///
+/// b1（主ベクトル）はr1に正確に重ね，b2はr1周りの回転を決めるためだけに使う（b1とr1の平面に揃える）．
+/// 重み付けは決定的で，b2の誤差は姿勢に入らない代わりにb1の誤差はすべて姿勢に入る．
+/// 精度の良い方のベクトルを主ベクトルにする．b1とb2が平行だと解は決まらない．
+pub fn triad(b1: Vector3<f64>, b2: Vector3<f64>, r1: Vector3<f64>, r2: Vector3<f64>) -> Quaternion<f64> {
+    let frame = |v1: Vector3<f64>, v2: Vector3<f64>| {
+        let t1 = quat::normalize_vec(v1);
+        let t2 = quat::normalize_vec(quat::cross_vec(v1, v2));
+        [t1, t2, quat::cross_vec(t1, t2)]
+    };
+    let (tb, tr) = (frame(b1, b2), frame(r1, r2));
+    // 機体座標系の正規直交基底を基準座標系の正規直交基底に移す行列 Σ tr[k] tb[k]^T
+    let mut m: DCM<f64> = [[0.0; 3]; 3];
+    for k in 0..3 {
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v += tr[k][i] * tb[k][j];
+            }
+        }
+    }
+    quat::from_dcm(m)
+}
+
+/// Davenportのq-method：重み付きのベクトルの組(b_k, r_k, w_k)から，Σ w_k |r_k - R b_k|^2 を最小にする回転を求める．
+///
+/// TRIAD法と違ってすべてのベクトルを重みに応じて使うので，信頼度の低いベクトルの重みを下げれば
+/// そのベクトルの誤差が姿勢に入る割合を連続的に減らせる．b_k，r_kは正規化してから使う（重みは方向の誤差分散の逆数にする）．
+/// 四元数qについての二次形式 Σ w_k |r_k ⊗ q - q ⊗ b_k|^2 を最小にする固有ベクトルをヤコビ法で求める．
+/// 重みが正のベクトルが1つしか無い（または全て平行）と解は決まらない．
+pub fn davenport(pairs: &[(Vector3<f64>, Vector3<f64>, f64)]) -> Quaternion<f64> {
+    let mut m = [[0.0; 4]; 4];
+    for &(b, r, w) in pairs {
+        let (b, r) = (quat::normalize_vec(b), quat::normalize_vec(r));
+        let d = quat::sub_vec(b, r);
+        let s = quat::add_vec(r, b);
+        // r ⊗ q - q ⊗ b = D q となる行列D
+        let dm = [
+            [0.0, d[0], d[1], d[2]],
+            [-d[0], 0.0, -s[2], s[1]],
+            [-d[1], s[2], 0.0, -s[0]],
+            [-d[2], -s[1], s[0], 0.0],
+        ];
+        // m += w D^T D
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v += w * (0..4).map(|k| dm[k][i] * dm[k][j]).sum::<f64>();
+            }
+        }
+    }
+    let (values, vectors) = symmetric_eigen(m);
+    let i_min = (0..4).min_by(|&a, &b| values[a].total_cmp(&values[b])).unwrap();
+    let q = (vectors[0][i_min], [vectors[1][i_min], vectors[2][i_min], vectors[3][i_min]]);
+    quat::normalize(q)
+}
+
+/// 特異値分解（SVD）：重み付きのベクトルの組(b_k, r_k, w_k)から，Σ w_k |r_k - R b_k|^2 を最小にする回転を求める．
+///
+/// ベクトルの数に制限が無いので，加速度・地磁気のほかに太陽センサなどの方向を加えられる．
+/// b_k，r_kは正規化してから使う．行列 B = Σ w_k r_k b_k^T を B = U S V^T と分解し，
+/// R = U diag(1, 1, det(U) det(V)) V^T とする（反転を含まない回転に限る）．
+/// 互いに平行でないベクトルが2つ以上無いと解は決まらない．
+pub fn svd(pairs: &[(Vector3<f64>, Vector3<f64>, f64)]) -> Quaternion<f64> {
+    let mut b_mat: DCM<f64> = [[0.0; 3]; 3];
+    for &(b, r, w) in pairs {
+        let (b, r) = (quat::normalize_vec(b), quat::normalize_vec(r));
+        for (i, row) in b_mat.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v += w * r[i] * b[j];
+            }
+        }
+    }
+    // B^T Bの固有ベクトルがV，固有値が特異値の2乗
+    let mut btb = [[0.0; 3]; 3];
+    for (i, row) in btb.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| b_mat[k][i] * b_mat[k][j]).sum();
+        }
+    }
+    let (values, vectors) = symmetric_eigen(btb);
+    let mut order = [0, 1, 2];
+    order.sort_by(|&a, &b| values[b].total_cmp(&values[a]));
+    let v: [Vector3<f64>; 3] = order.map(|i| [vectors[0][i], vectors[1][i], vectors[2][i]]);
+    let mul = |x: Vector3<f64>| b_mat.map(|row| quat::dot_vec(row, x));
+
+    // 最小の特異値は0になり得る（ベクトルが2つの場合など）ので，Uの3列目は外積で作る
+    let u1 = quat::normalize_vec(mul(v[0]));
+    let bv2 = mul(v[1]);
+    let u2 = quat::normalize_vec(quat::scale_add_vec(-quat::dot_vec(u1, bv2), u1, bv2));
+    let u3 = quat::cross_vec(u1, u2);
+    let det_v = quat::dot_vec(quat::cross_vec(v[0], v[1]), v[2]);
+    let u = [u1, u2, quat::scale_vec(det_v.signum(), u3)];
+
+    let mut m: DCM<f64> = [[0.0; 3]; 3];
+    for k in 0..3 {
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x += u[k][i] * v[k][j];
+            }
+        }
+    }
+    quat::from_dcm(m)
+}
+
+/// 対称行列の固有値と固有ベクトル（列ベクトル）を巡回ヤコビ法で求める．
+fn symmetric_eigen<const N: usize>(mut a: [[f64; N]; N]) -> ([f64; N], [[f64; N]; N]) {
+    let mut v = [[0.0; N]; N];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    for _ in 0..MAX_SWEEPS {
+        let off: f64 = (0..N).flat_map(|i| (0..N).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j]).sum();
+        let diag: f64 = (0..N).map(|i| a[i][i] * a[i][i]).sum();
+        if off <= f64::EPSILON * f64::EPSILON * diag || off == 0.0 {
+            break;
+        }
+        for p in 0..N {
+            for q in (p + 1)..N {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+                // a[p][q]を0にする回転角
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = (t * t + 1.0).sqrt().recip();
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = c * akp - s * akq;
+                    row[q] = s * akp + c * akq;
+                }
+                let (ap, aq) = (a[p], a[q]);
+                a[p] = std::array::from_fn(|k| c * ap[k] - s * aq[k]);
+                a[q] = std::array::from_fn(|k| s * ap[k] + c * aq[k]);
+                for row in v.iter_mut() {
+                    let (vkp, vkq) = (row[p], row[q]);
+                    row[p] = c * vkp - s * vkq;
+                    row[q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    let mut values = [0.0; N];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = a[i][i];
+    }
+    (values, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn angle_between(a: Quaternion<f64>, b: Quaternion<f64>) -> f64 {
+        2.0 * quat::dot(a, b).abs().min(1.0).acos()
+    }
+
+    #[test]
+    fn triad_trusts_primary_vector() {
+        let q_true = quat::from_axis_angle([0.3, -1.0, 0.5], 0.8);
+        let (r1, r2) = ([0.0, 0.0, 1.0], [0.0, 0.4, -0.9]);
+        let b1 = quat::frame_rotation(q_true, r1);
+        let b2 = quat::frame_rotation(q_true, r2);
+        assert!(angle_between(triad(b1, b2, r1, r2), q_true) < 1e-9);
+
+        // 副ベクトルの誤差は主ベクトルの向きに影響しない
+        let b2_err = quat::add_vec(b2, [0.1, -0.05, 0.2]);
+        let q = triad(b1, b2_err, r1, r2);
+        let b1_est = quat::frame_rotation(q, r1);
+        assert!(quat::norm_vec(quat::sub_vec(b1_est, quat::normalize_vec(b1))) < 1e-9);
+        assert!(angle_between(q, q_true) > 1e-3);
+    }
+
+    #[test]
+    fn davenport_weights_vectors() {
+        let q_true = quat::from_axis_angle([0.3, -1.0, 0.5], 0.8);
+        let refs = [[0.0, 0.0, 9.8], [0.0, 0.4, -0.9], [1.0, 0.0, 0.0]];
+        let pairs: Vec<_> = refs.iter().map(|&r| (quat::frame_rotation(q_true, r), r, 1.0)).collect();
+        let q = davenport(&pairs);
+        assert!(angle_between(q, q_true) < 1e-9);
+
+        // 重みが大きいベクトルほど正確に合わせる
+        let (r1, r2) = (refs[0], refs[1]);
+        let b1 = quat::add_vec(quat::frame_rotation(q_true, r1), [1.0, 0.0, 0.0]);
+        let b2 = quat::frame_rotation(q_true, r2);
+        let err_b1 = |q: Quaternion<f64>| quat::norm_vec(quat::sub_vec(quat::frame_rotation(q, quat::normalize_vec(r1)), quat::normalize_vec(b1)));
+        let q_heavy = davenport(&[(b1, r1, 10.0), (b2, r2, 1.0)]);
+        let q_light = davenport(&[(b1, r1, 0.1), (b2, r2, 1.0)]);
+        assert!(err_b1(q_heavy) < err_b1(q_light));
+        // 重みの比が極端ならTRIAD法と同じ解に近づく
+        assert!(angle_between(davenport(&[(b1, r1, 1e6), (b2, r2, 1.0)]), triad(b1, b2, r1, r2)) < 1e-3);
+    }
+
+    #[test]
+    fn svd_matches_davenport() {
+        let q_true = quat::from_axis_angle([-0.7, 0.2, 0.4], 2.5);
+        let refs = [[0.0, 0.0, 9.8], [0.0, 0.4, -0.9], [0.6, -0.8, 0.3], [1.0, 0.2, 0.0]];
+        let pairs: Vec<_> = refs.iter().map(|&r| (quat::frame_rotation(q_true, r), r, 1.0)).collect();
+        assert!(angle_between(svd(&pairs), q_true) < 1e-7);
+        assert!(angle_between(svd(&pairs[..2]), q_true) < 1e-7);
+
+        // 誤差のあるベクトルでも同じ最小二乗解になる
+        let noisy: Vec<_> = pairs.iter().zip([0.1, -0.2, 0.05, 0.3])
+            .map(|(&(b, r, _), e)| (quat::add_vec(b, [e, -e, 0.5 * e]), r, 1.0 + e))
+            .collect();
+        assert!(angle_between(svd(&noisy), davenport(&noisy)) < 1e-7);
+    }
+}
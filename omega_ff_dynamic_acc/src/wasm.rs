@@ -0,0 +1,48 @@
+//! WebAssembly向けのAPI
+//! 
+//! JavaScriptからは配列を扱いにくいので，ベクトルは要素ごとに受け取る．
+//! 予測ステップはサンプリング周期DTごとに呼び出すこと．
+
+use wasm_bindgen::prelude::*;
+
+use super::ahrs::AttitudeFilter;
+
+#[wasm_bindgen]
+pub struct Filter {
+    inner: AttitudeFilter,
+}
+
+#[wasm_bindgen]
+impl Filter {
+    /// 引数は`AttitudeFilter::new`と同じ．
+    #[wasm_bindgen(constructor)]
+    pub fn new(alpha: f64, beta: f64, thr_weak: f64, thr_strong: f64) -> Filter {
+        Filter { inner: AttitudeFilter::new(alpha, beta, thr_weak, thr_strong) }
+    }
+
+    /// 予測ステップ（角速度[rad/s]）
+    pub fn predict(&mut self, gx: f64, gy: f64, gz: f64) {
+        self.inner.predict([gx, gy, gz]);
+    }
+
+    /// 補正ステップ（加速度[m/s^2]，地磁気）
+    pub fn correct(&mut self, ax: f64, ay: f64, az: f64, mx: f64, my: f64, mz: f64) {
+        self.inner.correct([ax, ay, az], [mx, my, mz]);
+    }
+
+    /// 姿勢推定値 [q0, q1, q2, q3]
+    pub fn quaternion(&self) -> Vec<f64> {
+        let q = self.inner.q;
+        vec![q.0, q.1[0], q.1[1], q.1[2]]
+    }
+
+    /// 姿勢推定値 [x, y, z, w]（f32，three.jsのQuaternionなど描画に使う並び）
+    pub fn quaternion_xyzw(&self) -> Vec<f32> {
+        super::interop::to_xyzw_f32(self.inner.q).to_vec()
+    }
+
+    /// サンプリング周期[s]
+    pub fn dt() -> f64 {
+        super::DT
+    }
+}
@@ -0,0 +1,144 @@
+//! 姿勢の真値と推定値をネイティブのウィンドウでリアルタイムに3次元表示する（--window，windowフィーチャが必要）
+//!
+//! ブラウザでの表示（viewer.rs）と同じ内容をminifbのウィンドウに描く．
+//! 真値の座標軸は灰色の破線，推定値の座標軸は実線（x：赤，y：緑，z：青）で描き，
+//! 外乱を検知している間は推定値の座標軸の色を変える（弱い外乱：黄，強い外乱：赤）．
+//!
+//! 描画は表示の間隔ごとに推定のループの中で行う．ウィンドウを閉じても推定は止めない．
+
+use super::error::Result;
+use super::viewer::Frame;
+
+#[cfg(not(feature = "window"))]
+use super::error::Error;
+
+#[cfg(feature = "window")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "window")]
+use omega_ff_dynamic_acc::quat;
+
+/// ウィンドウの大きさ[px]
+#[cfg(feature = "window")]
+const WIDTH: usize = 480;
+#[cfg(feature = "window")]
+const HEIGHT: usize = 480;
+
+/// 座標軸の長さ[px]
+#[cfg(feature = "window")]
+const SCALE: f64 = 180.0;
+
+/// 視点の方位角と仰角[rad]
+#[cfg(feature = "window")]
+const AZ: f64 = -0.6;
+#[cfg(feature = "window")]
+const EL: f64 = 0.45;
+
+/// 描画する間隔（推定のステップごとには描かない）
+#[cfg(feature = "window")]
+const INTERVAL: Duration = Duration::from_millis(33);
+
+#[cfg(feature = "window")]
+mod color {
+    pub const BACKGROUND: u32 = 0xffffff;
+    pub const TRUTH: u32 = 0x999999;
+    pub const AXES: [u32; 3] = [0xee3333, 0x22aa22, 0x3333ee];
+    pub const WEAK: u32 = 0xe0b000;
+    pub const STRONG: u32 = 0xee0000;
+}
+
+pub struct Window {
+    #[cfg(feature = "window")]
+    window: minifb::Window,
+    #[cfg(feature = "window")]
+    buffer: Vec<u32>,
+    #[cfg(feature = "window")]
+    last: Option<Instant>,   // 最後に描画した時刻
+}
+
+#[cfg(feature = "window")]
+impl Window {
+    pub fn open() -> Result<Self> {
+        let mut window = minifb::Window::new("omega_ff_dynamic_acc", WIDTH, HEIGHT, minifb::WindowOptions::default())?;
+        // 描画の間隔は自分で決めるので，minifbに待たせない
+        window.set_target_fps(0);
+        Ok( Self { window, buffer: vec![color::BACKGROUND; WIDTH * HEIGHT], last: None } )
+    }
+
+    /// 前回の描画から表示の間隔が経っていれば描画する．
+    pub fn show(&mut self, frame: &Frame) {
+        if !self.window.is_open() || self.last.is_some_and(|last| last.elapsed() < INTERVAL) {
+            return;
+        }
+        self.last = Some( Instant::now() );
+
+        self.buffer.fill(color::BACKGROUND);
+        if let Some(q_true) = frame.q_true {
+            self.draw_axes(q_true, [color::TRUTH; 3], true, 2);
+        }
+        let colors = if frame.strong {
+            [color::STRONG; 3]
+        } else if frame.weak {
+            [color::WEAK; 3]
+        } else {
+            color::AXES
+        };
+        self.draw_axes(frame.q, colors, false, 4);
+
+        let state = if frame.strong { "強い外乱" } else if frame.weak { "弱い外乱" } else { "外乱なし" };
+        self.window.set_title(&format!("omega_ff_dynamic_acc  {:.2} s  {}", frame.time, state));
+        if let Err(e) = self.window.update_with_buffer(&self.buffer, WIDTH, HEIGHT) {
+            eprintln!("ウィンドウに描画できませんでした（{}）", e);
+        }
+    }
+
+    /// 機体座標系の座標軸を描く．
+    fn draw_axes(&mut self, q: quat::Quaternion<f64>, colors: [u32; 3], dashed: bool, width: usize) {
+        let o = project([0.0; 3]);
+        for (i, color) in colors.into_iter().enumerate() {
+            let mut e = [0.0; 3];
+            e[i] = 1.0;
+            let p = project( quat::vector_rotation(q, e) );
+            self.draw_line(o, p, color, dashed, width);
+        }
+    }
+
+    fn draw_line(&mut self, from: [f64; 2], to: [f64; 2], color: u32, dashed: bool, width: usize) {
+        /// 破線の線と間隔の長さ[px]
+        const DASH: usize = 6;
+
+        let n = (to[0] - from[0]).abs().max( (to[1] - from[1]).abs() ).ceil() as usize;
+        for k in 0..=n {
+            if dashed && (k / DASH) % 2 == 1 {
+                continue;
+            }
+            let t = if n == 0 { 0.0 } else { k as f64 / n as f64 };
+            let x = (from[0] + t * (to[0] - from[0])).round() as isize - width as isize / 2;
+            let y = (from[1] + t * (to[1] - from[1])).round() as isize - width as isize / 2;
+            for (dx, dy) in (0..width).flat_map(|dx| (0..width).map(move |dy| (dx, dy))) {
+                let (px, py) = (x + dx as isize, y + dy as isize);
+                if (0..WIDTH as isize).contains(&px) && (0..HEIGHT as isize).contains(&py) {
+                    self.buffer[py as usize * WIDTH + px as usize] = color;
+                }
+            }
+        }
+    }
+}
+
+/// 基準座標系の点をウィンドウに投影する（viewer.htmlと同じ視点）．
+#[cfg(feature = "window")]
+fn project(p: quat::Vector3<f64>) -> [f64; 2] {
+    let x = AZ.cos() * p[0] - AZ.sin() * p[1];
+    let d = AZ.sin() * p[0] + AZ.cos() * p[1];
+    let y = EL.cos() * p[2] - EL.sin() * d;
+    [WIDTH as f64 / 2.0 + SCALE * x, HEIGHT as f64 / 2.0 - SCALE * y]
+}
+
+#[cfg(not(feature = "window"))]
+impl Window {
+    pub fn open() -> Result<Self> {
+        Err( Error::Feature { option: "--window", feature: "window" } )
+    }
+
+    pub fn show(&mut self, _frame: &Frame) {}
+}
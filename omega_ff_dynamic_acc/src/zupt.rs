@@ -0,0 +1,83 @@
+//! 静止状態の検出（ZUPT：Zero-velocity UPdaTe用）
+//!
+//! 直近windowサンプルの角速度と加速度の分散（各軸の分散の和）がどちらも閾値以下なら静止しているとみなす．
+//! 分散だけでは一定の角速度で回転している状態と区別できないので，with_max_rate()で角速度の平均の大きさにも上限を設けられる．
+
+use std::collections::VecDeque;
+
+use super::quat::{self, Vector3};
+
+#[derive(Debug, Clone)]
+pub struct StationaryDetector {
+    window: usize,                    // 分散を計算するサンプル数
+    thr_gyr_var: f64,                 // 角速度の分散の閾値[(rad/s)^2]
+    thr_acc_var: f64,                 // 加速度の分散の閾値[(m/s^2)^2]
+    max_rate: Option<f64>,            // 角速度の平均の大きさの上限[rad/s]
+    buf: VecDeque<(Vector3<f64>, Vector3<f64>)>,  // 直近の（角速度，加速度）
+    sum: [Vector3<f64>; 2],           // 角速度，加速度の和
+    sum_sq: [Vector3<f64>; 2],        // 角速度，加速度の二乗和
+}
+
+impl StationaryDetector {
+    /// * window     : 分散を計算するサンプル数
+    /// * thr_gyr_var: 角速度の分散（3軸の和）の閾値[(rad/s)^2]
+    /// * thr_acc_var: 加速度の分散（3軸の和）の閾値[(m/s^2)^2]
+    pub fn new(window: usize, thr_gyr_var: f64, thr_acc_var: f64) -> Self {
+        Self {
+            window,
+            thr_gyr_var,
+            thr_acc_var,
+            max_rate: None,
+            buf: VecDeque::with_capacity(window + 1),
+            sum: [[0.0; 3]; 2],
+            sum_sq: [[0.0; 3]; 2],
+        }
+    }
+
+    /// 角速度の平均の大きさがmax_rate[rad/s]を超えている間は静止とみなさない．
+    /// 
+    /// 角速度バイアスより大きく，検出したい最も遅い回転より小さい値にする．
+    pub fn with_max_rate(mut self, max_rate: f64) -> Self {
+        self.max_rate = Some(max_rate);
+        self
+    }
+
+    /// 計測値を追加し，静止しているかどうかを返す（windowサンプル溜まるまではfalse）．
+    ///
+    /// * gyr: 機体上で計測した角速度[rad/s]
+    /// * acc: 機体上のセンサで計測した加速度[m/s^2]
+    pub fn update(&mut self, gyr: Vector3<f64>, acc: Vector3<f64>) -> bool {
+        self.accumulate(gyr, acc, 1.0);
+        self.buf.push_back((gyr, acc));
+        if self.buf.len() > self.window {
+            let (gyr_old, acc_old) = self.buf.pop_front().unwrap();
+            self.accumulate(gyr_old, acc_old, -1.0);
+        }
+
+        self.buf.len() == self.window
+            && self.variance(0) <= self.thr_gyr_var
+            && self.variance(1) <= self.thr_acc_var
+            && self.max_rate.is_none_or(|max| quat::norm_vec(self.sum[0]) <= max * self.buf.len() as f64)
+    }
+
+    /// 和と二乗和にsign倍して加える．
+    fn accumulate(&mut self, gyr: Vector3<f64>, acc: Vector3<f64>, sign: f64) {
+        for (k, v) in [gyr, acc].into_iter().enumerate() {
+            self.sum[k] = quat::scale_add_vec(sign, v, self.sum[k]);
+            for (s, x) in self.sum_sq[k].iter_mut().zip(v) {
+                *s += sign * x * x;
+            }
+        }
+    }
+
+    /// 各軸の分散の和（k=0:角速度，k=1:加速度）
+    fn variance(&self, k: usize) -> f64 {
+        let n = self.buf.len() as f64;
+        let mut var = 0.0;
+        for i in 0..3 {
+            let mean = self.sum[k][i] / n;
+            var += (self.sum_sq[k][i] / n - mean * mean).max(0.0);
+        }
+        var
+    }
+}
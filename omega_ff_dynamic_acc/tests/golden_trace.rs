@@ -0,0 +1,96 @@
+//! 固定シードのシナリオを外乱判定式ごとに実行し，記録済みの推定結果（tests/golden/trace_e1.csv，trace_e2.csv）と比較する．
+//!
+//! アルゴリズムのリファクタリングで推定結果が意図せず変わっていないことを確認するためのテスト．
+//! 推定結果を意図して変えた場合は，以下のコマンドで記録し直す．
+//!
+//! ```text
+//! UPDATE_GOLDEN=1 cargo test --test golden_trace
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use omega_ff_dynamic_acc::{ahrs, synthetic, DT};
+
+/// 乱数のシード
+const SEED: u64 = 20200501;
+
+/// シナリオの長さ[s]
+const SIM_TIME: f64 = 30.0;
+
+/// ノイズの分散（シミュレーションと同じ値）
+const NOISE: synthetic::NoiseVariance = synthetic::NoiseVariance { gyr: 0.0001, acc: 0.01, mag: 0.01 };
+
+/// 記録する間隔（サンプル数）
+const INTERVAL: usize = 10;
+
+/// 許容誤差
+const TOLERANCE: f64 = 1e-6;
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("tests/golden/trace_{}.csv", name))
+}
+
+/// シミュレーションと同じシナリオ（synthetic::generate()）を実行し，
+/// 時刻，姿勢推定値，バイアス推定値をINTERVALごとに返す．
+fn run_scenario(detector: ahrs::Detector) -> Vec<Vec<f64>> {
+    let n = (SIM_TIME / DT) as usize + 1;
+    let steps = synthetic::generate(&mut StdRng::seed_from_u64(SEED), n, NOISE);
+
+    let mut filter = ahrs::AttitudeFilter::new(1.0, 0.2, 0.04, 0.08).with_detector(detector);
+    let mut trace = Vec::new();
+    for (t, step) in steps.iter().enumerate() {
+        filter.predict(step.sample.gyr);
+        filter.correct(step.sample.acc, step.sample.mag);
+
+        if t % INTERVAL == 0 {
+            let mut row = vec![step.time, filter.q.0];
+            row.extend(filter.q.1);
+            row.extend(filter.gyro_bias());
+            trace.push(row);
+        }
+    }
+    trace
+}
+
+#[test]
+#[cfg(feature = "detector-e1")]
+fn golden_trace_e1() {
+    check_golden("e1", ahrs::Detector::E1);
+}
+
+#[test]
+#[cfg(feature = "detector-e2")]
+fn golden_trace_e2() {
+    check_golden("e2", ahrs::Detector::E2);
+}
+
+fn check_golden(name: &str, detector: ahrs::Detector) {
+    let trace = run_scenario(detector);
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        let lines: Vec<String> = trace.iter()
+            .map(|row| row.iter().map(|v| format!("{:.12}", v)).collect::<Vec<_>>().join(","))
+            .collect();
+        fs::write(golden_path(name), lines.join("\n") + "\n").unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(golden_path(name)).expect("記録済みの推定結果がありません（UPDATE_GOLDEN=1で記録してください）");
+    let golden: Vec<Vec<f64>> = golden.lines()
+        .map(|line| line.split(',').map(|v| v.parse().unwrap()).collect())
+        .collect();
+
+    assert_eq!(trace.len(), golden.len(), "記録済みの推定結果と長さが違います");
+    for (row, row_golden) in trace.iter().zip(&golden) {
+        for (v, v_golden) in row.iter().zip(row_golden) {
+            assert!(
+                (v - v_golden).abs() < TOLERANCE,
+                "時刻{}sで記録済みの推定結果と一致しません\n  推定結果: {:?}\n  記録済み: {:?}", row[0], row, row_golden
+            );
+        }
+    }
+}
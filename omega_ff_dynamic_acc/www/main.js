@@ -0,0 +1,108 @@
+// ブラウザのセンサイベントを姿勢推定フィルタに入力して，推定値を描画する．
+//
+// 座標系はどちらも東-北-天（ENU）で，端末座標系はW3C DeviceOrientation仕様に従う．
+//   x: 画面右，y: 画面上，z: 画面手前
+
+import init, { Filter } from '../pkg/omega_ff_dynamic_acc.js';
+
+const DEG2RAD = Math.PI / 180.0;
+
+// 最新の計測値
+let gyr = [0.0, 0.0, 0.0];
+let acc = [0.0, 0.0, 9.80665];
+let mag = [0.0, 1.0, 0.0];
+let magSource = 'none';
+
+// 端末の姿勢（Z-X'-Y''のオイラー角）から機体座標系上の北向きベクトルを計算する．
+// ブラウザは地磁気の生値を出さないことが多いので，その場合の代わりに使う．
+function magFromOrientation(alpha, beta, gamma) {
+  const [sa, ca] = [Math.sin(alpha * DEG2RAD), Math.cos(alpha * DEG2RAD)];
+  const [sb, cb] = [Math.sin(beta * DEG2RAD), Math.cos(beta * DEG2RAD)];
+  const [sg, cg] = [Math.sin(gamma * DEG2RAD), Math.cos(gamma * DEG2RAD)];
+  // R = Rz(alpha) Rx(beta) Ry(gamma) の2行目（= R^T [0, 1, 0]）
+  return [sa * cg + ca * sb * sg, ca * cb, sa * sg - ca * sb * cg];
+}
+
+// 四元数（機体 -> 基準座標系の回転）をCSSのmatrix3dに変換する．
+// CSSはy軸が下向きなので，y成分の符号を反転させる．
+function toMatrix3d(q) {
+  const [q0, q1, q2, q3] = q;
+  const r = [
+    [1 - 2 * (q2 * q2 + q3 * q3), 2 * (q1 * q2 - q0 * q3), 2 * (q1 * q3 + q0 * q2)],
+    [2 * (q1 * q2 + q0 * q3), 1 - 2 * (q1 * q1 + q3 * q3), 2 * (q2 * q3 - q0 * q1)],
+    [2 * (q1 * q3 - q0 * q2), 2 * (q2 * q3 + q0 * q1), 1 - 2 * (q1 * q1 + q2 * q2)],
+  ];
+  const s = [1, -1, 1];
+  const m = [];
+  for (let col = 0; col < 3; col++) {
+    for (let row = 0; row < 3; row++) {
+      m.push(s[row] * r[row][col] * s[col]);
+    }
+    m.push(0);
+  }
+  m.push(0, 0, 0, 1);
+  return `matrix3d(${m.join(',')})`;
+}
+
+function startSensors() {
+  window.addEventListener('devicemotion', (e) => {
+    const r = e.rotationRate;
+    if (r && r.alpha !== null) {
+      gyr = [r.beta * DEG2RAD, r.gamma * DEG2RAD, r.alpha * DEG2RAD];
+    }
+    const a = e.accelerationIncludingGravity;
+    if (a && a.x !== null) {
+      acc = [a.x, a.y, a.z];
+    }
+  });
+
+  // 地磁気センサ（Generic Sensor API）が使えればそれを使う
+  if ('Magnetometer' in window) {
+    try {
+      const sensor = new window.Magnetometer({ frequency: 50 });
+      sensor.addEventListener('reading', () => {
+        mag = [sensor.x, sensor.y, sensor.z];
+        magSource = 'magnetometer';
+      });
+      sensor.start();
+    } catch (err) {
+      console.log(err);
+    }
+  }
+  window.addEventListener('deviceorientationabsolute', (e) => {
+    if (magSource !== 'magnetometer' && e.alpha !== null) {
+      mag = magFromOrientation(e.alpha, e.beta, e.gamma);
+      magSource = 'orientation';
+    }
+  });
+}
+
+async function main() {
+  await init();
+  const filter = new Filter(1.0, 0.2, 0.04, 0.08);
+  const phone = document.getElementById('phone');
+  const info = document.getElementById('info');
+
+  document.getElementById('start').addEventListener('click', async () => {
+    // iOSではセンサの使用許可が必要
+    if (typeof DeviceMotionEvent !== 'undefined' && typeof DeviceMotionEvent.requestPermission === 'function') {
+      await DeviceMotionEvent.requestPermission();
+    }
+    startSensors();
+
+    // センサイベントの周期はまちまちなので，フィルタは一定周期（DT）で回す
+    setInterval(() => {
+      filter.predict(...gyr);
+      filter.correct(...acc, ...mag);
+      const q = filter.quaternion();
+      phone.style.transform = toMatrix3d(q);
+      info.textContent =
+        `q   : [${Array.from(q).map((v) => v.toFixed(4)).join(', ')}]\n` +
+        `gyr : [${gyr.map((v) => v.toFixed(3)).join(', ')}] rad/s\n` +
+        `acc : [${acc.map((v) => v.toFixed(3)).join(', ')}] m/s^2\n` +
+        `mag : [${mag.map((v) => v.toFixed(3)).join(', ')}] (${magSource})`;
+    }, Filter.dt() * 1000);
+  });
+}
+
+main();
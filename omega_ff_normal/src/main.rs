@@ -4,7 +4,6 @@
 
 use std::fs;
 use std::io::{Write, BufWriter};
-use std::mem::MaybeUninit;
 
 use rand::distributions::{Distribution, Normal};
 use quaternion_core as quat;
@@ -45,7 +44,7 @@ fn main() {
     for t in 0..N {
         let time = t as f64 * DT;
 
-        if time >= 10.0 && time <= 20.0 {
+        if (10.0..=20.0).contains(&time) {
             //a_dr[0] = 0.5 * (time * 5.0).sin() + 1.0;
             a_dr[0] = 3.0;
         } else {
@@ -78,47 +77,47 @@ fn main() {
 
         // ---------- データ書き込み ---------- //
         // 時刻
-        file.write( format!("{:.3},", time ).as_bytes() ).unwrap();
+        file.write_all( format!("{:.3},", time ).as_bytes() ).unwrap();
         // オイラー角の真値
         let ypr_true = quat::to_euler_angles( q );
-        for i in 0..3 {
-            file.write( format!("{:.7},", ypr_true[i] ).as_bytes() ).unwrap();
+        for v in ypr_true {
+            file.write_all( format!("{:.7},", v ).as_bytes() ).unwrap();
         }
         // オイラー角の推定値
         let ypr_hat = quat::to_euler_angles( filter.q );
-        for i in 0..3 {
-            file.write( format!("{:.7},", ypr_hat[i] ).as_bytes() ).unwrap();
+        for v in ypr_hat {
+            file.write_all( format!("{:.7},", v ).as_bytes() ).unwrap();
         }
         // 角速度バイアスの真値
-        for i in 0..3 {
-            file.write( format!("{:.7},", gyr_bias[i] ).as_bytes() ).unwrap();
+        for v in gyr_bias {
+            file.write_all( format!("{:.7},", v ).as_bytes() ).unwrap();
         }
         // 角速度バイアスの推定値（補正の仕方の問題で符号が反転している）
         for i in 0..3 {
-            file.write( format!("{:.7},", -beta * filter.gyr_integ[i] ).as_bytes() ).unwrap();
+            file.write_all( format!("{:.7},", -beta * filter.gyr_integ[i] ).as_bytes() ).unwrap();
         }
         // 四元数の真値
-        file.write( format!("{:.7},", q.0 ).as_bytes() ).unwrap();
+        file.write_all( format!("{:.7},", q.0 ).as_bytes() ).unwrap();
         for i in 0..3 {
-            file.write( format!("{:.7},", q.1[i] ).as_bytes() ).unwrap();
+            file.write_all( format!("{:.7},", q.1[i] ).as_bytes() ).unwrap();
         }
         // 四元数の推定値
-        file.write( format!("{:.7},", filter.q.0 ).as_bytes() ).unwrap();
+        file.write_all( format!("{:.7},", filter.q.0 ).as_bytes() ).unwrap();
         for i in 0..3 {
-            file.write( format!("{:.7},", filter.q.1[i] ).as_bytes() ).unwrap();
+            file.write_all( format!("{:.7},", filter.q.1[i] ).as_bytes() ).unwrap();
         }
         // 加速度外乱の真値
-        for i in 0..2 {
-            file.write( format!("{:.7},", a_dr[i] ).as_bytes() ).unwrap();
+        for v in &a_dr[0..2] {
+            file.write_all( format!("{:.7},", v ).as_bytes() ).unwrap();
         }
-        file.write( format!("{:.7}\n", a_dr[2] ).as_bytes() ).unwrap();
+        file.write_all( format!("{:.7}\n", a_dr[2] ).as_bytes() ).unwrap();
         // ------------------------------------ //
     }
 }
 
 /// ベクトルxにノイズを加える．
 fn add_noise(randn: &rand::distributions::Normal, variance: f64, x: quat::Vector3<f64>) -> quat::Vector3<f64> {
-    let mut noisy: quat::Vector3<f64> = unsafe {MaybeUninit::uninit().assume_init()};
+    let mut noisy = [0.0; 3];
 
     let tmp = variance.sqrt();
     for i in 0..3 {